@@ -6,13 +6,25 @@ pub(crate) struct Metric {
     description: &'static str,
 }
 
-pub(crate) const COUNTERS: [Metric; 4] = [
+pub(crate) const COUNTERS: [Metric; 9] = [
     TOPIC_MSG_IN_COUNTER,
     TOPIC_BYTES_IN_COUNTER,
+    TOPIC_SEGMENTS_DROPPED_COUNTER,
+    TOPIC_MESSAGES_EXPIRED_COUNTER,
     CONSUMER_MSG_OUT_COUNTER,
     CONSUMER_BYTES_OUT_COUNTER,
+    CACHE_HITS_COUNTER,
+    CACHE_MISSES_COUNTER,
+    CACHE_EVICTIONS_COUNTER,
+];
+pub(crate) const GAUGES: [Metric; 6] = [
+    BROKER_TOPICS,
+    TOPIC_PRODUCERS,
+    TOPIC_CONSUMERS,
+    CONSUMERS_BLOCKED_ON_FLOW,
+    CACHE_BYTES,
+    CACHE_PINNED_BYTES,
 ];
-pub(crate) const GAUGES: [Metric; 3] = [BROKER_TOPICS, TOPIC_PRODUCERS, TOPIC_CONSUMERS];
 pub(crate) const HISTOGRAMS: [Metric; 1] = [PRODUCER_MSG_OUT_RATE];
 
 // BROKER Metrics --------------------------
@@ -34,6 +46,18 @@ pub(crate) const TOPIC_BYTES_IN_COUNTER: Metric = Metric {
     description: "Total bytes published to the topic (bytes)",
 };
 
+// emitted by the reliable dispatch, when the retention limits of the topic are reached
+pub(crate) const TOPIC_SEGMENTS_DROPPED_COUNTER: Metric = Metric {
+    name: danube_reliable_dispatch::SEGMENTS_DROPPED_COUNTER,
+    description: "Total segments dropped from the topic before being consumed (segments)",
+};
+
+// emitted by the dispatchers, and by the reliable dispatch, as the expired messages are skipped
+pub(crate) const TOPIC_MESSAGES_EXPIRED_COUNTER: Metric = Metric {
+    name: danube_reliable_dispatch::MESSAGES_EXPIRED_COUNTER,
+    description: "Total messages expired before being delivered to a subscription (msg)",
+};
+
 pub(crate) const TOPIC_PRODUCERS: Metric = Metric {
     name: "danube_topic_producers",
     description: "Total number of producers per topic",
@@ -63,6 +87,40 @@ pub(crate) const CONSUMER_BYTES_OUT_COUNTER: Metric = Metric {
     description: "Total bytes delivered to consumer (bytes)",
 };
 
+// the consumers that used all their permits, waiting for the client to grant more
+pub(crate) const CONSUMERS_BLOCKED_ON_FLOW: Metric = Metric {
+    name: "danube_consumers_blocked_on_flow",
+    description: "Total number of consumers per topic without permits to receive messages",
+};
+
+// CACHE Metrics --------------------------
+// emitted by the reliable dispatch, for the segments cache shared by the topics of the broker
+
+pub(crate) const CACHE_HITS_COUNTER: Metric = Metric {
+    name: danube_reliable_dispatch::CACHE_HITS_COUNTER,
+    description: "Total segment lookups served from the cache",
+};
+
+pub(crate) const CACHE_MISSES_COUNTER: Metric = Metric {
+    name: danube_reliable_dispatch::CACHE_MISSES_COUNTER,
+    description: "Total segment lookups loaded from the storage backend",
+};
+
+pub(crate) const CACHE_EVICTIONS_COUNTER: Metric = Metric {
+    name: danube_reliable_dispatch::CACHE_EVICTIONS_COUNTER,
+    description: "Total segments evicted from the cache to stay within its budget",
+};
+
+pub(crate) const CACHE_BYTES: Metric = Metric {
+    name: danube_reliable_dispatch::CACHE_BYTES_GAUGE,
+    description: "Size of the closed segments held in the cache (bytes)",
+};
+
+pub(crate) const CACHE_PINNED_BYTES: Metric = Metric {
+    name: danube_reliable_dispatch::CACHE_PINNED_BYTES_GAUGE,
+    description: "Size of the open segments pinned in the cache (bytes)",
+};
+
 pub(crate) fn init_metrics(prom_addr: Option<std::net::SocketAddr>) {
     info!("Initializing metrics exporter");
 
@@ -7,11 +7,13 @@ mod producer_handler;
 use crate::auth::{AuthConfig, AuthMode};
 use crate::auth_jwt::jwt_auth_interceptor;
 use crate::broker_service::BrokerService;
+use crate::consumer::FlowPermits;
 use danube_core::proto::{
     auth_service_server::AuthServiceServer, consumer_service_server::ConsumerServiceServer,
     discovery_server::DiscoveryServer, health_check_server::HealthCheckServer,
     producer_service_server::ProducerServiceServer,
 };
+use dashmap::DashMap;
 
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -25,6 +27,9 @@ use tracing::warn;
 #[derive(Debug, Clone)]
 pub(crate) struct DanubeServerImpl {
     service: Arc<Mutex<BrokerService>>,
+    // maps consumer_id to the permits of its receive stream, the client grants the permits
+    // without taking the broker lock, held while the messages are dispatched
+    consumer_permits: Arc<DashMap<u64, Arc<FlowPermits>>>,
     broker_addr: SocketAddr,
     auth: AuthConfig,
     // the api key is used to authenticate the user for JWT auth
@@ -39,6 +44,7 @@ impl DanubeServerImpl {
     ) -> Self {
         DanubeServerImpl {
             service,
+            consumer_permits: Arc::new(DashMap::new()),
             broker_addr,
             auth,
             valid_api_keys: Vec::new(),
@@ -1,4 +1,4 @@
-use crate::auth_jwt::{create_token, Claims};
+use crate::auth_jwt::{create_token, validate_token, Claims};
 use crate::broker_server::DanubeServerImpl;
 use crate::error_message::create_error_status;
 
@@ -17,13 +17,16 @@ impl AuthService for DanubeServerImpl {
     ) -> std::result::Result<Response<AuthResponse>, tonic::Status> {
         let req = request.into_inner();
 
-        // Validate API key
-        if self.valid_api_keys.contains(&req.api_key) {
+        // Validate API key, the brokers of the cluster authenticate with a token signed with its JWT secret
+        let jwt_secret = &self.auth.jwt.as_ref().unwrap().secret_key;
+        if self.valid_api_keys.contains(&req.api_key)
+            || validate_token(&req.api_key, jwt_secret).is_ok()
+        {
             let claims = Claims {
                 iss: "example".to_string(),
                 exp: 10000000000, // Set the expiration time
             };
-            let token = match create_token(&claims, &self.auth.jwt.as_ref().unwrap().secret_key) {
+            let token = match create_token(&claims, jwt_secret) {
                 Ok(token) => token,
                 Err(e) => {
                     let error_string = format!("Unable to create JWT token: {}", e);
@@ -1,12 +1,15 @@
 use crate::broker_server::DanubeServerImpl;
-use crate::message::AckMessage;
+use crate::dead_letter::DeadLetterPolicy;
+use crate::message::{AckMessage, NackMessage};
 use crate::subscription::SubscriptionOptions;
 use danube_core::proto::{
     consumer_service_server::ConsumerService, AckRequest, AckResponse, ConsumerRequest,
-    ConsumerResponse, ReceiveRequest, StreamMessage,
+    ConsumerResponse, FlowRequest, FlowResponse, NackRequest, NackResponse, ReceiveRequest,
+    StreamMessage,
 };
 
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
@@ -72,8 +75,35 @@ impl ConsumerService for DanubeServerImpl {
             subscription_type: req.subscription_type,
             consumer_id: None,
             consumer_name: req.consumer_name.clone(),
+            dead_letter_policy: req.dead_letter_policy.map(DeadLetterPolicy::from),
+            ack_timeout_ms: req.ack_timeout_ms,
+            backoff_multiplier: req.backoff_multiplier,
+            max_backoff_ms: req.max_backoff_ms,
+            max_unacked_messages: req.max_unacked_messages,
+            priority_level: req.priority_level,
         };
 
+        if subscription_options.max_unacked_messages == Some(0) {
+            let status = Status::invalid_argument(
+                "Invalid max unacked messages, at least one message should be in flight",
+            );
+            return Err(status);
+        }
+
+        // the redeliveries should neither spin nor shrink
+        let redelivery_backoff = subscription_options.redelivery_backoff();
+        if redelivery_backoff.ack_timeout.is_zero()
+            || !(redelivery_backoff.backoff_multiplier >= 1.0
+                && redelivery_backoff.backoff_multiplier.is_finite())
+            || redelivery_backoff.max_backoff < redelivery_backoff.ack_timeout
+        {
+            let status = Status::invalid_argument(format!(
+                "Invalid redelivery schedule: {:?}, the ack timeout should be positive, the backoff multiplier at least 1 and the max backoff not below the ack timeout",
+                redelivery_backoff
+            ));
+            return Err(status);
+        }
+
         let sub_name = subscription_options.subscription_name.clone();
 
         let consumer_id = service
@@ -106,7 +136,8 @@ impl ConsumerService for DanubeServerImpl {
         &self,
         request: tonic::Request<ReceiveRequest>,
     ) -> std::result::Result<tonic::Response<Self::ReceiveMessagesStream>, tonic::Status> {
-        let consumer_id = request.into_inner().consumer_id;
+        let receive_request = request.into_inner();
+        let consumer_id = receive_request.consumer_id;
 
         // Create a new mpsc channel to stream messages to the client via gRPC
         let (grpc_tx, grpc_rx) = mpsc::channel(4); // Buffer size of 4, adjust as needed
@@ -116,7 +147,7 @@ impl ConsumerService for DanubeServerImpl {
         let arc_service = self.service.clone();
         let mut service = arc_service.lock().await;
 
-        let rx = if let Some(consumer) = service.find_consumer_rx(consumer_id).await {
+        let consumer = if let Some(consumer) = service.find_consumer_by_id(consumer_id).await {
             consumer
         } else {
             let status = Status::not_found(format!(
@@ -126,18 +157,44 @@ impl ConsumerService for DanubeServerImpl {
             return Err(status);
         };
 
-        let rx_cloned = Arc::clone(&rx);
+        // a new stream starts the flow with the initial permits of the client
+        let stream = consumer.permits.start(receive_request.permits);
+        self.consumer_permits
+            .insert(consumer_id, consumer.permits.clone());
+        drop(service);
+
+        let consumer_permits = self.consumer_permits.clone();
+        let rx_cloned = Arc::clone(&consumer.rx_cons);
 
         tokio::spawn(async move {
             let mut rx_guard = rx_cloned.lock().await;
 
-            while let Some(stream_message) = rx_guard.recv().await {
-                if grpc_tx.send(Ok(stream_message.into())).await.is_err() {
-                    // Error handling for when the client disconnects
-                    warn!("Client disconnected for consumer_id: {}", consumer_id);
-                    break;
+            loop {
+                tokio::select! {
+                    stream_message = rx_guard.recv() => {
+                        let Some(stream_message) = stream_message else {
+                            break;
+                        };
+                        if grpc_tx.send(Ok(stream_message.into())).await.is_err() {
+                            // Error handling for when the client disconnects
+                            warn!("Client disconnected for consumer_id: {}", consumer_id);
+                            break;
+                        }
+                    }
+                    _ = grpc_tx.closed() => {
+                        warn!("Client disconnected for consumer_id: {}", consumer_id);
+                        break;
+                    }
                 }
             }
+
+            // the dispatcher hands the messages over to the standby consumers, if any
+            if consumer.permits.stop(stream) {
+                consumer.set_status_false().await;
+                consumer.permits.wake_dispatcher();
+            }
+            // unless the client reconnected in the meantime
+            consumer_permits.remove_if(&consumer_id, |_, permits| permits.is_current(stream));
         });
 
         Ok(Response::new(ReceiverStream::new(grpc_rx)))
@@ -174,4 +231,68 @@ impl ConsumerService for DanubeServerImpl {
             }
         }
     }
+
+    // Consumer negatively acknowledge the received message, to be redelivered
+    async fn nack(
+        &self,
+        request: tonic::Request<NackRequest>,
+    ) -> std::result::Result<tonic::Response<NackResponse>, tonic::Status> {
+        let nack_request = request.into_inner();
+        let msg_id = nack_request
+            .msg_id
+            .ok_or_else(|| Status::invalid_argument("The message id is missing"))?;
+        let nack = NackMessage {
+            request_id: nack_request.request_id,
+            msg_id: msg_id.into(),
+            subscription_name: nack_request.subscription_name,
+            redelivery_delay: nack_request.redelivery_delay_ms.map(Duration::from_millis),
+        };
+
+        let request_id = nack_request.request_id;
+        let msg_id = nack.msg_id.clone();
+
+        trace!("Received nack request for message_id: {}", nack.msg_id);
+
+        let arc_service = self.service.clone();
+        let mut service = arc_service.lock().await;
+
+        match service.nack_message(nack).await {
+            Ok(()) => {
+                trace!("Message with id: {} was negatively acknowledged", msg_id);
+                Ok(tonic::Response::new(NackResponse { request_id }))
+            }
+            Err(err) => {
+                let status =
+                    Status::internal(format!("Error negatively acknowledging message: {}", err));
+                Err(status)
+            }
+        }
+    }
+
+    async fn flow(
+        &self,
+        request: tonic::Request<FlowRequest>,
+    ) -> std::result::Result<tonic::Response<FlowResponse>, tonic::Status> {
+        let flow_request = request.into_inner();
+
+        trace!(
+            "Consumer {} granted {} permits",
+            flow_request.consumer_id,
+            flow_request.permits
+        );
+
+        // the permits are granted without the broker lock, so the dispatchers are never blocked on them
+        match self.consumer_permits.get(&flow_request.consumer_id) {
+            Some(permits) => {
+                permits.grant(flow_request.permits);
+                Ok(tonic::Response::new(FlowResponse {
+                    request_id: flow_request.request_id,
+                }))
+            }
+            None => Err(Status::not_found(format!(
+                "The consumer with the id {} does not exist",
+                flow_request.consumer_id
+            ))),
+        }
+    }
 }
@@ -1,14 +1,18 @@
-use crate::{broker_metrics::PRODUCER_MSG_OUT_RATE, broker_server::DanubeServerImpl};
+use crate::{
+    broker_metrics::PRODUCER_MSG_OUT_RATE, broker_server::DanubeServerImpl,
+    error_message::create_error_status,
+};
 use danube_core::proto::{
-    producer_service_server::ProducerService, MessageResponse, ProducerRequest, ProducerResponse,
-    StreamMessage as ProtoStreamMessage,
+    producer_service_server::ProducerService, ErrorType, MessageResponse, ProducerRequest,
+    ProducerResponse, StreamMessage as ProtoStreamMessage,
 };
 
 use danube_core::message::StreamMessage;
+use danube_reliable_dispatch::ReliableDispatchError;
 use metrics::histogram;
 use std::collections::hash_map::Entry;
 use std::time::Instant;
-use tonic::{Request, Response, Status};
+use tonic::{Code, Request, Response, Status};
 use tracing::{info, trace, Level};
 
 #[tonic::async_trait]
@@ -133,7 +137,16 @@ impl ProducerService for DanubeServerImpl {
         let producer_id = stream_message.msg_id.producer_id;
 
         topic.publish_message(stream_message).await.map_err(|err| {
-            Status::permission_denied(format!("Unable to publish the message: {}", err))
+            match err.downcast_ref::<ReliableDispatchError>() {
+                // the topic is full, the producer should retry once the subscriptions catch up
+                Some(ReliableDispatchError::RetentionLimitReached(_)) => create_error_status(
+                    Code::ResourceExhausted,
+                    ErrorType::TopicRetentionLimitReached,
+                    &format!("Unable to publish the message: {}", err),
+                    None,
+                ),
+                _ => Status::permission_denied(format!("Unable to publish the message: {}", err)),
+            }
         })?;
 
         // Measure the elapsed time
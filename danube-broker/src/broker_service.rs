@@ -1,10 +1,10 @@
 use anyhow::{anyhow, Result};
-use danube_core::{dispatch_strategy::ConfigDispatchStrategy, message::StreamMessage};
+use danube_core::dispatch_strategy::ConfigDispatchStrategy;
 use danube_reliable_dispatch::TopicCache;
 use metrics::gauge;
 use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
 use tonic::{Code, Status};
 use tracing::{info, warn};
 
@@ -12,8 +12,11 @@ use danube_core::proto::{ErrorType, Schema as ProtoSchema, TopicDispatchStrategy
 
 use crate::{
     broker_metrics::{BROKER_TOPICS, TOPIC_CONSUMERS, TOPIC_PRODUCERS},
+    cursor_persister::{flush_cursors, CursorPersister, CursorRequest},
+    dead_letter::{DeadLetterPublisher, DeadLetterRequest},
+    dispatch_strategy::DispatchStrategy,
     error_message::create_error_status,
-    message::AckMessage,
+    message::{AckMessage, NackMessage},
     policies::Policies,
     resources::Resources,
     schema::SchemaType,
@@ -39,10 +42,22 @@ pub(crate) struct BrokerService {
     pub(crate) producer_index: HashMap<u64, String>,
     // maps consumer_id to (topic_name, subscription_name)
     pub(crate) consumer_index: HashMap<u64, (String, String)>,
+    // the messages sent to the dead letter topics by the subscriptions
+    dead_letter_tx: mpsc::Sender<DeadLetterRequest>,
+    // the cursors of the reliable subscriptions, written to the metadata store
+    cursor_tx: mpsc::UnboundedSender<CursorRequest>,
 }
 
+// How long the unloaded topic waits for the cursors of its subscriptions to be persisted
+const CURSOR_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
 impl BrokerService {
-    pub(crate) fn new(resources: Resources, storage_backend: TopicCache) -> Self {
+    pub(crate) fn new(
+        resources: Resources,
+        storage_backend: TopicCache,
+        dead_letter_tx: mpsc::Sender<DeadLetterRequest>,
+        cursor_tx: mpsc::UnboundedSender<CursorRequest>,
+    ) -> Self {
         let broker_id = get_random_id();
         BrokerService {
             broker_id,
@@ -51,6 +66,8 @@ impl BrokerService {
             storage_backend,
             producer_index: HashMap::new(),
             consumer_index: HashMap::new(),
+            dead_letter_tx,
+            cursor_tx,
         }
     }
 
@@ -330,12 +347,21 @@ impl BrokerService {
 
         let dispatch_strategy = dispatch_strategy.unwrap();
 
+        // get the persisted cursors of the subscriptions, if the topic was served before
+        let subscription_cursors = self
+            .resources
+            .topic
+            .get_subscription_cursors(topic_name)
+            .await;
+
         // create the topic,
         let mut new_topic = Topic::new(
             topic_name,
             dispatch_strategy.clone(),
             self.storage_backend.clone(),
-        );
+            subscription_cursors,
+        )
+        .await?;
 
         // get schema from local_cache
         let schema = self.resources.topic.get_schema(topic_name);
@@ -396,6 +422,23 @@ impl BrokerService {
             self.consumer_index.remove(&consumer_id);
         }
 
+        // the subscriptions resume from their last acknowledged message once the topic is served again
+        if let DispatchStrategy::Reliable(reliable_dispatch) = &topic.dispatch_strategy {
+            for (subscription_name, cursor) in reliable_dispatch.get_subscription_cursors() {
+                let _ = self.cursor_tx.send(CursorRequest::Persist {
+                    topic_name: topic_name.to_string(),
+                    subscription_name,
+                    cursor,
+                });
+            }
+            if let Err(err) = flush_cursors(&self.cursor_tx, CURSOR_FLUSH_TIMEOUT).await {
+                warn!(
+                    "Unable to persist the subscription cursors of the topic {}: {}",
+                    topic_name, err
+                );
+            }
+        }
+
         // removing the topic should delete all the resources associated with topic
         match self.topics.remove(topic_name) {
             Some(topic) => {
@@ -547,21 +590,6 @@ impl BrokerService {
     }
 
     // finding the receiver for the provided consumer_id
-    pub(crate) async fn find_consumer_rx(
-        &mut self,
-        consumer_id: u64,
-    ) -> Option<Arc<Mutex<mpsc::Receiver<StreamMessage>>>> {
-        if let Some((topic_name, subscription_name)) = self.consumer_index.get(&consumer_id) {
-            if let Some(topic) = self.topics.get(topic_name) {
-                if let Some(subscription) = topic.subscriptions.lock().await.get(subscription_name)
-                {
-                    return subscription.get_consumer_rx(consumer_id);
-                }
-            }
-        }
-        None
-    }
-
     // finding the ConsumerInfo for the provided consumer_id
     pub(crate) async fn find_consumer_by_id(&mut self, consumer_id: u64) -> Option<ConsumerInfo> {
         if let Some((topic_name, subscription_name)) = self.consumer_index.get(&consumer_id) {
@@ -626,8 +654,31 @@ impl BrokerService {
         // the caller of this function should ensure that the topic is served by this broker
 
         if let Some(topic) = self.topics.get_mut(topic_name) {
+            let dead_letter = subscription_options
+                .dead_letter_policy
+                .clone()
+                .map(|policy| {
+                    DeadLetterPublisher::new(
+                        policy,
+                        topic_name,
+                        &subscription_options.subscription_name,
+                        self.dead_letter_tx.clone(),
+                    )
+                });
+
+            let cursor_persister = CursorPersister::new(
+                topic_name,
+                &subscription_options.subscription_name,
+                self.cursor_tx.clone(),
+            );
+
             let consumer_id = topic
-                .subscribe(topic_name, subscription_options.clone())
+                .subscribe(
+                    topic_name,
+                    subscription_options.clone(),
+                    dead_letter,
+                    cursor_persister,
+                )
                 .await?;
 
             // insert into consumer_index for efficient searches and retrievals
@@ -666,6 +717,13 @@ impl BrokerService {
         Ok(())
     }
 
+    pub(crate) async fn nack_message(&mut self, nack_msg: NackMessage) -> Result<()> {
+        if let Some(topic) = self.topics.get_mut(&nack_msg.msg_id.topic_name) {
+            topic.nack_message(nack_msg).await?;
+        }
+        Ok(())
+    }
+
     // unsubscribe subscription from topic
     // only if subscription is empty, so no consumers attached
     pub(crate) async fn unsubscribe(
@@ -678,6 +736,11 @@ impl BrokerService {
             if let Some(value) = topic.check_subscription(subscription_name).await {
                 if value == false {
                     topic.unsubscribe(subscription_name).await;
+                    // the deleted subscription is not restored once the topic is served again
+                    let _ = self.cursor_tx.send(CursorRequest::Delete {
+                        topic_name: topic_name.to_string(),
+                        subscription_name: subscription_name.to_string(),
+                    });
                     return Ok(());
                 }
             }
@@ -1,11 +1,16 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use danube_core::message::StreamMessage;
-use metrics::counter;
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use metrics::{counter, gauge};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tokio::sync::{mpsc, Mutex, Notify};
 use tracing::{trace, warn};
 
-use crate::broker_metrics::{CONSUMER_BYTES_OUT_COUNTER, CONSUMER_MSG_OUT_COUNTER};
+use crate::broker_metrics::{
+    CONSUMERS_BLOCKED_ON_FLOW, CONSUMER_BYTES_OUT_COUNTER, CONSUMER_MSG_OUT_COUNTER,
+};
 
 /// Represents a consumer connected and associated with a Subscription.
 #[allow(dead_code)]
@@ -14,35 +19,59 @@ pub(crate) struct Consumer {
     pub(crate) consumer_id: u64,
     pub(crate) consumer_name: String,
     pub(crate) subscription_type: i32,
+    // 0 is the highest priority
+    pub(crate) priority_level: u32,
     pub(crate) topic_name: String,
     pub(crate) tx_cons: mpsc::Sender<StreamMessage>,
     // status = true -> consumer OK, status = false -> Close the consumer
     pub(crate) status: Arc<Mutex<bool>>,
+    // the messages the consumer is ready to receive
+    pub(crate) permits: Arc<FlowPermits>,
 }
 
 impl Consumer {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         consumer_id: u64,
         consumer_name: &str,
         subscription_type: i32,
+        priority_level: u32,
         topic_name: &str,
         tx_cons: mpsc::Sender<StreamMessage>,
         status: Arc<Mutex<bool>>,
+        permits: Arc<FlowPermits>,
     ) -> Self {
         Consumer {
             consumer_id: consumer_id.into(),
             consumer_name: consumer_name.into(),
             subscription_type,
+            priority_level,
             topic_name: topic_name.into(),
             tx_cons,
             status,
+            permits,
         }
     }
 
     // The consumer task runs asynchronously, handling message delivery to the gRPC `ReceiverStream`.
+    // The message takes a permit of the consumer, without waiting for the client to grant one,
+    // so the dispatcher keeps the message for another consumer or a later attempt if none is available.
     pub(crate) async fn send_message(&mut self, message: StreamMessage) -> Result<()> {
         // Since u8 is exactly 1 byte, the size in bytes will be equal to the number of elements in the vector.
         let payload_size = message.payload.len();
+        if !self.permits.try_acquire() {
+            if self.permits.is_stopped() {
+                warn!(
+                    "The consumer with id: {} stopped receiving the messages",
+                    self.consumer_id
+                );
+                *self.status.lock().await = false;
+            }
+            return Err(anyhow!(
+                "The consumer with id: {} has no permits to receive the message",
+                self.consumer_id
+            ));
+        }
         // Send the message to the other channel
         if let Err(err) = self.tx_cons.send(message).await {
             // Log the error and handle the channel closure scenario
@@ -65,4 +94,181 @@ impl Consumer {
     pub(crate) async fn get_status(&self) -> bool {
         *self.status.lock().await
     }
+
+    // the consumer is healthy and has a permit to receive a message
+    pub(crate) async fn is_ready(&self) -> bool {
+        self.permits.has_permit() && self.get_status().await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FlowState {
+    // the consumer didn't start to receive the messages
+    Waiting,
+    // the receive stream of the consumer ended
+    Stopped,
+    // the client doesn't use the flow control
+    Unlimited,
+    Permits(u32),
+}
+
+/// FlowPermits implements the flow control of a consumer.
+/// The client grants permits as the application drains the received messages,
+/// and each message sent to the consumer takes a permit.
+#[derive(Debug)]
+pub(crate) struct FlowPermits {
+    topic_name: String,
+    state: std::sync::Mutex<FlowState>,
+    // the latest receive stream of the consumer, as the client may reconnect
+    stream: AtomicU64,
+    // wakes the dispatcher of the subscription, to send the messages waiting for the permits
+    dispatch_notify: Option<Arc<Notify>>,
+}
+
+impl FlowPermits {
+    pub(crate) fn new(topic_name: &str, dispatch_notify: Option<Arc<Notify>>) -> Self {
+        FlowPermits {
+            topic_name: topic_name.into(),
+            state: std::sync::Mutex::new(FlowState::Waiting),
+            stream: AtomicU64::new(0),
+            dispatch_notify,
+        }
+    }
+
+    /// Starts the flow as the consumer starts to receive the messages,
+    /// with the initial permits of the client, or without flow control if 0.
+    /// Returns the id of the receive stream, to stop it.
+    pub(crate) fn start(&self, permits: u32) -> u64 {
+        let stream = self.stream.fetch_add(1, Ordering::SeqCst) + 1;
+        let state = if permits == 0 {
+            FlowState::Unlimited
+        } else {
+            FlowState::Permits(permits)
+        };
+        self.set_state(state);
+        stream
+    }
+
+    /// Adds the permits granted by the client
+    pub(crate) fn grant(&self, permits: u32) {
+        let state = match *self.state.lock().unwrap() {
+            FlowState::Permits(available) => FlowState::Permits(available.saturating_add(permits)),
+            state => state,
+        };
+        self.set_state(state);
+    }
+
+    /// Stops the flow as the receive stream of the consumer ends, unless a newer stream started
+    pub(crate) fn stop(&self, stream: u64) -> bool {
+        if self.stream.load(Ordering::SeqCst) != stream {
+            return false;
+        }
+        self.set_state(FlowState::Stopped);
+        true
+    }
+
+    /// Whether the stream is still the latest receive stream of the consumer
+    pub(crate) fn is_current(&self, stream: u64) -> bool {
+        self.stream.load(Ordering::SeqCst) == stream
+    }
+
+    // wakes the dispatcher, to send the messages to another consumer
+    pub(crate) fn wake_dispatcher(&self) {
+        if let Some(dispatch_notify) = &self.dispatch_notify {
+            dispatch_notify.notify_one();
+        }
+    }
+
+    pub(crate) fn has_permit(&self) -> bool {
+        match *self.state.lock().unwrap() {
+            FlowState::Waiting | FlowState::Stopped | FlowState::Permits(0) => false,
+            FlowState::Unlimited | FlowState::Permits(_) => true,
+        }
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        *self.state.lock().unwrap() == FlowState::Stopped
+    }
+
+    // takes a permit, false if none is available, the dispatcher is woken up as the client grants more
+    pub(crate) fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            FlowState::Unlimited => true,
+            FlowState::Permits(available) if available > 0 => {
+                *state = FlowState::Permits(available - 1);
+                if available == 1 {
+                    gauge!(CONSUMERS_BLOCKED_ON_FLOW.name, "topic" => self.topic_name.clone())
+                        .increment(1);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn set_state(&self, new_state: FlowState) {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), new_state);
+        if previous == FlowState::Permits(0) && new_state != FlowState::Permits(0) {
+            gauge!(CONSUMERS_BLOCKED_ON_FLOW.name, "topic" => self.topic_name.clone()).decrement(1);
+        }
+        if self.has_permit() {
+            self.wake_dispatcher();
+        }
+    }
+}
+
+impl Drop for FlowPermits {
+    fn drop(&mut self) {
+        if *self.state.lock().unwrap() == FlowState::Permits(0) {
+            gauge!(CONSUMERS_BLOCKED_ON_FLOW.name, "topic" => self.topic_name.clone()).decrement(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_flow_permits() {
+        let dispatch_notify = Arc::new(Notify::new());
+        let permits = Arc::new(FlowPermits::new(
+            "/default/topic",
+            Some(dispatch_notify.clone()),
+        ));
+        // no permit before the consumer starts to receive
+        assert!(!permits.has_permit());
+        assert!(!permits.try_acquire());
+
+        let stream = permits.start(2);
+        assert!(permits.try_acquire());
+        assert!(permits.try_acquire());
+        assert!(!permits.has_permit());
+
+        // the dispatcher is woken up as the client grants more permits
+        let woken = dispatch_notify.notified();
+        permits.grant(1);
+        tokio::time::timeout(std::time::Duration::from_secs(1), woken)
+            .await
+            .expect("the dispatcher should be woken up");
+        assert!(permits.try_acquire());
+        assert!(!permits.has_permit());
+
+        // no permit once the consumer stops receiving
+        assert!(permits.stop(stream));
+        assert!(permits.is_stopped());
+        permits.grant(1);
+        assert!(!permits.try_acquire());
+
+        // a new receive stream restarts the flow, 0 disables the flow control
+        let stream = permits.start(0);
+        assert!(!permits.is_current(stream - 1));
+        assert!(!permits.stop(stream - 1));
+        for _ in 0..10 {
+            assert!(permits.try_acquire());
+        }
+        permits.grant(5);
+        assert!(permits.has_permit());
+    }
 }
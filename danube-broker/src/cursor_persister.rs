@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use danube_reliable_dispatch::SubscriptionCursor;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::{timeout, Duration};
+use tracing::{trace, warn};
+
+use crate::resources::Resources;
+
+// How long the failed writes wait before they are retried
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+// A change of the subscription cursors, written to the metadata store in the order received
+#[derive(Debug)]
+pub(crate) enum CursorRequest {
+    // the subscription cursor moved, its latest position is written
+    Persist {
+        topic_name: String,
+        subscription_name: String,
+        cursor: Arc<Mutex<SubscriptionCursor>>,
+    },
+    // the subscription was deleted, its cursor is removed
+    Delete {
+        topic_name: String,
+        subscription_name: String,
+    },
+    // replies once the previous requests are written
+    Flush(oneshot::Sender<()>),
+}
+
+/// Persists the cursor of a reliable subscription as its messages are acknowledged,
+/// so the subscription resumes right after its last acknowledged message
+/// once the topic is moved or the broker restarts
+#[derive(Debug, Clone)]
+pub(crate) struct CursorPersister {
+    topic_name: String,
+    subscription_name: String,
+    tx: mpsc::UnboundedSender<CursorRequest>,
+}
+
+impl CursorPersister {
+    pub(crate) fn new(
+        topic_name: &str,
+        subscription_name: &str,
+        tx: mpsc::UnboundedSender<CursorRequest>,
+    ) -> Self {
+        CursorPersister {
+            topic_name: topic_name.to_string(),
+            subscription_name: subscription_name.to_string(),
+            tx,
+        }
+    }
+
+    // Requests the write of the cursor, without waiting for the metadata store,
+    // the position is read when written so the requests queued meanwhile are written once
+    pub(crate) fn persist(&self, cursor: Arc<Mutex<SubscriptionCursor>>) {
+        let _ = self.tx.send(CursorRequest::Persist {
+            topic_name: self.topic_name.clone(),
+            subscription_name: self.subscription_name.clone(),
+            cursor,
+        });
+    }
+}
+
+// Waits for the cursors requested before to be written
+pub(crate) async fn flush_cursors(
+    tx: &mpsc::UnboundedSender<CursorRequest>,
+    wait: Duration,
+) -> Result<()> {
+    let (reply_tx, reply_rx) = oneshot::channel();
+    tx.send(CursorRequest::Flush(reply_tx))
+        .map_err(|_| anyhow!("The cursor persistence task is not running"))?;
+
+    timeout(wait, reply_rx)
+        .await
+        .map_err(|_| anyhow!("Timed out persisting the subscription cursors"))?
+        .map_err(|_| anyhow!("The cursor flush request was dropped"))
+}
+
+// Writes the subscription cursors to the metadata store. The requests received meanwhile are batched,
+// only the latest position of each subscription is written. The failed writes are retried.
+pub(crate) fn start_cursor_persistence_task(
+    mut resources: Resources,
+    mut rx: mpsc::UnboundedReceiver<CursorRequest>,
+) {
+    tokio::spawn(async move {
+        // (topic, subscription) -> the cursor to write, None to delete it
+        let mut pending: HashMap<(String, String), Option<Arc<Mutex<SubscriptionCursor>>>> =
+            HashMap::new();
+        let mut flushes: Vec<oneshot::Sender<()>> = Vec::new();
+
+        loop {
+            let request = if pending.is_empty() {
+                match rx.recv().await {
+                    Some(request) => Some(request),
+                    None => break,
+                }
+            } else {
+                // retry the failed writes, unless new requests come first
+                timeout(RETRY_DELAY, rx.recv()).await.unwrap_or(None)
+            };
+
+            let mut requests: Vec<CursorRequest> = request.into_iter().collect();
+            while let Ok(request) = rx.try_recv() {
+                requests.push(request);
+            }
+            for request in requests {
+                match request {
+                    CursorRequest::Persist {
+                        topic_name,
+                        subscription_name,
+                        cursor,
+                    } => {
+                        pending.insert((topic_name, subscription_name), Some(cursor));
+                    }
+                    CursorRequest::Delete {
+                        topic_name,
+                        subscription_name,
+                    } => {
+                        pending.insert((topic_name, subscription_name), None);
+                    }
+                    CursorRequest::Flush(reply) => flushes.push(reply),
+                }
+            }
+
+            let mut failed = HashMap::new();
+            for ((topic_name, subscription_name), cursor) in pending.drain() {
+                let result = match &cursor {
+                    Some(cursor) => {
+                        let position = cursor.lock().await.clone();
+                        resources
+                            .topic
+                            .set_subscription_cursor(&topic_name, &subscription_name, &position)
+                            .await
+                    }
+                    None => {
+                        resources
+                            .topic
+                            .delete_subscription_cursor(&topic_name, &subscription_name)
+                            .await
+                    }
+                };
+
+                match result {
+                    Ok(()) => trace!(
+                        "Persisted the cursor of the subscription {} on topic {}",
+                        subscription_name,
+                        topic_name
+                    ),
+                    Err(err) => {
+                        warn!(
+                            "Unable to persist the cursor of the subscription {} on topic {} due to {}",
+                            subscription_name, topic_name, err
+                        );
+                        failed.insert((topic_name, subscription_name), cursor);
+                    }
+                }
+            }
+            pending = failed;
+
+            // the flushes wait for the failed writes too, up to their own timeout
+            if pending.is_empty() {
+                for reply in flushes.drain(..) {
+                    let _ = reply.send(());
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,350 @@
+use anyhow::{anyhow, Result};
+use danube_client::{DanubeClient, Producer, SchemaType};
+use danube_core::message::StreamMessage;
+use danube_core::proto::DeadLetterPolicy as ProtoDeadLetterPolicy;
+use danube_reliable_dispatch::SubscriptionDispatch;
+use serde::{Deserialize, Serialize};
+use std::collections::{hash_map::Entry, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{timeout, Duration};
+use tracing::{info, warn};
+
+use crate::auth::{AuthConfig, AuthMode};
+use crate::auth_jwt::{create_token, Claims};
+use crate::dispatcher::DispatcherCommand;
+
+// The attributes added to the dead lettered messages
+pub(crate) const DLQ_ORIGINAL_TOPIC: &str = "danube.dlq.original_topic";
+pub(crate) const DLQ_SUBSCRIPTION: &str = "danube.dlq.subscription";
+pub(crate) const DLQ_FAILURES: &str = "danube.dlq.failures";
+
+// How long the message waits for the dead letter topic to store it, before it is retried
+const DEAD_LETTER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The messages not acknowledged after `max_redeliveries` redeliveries
+/// are published to the dead letter topic, and the subscription moves past them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct DeadLetterPolicy {
+    pub(crate) max_redeliveries: u32,
+    pub(crate) dead_letter_topic: String,
+}
+
+impl From<ProtoDeadLetterPolicy> for DeadLetterPolicy {
+    fn from(policy: ProtoDeadLetterPolicy) -> Self {
+        DeadLetterPolicy {
+            max_redeliveries: policy.max_redeliveries,
+            dead_letter_topic: policy.dead_letter_topic,
+        }
+    }
+}
+
+// A message to be stored by the dead letter topic, the outcome is sent back on reply
+#[derive(Debug)]
+pub(crate) struct DeadLetterRequest {
+    pub(crate) dead_letter_topic: String,
+    pub(crate) message: StreamMessage,
+    pub(crate) reply: oneshot::Sender<Result<()>>,
+}
+
+/// Publishes the messages of a subscription that exceeded its redeliveries to the dead letter topic
+#[derive(Debug, Clone)]
+pub(crate) struct DeadLetterPublisher {
+    pub(crate) policy: DeadLetterPolicy,
+    topic_name: String,
+    subscription_name: String,
+    tx: mpsc::Sender<DeadLetterRequest>,
+}
+
+impl DeadLetterPublisher {
+    pub(crate) fn new(
+        policy: DeadLetterPolicy,
+        topic_name: &str,
+        subscription_name: &str,
+        tx: mpsc::Sender<DeadLetterRequest>,
+    ) -> Self {
+        DeadLetterPublisher {
+            policy,
+            topic_name: topic_name.to_string(),
+            subscription_name: subscription_name.to_string(),
+            tx,
+        }
+    }
+
+    // Hands the pending message of the subscription over to the dead letter topic, without waiting
+    // for the topic to store it, the dispatcher is told the outcome over its control channel.
+    // The subscription moves past the message only once it is stored.
+    pub(crate) async fn dead_letter_pending(
+        &self,
+        subscription_dispatch: &mut SubscriptionDispatch,
+        control_tx: mpsc::WeakSender<DispatcherCommand>,
+        notify_dispatch: Arc<Notify>,
+    ) -> Result<()> {
+        let (message, deliveries) = subscription_dispatch
+            .take_dead_letter()
+            .await?
+            .ok_or_else(|| anyhow!("No pending message to dead letter"))?;
+
+        let publisher = self.clone();
+        tokio::spawn(async move {
+            let msg_id = message.msg_id.clone();
+            let result = publisher.publish(message, deliveries).await;
+            match &result {
+                Ok(()) => info!(
+                    "Message {:?} of subscription {} moved to the dead letter topic {} after {} deliveries",
+                    msg_id, publisher.subscription_name, publisher.policy.dead_letter_topic, deliveries
+                ),
+                Err(e) => warn!(
+                    "Failed to dead letter the message {:?} of subscription {}: {}",
+                    msg_id, publisher.subscription_name, e
+                ),
+            }
+
+            // the dispatcher is gone if the subscription was closed meanwhile
+            if let Some(control_tx) = control_tx.upgrade() {
+                if control_tx
+                    .send(DispatcherCommand::DeadLettered(msg_id, result.is_ok()))
+                    .await
+                    .is_ok()
+                {
+                    notify_dispatch.notify_one();
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn publish(&self, mut message: StreamMessage, deliveries: u32) -> Result<()> {
+        message
+            .attributes
+            .insert(DLQ_ORIGINAL_TOPIC.to_string(), self.topic_name.clone());
+        message
+            .attributes
+            .insert(DLQ_SUBSCRIPTION.to_string(), self.subscription_name.clone());
+        message
+            .attributes
+            .insert(DLQ_FAILURES.to_string(), deliveries.to_string());
+
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.tx
+            .send(DeadLetterRequest {
+                dead_letter_topic: self.policy.dead_letter_topic.clone(),
+                message,
+                reply: reply_tx,
+            })
+            .await
+            .map_err(|_| anyhow!("The dead letter task is not running"))?;
+
+        timeout(DEAD_LETTER_TIMEOUT, reply_rx)
+            .await
+            .map_err(|_| anyhow!("Timed out storing the message to the dead letter topic"))?
+            .map_err(|_| anyhow!("The dead letter request was dropped"))?
+    }
+}
+
+// How the dead letter task connects to the cluster, through this broker with its own auth settings
+#[derive(Debug, Clone)]
+pub(crate) struct DeadLetterConnection {
+    service_url: String,
+    auth: AuthConfig,
+}
+
+impl DeadLetterConnection {
+    pub(crate) fn new(
+        advertised_addr: Option<&str>,
+        broker_addr: SocketAddr,
+        auth: AuthConfig,
+    ) -> Self {
+        let scheme = match auth.mode {
+            AuthMode::None => "http",
+            AuthMode::Tls | AuthMode::TlsWithJwt => "https",
+        };
+        let addr = match advertised_addr {
+            Some(advertised_addr) => advertised_addr.to_string(),
+            // the broker listening on all the interfaces is reached on the loopback
+            None if broker_addr.ip().is_unspecified() => {
+                SocketAddr::new(Ipv4Addr::LOCALHOST.into(), broker_addr.port()).to_string()
+            }
+            None => broker_addr.to_string(),
+        };
+
+        DeadLetterConnection {
+            service_url: format!("{}://{}", scheme, addr),
+            auth,
+        }
+    }
+
+    // Creates the client, with the time its api key expires for the JWT auth.
+    // The api key is a token signed with the JWT secret of the cluster, accepted by all its brokers
+    async fn connect(&self) -> Result<(DanubeClient, Option<u64>)> {
+        let mut builder = DanubeClient::builder().service_url(self.service_url.clone());
+
+        if let AuthMode::Tls | AuthMode::TlsWithJwt = self.auth.mode {
+            let tls_config = self
+                .auth
+                .tls
+                .as_ref()
+                .ok_or_else(|| anyhow!("TLS config required"))?;
+            builder = builder.with_tls(&tls_config.ca_file)?;
+        }
+
+        let mut expires_at = None;
+        if let AuthMode::TlsWithJwt = self.auth.mode {
+            let jwt_config = self
+                .auth
+                .jwt
+                .as_ref()
+                .ok_or_else(|| anyhow!("JWT config required"))?;
+            let claims = Claims {
+                iss: jwt_config.issuer.clone(),
+                exp: now_secs() + jwt_config.expiration_time,
+            };
+            builder = builder.with_api_key(create_token(&claims, &jwt_config.secret_key)?);
+            expires_at = Some(claims.exp);
+        }
+
+        Ok((builder.build().await?, expires_at))
+    }
+}
+
+// Publishes the dead lettered messages to their topics, which may be served by any broker of the cluster.
+// The messages are published with the client of the broker, looking up the broker serving the topic,
+// a producer is created per dead letter topic. The client connects once the first message is dead lettered,
+// and again once its api key expires.
+pub(crate) fn start_dead_letter_task(
+    connection: DeadLetterConnection,
+    broker_id: u64,
+    mut rx: mpsc::Receiver<DeadLetterRequest>,
+) {
+    tokio::spawn(async move {
+        let mut client: Option<(DanubeClient, Option<u64>)> = None;
+        let mut producers: HashMap<String, Producer> = HashMap::new();
+
+        while let Some(request) = rx.recv().await {
+            if let Some((_, Some(expires_at))) = &client {
+                if *expires_at <= now_secs() {
+                    client = None;
+                    producers.clear();
+                }
+            }
+            if client.is_none() {
+                match connection.connect().await {
+                    Ok(connected) => client = Some(connected),
+                    Err(e) => {
+                        warn!(
+                            "Unable to connect to {} to store the dead lettered messages: {}",
+                            connection.service_url, e
+                        );
+                        let _ = request.reply.send(Err(e));
+                        continue;
+                    }
+                }
+            }
+            let Some((client, _)) = &client else {
+                continue;
+            };
+
+            let result = publish_dead_letter(
+                client,
+                &mut producers,
+                broker_id,
+                &request.dead_letter_topic,
+                request.message,
+            )
+            .await;
+
+            if let Err(e) = &result {
+                warn!(
+                    "Unable to store the message to the dead letter topic {}: {}",
+                    request.dead_letter_topic, e
+                );
+                // the producer is created again, the topic may have moved to another broker
+                producers.remove(&request.dead_letter_topic);
+            }
+            let _ = request.reply.send(result);
+        }
+    });
+}
+
+async fn publish_dead_letter(
+    client: &DanubeClient,
+    producers: &mut HashMap<String, Producer>,
+    broker_id: u64,
+    dead_letter_topic: &str,
+    message: StreamMessage,
+) -> Result<()> {
+    let producer = match producers.entry(dead_letter_topic.to_string()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => {
+            let mut producer = client
+                .new_producer()
+                .with_topic(dead_letter_topic)
+                .with_name(format!("dead-letter-producer-{}", broker_id))
+                .with_schema("dead_letter".into(), SchemaType::Bytes)
+                .build();
+            producer.create().await?;
+            entry.insert(producer)
+        }
+    };
+
+    // the message was already due, it is delivered right away from the dead letter topic,
+    // and kept for inspection, whatever its expiration
+    match message.key {
+        Some(key) => {
+            producer
+                .send_with_key(key, message.payload, Some(message.attributes))
+                .await?
+        }
+        None => {
+            producer
+                .send(message.payload, Some(message.attributes))
+                .await?
+        }
+    };
+    Ok(())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth_config(mode: AuthMode) -> AuthConfig {
+        AuthConfig {
+            mode,
+            tls: None,
+            jwt: None,
+        }
+    }
+
+    #[test]
+    fn test_dead_letter_connection_url() {
+        let broker_addr: SocketAddr = "0.0.0.0:6650".parse().unwrap();
+
+        // the advertised address is used with the scheme of the auth mode
+        let connection = DeadLetterConnection::new(
+            Some("broker1.danube:6650"),
+            broker_addr,
+            auth_config(AuthMode::Tls),
+        );
+        assert_eq!(connection.service_url, "https://broker1.danube:6650");
+
+        // the broker listening on all the interfaces is reached on the loopback
+        let connection = DeadLetterConnection::new(None, broker_addr, auth_config(AuthMode::None));
+        assert_eq!(connection.service_url, "http://127.0.0.1:6650");
+
+        let broker_addr: SocketAddr = "10.0.0.5:6650".parse().unwrap();
+        let connection =
+            DeadLetterConnection::new(None, broker_addr, auth_config(AuthMode::TlsWithJwt));
+        assert_eq!(connection.service_url, "https://10.0.0.5:6650");
+    }
+}
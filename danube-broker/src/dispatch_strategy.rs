@@ -5,5 +5,5 @@ pub(crate) enum DispatchStrategy {
     // Does not store messages, sends them directly to the dispatcher
     NonReliable,
     // Stores messages for reliable delivery
-    Reliable(ReliableDispatch),
+    Reliable(Box<ReliableDispatch>),
 }
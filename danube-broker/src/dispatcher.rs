@@ -1,8 +1,24 @@
 use anyhow::Result;
 use danube_core::message::{MessageID, StreamMessage};
+use metrics::counter;
 
-use crate::{consumer::Consumer, message::AckMessage};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::Notify;
+use tracing::{info, trace};
 
+use crate::{
+    broker_metrics::TOPIC_MESSAGES_EXPIRED_COUNTER,
+    consumer::Consumer,
+    message::{AckMessage, NackMessage},
+};
+
+pub(crate) mod consistent_hash;
 pub(crate) mod dispatcher_multiple_consumers;
 pub(crate) mod dispatcher_reliable_multiple_consumers;
 pub(crate) mod dispatcher_reliable_single_consumer;
@@ -23,12 +39,15 @@ pub(crate) enum Dispatcher {
 }
 
 // Control messages for the dispatcher
-enum DispatcherCommand {
+pub(crate) enum DispatcherCommand {
     AddConsumer(Consumer),
     RemoveConsumer(u64),
     DisconnectAllConsumers,
     DispatchMessage(StreamMessage),
     MessageAcked(u64, MessageID),
+    MessageNacked(u64, MessageID, Option<Duration>),
+    // the outcome of the message handed over to the dead letter topic, true if stored
+    DeadLettered(MessageID, bool),
 }
 
 impl Dispatcher {
@@ -64,6 +83,20 @@ impl Dispatcher {
             }
         }
     }
+    pub(crate) async fn nack_message(&self, nack_msg: NackMessage) -> Result<()> {
+        match self {
+            Dispatcher::OneConsumer(dispatcher) => Ok(dispatcher.nack_message(nack_msg).await?),
+            Dispatcher::MultipleConsumers(dispatcher) => {
+                Ok(dispatcher.nack_message(nack_msg).await?)
+            }
+            Dispatcher::ReliableOneConsumer(dispatcher) => {
+                Ok(dispatcher.nack_message(nack_msg).await?)
+            }
+            Dispatcher::ReliableMultipleConsumers(dispatcher) => {
+                Ok(dispatcher.nack_message(nack_msg).await?)
+            }
+        }
+    }
     pub(crate) async fn add_consumer(&mut self, consumer: Consumer) -> Result<()> {
         match self {
             Dispatcher::OneConsumer(dispatcher) => Ok(dispatcher.add_consumer(consumer).await?),
@@ -96,6 +129,15 @@ impl Dispatcher {
         }
     }
 
+    // the notifier of the dispatchers holding messages, to send the messages waiting for the consumers
+    pub(crate) fn get_notifier(&self) -> Option<Arc<Notify>> {
+        match self {
+            Dispatcher::OneConsumer(_) => None,
+            Dispatcher::MultipleConsumers(dispatcher) => Some(dispatcher.get_notifier()),
+            Dispatcher::ReliableOneConsumer(dispatcher) => Some(dispatcher.get_notifier()),
+            Dispatcher::ReliableMultipleConsumers(dispatcher) => Some(dispatcher.get_notifier()),
+        }
+    }
     pub(crate) async fn disconnect_all_consumers(&mut self) -> Result<()> {
         match self {
             Dispatcher::OneConsumer(dispatcher) => {
@@ -113,3 +155,192 @@ impl Dispatcher {
         }
     }
 }
+
+/// Elects the active consumer of the Exclusive and Failover subscriptions, the healthy consumer
+/// first by priority level then by consumer name, so the standby consumers take over
+/// in a deterministic order.
+/// Returns true if another consumer became active.
+pub(crate) async fn elect_active_consumer(
+    consumers: &[Consumer],
+    active_consumer: &mut Option<Consumer>,
+) -> bool {
+    let mut elected: Option<&Consumer> = None;
+    for consumer in consumers {
+        if !consumer.get_status().await {
+            continue;
+        }
+        let precedes = elected.is_none_or(|elected| {
+            (
+                consumer.priority_level,
+                &consumer.consumer_name,
+                consumer.consumer_id,
+            ) < (
+                elected.priority_level,
+                &elected.consumer_name,
+                elected.consumer_id,
+            )
+        });
+        if precedes {
+            elected = Some(consumer);
+        }
+    }
+
+    let previous = active_consumer.as_ref().map(|c| c.consumer_id);
+    *active_consumer = elected.cloned();
+
+    match elected {
+        Some(elected) if previous != Some(elected.consumer_id) => {
+            info!(
+                "Consumer {} is the active consumer of the subscription",
+                elected.consumer_name
+            );
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Selects the next consumer round-robin among the healthy consumers of the highest priority,
+/// with a permit if `with_permit`, so the consumers of the lower priority levels
+/// receive the messages only while the higher ones are saturated.
+pub(crate) async fn next_consumer_by_priority(
+    consumers: &[Consumer],
+    index_consumer: &AtomicUsize,
+    with_permit: bool,
+) -> Option<usize> {
+    let mut available = Vec::with_capacity(consumers.len());
+    for consumer in consumers {
+        let is_available = if with_permit {
+            consumer.is_ready().await
+        } else {
+            consumer.get_status().await
+        };
+        available.push(is_available);
+    }
+
+    let priority_level = consumers
+        .iter()
+        .zip(&available)
+        .filter(|(_, is_available)| **is_available)
+        .map(|(consumer, _)| consumer.priority_level)
+        .min()?;
+
+    let num_consumers = consumers.len();
+    let start_index = index_consumer.load(Ordering::SeqCst) % num_consumers;
+    for i in 0..num_consumers {
+        let index = (start_index + i) % num_consumers;
+        if available[index] && consumers[index].priority_level == priority_level {
+            index_consumer.store(index + 1, Ordering::SeqCst);
+            return Some(index);
+        }
+    }
+    None
+}
+
+/// Drops the expired message of the non-reliable subscriptions, instead of delivering it.
+/// Returns true if the message expired.
+pub(crate) fn skip_expired_message(message: &StreamMessage) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64;
+    if !message.is_expired(now) {
+        return false;
+    }
+
+    counter!(TOPIC_MESSAGES_EXPIRED_COUNTER.name, "topic" => message.msg_id.topic_name.clone())
+        .increment(1);
+    trace!("Expired message with id {:?} not delivered", message.msg_id);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::FlowPermits;
+    use tokio::sync::{mpsc, Mutex};
+
+    fn test_consumer(consumer_id: u64, consumer_name: &str, priority_level: u32) -> Consumer {
+        let (tx_cons, _rx_cons) = mpsc::channel(1);
+        Consumer::new(
+            consumer_id,
+            consumer_name,
+            2,
+            priority_level,
+            "/default/topic",
+            tx_cons,
+            Arc::new(Mutex::new(true)),
+            Arc::new(FlowPermits::new("/default/topic", None)),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_failover_election() {
+        let consumers = vec![
+            test_consumer(1, "consumer-c", 0),
+            test_consumer(2, "consumer-a", 0),
+            test_consumer(3, "consumer-b", 0),
+        ];
+        let mut active_consumer = None;
+
+        assert!(elect_active_consumer(&consumers, &mut active_consumer).await);
+        assert_eq!(active_consumer.as_ref().unwrap().consumer_id, 2);
+        assert!(!elect_active_consumer(&consumers, &mut active_consumer).await);
+
+        // the next consumer by name takes over from the failed active consumer
+        *consumers[1].status.lock().await = false;
+        assert!(elect_active_consumer(&consumers, &mut active_consumer).await);
+        assert_eq!(active_consumer.as_ref().unwrap().consumer_id, 3);
+
+        // the recovered consumer is active again
+        *consumers[1].status.lock().await = true;
+        assert!(elect_active_consumer(&consumers, &mut active_consumer).await);
+        assert_eq!(active_consumer.as_ref().unwrap().consumer_id, 2);
+
+        for consumer in &consumers {
+            *consumer.status.lock().await = false;
+        }
+        assert!(!elect_active_consumer(&consumers, &mut active_consumer).await);
+        assert!(active_consumer.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_priority_levels() {
+        let consumers = vec![
+            test_consumer(1, "overflow", 1),
+            test_consumer(2, "consumer-b", 0),
+            test_consumer(3, "consumer-a", 0),
+        ];
+        for consumer in &consumers {
+            consumer.permits.start(1);
+        }
+        let index_consumer = AtomicUsize::new(0);
+
+        // round-robin among the consumers of the highest priority
+        let mut selected = Vec::new();
+        for _ in 0..4 {
+            let index = next_consumer_by_priority(&consumers, &index_consumer, true)
+                .await
+                .unwrap();
+            selected.push(consumers[index].consumer_id);
+        }
+        assert_eq!(selected, vec![2, 3, 2, 3]);
+
+        // the lower priority receives the messages while the higher ones have no permits
+        assert!(consumers[1].permits.try_acquire());
+        assert!(consumers[2].permits.try_acquire());
+        let index = next_consumer_by_priority(&consumers, &index_consumer, true)
+            .await
+            .unwrap();
+        assert_eq!(consumers[index].consumer_id, 1);
+        let index = next_consumer_by_priority(&consumers, &index_consumer, false)
+            .await
+            .unwrap();
+        assert_eq!(consumers[index].priority_level, 0);
+
+        // the failover subscriptions elect the highest priority first
+        let mut active_consumer = None;
+        assert!(elect_active_consumer(&consumers, &mut active_consumer).await);
+        assert_eq!(active_consumer.unwrap().consumer_id, 3);
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::BTreeMap;
+
+use crate::consumer::Consumer;
+
+// The points of each consumer on the ring, so the keys spread evenly among the consumers
+const VIRTUAL_NODES: u64 = 128;
+
+/// ConsistentHashRing assigns the message keys of the Key_Shared subscriptions to the consumers.
+/// As a consumer joins or leaves, only the keys of its ring ranges move to another consumer,
+/// the other keys keep their consumer, and so the ordering of their messages.
+#[derive(Debug, Default)]
+pub(crate) struct ConsistentHashRing {
+    // ring point -> consumer_id
+    ring: BTreeMap<u64, u64>,
+}
+
+impl ConsistentHashRing {
+    pub(crate) fn add_consumer(&mut self, consumer_id: u64) {
+        for replica in 0..VIRTUAL_NODES {
+            let point = hash_key(&format!("{}-{}", consumer_id, replica));
+            self.ring.insert(point, consumer_id);
+        }
+    }
+
+    pub(crate) fn remove_consumer(&mut self, consumer_id: u64) {
+        self.ring.retain(|_, id| *id != consumer_id);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ring.clear();
+    }
+
+    /// The consumers of the key in order of preference, the owner of the key first,
+    /// then the following consumers on the ring, to take over while the owner is unavailable
+    pub(crate) fn consumers_for(&self, key: &str) -> Vec<u64> {
+        let point = hash_key(key);
+        let mut consumers: Vec<u64> = Vec::new();
+        for (_, consumer_id) in self.ring.range(point..).chain(self.ring.range(..point)) {
+            if !consumers.contains(consumer_id) {
+                consumers.push(*consumer_id);
+            }
+        }
+        consumers
+    }
+
+    /// The index of the healthy consumer of the key, if it has a permit to receive the message.
+    /// None while the consumer of the key has no permits, the message waits for it to keep the order of the key
+    pub(crate) async fn select_consumer(&self, consumers: &[Consumer], key: &str) -> Option<usize> {
+        for consumer_id in self.consumers_for(key) {
+            if let Some(index) = consumers.iter().position(|c| c.consumer_id == consumer_id) {
+                if consumers[index].get_status().await {
+                    return consumers[index].permits.has_permit().then_some(index);
+                }
+            }
+        }
+        None
+    }
+}
+
+// FNV-1a, as the producers route the keys to the partitions, followed by
+// the murmur3 finalizer to spread the close ring points of the consumer replicas
+fn hash_key(key: &str) -> u64 {
+    let mut hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owners(ring: &ConsistentHashRing, keys: &[String]) -> Vec<u64> {
+        keys.iter().map(|key| ring.consumers_for(key)[0]).collect()
+    }
+
+    #[test]
+    fn test_keys_keep_their_consumer() {
+        let mut ring = ConsistentHashRing::default();
+        assert!(ring.consumers_for("key").is_empty());
+
+        for consumer_id in 1..=3 {
+            ring.add_consumer(consumer_id);
+        }
+        assert_eq!(ring.consumers_for("key").len(), 3);
+        assert_eq!(ring.consumers_for("key"), ring.consumers_for("key"));
+
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let before = owners(&ring, &keys);
+        for consumer_id in 1..=3 {
+            let share = before.iter().filter(|id| **id == consumer_id).count();
+            assert!(share > 200, "consumer {} owns {} keys", consumer_id, share);
+        }
+
+        // only the keys of the joining consumer move
+        ring.add_consumer(4);
+        let after = owners(&ring, &keys);
+        for (before, after) in before.iter().zip(&after) {
+            assert!(before == after || *after == 4);
+        }
+
+        // only the keys of the leaving consumer move
+        ring.remove_consumer(2);
+        let after_leave = owners(&ring, &keys);
+        for (after, after_leave) in after.iter().zip(&after_leave) {
+            assert!(after == after_leave || *after == 2);
+        }
+        assert!(!after_leave.contains(&2));
+    }
+}
@@ -1,37 +1,82 @@
 use anyhow::{anyhow, Result};
 use danube_core::message::StreamMessage;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use tokio::sync::mpsc;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{atomic::AtomicUsize, Arc};
+use tokio::sync::{mpsc, Notify};
 use tracing::{trace, warn};
 
-use crate::{consumer::Consumer, dispatcher::DispatcherCommand, message::AckMessage};
+use crate::{
+    consumer::Consumer,
+    dispatcher::{
+        consistent_hash::ConsistentHashRing, next_consumer_by_priority, skip_expired_message,
+        DispatcherCommand,
+    },
+    message::{AckMessage, NackMessage},
+};
+
+// The keyed messages held while the consumers of their keys have no permits,
+// the following messages are dropped once reached
+const MAX_HELD_MESSAGES: usize = 1000;
 
 #[derive(Debug)]
 pub(crate) struct DispatcherMultipleConsumers {
     control_tx: mpsc::Sender<DispatcherCommand>,
+    // woken up as the consumers are granted permits, to send the held messages
+    notify_dispatch: Arc<Notify>,
 }
 
 impl DispatcherMultipleConsumers {
+    /// Shared subscriptions, the messages are distributed round-robin among the consumers
     pub(crate) fn new() -> Self {
+        Self::start(None)
+    }
+
+    /// Key_Shared subscriptions, the messages with the same key go to the same consumer
+    pub(crate) fn new_key_shared() -> Self {
+        Self::start(Some(ConsistentHashRing::default()))
+    }
+
+    fn start(mut key_ring: Option<ConsistentHashRing>) -> Self {
         let (control_tx, mut control_rx) = mpsc::channel(16);
+        let notify_dispatch = Arc::new(Notify::new());
+        let notify_dispatch_clone = notify_dispatch.clone();
 
         // Spawn the dispatcher task
         tokio::spawn(async move {
             let mut consumers: Vec<Consumer> = Vec::new();
             let mut index_consumer = AtomicUsize::new(0);
+            // the keyed messages waiting for the permits of the consumers of their keys, in order
+            let mut held_messages: VecDeque<StreamMessage> = VecDeque::new();
 
             loop {
-                if let Some(command) = control_rx.recv().await {
+                let command = tokio::select! {
+                    command = control_rx.recv() => match command {
+                        Some(command) => Some(command),
+                        None => break,
+                    },
+                    _ = notify_dispatch_clone.notified() => None,
+                };
+
+                if let Some(command) = command {
                     match command {
                         DispatcherCommand::AddConsumer(consumer) => {
+                            if let Some(key_ring) = key_ring.as_mut() {
+                                key_ring.add_consumer(consumer.consumer_id);
+                            }
                             consumers.push(consumer);
                             trace!("Consumer added. Total consumers: {}", consumers.len());
                         }
                         DispatcherCommand::RemoveConsumer(consumer_id) => {
+                            if let Some(key_ring) = key_ring.as_mut() {
+                                key_ring.remove_consumer(consumer_id);
+                            }
                             consumers.retain(|c| c.consumer_id != consumer_id);
                             trace!("Consumer removed. Total consumers: {}", consumers.len());
                         }
                         DispatcherCommand::DisconnectAllConsumers => {
+                            if let Some(key_ring) = key_ring.as_mut() {
+                                key_ring.clear();
+                            }
                             consumers.clear();
                             trace!("All consumers disconnected.");
                         }
@@ -39,6 +84,8 @@ impl DispatcherMultipleConsumers {
                             if let Err(error) = Self::handle_dispatch_message(
                                 &mut consumers,
                                 &mut index_consumer,
+                                key_ring.as_ref(),
+                                &mut held_messages,
                                 message,
                             )
                             .await
@@ -51,12 +98,35 @@ impl DispatcherMultipleConsumers {
                                 "Non-reliable dispatcher does not care about acked messages"
                             );
                         }
+                        DispatcherCommand::MessageNacked(_, _, _) => {
+                            unreachable!(
+                                "Non-reliable dispatcher does not care about nacked messages"
+                            );
+                        }
+                        DispatcherCommand::DeadLettered(_, _) => {
+                            unreachable!(
+                                "Non-reliable dispatcher does not dead letter the messages"
+                            );
+                        }
                     }
                 }
+
+                if let Some(key_ring) = key_ring.as_ref() {
+                    Self::dispatch_held_messages(&mut consumers, key_ring, &mut held_messages)
+                        .await;
+                }
             }
         });
 
-        DispatcherMultipleConsumers { control_tx }
+        DispatcherMultipleConsumers {
+            control_tx,
+            notify_dispatch,
+        }
+    }
+
+    // the notifier woken up by the consumer permits
+    pub(crate) fn get_notifier(&self) -> Arc<Notify> {
+        self.notify_dispatch.clone()
     }
 
     /// Dispatch a message to the active consumer
@@ -73,6 +143,12 @@ impl DispatcherMultipleConsumers {
         Ok(())
     }
 
+    /// Negatively acknowledge a message, to be redelivered to the consumer
+    /// Non-reliable dispatchers do not redeliver messages
+    pub(crate) async fn nack_message(&self, _nack_msg: NackMessage) -> Result<()> {
+        Ok(())
+    }
+
     /// Add a new consumer to the dispatcher
     pub(crate) async fn add_consumer(&self, consumer: Consumer) -> Result<()> {
         self.control_tx
@@ -102,6 +178,8 @@ impl DispatcherMultipleConsumers {
     async fn handle_dispatch_message(
         consumers: &mut [Consumer],
         index_consumer: &mut AtomicUsize,
+        key_ring: Option<&ConsistentHashRing>,
+        held_messages: &mut VecDeque<StreamMessage>,
         message: StreamMessage,
     ) -> Result<()> {
         let num_consumers = consumers.len();
@@ -109,22 +187,164 @@ impl DispatcherMultipleConsumers {
             return Err(anyhow!("No consumers available to dispatch the message"));
         }
 
-        for _ in 0..num_consumers {
-            let index = index_consumer.fetch_add(1, Ordering::SeqCst) % num_consumers;
+        if skip_expired_message(&message) {
+            return Ok(());
+        }
+
+        // the keyed messages of the Key_Shared subscriptions go to the consumer of the key,
+        // they are held while the consumer has no permits, or behind the held messages of the key
+        if let (Some(key_ring), Some(key)) = (key_ring, message.key.as_deref()) {
+            let key_held = held_messages
+                .iter()
+                .any(|held| held.key.as_deref() == Some(key));
+            let index = match key_held {
+                true => None,
+                false => key_ring.select_consumer(consumers, key).await,
+            };
+            let Some(index) = index else {
+                if held_messages.len() >= MAX_HELD_MESSAGES {
+                    return Err(anyhow!(
+                        "The message is dropped, {} messages are held for the consumers without permits",
+                        held_messages.len()
+                    ));
+                }
+                held_messages.push_back(message);
+                return Ok(());
+            };
             let consumer = &mut consumers[index];
+            consumer.send_message(message).await?;
+            trace!(
+                "Dispatcher sent the keyed message to consumer: {}",
+                consumer.consumer_id
+            );
+            return Ok(());
+        }
+
+        // the next consumer of the highest priority with a permit,
+        // the message is dropped if none of the consumers is ready to receive it
+        let index = next_consumer_by_priority(consumers, index_consumer, true)
+            .await
+            .ok_or_else(|| anyhow!("No consumers with permits available to handle the message"))?;
+        let consumer = &mut consumers[index];
+        consumer.send_message(message).await?;
+        trace!(
+            "Dispatcher sent the message to consumer: {}",
+            consumer.consumer_id
+        );
+        Ok(())
+    }
 
-            if consumer.get_status().await {
-                consumer.send_message(message).await?;
+    // Sends the held messages to the consumers of their keys granted permits meanwhile,
+    // the messages of a key are sent in order, the expired messages are dropped
+    async fn dispatch_held_messages(
+        consumers: &mut [Consumer],
+        key_ring: &ConsistentHashRing,
+        held_messages: &mut VecDeque<StreamMessage>,
+    ) {
+        if held_messages.is_empty() {
+            return;
+        }
+
+        let mut blocked_keys: HashSet<String> = HashSet::new();
+        let mut still_held = VecDeque::with_capacity(held_messages.len());
+        for message in held_messages.drain(..) {
+            if skip_expired_message(&message) {
+                continue;
+            }
+            let key = message.key.clone().unwrap_or_default();
+            if blocked_keys.contains(&key) {
+                still_held.push_back(message);
+                continue;
+            }
+            let Some(index) = key_ring.select_consumer(consumers, &key).await else {
+                blocked_keys.insert(key);
+                still_held.push_back(message);
+                continue;
+            };
+            let consumer = &mut consumers[index];
+            if let Err(error) = consumer.send_message(message).await {
+                warn!("Failed to dispatch the held message: {}", error);
+            } else {
                 trace!(
-                    "Dispatcher sent the message to consumer: {}",
+                    "Dispatcher sent the held message to consumer: {}",
                     consumer.consumer_id
                 );
-                return Ok(());
             }
         }
+        *held_messages = still_held;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consumer::FlowPermits;
+    use danube_core::message::MessageID;
+    use std::collections::HashMap;
+    use tokio::sync::Mutex;
+    use tokio::time::{timeout, Duration};
+
+    fn keyed_message(request_id: u64, key: &str) -> StreamMessage {
+        StreamMessage {
+            request_id,
+            msg_id: MessageID {
+                producer_id: 1,
+                topic_name: "/default/topic".to_string(),
+                broker_addr: "localhost:6650".to_string(),
+                segment_id: 0,
+                segment_offset: request_id,
+            },
+            payload: vec![1],
+            publish_time: 0,
+            producer_name: "producer".to_string(),
+            subscription_name: None,
+            attributes: HashMap::new(),
+            key: Some(key.to_string()),
+            deliver_at: None,
+            deliver_after: None,
+            ttl: None,
+            expire_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_key_shared_holds_messages_without_permits() {
+        let dispatcher = DispatcherMultipleConsumers::new_key_shared();
+        let (tx_cons, mut rx_cons) = mpsc::channel(4);
+        let permits = Arc::new(FlowPermits::new(
+            "/default/topic",
+            Some(dispatcher.get_notifier()),
+        ));
+        permits.start(1);
+        let consumer = Consumer::new(
+            1,
+            "consumer",
+            3,
+            0,
+            "/default/topic",
+            tx_cons,
+            Arc::new(Mutex::new(true)),
+            permits.clone(),
+        );
+        dispatcher.add_consumer(consumer).await.unwrap();
+
+        for request_id in 0..3 {
+            dispatcher
+                .dispatch_message(keyed_message(request_id, "key"))
+                .await
+                .unwrap();
+        }
+        let received = rx_cons.recv().await.unwrap();
+        assert_eq!(received.request_id, 0);
 
-        Err(anyhow!(
-            "No active consumers available to handle the message"
-        ))
+        // the messages of the key are held until the consumer is granted permits, in order
+        assert!(timeout(Duration::from_millis(50), rx_cons.recv())
+            .await
+            .is_err());
+        permits.grant(2);
+        for request_id in 1..3 {
+            let received = rx_cons.recv().await.unwrap();
+            assert_eq!(received.request_id, request_id);
+        }
     }
 }
@@ -1,11 +1,21 @@
 use anyhow::{anyhow, Result};
+use danube_core::message::StreamMessage;
 use danube_reliable_dispatch::{ReliableDispatchError, SubscriptionDispatch};
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
 use tokio::sync::{mpsc, Notify};
+use tokio::time::timeout_at;
 use tracing::{trace, warn};
 
-use crate::{consumer::Consumer, dispatcher::DispatcherCommand, message::AckMessage};
+use crate::{
+    consumer::Consumer,
+    cursor_persister::CursorPersister,
+    dead_letter::DeadLetterPublisher,
+    dispatcher::{
+        consistent_hash::ConsistentHashRing, next_consumer_by_priority, DispatcherCommand,
+    },
+    message::{AckMessage, NackMessage},
+};
 
 /// Reliable dispatcher for multiple consumers, it sends ordered messages to multiple consumers
 #[derive(Debug)]
@@ -15,32 +25,79 @@ pub(crate) struct DispatcherReliableMultipleConsumers {
 }
 
 impl DispatcherReliableMultipleConsumers {
-    pub(crate) fn new(mut subscription_dispatch: SubscriptionDispatch) -> Self {
+    /// Shared subscriptions, the messages are distributed round-robin among the consumers
+    pub(crate) fn new(
+        subscription_dispatch: SubscriptionDispatch,
+        dead_letter: Option<DeadLetterPublisher>,
+        cursor_persister: Option<CursorPersister>,
+    ) -> Self {
+        Self::start(subscription_dispatch, dead_letter, cursor_persister, None)
+    }
+
+    /// Key_Shared subscriptions, the messages with the same key go to the same consumer, in order
+    pub(crate) fn new_key_shared(
+        subscription_dispatch: SubscriptionDispatch,
+        dead_letter: Option<DeadLetterPublisher>,
+        cursor_persister: Option<CursorPersister>,
+    ) -> Self {
+        Self::start(
+            subscription_dispatch,
+            dead_letter,
+            cursor_persister,
+            Some(ConsistentHashRing::default()),
+        )
+    }
+
+    fn start(
+        mut subscription_dispatch: SubscriptionDispatch,
+        dead_letter: Option<DeadLetterPublisher>,
+        cursor_persister: Option<CursorPersister>,
+        mut key_ring: Option<ConsistentHashRing>,
+    ) -> Self {
         let (control_tx, mut control_rx) = mpsc::channel(16);
         let notify_dispatch = Arc::new(Notify::new());
         let notify_dispatch_clone = notify_dispatch.clone();
+        // the outcome of the dead lettered messages is sent back to the dispatcher
+        let control_tx_weak = control_tx.downgrade();
 
         // Spawn dispatcher task
         tokio::spawn(async move {
             let mut consumers: Vec<Consumer> = Vec::new();
             let index_consumer = AtomicUsize::new(0);
+            // the in-flight window of each consumer
+            let consumer_window = subscription_dispatch.max_in_flight();
 
             loop {
-                // Wait for a notification or a control command
-                notify_dispatch_clone.notified().await;
+                // Wait for a notification, a control command or the redelivery of the pending message
+                match subscription_dispatch.redelivery_deadline() {
+                    Some(deadline) => {
+                        let _ = timeout_at(deadline, notify_dispatch_clone.notified()).await;
+                    }
+                    None => notify_dispatch_clone.notified().await,
+                }
 
-                // Process control commands first
+                // Process control commands first, the cursor is persisted once for the acknowledgments received
+                let mut cursor_moved = false;
                 while let Ok(command) = control_rx.try_recv() {
                     match command {
                         DispatcherCommand::AddConsumer(consumer) => {
+                            if let Some(key_ring) = key_ring.as_mut() {
+                                key_ring.add_consumer(consumer.consumer_id);
+                            }
                             consumers.push(consumer);
                             trace!("Consumer added. Total consumers: {}", consumers.len());
                         }
                         DispatcherCommand::RemoveConsumer(consumer_id) => {
+                            if let Some(key_ring) = key_ring.as_mut() {
+                                key_ring.remove_consumer(consumer_id);
+                            }
                             consumers.retain(|c| c.consumer_id != consumer_id);
                             trace!("Consumer removed. Total consumers: {}", consumers.len());
                         }
                         DispatcherCommand::DisconnectAllConsumers => {
+                            if let Some(key_ring) = key_ring.as_mut() {
+                                key_ring.clear();
+                            }
                             consumers.clear();
                             trace!("All consumers disconnected.");
                         }
@@ -50,42 +107,96 @@ impl DispatcherReliableMultipleConsumers {
                             );
                         }
                         DispatcherCommand::MessageAcked(request_id, msg_id) => {
-                            // First check if we have an active consumer
-                            if let Some(active_idx) =
-                                Self::get_next_active_consumer(&consumers, &index_consumer).await
+                            // the next messages are sent below, to the consumers with permits
+                            match subscription_dispatch.acknowledge(request_id, msg_id).await {
+                                Ok(()) => cursor_moved = true,
+                                Err(e) => trace!("Failed to handle the acknowledgment: {}", e),
+                            }
+                        }
+                        DispatcherCommand::MessageNacked(request_id, msg_id, redelivery_delay) => {
+                            // the dispatcher waits for the redelivery deadline of the nacked message
+                            if let Err(e) = subscription_dispatch
+                                .handle_message_nacked(request_id, msg_id, redelivery_delay)
+                                .await
                             {
-                                if let Ok(Some(next_message)) = subscription_dispatch
-                                    .handle_message_acked(request_id, msg_id)
-                                    .await
-                                {
-                                    if let Err(e) =
-                                        consumers[active_idx].send_message(next_message).await
-                                    {
-                                        warn!("Failed to dispatch message: {}", e);
+                                warn!("Failed to handle the negative acknowledgment: {}", e);
+                            }
+                        }
+                        DispatcherCommand::DeadLettered(msg_id, stored) => {
+                            // the subscription moves past the stored message, otherwise it is retried
+                            if stored {
+                                match subscription_dispatch.skip_message(&msg_id).await {
+                                    Ok(()) => cursor_moved = true,
+                                    Err(e) => {
+                                        trace!("Failed to skip the dead lettered message: {}", e)
                                     }
                                 }
+                            } else {
+                                subscription_dispatch.retry_dead_letter(&msg_id);
                             }
                         }
                     }
                 }
 
-                // A notification has been received, so we can attempt to send the next message
-                // Send ordered messages from the TopicStore to the consumers
-                // Only process segments if we have an active consumer that's healthy
-                if let Some(active_idx) =
-                    Self::get_next_active_consumer(&consumers, &index_consumer).await
-                {
-                    match subscription_dispatch.process_current_segment().await {
-                        Ok(msg) => {
-                            if let Err(e) = consumers[active_idx].send_message(msg).await {
-                                warn!("Failed to dispatch message: {}", e);
+                match &cursor_persister {
+                    Some(cursor_persister) if cursor_moved => {
+                        cursor_persister.persist(subscription_dispatch.cursor())
+                    }
+                    _ => {}
+                }
+
+                // The subscription keeps in flight the window of each of its consumers
+                subscription_dispatch.set_max_in_flight(consumer_window * consumers.len().max(1));
+
+                // A notification has been received, so we can attempt to send the next messages
+                // Send ordered messages from the TopicStore to the consumers, up to the in-flight window
+                // The messages are distributed round-robin among the healthy consumers with permits,
+                // or to the consumer of their key for the Key_Shared subscriptions,
+                // the dispatch stops as the consumer of the key runs out of permits, to keep the order of the key
+                while Self::has_active_consumer(&consumers).await {
+                    let msg = match subscription_dispatch.process_current_segment().await {
+                        Ok(msg) => msg,
+                        Err(e) => match (e, &dead_letter) {
+                            (ReliableDispatchError::NoMessagesAvailable, _) => break,
+                            // the message exceeded its redeliveries, it goes to the dead letter topic
+                            (ReliableDispatchError::MaxRetriesExceeded, Some(dead_letter)) => {
+                                // the dispatch goes on with the next messages while the message is handed over
+                                if let Err(e) = dead_letter
+                                    .dead_letter_pending(
+                                        &mut subscription_dispatch,
+                                        control_tx_weak.clone(),
+                                        notify_dispatch_clone.clone(),
+                                    )
+                                    .await
+                                {
+                                    warn!("Failed to dead letter the message: {}", e);
+                                    break;
+                                }
+                                continue;
+                            }
+                            (err, _) => {
+                                warn!("Error processing current segment: {}", err);
+                                break;
                             }
-                        }
-                        Err(e) => match e {
-                            ReliableDispatchError::NoMessagesAvailable => continue,
-                            err => warn!("Error processing current segment: {}", err),
                         },
                     };
+
+                    // the message is put back if its consumer can't receive it, it is the next one
+                    // to be dispatched, once the consumer of its key is granted the permits
+                    let msg_id = msg.msg_id.clone();
+                    let Some(active_idx) =
+                        Self::select_consumer(&consumers, &index_consumer, key_ring.as_ref(), &msg)
+                            .await
+                    else {
+                        subscription_dispatch.release_message(&msg_id);
+                        break;
+                    };
+
+                    if let Err(e) = consumers[active_idx].send_message(msg).await {
+                        trace!("Message {:?} not dispatched: {}", msg_id, e);
+                        subscription_dispatch.release_message(&msg_id);
+                        break;
+                    }
                 }
             }
         });
@@ -120,6 +231,23 @@ impl DispatcherReliableMultipleConsumers {
         Ok(())
     }
 
+    /// Negatively acknowledge a message, which means that the consumer failed to process the message
+    /// and it should be redelivered after the redelivery delay
+    pub(crate) async fn nack_message(&self, nack_msg: NackMessage) -> Result<()> {
+        self.control_tx
+            .send(DispatcherCommand::MessageNacked(
+                nack_msg.request_id,
+                nack_msg.msg_id,
+                nack_msg.redelivery_delay,
+            ))
+            .await
+            .map_err(|_| anyhow!("Failed to send message nacked command"))?;
+
+        // Notify the dispatcher
+        self.wake_dispatcher();
+        Ok(())
+    }
+
     /// Add a new consumer to the dispatcher
     pub(crate) async fn add_consumer(&self, consumer: Consumer) -> Result<()> {
         self.control_tx
@@ -157,25 +285,26 @@ impl DispatcherReliableMultipleConsumers {
         Ok(())
     }
 
-    async fn get_next_active_consumer(
+    async fn has_active_consumer(consumers: &[Consumer]) -> bool {
+        for consumer in consumers {
+            if consumer.is_ready().await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Select the consumer of the message, the consumer of its key for the Key_Shared subscriptions,
+    /// otherwise the next healthy consumer with a permit, of the highest priority
+    async fn select_consumer(
         consumers: &[Consumer],
         index_consumer: &AtomicUsize,
+        key_ring: Option<&ConsistentHashRing>,
+        message: &StreamMessage,
     ) -> Option<usize> {
-        if consumers.is_empty() {
-            return None;
-        }
-
-        let num_consumers = consumers.len();
-        let start_index = index_consumer.load(Ordering::SeqCst) % num_consumers;
-
-        // Try each consumer starting from current index
-        for i in 0..num_consumers {
-            let check_index = (start_index + i) % num_consumers;
-            if consumers[check_index].get_status().await {
-                index_consumer.store(check_index + 1, Ordering::SeqCst);
-                return Some(check_index);
-            }
+        match (key_ring, message.key.as_deref()) {
+            (Some(key_ring), Some(key)) => key_ring.select_consumer(consumers, key).await,
+            _ => next_consumer_by_priority(consumers, index_consumer, true).await,
         }
-        None
     }
 }
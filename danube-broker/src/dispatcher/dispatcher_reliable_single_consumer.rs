@@ -2,9 +2,16 @@ use anyhow::{anyhow, Result};
 use danube_reliable_dispatch::{ReliableDispatchError, SubscriptionDispatch};
 use std::sync::Arc;
 use tokio::sync::{mpsc, Notify};
+use tokio::time::timeout_at;
 use tracing::{trace, warn};
 
-use crate::{consumer::Consumer, dispatcher::DispatcherCommand, message::AckMessage};
+use crate::{
+    consumer::Consumer,
+    cursor_persister::CursorPersister,
+    dead_letter::DeadLetterPublisher,
+    dispatcher::{elect_active_consumer, DispatcherCommand},
+    message::{AckMessage, NackMessage},
+};
 
 /// Reliable dispatcher for single consumer, it sends ordered messages to a single consumer
 #[derive(Debug)]
@@ -14,10 +21,16 @@ pub(crate) struct DispatcherReliableSingleConsumer {
 }
 
 impl DispatcherReliableSingleConsumer {
-    pub(crate) fn new(mut subscription_dispatch: SubscriptionDispatch) -> Self {
+    pub(crate) fn new(
+        mut subscription_dispatch: SubscriptionDispatch,
+        dead_letter: Option<DeadLetterPublisher>,
+        cursor_persister: Option<CursorPersister>,
+    ) -> Self {
         let (control_tx, mut control_rx) = mpsc::channel(16);
         let notify_dispatch = Arc::new(Notify::new());
         let notify_dispatch_clone = notify_dispatch.clone();
+        // the outcome of the dead lettered messages is sent back to the dispatcher
+        let control_tx_weak = control_tx.downgrade();
 
         // Spawn dispatcher task
         tokio::spawn(async move {
@@ -25,30 +38,27 @@ impl DispatcherReliableSingleConsumer {
             let mut active_consumer: Option<Consumer> = None;
 
             loop {
-                // Wait for a notification or a control command
-                notify_dispatch_clone.notified().await;
+                // Wait for a notification, a control command or the redelivery of the pending message
+                match subscription_dispatch.redelivery_deadline() {
+                    Some(deadline) => {
+                        let _ = timeout_at(deadline, notify_dispatch_clone.notified()).await;
+                    }
+                    None => notify_dispatch_clone.notified().await,
+                }
 
-                // Process control commands first
+                // Process control commands first, the cursor is persisted once for the acknowledgments received
+                let mut cursor_moved = false;
                 while let Ok(command) = control_rx.try_recv() {
                     match command {
                         DispatcherCommand::AddConsumer(consumer) => {
-                            if let Err(e) = Self::handle_add_consumer(
-                                &mut consumers,
-                                &mut active_consumer,
-                                consumer,
-                            )
-                            .await
+                            if let Err(e) =
+                                Self::handle_add_consumer(&mut consumers, consumer).await
                             {
                                 warn!("Failed to add consumer: {}", e);
                             }
                         }
                         DispatcherCommand::RemoveConsumer(consumer_id) => {
-                            Self::handle_remove_consumer(
-                                &mut consumers,
-                                &mut active_consumer,
-                                consumer_id,
-                            )
-                            .await;
+                            Self::handle_remove_consumer(&mut consumers, consumer_id).await;
                         }
                         DispatcherCommand::DisconnectAllConsumers => {
                             Self::handle_disconnect_all(&mut consumers, &mut active_consumer).await;
@@ -59,40 +69,90 @@ impl DispatcherReliableSingleConsumer {
                             );
                         }
                         DispatcherCommand::MessageAcked(request_id, msg_id) => {
-                            if let Some(consumer) =
-                                Self::get_active_consumer(&mut active_consumer).await
+                            // the next messages are sent below, once the consumer has permits
+                            match subscription_dispatch.acknowledge(request_id, msg_id).await {
+                                Ok(()) => cursor_moved = true,
+                                Err(e) => trace!("Failed to handle the acknowledgment: {}", e),
+                            }
+                        }
+                        DispatcherCommand::MessageNacked(request_id, msg_id, redelivery_delay) => {
+                            // the dispatcher waits for the redelivery deadline of the nacked message
+                            if let Err(e) = subscription_dispatch
+                                .handle_message_nacked(request_id, msg_id, redelivery_delay)
+                                .await
                             {
-                                if let Ok(Some(next_message)) = subscription_dispatch
-                                    .handle_message_acked(request_id, msg_id)
-                                    .await
-                                {
-                                    if let Err(e) = consumer.send_message(next_message).await {
-                                        warn!("Failed to dispatch message: {}", e);
+                                warn!("Failed to handle the negative acknowledgment: {}", e);
+                            }
+                        }
+                        DispatcherCommand::DeadLettered(msg_id, stored) => {
+                            // the subscription moves past the stored message, otherwise it is retried
+                            if stored {
+                                match subscription_dispatch.skip_message(&msg_id).await {
+                                    Ok(()) => cursor_moved = true,
+                                    Err(e) => {
+                                        trace!("Failed to skip the dead lettered message: {}", e)
                                     }
                                 }
-
-                                // Notify the dispatcher to attempt sending the next message
-                                // ?? notify_dispatch_clone.notify_one();
+                            } else {
+                                subscription_dispatch.retry_dead_letter(&msg_id);
                             }
                         }
                     }
                 }
 
-                // A notification has been received, so we can attempt to send the next message
-                // Send ordered messages from the TopicStore to the consumers
-                // Only process segments if we have an active consumer that's healthy
-                if let Some(consumer) = Self::get_active_consumer(&mut active_consumer).await {
-                    match subscription_dispatch.process_current_segment().await {
-                        Ok(msg) => {
-                            if let Err(e) = consumer.send_message(msg).await {
-                                warn!("Failed to dispatch message: {}", e);
-                            }
+                match &cursor_persister {
+                    Some(cursor_persister) if cursor_moved => {
+                        cursor_persister.persist(subscription_dispatch.cursor())
+                    }
+                    _ => {}
+                }
+
+                // A notification has been received, so we can attempt to send the next messages
+                // Send ordered messages from the TopicStore to the consumers, up to the in-flight window
+                // The active consumer is elected as the consumers join, leave or fail,
+                // a new active consumer resumes from the last acknowledged message
+                if elect_active_consumer(&consumers, &mut active_consumer).await {
+                    subscription_dispatch.reset_pending();
+                }
+
+                // Only process segments if we have an active consumer that's healthy,
+                // while it has the permits to receive the messages
+                if let Some(consumer) = active_consumer.as_mut() {
+                    while consumer.permits.has_permit() {
+                        let msg = match subscription_dispatch.process_current_segment().await {
+                            Ok(msg) => msg,
+                            Err(e) => match (e, &dead_letter) {
+                                (ReliableDispatchError::NoMessagesAvailable, _) => break,
+                                // the message exceeded its redeliveries, it goes to the dead letter topic
+                                (ReliableDispatchError::MaxRetriesExceeded, Some(dead_letter)) => {
+                                    // the dispatch goes on with the next messages while the message is handed over
+                                    if let Err(e) = dead_letter
+                                        .dead_letter_pending(
+                                            &mut subscription_dispatch,
+                                            control_tx_weak.clone(),
+                                            notify_dispatch_clone.clone(),
+                                        )
+                                        .await
+                                    {
+                                        warn!("Failed to dead letter the message: {}", e);
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                (err, _) => {
+                                    warn!("Error processing current segment: {}", err);
+                                    break;
+                                }
+                            },
+                        };
+
+                        let msg_id = msg.msg_id.clone();
+                        if let Err(e) = consumer.send_message(msg).await {
+                            warn!("Failed to dispatch message: {}", e);
+                            subscription_dispatch.release_message(&msg_id);
+                            break;
                         }
-                        Err(e) => match e {
-                            ReliableDispatchError::NoMessagesAvailable => continue,
-                            err => warn!("Error processing current segment: {}", err),
-                        },
-                    };
+                    }
                 }
             }
         });
@@ -127,6 +187,23 @@ impl DispatcherReliableSingleConsumer {
         Ok(())
     }
 
+    /// Negatively acknowledge a message, which means that the consumer failed to process the message
+    /// and it should be redelivered after the redelivery delay
+    pub(crate) async fn nack_message(&self, nack_msg: NackMessage) -> Result<()> {
+        self.control_tx
+            .send(DispatcherCommand::MessageNacked(
+                nack_msg.request_id,
+                nack_msg.msg_id,
+                nack_msg.redelivery_delay,
+            ))
+            .await
+            .map_err(|_| anyhow!("Failed to send message nacked command"))?;
+
+        // Notify the dispatcher
+        self.wake_dispatcher();
+        Ok(())
+    }
+
     /// Add a consumer
     pub(crate) async fn add_consumer(&self, consumer: Consumer) -> Result<()> {
         self.control_tx
@@ -165,11 +242,7 @@ impl DispatcherReliableSingleConsumer {
     }
 
     /// Handle adding a consumer
-    async fn handle_add_consumer(
-        consumers: &mut Vec<Consumer>,
-        active_consumer: &mut Option<Consumer>,
-        consumer: Consumer,
-    ) -> Result<()> {
+    async fn handle_add_consumer(consumers: &mut Vec<Consumer>, consumer: Consumer) -> Result<()> {
         if consumer.subscription_type == 1 {
             return Err(anyhow!(
                 "Shared subscription should use a multi-consumer dispatcher"
@@ -188,10 +261,6 @@ impl DispatcherReliableSingleConsumer {
 
         consumers.push(consumer.clone());
 
-        if active_consumer.is_none() {
-            *active_consumer = Some(consumer.clone());
-        }
-
         trace!(
             "Consumer {} added to single-consumer dispatcher",
             consumer.consumer_name
@@ -201,30 +270,9 @@ impl DispatcherReliableSingleConsumer {
     }
 
     /// Handle removing a consumer
-    async fn handle_remove_consumer(
-        consumers: &mut Vec<Consumer>,
-        active_consumer: &mut Option<Consumer>,
-        consumer_id: u64,
-    ) {
+    async fn handle_remove_consumer(consumers: &mut Vec<Consumer>, consumer_id: u64) {
         consumers.retain(|c| c.consumer_id != consumer_id);
-
-        if let Some(ref active) = active_consumer {
-            if active.consumer_id == consumer_id {
-                *active_consumer = None;
-            }
-        }
-
         trace!("Consumer {} removed from dispatcher", consumer_id);
-
-        // Re-pick an active consumer if needed
-        if active_consumer.is_none() && !consumers.is_empty() {
-            for consumer in consumers {
-                if consumer.get_status().await {
-                    *active_consumer = Some(consumer.clone());
-                    break;
-                }
-            }
-        }
     }
 
     /// Handle disconnecting all consumers
@@ -236,12 +284,4 @@ impl DispatcherReliableSingleConsumer {
         *active_consumer = None;
         trace!("All consumers disconnected from dispatcher");
     }
-
-    /// Get the active consumer
-    async fn get_active_consumer(active_consumer: &mut Option<Consumer>) -> Option<&mut Consumer> {
-        match active_consumer {
-            Some(consumer) if consumer.get_status().await => Some(consumer),
-            _ => None,
-        }
-    }
 }
@@ -3,7 +3,11 @@ use danube_core::message::StreamMessage;
 use tokio::sync::mpsc;
 use tracing::{trace, warn};
 
-use crate::{consumer::Consumer, dispatcher::DispatcherCommand, message::AckMessage};
+use crate::{
+    consumer::Consumer,
+    dispatcher::{elect_active_consumer, skip_expired_message, DispatcherCommand},
+    message::{AckMessage, NackMessage},
+};
 
 #[derive(Debug)]
 pub(crate) struct DispatcherSingleConsumer {
@@ -45,8 +49,12 @@ impl DispatcherSingleConsumer {
                             Self::handle_disconnect_all(&mut consumers, &mut active_consumer).await;
                         }
                         DispatcherCommand::DispatchMessage(message) => {
-                            if let Err(e) =
-                                Self::handle_dispatch_message(&mut active_consumer, message).await
+                            if let Err(e) = Self::handle_dispatch_message(
+                                &consumers,
+                                &mut active_consumer,
+                                message,
+                            )
+                            .await
                             {
                                 warn!("Failed to dispatch message: {}", e);
                             }
@@ -56,6 +64,16 @@ impl DispatcherSingleConsumer {
                                 "Non-reliable dispatcher does not care about acked messages"
                             );
                         }
+                        DispatcherCommand::MessageNacked(_, _, _) => {
+                            unreachable!(
+                                "Non-reliable dispatcher does not care about nacked messages"
+                            );
+                        }
+                        DispatcherCommand::DeadLettered(_, _) => {
+                            unreachable!(
+                                "Non-reliable dispatcher does not dead letter the messages"
+                            );
+                        }
                     }
                 }
             }
@@ -78,6 +96,12 @@ impl DispatcherSingleConsumer {
         Ok(())
     }
 
+    /// Negatively acknowledge a message, to be redelivered to the consumer
+    /// Non-reliable dispatchers do not redeliver messages
+    pub(crate) async fn nack_message(&self, _nack_msg: NackMessage) -> Result<()> {
+        Ok(())
+    }
+
     /// Add a consumer
     pub(crate) async fn add_consumer(&self, consumer: Consumer) -> Result<()> {
         self.control_tx
@@ -126,10 +150,7 @@ impl DispatcherSingleConsumer {
         }
 
         consumers.push(consumer.clone());
-
-        if active_consumer.is_none() {
-            *active_consumer = Some(consumer.clone());
-        }
+        elect_active_consumer(consumers, active_consumer).await;
 
         trace!(
             "Consumer {} added to single-consumer dispatcher",
@@ -146,24 +167,10 @@ impl DispatcherSingleConsumer {
         consumer_id: u64,
     ) {
         consumers.retain(|c| c.consumer_id != consumer_id);
-
-        if let Some(ref active) = active_consumer {
-            if active.consumer_id == consumer_id {
-                *active_consumer = None;
-            }
-        }
-
         trace!("Consumer {} removed from dispatcher", consumer_id);
 
-        // Re-pick an active consumer if needed
-        if active_consumer.is_none() && !consumers.is_empty() {
-            for consumer in consumers {
-                if consumer.get_status().await {
-                    *active_consumer = Some(consumer.clone());
-                    break;
-                }
-            }
-        }
+        // a standby consumer takes over, if the active consumer left
+        elect_active_consumer(consumers, active_consumer).await;
     }
 
     /// Handle disconnecting all consumers
@@ -176,20 +183,25 @@ impl DispatcherSingleConsumer {
         trace!("All consumers disconnected from dispatcher");
     }
 
-    /// Dispatch a message to the active consumer
+    /// Dispatch a message to the active consumer, a standby consumer takes over if it failed
     async fn handle_dispatch_message(
+        consumers: &[Consumer],
         active_consumer: &mut Option<Consumer>,
         message: StreamMessage,
     ) -> Result<()> {
+        elect_active_consumer(consumers, active_consumer).await;
+
+        if skip_expired_message(&message) {
+            return Ok(());
+        }
+
         if let Some(consumer) = active_consumer {
-            if consumer.get_status().await {
-                consumer.send_message(message).await?;
-                trace!(
-                    "Message dispatched to active consumer {}",
-                    consumer.consumer_id
-                );
-                return Ok(());
-            }
+            consumer.send_message(message).await?;
+            trace!(
+                "Message dispatched to active consumer {}",
+                consumer.consumer_id
+            );
+            return Ok(());
         }
 
         Err(anyhow!("No active consumer available to dispatch message"))
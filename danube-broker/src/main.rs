@@ -6,7 +6,9 @@ mod broker_metrics;
 mod broker_server;
 mod broker_service;
 mod consumer;
+mod cursor_persister;
 mod danube_service;
+mod dead_letter;
 mod dispatch_strategy;
 mod dispatcher;
 mod error_message;
@@ -26,7 +28,9 @@ use crate::{
     args_parse::Args,
     broker_metrics::init_metrics,
     broker_service::BrokerService,
+    cursor_persister::start_cursor_persistence_task,
     danube_service::{DanubeService, LeaderElection, LoadManager, LocalCache, Syncronizer},
+    dead_letter::{start_dead_letter_task, DeadLetterConnection},
     resources::{Resources, LEADER_ELECTION_PATH},
     service_configuration::{LoadConfiguration, ServiceConfiguration},
 };
@@ -35,7 +39,7 @@ use anyhow::{Context, Result};
 use danube_metadata_store::{EtcdStore, MetadataStorage};
 use danube_reliable_dispatch::create_message_storage;
 use std::net::SocketAddr;
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc, Mutex};
 use tracing::info;
 use tracing_subscriber;
 
@@ -103,7 +107,9 @@ async fn main() -> Result<()> {
         "Initializing {} for message persistence",
         service_config.storage
     );
-    let message_storage = create_message_storage(&service_config.storage).await;
+    let message_storage = create_message_storage(&service_config.storage)
+        .await
+        .context("Failed to initialize the message storage")?;
 
     // caching metadata locally to reduce the number of remote calls to Metadata Store
     let local_cache = LocalCache::new(metadata_store.clone());
@@ -117,7 +123,17 @@ async fn main() -> Result<()> {
     let syncroniser = Syncronizer::new();
 
     // the broker service, is responsible to reliable deliver the messages from producers to consumers.
-    let broker_service = BrokerService::new(resources.clone(), message_storage);
+    // the messages exceeding the redeliveries of their subscription, stored to the dead letter topics
+    // the cursors of the reliable subscriptions, persisted as the messages are acknowledged
+    let (dead_letter_tx, dead_letter_rx) = mpsc::channel(16);
+    let (cursor_tx, cursor_rx) = mpsc::unbounded_channel();
+    start_cursor_persistence_task(resources.clone(), cursor_rx);
+    let broker_service = BrokerService::new(
+        resources.clone(),
+        message_storage,
+        dead_letter_tx,
+        cursor_tx,
+    );
     let broker_id = broker_service.broker_id;
 
     // the service selects one broker per cluster to be the leader to coordinate and take assignment decision.
@@ -133,6 +149,15 @@ async fn main() -> Result<()> {
     let broker: Arc<Mutex<BrokerService>> = Arc::new(Mutex::new(broker_service));
 
     let broker_addr = service_config.broker_addr;
+
+    // the dead letter topics may be served by any broker of the cluster,
+    // the messages are published with a client of this broker, which looks up the serving broker
+    let dead_letter_connection = DeadLetterConnection::new(
+        service_config.advertised_addr.as_deref(),
+        broker_addr,
+        service_config.auth.clone(),
+    );
+    start_dead_letter_task(dead_letter_connection, broker_id, dead_letter_rx);
     info!(
         "Initializing Danube Message Broker service on {}",
         broker_addr
@@ -1,4 +1,5 @@
 use danube_core::message::MessageID;
+use std::time::Duration;
 
 #[derive(Debug, Clone)]
 pub(crate) struct AckMessage {
@@ -6,3 +7,11 @@ pub(crate) struct AckMessage {
     pub(crate) msg_id: MessageID,
     pub(crate) subscription_name: String,
 }
+
+#[derive(Debug, Clone)]
+pub(crate) struct NackMessage {
+    pub(crate) request_id: u64,
+    pub(crate) msg_id: MessageID,
+    pub(crate) subscription_name: String,
+    pub(crate) redelivery_delay: Option<Duration>,
+}
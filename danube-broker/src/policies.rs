@@ -38,6 +38,12 @@ pub struct Policies {
     // Limits the maximum size of a single message that can be published to the topic.
     #[serde(default = "default_max_message_size")]
     max_message_size: u32,
+
+    /// The time to live of the messages published to the topic, in seconds, unless set on the message.
+    /// The expired messages are skipped instead of delivered to the consumers.
+    /// Default is 0, the messages don't expire.
+    #[serde(default)]
+    message_ttl: u32,
 }
 
 // Custom function to return the default 10 MB value
@@ -51,6 +57,11 @@ impl Policies {
             ..Default::default()
         }
     }
+    /// The time to live of the messages in seconds, 0 if the messages don't expire
+    pub(crate) fn message_ttl(&self) -> u32 {
+        self.message_ttl
+    }
+
     #[allow(dead_code)]
     pub(crate) fn get_fields_as_map(&self) -> Map<String, Value> {
         let serialized = serde_json::to_value(self).unwrap();
@@ -124,6 +135,12 @@ impl Policies {
                     }
                     found_fields.insert("max_message_size");
                 }
+                // optional, not set by the previous releases
+                "message_ttl" => {
+                    if let Some(val) = value.as_u64() {
+                        policies.message_ttl = val as u32;
+                    }
+                }
                 _ => {} // Ignore unknown fields
             }
         }
@@ -1,7 +1,9 @@
 use anyhow::Result;
 use danube_core::dispatch_strategy::ConfigDispatchStrategy;
 use danube_metadata_store::{MetaOptions, MetadataStorage, MetadataStore};
+use danube_reliable_dispatch::SubscriptionCursor;
 use serde_json::Value;
+use std::collections::HashMap;
 
 use crate::{
     policies::Policies, resources::BASE_TOPICS_PATH, schema::Schema, utils::join_path, LocalCache,
@@ -129,6 +131,67 @@ impl TopicResources {
         Ok(())
     }
 
+    pub(crate) async fn set_subscription_cursor(
+        &mut self,
+        topic_name: &str,
+        subscription_name: &str,
+        cursor: &SubscriptionCursor,
+    ) -> Result<()> {
+        let path = join_path(&[
+            BASE_TOPICS_PATH,
+            topic_name,
+            "subscriptions",
+            subscription_name,
+            "cursor",
+        ]);
+        let data = serde_json::to_value(cursor).unwrap();
+        self.create(&path, data).await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn delete_subscription_cursor(
+        &mut self,
+        topic_name: &str,
+        subscription_name: &str,
+    ) -> Result<()> {
+        let path = join_path(&[
+            BASE_TOPICS_PATH,
+            topic_name,
+            "subscriptions",
+            subscription_name,
+            "cursor",
+        ]);
+        self.delete(&path).await?;
+
+        Ok(())
+    }
+
+    // return the persisted cursors of the topic subscriptions, subscription_name -> cursor
+    pub(crate) async fn get_subscription_cursors(
+        &self,
+        topic_name: &str,
+    ) -> HashMap<String, SubscriptionCursor> {
+        let mut cursors = HashMap::new();
+
+        for subscription_name in self.get_subscription_for_topic(topic_name).await {
+            let path = join_path(&[
+                BASE_TOPICS_PATH,
+                topic_name,
+                "subscriptions",
+                &subscription_name,
+                "cursor",
+            ]);
+            if let Some(value) = self.local_cache.get(&path) {
+                if let Ok(cursor) = serde_json::from_value::<SubscriptionCursor>(value) {
+                    cursors.insert(subscription_name, cursor);
+                }
+            }
+        }
+
+        cursors
+    }
+
     pub(crate) fn get_schema(&self, topic_name: &str) -> Option<Schema> {
         let path = join_path(&[BASE_TOPICS_PATH, topic_name, "schema"]);
         let result = self.local_cache.get(&path);
@@ -171,8 +234,11 @@ impl TopicResources {
         for path in paths {
             let parts: Vec<&str> = path.split('/').collect();
 
+            // the subscription path may have nested keys, like the subscription cursor
             if let Some(subscription) = parts.get(5) {
-                subscriptions.push(subscription.to_string());
+                if !subscriptions.iter().any(|sub| sub == subscription) {
+                    subscriptions.push(subscription.to_string());
+                }
             }
         }
 
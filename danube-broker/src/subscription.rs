@@ -1,14 +1,17 @@
 use anyhow::{anyhow, Ok, Result};
 use danube_core::message::StreamMessage;
+use danube_reliable_dispatch::RedeliveryBackoff;
 use metrics::gauge;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::sync::{mpsc, Mutex, Notify};
 use tracing::trace;
 
 use crate::{
     broker_metrics::TOPIC_CONSUMERS,
-    consumer::Consumer,
+    consumer::{Consumer, FlowPermits},
+    cursor_persister::CursorPersister,
+    dead_letter::{DeadLetterPolicy, DeadLetterPublisher},
     dispatch_strategy::DispatchStrategy,
     dispatcher::{
         dispatcher_multiple_consumers::DispatcherMultipleConsumers,
@@ -16,7 +19,7 @@ use crate::{
         dispatcher_reliable_single_consumer::DispatcherReliableSingleConsumer,
         dispatcher_single_consumer::DispatcherSingleConsumer, Dispatcher,
     },
-    message::AckMessage,
+    message::{AckMessage, NackMessage},
     utils::get_random_id,
 };
 
@@ -37,6 +40,7 @@ pub(crate) struct ConsumerInfo {
     pub(crate) sub_options: SubscriptionOptions,
     pub(crate) status: Arc<Mutex<bool>>,
     pub(crate) rx_cons: Arc<Mutex<mpsc::Receiver<StreamMessage>>>,
+    pub(crate) permits: Arc<FlowPermits>,
 }
 
 impl ConsumerInfo {
@@ -57,6 +61,42 @@ pub(crate) struct SubscriptionOptions {
     pub(crate) subscription_type: i32, // should be moved to SubscriptionType
     pub(crate) consumer_id: Option<u64>,
     pub(crate) consumer_name: String,
+    // only for the reliable topics, the messages exceeding the redeliveries go to the dead letter topic
+    #[serde(default)]
+    pub(crate) dead_letter_policy: Option<DeadLetterPolicy>,
+    // only for the reliable topics, the redelivery schedule of the unacknowledged messages
+    #[serde(default)]
+    pub(crate) ack_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub(crate) backoff_multiplier: Option<f64>,
+    #[serde(default)]
+    pub(crate) max_backoff_ms: Option<u64>,
+    // only for the reliable topics, the in-flight window of each consumer
+    #[serde(default)]
+    pub(crate) max_unacked_messages: Option<u32>,
+    // the Shared subscriptions deliver first to the consumers of the highest priority, 0 is the highest
+    #[serde(default)]
+    pub(crate) priority_level: u32,
+}
+
+impl SubscriptionOptions {
+    // the redelivery schedule requested by the consumer, the defaults for the options not provided
+    pub(crate) fn redelivery_backoff(&self) -> RedeliveryBackoff {
+        let default = RedeliveryBackoff::default();
+        let ack_timeout = self
+            .ack_timeout_ms
+            .map_or(default.ack_timeout, Duration::from_millis);
+        // the default max backoff never caps a longer ack timeout
+        let max_backoff = self
+            .max_backoff_ms
+            .map_or(default.max_backoff.max(ack_timeout), Duration::from_millis);
+        RedeliveryBackoff::new(
+            ack_timeout,
+            self.backoff_multiplier
+                .unwrap_or(default.backoff_multiplier),
+            max_backoff,
+        )
+    }
 }
 
 impl Subscription {
@@ -85,16 +125,20 @@ impl Subscription {
 
         let consumer_id = get_random_id();
         let consumer_status = Arc::new(Mutex::new(true));
+        let dispatcher = self.dispatcher.as_mut().unwrap();
+        // the consumer receives the messages as the client grants the permits
+        let consumer_permits = Arc::new(FlowPermits::new(topic_name, dispatcher.get_notifier()));
         let consumer = Consumer::new(
             consumer_id,
             &options.consumer_name,
             options.subscription_type,
+            options.priority_level,
             topic_name,
             tx_cons,
             consumer_status.clone(),
+            consumer_permits.clone(),
         );
 
-        let dispatcher = self.dispatcher.as_mut().unwrap();
         // Add the consumer to the dispatcher
         dispatcher.add_consumer(consumer).await?;
 
@@ -103,6 +147,7 @@ impl Subscription {
             sub_options: options,
             status: consumer_status,
             rx_cons: Arc::new(Mutex::new(rx_cons)),
+            permits: consumer_permits,
         };
 
         // Insert the consumer into the subscription's consumer list
@@ -121,6 +166,8 @@ impl Subscription {
         &mut self,
         options: SubscriptionOptions,
         dispatch_strategy: &DispatchStrategy,
+        dead_letter: Option<DeadLetterPublisher>,
+        cursor_persister: Option<CursorPersister>,
     ) -> Result<Option<Arc<Notify>>> {
         let (new_dispatcher, notifier) = match dispatch_strategy {
             DispatchStrategy::NonReliable => match options.subscription_type {
@@ -136,26 +183,45 @@ impl Subscription {
                     None,
                 ),
 
-                // Failover
+                // Failover, the standby consumers take over from the active consumer
                 2 => (
                     Dispatcher::OneConsumer(DispatcherSingleConsumer::new()),
                     None,
                 ),
 
+                // Key_Shared
+                3 => (
+                    Dispatcher::MultipleConsumers(DispatcherMultipleConsumers::new_key_shared()),
+                    None,
+                ),
+
                 _ => {
                     return Err(anyhow!("Should not get here"));
                 }
             },
             DispatchStrategy::Reliable(reliable_dispatcher) => {
-                let subscription_dispatch = reliable_dispatcher
+                let mut subscription_dispatch = reliable_dispatcher
                     .new_subscription_dispatch(&options.subscription_name)
                     .await?;
+                if let Some(dead_letter) = &dead_letter {
+                    subscription_dispatch = subscription_dispatch
+                        .with_max_redeliveries(dead_letter.policy.max_redeliveries);
+                }
+                subscription_dispatch =
+                    subscription_dispatch.with_redelivery_backoff(options.redelivery_backoff());
+                if let Some(max_unacked_messages) = options.max_unacked_messages {
+                    subscription_dispatch =
+                        subscription_dispatch.with_max_in_flight(max_unacked_messages as usize);
+                }
 
                 match options.subscription_type {
                     // Exclusive
                     0 => {
-                        let new_dispatcher =
-                            DispatcherReliableSingleConsumer::new(subscription_dispatch);
+                        let new_dispatcher = DispatcherReliableSingleConsumer::new(
+                            subscription_dispatch,
+                            dead_letter,
+                            cursor_persister,
+                        );
                         let notifier = new_dispatcher.get_notifier();
                         (
                             Dispatcher::ReliableOneConsumer(new_dispatcher),
@@ -165,8 +231,11 @@ impl Subscription {
 
                     // Shared
                     1 => {
-                        let new_dispatcher =
-                            DispatcherReliableMultipleConsumers::new(subscription_dispatch);
+                        let new_dispatcher = DispatcherReliableMultipleConsumers::new(
+                            subscription_dispatch,
+                            dead_letter,
+                            cursor_persister,
+                        );
                         let notifier = new_dispatcher.get_notifier();
                         (
                             Dispatcher::ReliableMultipleConsumers(new_dispatcher),
@@ -174,10 +243,13 @@ impl Subscription {
                         )
                     }
 
-                    // Failover
+                    // Failover, the standby consumers take over from the active consumer
                     2 => {
-                        let new_dispatcher =
-                            DispatcherReliableSingleConsumer::new(subscription_dispatch);
+                        let new_dispatcher = DispatcherReliableSingleConsumer::new(
+                            subscription_dispatch,
+                            dead_letter,
+                            cursor_persister,
+                        );
                         let notifier = new_dispatcher.get_notifier();
                         (
                             Dispatcher::ReliableOneConsumer(new_dispatcher),
@@ -185,6 +257,20 @@ impl Subscription {
                         )
                     }
 
+                    // Key_Shared
+                    3 => {
+                        let new_dispatcher = DispatcherReliableMultipleConsumers::new_key_shared(
+                            subscription_dispatch,
+                            dead_letter,
+                            cursor_persister,
+                        );
+                        let notifier = new_dispatcher.get_notifier();
+                        (
+                            Dispatcher::ReliableMultipleConsumers(new_dispatcher),
+                            Some(notifier),
+                        )
+                    }
+
                     _ => {
                         return Err(anyhow!("Should not get here"));
                     }
@@ -216,14 +302,13 @@ impl Subscription {
         Ok(())
     }
 
-    pub(crate) fn get_consumer_rx(
-        &self,
-        consumer_id: u64,
-    ) -> Option<Arc<Mutex<mpsc::Receiver<StreamMessage>>>> {
-        if let Some(consumer_info) = self.consumers.get(&consumer_id) {
-            return Some(consumer_info.rx_cons.clone());
+    pub(crate) async fn nack_message(&self, nack_msg: NackMessage) -> Result<()> {
+        if let Some(dispatcher) = self.dispatcher.as_ref() {
+            dispatcher.nack_message(nack_msg).await?;
+        } else {
+            return Err(anyhow!("Dispatcher not initialized"));
         }
-        None
+        Ok(())
     }
 
     pub(crate) fn get_consumer_info(&self, consumer_id: u64) -> Option<ConsumerInfo> {
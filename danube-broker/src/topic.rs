@@ -1,16 +1,19 @@
 use anyhow::{anyhow, Result};
 use danube_core::{dispatch_strategy::ConfigDispatchStrategy, message::StreamMessage};
-use danube_reliable_dispatch::{ReliableDispatch, TopicCache};
+use danube_reliable_dispatch::{ReliableDispatch, SubscriptionCursor, TopicCache};
 use metrics::counter;
 use std::collections::{hash_map::Entry, HashMap};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::{Mutex, Notify};
 use tracing::{info, warn};
 
 use crate::{
     broker_metrics::{TOPIC_BYTES_IN_COUNTER, TOPIC_MSG_IN_COUNTER},
+    cursor_persister::CursorPersister,
+    dead_letter::DeadLetterPublisher,
     dispatch_strategy::DispatchStrategy,
-    message::AckMessage,
+    message::{AckMessage, NackMessage},
     policies::Policies,
     producer::Producer,
     schema::Schema,
@@ -44,19 +47,28 @@ pub(crate) struct Topic {
 }
 
 impl Topic {
-    pub(crate) fn new(
+    pub(crate) async fn new(
         topic_name: &str,
         dispatch_strategy: ConfigDispatchStrategy,
         storage_backend: TopicCache,
-    ) -> Self {
+        subscription_cursors: HashMap<String, SubscriptionCursor>,
+    ) -> Result<Self> {
         let dispatch_strategy = match dispatch_strategy {
             ConfigDispatchStrategy::NonReliable => DispatchStrategy::NonReliable,
-            ConfigDispatchStrategy::Reliable(reliable_options) => DispatchStrategy::Reliable(
-                ReliableDispatch::new(topic_name, reliable_options, storage_backend),
-            ),
+            ConfigDispatchStrategy::Reliable(reliable_options) => {
+                DispatchStrategy::Reliable(Box::new(
+                    ReliableDispatch::new(
+                        topic_name,
+                        reliable_options,
+                        storage_backend,
+                        subscription_cursors,
+                    )
+                    .await?,
+                ))
+            }
         };
 
-        Topic {
+        Ok(Topic {
             topic_name: topic_name.into(),
             schema: None,
             topic_policies: None,
@@ -64,7 +76,7 @@ impl Topic {
             producers: HashMap::new(),
             dispatch_strategy,
             notifiers: Mutex::new(Vec::new()),
-        }
+        })
     }
 
     #[allow(unused_assignments)]
@@ -144,6 +156,52 @@ impl Topic {
             }
         }
 
+        let stream_message = self.resolve_delivery_time(stream_message)?;
+        let stream_message = self.resolve_expiration_time(stream_message);
+
+        self.dispatch_message(stream_message).await
+    }
+
+    // The delayed messages are held back by the reliable subscriptions until their delivery time,
+    // the delay is resolved against the broker clock, so the subscriptions only track the delivery time
+    fn resolve_delivery_time(&self, mut stream_message: StreamMessage) -> Result<StreamMessage> {
+        if !stream_message.is_delayed() {
+            return Ok(stream_message);
+        }
+        if let DispatchStrategy::NonReliable = self.dispatch_strategy {
+            return Err(anyhow!(
+                "the delayed delivery requires a reliable topic, the topic {} is non-reliable",
+                self.topic_name
+            ));
+        }
+
+        if let Some(deliver_after) = stream_message.deliver_after.take() {
+            stream_message.deliver_at = Some(now_millis().saturating_add(deliver_after));
+        }
+
+        Ok(stream_message)
+    }
+
+    // The time to live of the message, otherwise the message_ttl policy of the topic,
+    // is resolved against the broker clock into the expiration time of the message
+    fn resolve_expiration_time(&self, mut stream_message: StreamMessage) -> StreamMessage {
+        let ttl = stream_message
+            .ttl
+            .take()
+            .filter(|ttl| *ttl > 0)
+            .or_else(|| {
+                self.topic_policies
+                    .as_ref()
+                    .map(|policies| policies.message_ttl() as u64 * 1000)
+                    .filter(|ttl| *ttl > 0)
+            });
+        stream_message.expire_at = ttl.map(|ttl| now_millis().saturating_add(ttl));
+
+        stream_message
+    }
+
+    // Stores the message for the reliable topics, or sends it to the active consumers
+    async fn dispatch_message(&self, stream_message: StreamMessage) -> Result<()> {
         match &self.dispatch_strategy {
             DispatchStrategy::NonReliable => {
                 // Collect subscriptions that need to be unsubscribed, if contain no active consumers
@@ -195,6 +253,15 @@ impl Topic {
         Ok(())
     }
 
+    pub(crate) async fn nack_message(&self, nack_msg: NackMessage) -> Result<()> {
+        let mut subscriptions = self.subscriptions.lock().await;
+        let subscription = subscriptions
+            .get_mut(nack_msg.subscription_name.as_str())
+            .ok_or_else(|| anyhow!("Subscription not found"))?;
+        subscription.nack_message(nack_msg).await?;
+        Ok(())
+    }
+
     pub(crate) fn get_producer_status(&self, producer_id: u64) -> bool {
         if let Some(producer) = self.producers.get(&producer_id) {
             if producer.status == true {
@@ -209,6 +276,8 @@ impl Topic {
         &self,
         topic_name: &str,
         options: SubscriptionOptions,
+        dead_letter: Option<DeadLetterPublisher>,
+        cursor_persister: CursorPersister,
     ) -> Result<u64> {
         //Todo! sub_metadata is user-defined information to the subscription,
         //maybe for user internal business, management and montoring
@@ -229,7 +298,12 @@ impl Topic {
                     .await?;
 
                 let notifier = new_subscription
-                    .create_new_dispatcher(options.clone(), &self.dispatch_strategy)
+                    .create_new_dispatcher(
+                        options.clone(),
+                        &self.dispatch_strategy,
+                        dead_letter,
+                        Some(cursor_persister),
+                    )
                     .await?;
 
                 if let Some(notifier) = notifier {
@@ -237,7 +311,7 @@ impl Topic {
                 }
             } else {
                 let _ = new_subscription
-                    .create_new_dispatcher(options.clone(), &self.dispatch_strategy)
+                    .create_new_dispatcher(options.clone(), &self.dispatch_strategy, None, None)
                     .await?;
             }
 
@@ -264,6 +338,11 @@ impl Topic {
     // should be called if all consumers are disconnected
     pub(crate) async fn unsubscribe(&self, subscription_name: &str) {
         let _ = self.subscriptions.lock().await.remove(subscription_name);
+
+        // the cursor of the removed subscription no longer holds back the retention of the topic
+        if let DispatchStrategy::Reliable(reliable_dispatch) = &self.dispatch_strategy {
+            reliable_dispatch.remove_subscription(subscription_name);
+        }
     }
 
     pub(crate) async fn validate_consumer(
@@ -326,3 +405,10 @@ impl Topic {
         todo!()
     }
 }
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
@@ -1,9 +1,9 @@
 use anyhow::{Context, Result};
 use clap::{Parser, ValueEnum};
-use danube_client::{DanubeClient, SchemaType, SubType};
+use danube_client::{DanubeClient, DeadLetterPolicy, SchemaType, SubType};
 use danube_core::message::MessageID;
 use serde_json::{from_slice, Value};
-use std::{collections::HashMap, str::from_utf8};
+use std::{collections::HashMap, str::from_utf8, time::Duration};
 
 // Print the message to the console only if the message is not too large
 const LARGE_MESSAGE_THRESHOLD: usize = 1024; // 1KB threshold
@@ -43,6 +43,58 @@ pub struct Consume {
 
     #[arg(long, value_enum, help = "The subscription type. Default: Shared")]
     pub sub_type: Option<SubTypeArg>,
+
+    #[arg(
+        long,
+        requires = "max_redeliveries",
+        help = "The topic receiving the messages not acknowledged after max-redeliveries, for reliable topics"
+    )]
+    pub dead_letter_topic: Option<String>,
+
+    #[arg(
+        long,
+        requires = "dead_letter_topic",
+        help = "The redeliveries of an unacknowledged message, before it is moved to the dead letter topic"
+    )]
+    pub max_redeliveries: Option<u32>,
+
+    #[arg(
+        long,
+        help = "The milliseconds to wait for the acknowledgment, before the message is redelivered, for reliable topics. Default: 10000"
+    )]
+    pub ack_timeout_ms: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "max_backoff_ms",
+        help = "The growth of the delay between the redeliveries of an unacknowledged message. Default: 2"
+    )]
+    pub backoff_multiplier: Option<f64>,
+
+    #[arg(
+        long,
+        requires = "backoff_multiplier",
+        help = "The longest delay in milliseconds between the redeliveries of an unacknowledged message. Default: 30000"
+    )]
+    pub max_backoff_ms: Option<u64>,
+
+    #[arg(
+        long,
+        help = "The messages delivered and awaiting acknowledgment, for reliable topics. Default: 1"
+    )]
+    pub max_unacked_messages: Option<u32>,
+
+    #[arg(
+        long,
+        help = "The messages the broker sends ahead of the consumer, granted again as they are received. Default: 100"
+    )]
+    pub receiver_permits: Option<u32>,
+
+    #[arg(
+        long,
+        help = "The priority of the consumer, the lower priorities receive the messages once the higher ones are saturated. 0 is the highest. Default: 0"
+    )]
+    pub priority_level: Option<u32>,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
@@ -50,6 +102,7 @@ pub enum SubTypeArg {
     Exclusive,
     Shared,
     FailOver,
+    KeyShared,
 }
 
 const EXAMPLES_TEXT: &str = r#"
@@ -60,11 +113,32 @@ EXAMPLES:
     # Receive messages from an exclusive subscription
     danube-cli consume -s http://localhost:6650 -m my_exclusive --sub-type exclusive
 
+    # Receive messages as the active or standby consumer of a failover subscription
+    danube-cli consume -s http://localhost:6650 -n consumer_a -m my_failover --sub-type fail-over
+
+    # Receive in order the messages of the keys assigned to this consumer
+    danube-cli consume -s http://localhost:6650 -m my_key_shared --sub-type key-shared
+
     # Receive messages for a custom consumer name
     danube-cli consume -s http://localhost:6650 -n my_consumer -m my_subscription
 
     # Receive messages from a specific topic
     danube-cli consume -s http://localhost:6650 -t my_topic -m my_subscription
+
+    # Move the messages not acknowledged after 5 redeliveries to a dead letter topic
+    danube-cli consume -s http://localhost:6650 -m my_subscription --max-redeliveries 5 --dead-letter-topic /default/my_dlq
+
+    # Redeliver the unacknowledged messages after 2s, then 6s, 18s, up to 60s
+    danube-cli consume -s http://localhost:6650 -m my_subscription --ack-timeout-ms 2000 --backoff-multiplier 3 --max-backoff-ms 60000
+
+    # Keep up to 100 messages in flight, awaiting acknowledgment
+    danube-cli consume -s http://localhost:6650 -m my_subscription --max-unacked-messages 100
+
+    # Let the broker send up to 10 messages ahead of the consumer
+    danube-cli consume -s http://localhost:6650 -m my_subscription --receiver-permits 10
+
+    # Receive the messages only while the consumers of the highest priority are saturated
+    danube-cli consume -s http://localhost:6650 -m my_subscription --priority-level 1
 "#;
 
 pub async fn handle_consume(consume: Consume) -> Result<()> {
@@ -75,13 +149,44 @@ pub async fn handle_consume(consume: Consume) -> Result<()> {
         .build()
         .await?;
 
-    let mut consumer = client
+    let mut consumer_builder = client
         .new_consumer()
         .with_topic(consume.topic.clone())
         .with_consumer_name(consume.consumer)
         .with_subscription(consume.subscription)
-        .with_subscription_type(sub_type)
-        .build();
+        .with_subscription_type(sub_type);
+
+    if let (Some(dead_letter_topic), Some(max_redeliveries)) =
+        (consume.dead_letter_topic, consume.max_redeliveries)
+    {
+        consumer_builder = consumer_builder
+            .with_dead_letter_policy(DeadLetterPolicy::new(max_redeliveries, dead_letter_topic));
+    }
+
+    if let Some(ack_timeout_ms) = consume.ack_timeout_ms {
+        consumer_builder = consumer_builder.with_ack_timeout(Duration::from_millis(ack_timeout_ms));
+    }
+
+    if let (Some(backoff_multiplier), Some(max_backoff_ms)) =
+        (consume.backoff_multiplier, consume.max_backoff_ms)
+    {
+        consumer_builder = consumer_builder
+            .with_redelivery_backoff(backoff_multiplier, Duration::from_millis(max_backoff_ms));
+    }
+
+    if let Some(max_unacked_messages) = consume.max_unacked_messages {
+        consumer_builder = consumer_builder.with_max_unacked_messages(max_unacked_messages);
+    }
+
+    if let Some(receiver_permits) = consume.receiver_permits {
+        consumer_builder = consumer_builder.with_receiver_permits(receiver_permits);
+    }
+
+    if let Some(priority_level) = consume.priority_level {
+        consumer_builder = consumer_builder.with_priority(priority_level);
+    }
+
+    let mut consumer = consumer_builder.build();
 
     // Retrieve schema type and schema definition
     let schema = client.get_schema(consume.topic).await?;
@@ -212,6 +317,7 @@ impl From<SubTypeArg> for SubType {
             SubTypeArg::Exclusive => SubType::Exclusive,
             SubTypeArg::Shared => SubType::Shared,
             SubTypeArg::FailOver => SubType::FailOver,
+            SubTypeArg::KeyShared => SubType::KeyShared,
         }
     }
 }
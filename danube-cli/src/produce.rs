@@ -1,6 +1,9 @@
 use anyhow::Result;
 use clap::{Args, Parser, ValueEnum};
-use danube_client::{ConfigReliableOptions, ConfigRetentionPolicy, DanubeClient, SchemaType};
+use danube_client::{
+    ConfigReliableOptions, ConfigRetentionLimitPolicy, ConfigRetentionPolicy,
+    ConfigSegmentCompression, DanubeClient, SchemaType,
+};
 use std::collections::HashMap;
 use tokio::time::{sleep, Duration};
 
@@ -79,6 +82,26 @@ pub struct ExtendedArgs {
     )]
     pub attributes: Option<HashMap<String, String>>,
 
+    #[arg(
+        long,
+        short = 'k',
+        help = "The message key, compacted topics keep only the latest message of each key."
+    )]
+    pub key: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "key",
+        help = "Delay the delivery of the messages to the consumers, in milliseconds. Requires --reliable."
+    )]
+    pub deliver_after: Option<u64>,
+
+    #[arg(
+        long,
+        help = "The time to live of the messages in milliseconds, the expired messages are not delivered to the consumers."
+    )]
+    pub message_ttl: Option<u64>,
+
     #[arg(long, short = 'p', help = "The number of partitions for the topic.")]
     pub partitions: Option<u32>,
 
@@ -116,7 +139,7 @@ pub struct ReliableArgs {
         long,
         value_enum,
         default_value = "expire",
-        help = "Retention policy: ack (retain until acknowledged) or expire (retain until time expires)"
+        help = "Retention policy: ack (retain until acknowledged), expire (retain until time expires) or compact (latest message per key)"
     )]
     pub retention: Option<RetentionPolicyArg>,
 
@@ -126,12 +149,63 @@ pub struct ReliableArgs {
         help = "Retention period in seconds for reliable delivery (default: 3600)"
     )]
     pub retention_period: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Maximum size of the topic in bytes, for reliable delivery (default: 0, no limit)"
+    )]
+    pub retention_size: u64,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Maximum number of segments of the topic, for reliable delivery (default: 0, no limit)"
+    )]
+    pub max_segments: u64,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value = "drop-oldest",
+        help = "Once the retention size or max segments is reached: drop-oldest (drop the oldest segments) or backpressure (reject the messages)"
+    )]
+    pub retention_limit: Option<RetentionLimitArg>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Compression of the stored segments: none, lz4, zstd or snappy (default: the broker storage compression)"
+    )]
+    pub compression: Option<CompressionArg>,
+
+    #[arg(
+        long,
+        default_value = "0",
+        help = "Maximum age in seconds of the open segment, it is closed even if not full (default: 0, no limit)"
+    )]
+    pub segment_max_age: u64,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
 pub enum RetentionPolicyArg {
     Ack,
     Expire,
+    Compact,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum RetentionLimitArg {
+    DropOldest,
+    Backpressure,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
+pub enum CompressionArg {
+    None,
+    Lz4,
+    Zstd,
+    Snappy,
 }
 
 #[derive(Debug, Clone, Copy, ValueEnum, PartialEq)]
@@ -164,6 +238,21 @@ EXAMPLES:
         --retention expire \
         --retention-period 7200
 
+    # Reliable message delivery, rejecting the messages once the topic holds 1 GB
+    danube-cli produce -s http://localhost:6650 -m "Hello Danube" -c 100 \
+        --reliable \
+        --retention ack \
+        --retention-size 1073741824 \
+        --retention-limit backpressure
+
+    # Reliable message delivery, the messages are delivered to the consumers after 1 minute
+    danube-cli produce -s http://localhost:6650 -m "Reminder" -c 10 \
+        --reliable \
+        --deliver-after 60000
+
+    # Producing messages worthless after 30 seconds, they expire unless delivered within 30 seconds
+    danube-cli produce -s http://localhost:6650 -m "Price update" -c 100 --message-ttl 30000
+
     # Producing with attributes
     danube-cli produce -s http://localhost:6650 -m "Hello Danube" -a "key1:value1,key2:value2"
 "#;
@@ -194,6 +283,10 @@ pub async fn handle_produce(produce: Produce) -> Result<()> {
         producer_builder = producer_builder.with_partitions(partitions as usize)
     }
 
+    if let Some(message_ttl) = produce.extended_args.message_ttl {
+        producer_builder = producer_builder.with_message_ttl(Duration::from_millis(message_ttl))
+    }
+
     if produce.reliable_args.reliable {
         let retention_policy = match produce
             .reliable_args
@@ -202,13 +295,38 @@ pub async fn handle_produce(produce: Produce) -> Result<()> {
         {
             RetentionPolicyArg::Ack => ConfigRetentionPolicy::RetainUntilAck,
             RetentionPolicyArg::Expire => ConfigRetentionPolicy::RetainUntilExpire,
+            RetentionPolicyArg::Compact => ConfigRetentionPolicy::Compact,
+        };
+
+        let retention_limit_policy = match produce
+            .reliable_args
+            .retention_limit
+            .unwrap_or(RetentionLimitArg::DropOldest)
+        {
+            RetentionLimitArg::DropOldest => ConfigRetentionLimitPolicy::DropOldest,
+            RetentionLimitArg::Backpressure => ConfigRetentionLimitPolicy::Backpressure,
+        };
+
+        let compression = match produce.reliable_args.compression {
+            None => ConfigSegmentCompression::BrokerDefault,
+            Some(CompressionArg::None) => ConfigSegmentCompression::Uncompressed,
+            Some(CompressionArg::Lz4) => ConfigSegmentCompression::Lz4,
+            Some(CompressionArg::Zstd) => ConfigSegmentCompression::Zstd,
+            Some(CompressionArg::Snappy) => ConfigSegmentCompression::Snappy,
         };
 
         let reliable_options = ConfigReliableOptions::new(
             produce.reliable_args.segment_size as u64,
             retention_policy,
             produce.reliable_args.retention_period,
-        );
+        )
+        .with_retention_limits(
+            produce.reliable_args.retention_size,
+            produce.reliable_args.max_segments,
+            retention_limit_policy,
+        )
+        .with_compression(compression)
+        .with_segment_max_age(produce.reliable_args.segment_max_age);
 
         producer_builder = producer_builder.with_reliable_dispatch(reliable_options);
     }
@@ -230,7 +348,26 @@ pub async fn handle_produce(produce: Produce) -> Result<()> {
 
     for _ in 0..produce.extended_args.count {
         let cloned_attributes = produce.extended_args.attributes.clone();
-        match producer.send(encoded_data.clone(), cloned_attributes).await {
+        let result = match &produce.extended_args.key {
+            Some(key) => {
+                producer
+                    .send_with_key(key.clone(), encoded_data.clone(), cloned_attributes)
+                    .await
+            }
+            None => match produce.extended_args.deliver_after {
+                Some(deliver_after) => {
+                    producer
+                        .send_delayed(
+                            encoded_data.clone(),
+                            cloned_attributes,
+                            Duration::from_millis(deliver_after),
+                        )
+                        .await
+                }
+                None => producer.send(encoded_data.clone(), cloned_attributes).await,
+            },
+        };
+        match result {
             Ok(message_id) => println!("Message sent successfully with ID: {}", message_id),
             Err(e) => eprintln!("Failed to send message: {}", e),
         }
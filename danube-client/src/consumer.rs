@@ -8,20 +8,27 @@ use danube_core::message::StreamMessage;
 use futures::{future::join_all, StreamExt};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{mpsc, Mutex};
 
+// the flow control permits of the consumers, if not configured
+const DEFAULT_RECEIVER_PERMITS: u32 = 100;
+
 /// Represents the type of subscription
 ///
 /// Variants:
 /// - `Exclusive`: Only one consumer can subscribe to the topic at a time.
 /// - `Shared`: Multiple consumers can subscribe to the topic concurrently.
-/// - `FailOver`: Only one consumer can subscribe to the topic at a time,
-///             multiple can subscribe but waits in standby, if the active consumer disconnect        
+/// - `FailOver`: Multiple consumers can subscribe, the first by consumer name receives the messages,
+///   the others wait in standby and take over in that order if the active consumer disconnects.
+/// - `KeyShared`: Multiple consumers can subscribe to the topic concurrently,
+///   the messages with the same key are delivered to the same consumer, in order.
 #[derive(Debug, Clone)]
 pub enum SubType {
     Exclusive,
     Shared,
     FailOver,
+    KeyShared,
 }
 
 /// Consumer represents a message consumer that subscribes to a topic and receives messages.
@@ -150,21 +157,34 @@ impl Consumer {
     /// - `Ok(mpsc::Receiver<StreamMessage>)` if the receive client is successfully created and ready to receive messages.
     /// - `Err(e)` if the receive client cannot be created or if other issues occur.
     pub async fn receive(&mut self) -> Result<mpsc::Receiver<StreamMessage>> {
-        // Create a channel to send messages to the client
-        let (tx, rx) = mpsc::channel(100); // Buffer size of 100, adjust as needed
+        // The broker sends the messages as the client grants the permits,
+        // granted again as the application receives the messages
+        let permits = self
+            .consumer_options
+            .receiver_permits
+            .unwrap_or(DEFAULT_RECEIVER_PERMITS)
+            .max(1);
+        let replenish_threshold = (permits / 2).max(1);
+
+        // Create a channel to send messages to the client. It holds a single message,
+        // so a message is handed over once the application received the previous one,
+        // and the messages buffered ahead of the application are bounded by the permits
+        let (tx, rx) = mpsc::channel(1);
 
         // Spawn a task for each cloned TopicConsumer
         for (_, consumer) in &self.consumers {
             let tx = tx.clone();
+            let topic_consumer = consumer.clone();
 
             let stream_result = {
                 let mut consumer = consumer.lock().await;
-                consumer.receive().await
+                consumer.receive(permits).await
             };
 
             if let Ok(stream) = stream_result {
                 tokio::spawn(async move {
                     let mut stream = stream;
+                    let mut drained = 0;
                     while let Some(message) = stream.next().await {
                         match message {
                             Ok(stream_message) => {
@@ -173,6 +193,16 @@ impl Consumer {
                                     // if the channel is closed exit the loop
                                     break;
                                 }
+
+                                drained += 1;
+                                if drained >= replenish_threshold {
+                                    let mut topic_consumer = topic_consumer.lock().await;
+                                    if let Err(e) = topic_consumer.send_flow(drained).await {
+                                        eprintln!("Error granting permits: {}", e);
+                                        break;
+                                    }
+                                    drained = 0;
+                                }
                             }
                             Err(e) => {
                                 eprintln!("Error receiving message: {}", e);
@@ -202,6 +232,43 @@ impl Consumer {
         }
         Ok(())
     }
+
+    /// Negatively acknowledges the message, the broker redelivers it right away.
+    ///
+    /// Only the reliable subscriptions redeliver the messages, and the redeliveries count
+    /// toward the `max_redeliveries` of the dead letter policy.
+    pub async fn nack(&mut self, message: &StreamMessage) -> Result<()> {
+        self.send_nack(message, None).await
+    }
+
+    /// Negatively acknowledges the message, the broker redelivers it once `redelivery_delay` elapsed.
+    pub async fn nack_with_delay(
+        &mut self,
+        message: &StreamMessage,
+        redelivery_delay: Duration,
+    ) -> Result<()> {
+        self.send_nack(message, Some(redelivery_delay)).await
+    }
+
+    async fn send_nack(
+        &mut self,
+        message: &StreamMessage,
+        redelivery_delay: Option<Duration>,
+    ) -> Result<()> {
+        let topic_name = message.msg_id.topic_name.clone();
+        if let Some(topic_consumer) = self.consumers.get_mut(&topic_name) {
+            let mut topic_consumer = topic_consumer.lock().await;
+            topic_consumer
+                .send_nack(
+                    message.request_id,
+                    message.msg_id.clone(),
+                    &self.subscription,
+                    redelivery_delay,
+                )
+                .await?;
+        }
+        Ok(())
+    }
 }
 
 /// ConsumerBuilder is a builder for creating a new Consumer instance.
@@ -276,11 +343,100 @@ impl ConsumerBuilder {
     ///   - `SubType::Exclusive`: The consumer exclusively receives all messages for the subscription.
     ///   - `SubType::Shared`: Messages are distributed among multiple consumers sharing the same subscription. Default if not specified.
     ///   - `SubType::FailOver`: Only one consumer receives messages, and if it fails, another consumer takes over.
+    ///   - `SubType::KeyShared`: Messages are distributed among multiple consumers by their key, the messages with the same key go to the same consumer, in order.
     pub fn with_subscription_type(mut self, subscription_type: SubType) -> Self {
         self.subscription_type = Some(subscription_type);
         self
     }
 
+    /// Sets the dead letter policy of the subscription. This field is optional, for the reliable topics.
+    ///
+    /// A message not acknowledged after `max_redeliveries` redeliveries is published to the dead letter topic,
+    /// with the `danube.dlq.original_topic`, `danube.dlq.subscription` and `danube.dlq.failures` attributes,
+    /// and the subscription continues with the next message.
+    ///
+    /// # Parameters
+    ///
+    /// - `dead_letter_policy`: The redeliveries limit and the dead letter topic, served by the same broker.
+    pub fn with_dead_letter_policy(mut self, dead_letter_policy: DeadLetterPolicy) -> Self {
+        self.consumer_options.dead_letter_policy = Some(dead_letter_policy);
+        self
+    }
+
+    /// Sets the ack timeout of the subscription. This field is optional, for the reliable topics.
+    ///
+    /// A message not acknowledged within the `ack_timeout` of its delivery is redelivered.
+    /// The broker default is 10 seconds.
+    ///
+    /// # Parameters
+    ///
+    /// - `ack_timeout`: The time to wait for the acknowledgment, before the first redelivery.
+    pub fn with_ack_timeout(mut self, ack_timeout: Duration) -> Self {
+        self.consumer_options.ack_timeout = Some(ack_timeout);
+        self
+    }
+
+    /// Sets the backoff between the redeliveries of an unacknowledged message. This field is optional, for the reliable topics.
+    ///
+    /// Each redelivery waits `backoff_multiplier` times longer than the previous one, up to `max_backoff`.
+    /// The broker defaults are a multiplier of 2 and a max backoff of 30 seconds.
+    ///
+    /// # Parameters
+    ///
+    /// - `backoff_multiplier`: The growth of the delay between the redeliveries, at least 1.
+    /// - `max_backoff`: The longest delay between the redeliveries, not below the ack timeout.
+    pub fn with_redelivery_backoff(
+        mut self,
+        backoff_multiplier: f64,
+        max_backoff: Duration,
+    ) -> Self {
+        self.consumer_options.backoff_multiplier = Some(backoff_multiplier);
+        self.consumer_options.max_backoff = Some(max_backoff);
+        self
+    }
+
+    /// Sets the in-flight window of the consumer. This field is optional, for the reliable topics.
+    ///
+    /// The broker delivers up to `max_unacked_messages` messages before any acknowledgment,
+    /// the messages are acknowledged individually and only the unacknowledged ones are redelivered.
+    /// The broker default is a single message, for the strict ordering of the deliveries.
+    ///
+    /// # Parameters
+    ///
+    /// - `max_unacked_messages`: The messages delivered and awaiting acknowledgment, at least 1.
+    pub fn with_max_unacked_messages(mut self, max_unacked_messages: u32) -> Self {
+        self.consumer_options.max_unacked_messages = Some(max_unacked_messages);
+        self
+    }
+
+    /// Sets the flow control permits of the consumer. This field is optional.
+    ///
+    /// The broker sends up to `receiver_permits` messages ahead of the application,
+    /// and the consumer grants the permits again as the application receives the messages.
+    /// The default is 100 permits.
+    ///
+    /// # Parameters
+    ///
+    /// - `receiver_permits`: The messages buffered for the application, at least 1.
+    pub fn with_receiver_permits(mut self, receiver_permits: u32) -> Self {
+        self.consumer_options.receiver_permits = Some(receiver_permits);
+        self
+    }
+
+    /// Sets the priority level of the consumer. This field is optional.
+    ///
+    /// The Shared subscriptions deliver the messages to the consumers of the highest priority,
+    /// and to the lower priorities only while the higher ones have no permits left.
+    /// The Failover subscriptions elect the active consumer of the highest priority.
+    ///
+    /// # Parameters
+    ///
+    /// - `priority_level`: The priority of the consumer, 0 is the highest and the default.
+    pub fn with_priority(mut self, priority_level: u32) -> Self {
+        self.consumer_options.priority_level = Some(priority_level);
+        self
+    }
+
     /// Creates a new `Consumer` instance using the settings configured in the `ConsumerBuilder`.
     ///
     /// This method performs validation to ensure that all required fields are set before creating the `Consumer`.  Once validation is successful, it constructs and returns a new `Consumer` instance configured with the specified settings.
@@ -312,4 +468,34 @@ impl ConsumerBuilder {
 pub struct ConsumerOptions {
     // schema used to encode the messages
     pub others: String,
+    // the messages exceeding the redeliveries are moved to the dead letter topic
+    pub dead_letter_policy: Option<DeadLetterPolicy>,
+    // the unacknowledged messages are redelivered after the ack timeout
+    pub ack_timeout: Option<Duration>,
+    // the growth of the delay between the redeliveries
+    pub backoff_multiplier: Option<f64>,
+    // the longest delay between the redeliveries
+    pub max_backoff: Option<Duration>,
+    // the messages delivered and awaiting acknowledgment
+    pub max_unacked_messages: Option<u32>,
+    // the messages the broker sends ahead of the application
+    pub receiver_permits: Option<u32>,
+    // the Shared subscriptions deliver first to the consumers of the highest priority, 0 is the highest
+    pub priority_level: Option<u32>,
+}
+
+/// The messages not acknowledged after `max_redeliveries` redeliveries are moved to the dead letter topic
+#[derive(Debug, Clone)]
+pub struct DeadLetterPolicy {
+    pub max_redeliveries: u32,
+    pub dead_letter_topic: String,
+}
+
+impl DeadLetterPolicy {
+    pub fn new(max_redeliveries: u32, dead_letter_topic: impl Into<String>) -> Self {
+        DeadLetterPolicy {
+            max_redeliveries,
+            dead_letter_topic: dead_letter_topic.into(),
+        }
+    }
 }
@@ -71,6 +71,7 @@ pub(crate) fn error_type_from_i32(value: i32) -> Option<ErrorType> {
         4 => Some(ErrorType::ProducerAlreadyExists),
         5 => Some(ErrorType::SubscribePermissionDenied),
         6 => Some(ErrorType::SubscriptionNotFound),
+        7 => Some(ErrorType::TopicRetentionLimitReached),
         _ => None,
     }
 }
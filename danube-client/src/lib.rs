@@ -15,7 +15,7 @@ pub use producer::{Producer, ProducerBuilder, ProducerOptions};
 mod topic_producer;
 
 mod consumer;
-pub use consumer::{Consumer, ConsumerBuilder, ConsumerOptions, SubType};
+pub use consumer::{Consumer, ConsumerBuilder, ConsumerOptions, DeadLetterPolicy, SubType};
 
 mod topic_consumer;
 
@@ -33,4 +33,7 @@ mod connection_manager;
 mod health_check;
 
 mod reliable_options;
-pub use reliable_options::{ConfigReliableOptions, ConfigRetentionPolicy};
+pub use reliable_options::{
+    ConfigReliableOptions, ConfigRetentionLimitPolicy, ConfigRetentionPolicy,
+    ConfigSegmentCompression,
+};
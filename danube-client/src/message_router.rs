@@ -23,4 +23,13 @@ impl MessageRouter {
 
         next
     }
+
+    // routes all the messages of a key to the same partition, so their order is preserved
+    pub(crate) fn route_key(&self, key: &str) -> usize {
+        // FNV-1a, stable across the client versions and platforms
+        let hash = key.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+        (hash % self.partitions as u64) as usize
+    }
 }
@@ -7,6 +7,7 @@ use crate::{
 use danube_core::dispatch_strategy::ConfigDispatchStrategy;
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
 
 /// Represents a message producer responsible for sending messages to partitioned or non-partitioned topics distributed across message brokers.
@@ -130,22 +131,148 @@ impl Producer {
         data: Vec<u8>,
         attributes: Option<HashMap<String, String>>,
     ) -> Result<u64> {
-        let next_partition = match self.partitions {
+        let next_partition = self.next_partition();
+
+        let producers = self.producers.lock().await;
+
+        let sequence_id = producers[next_partition]
+            .send(data, attributes, None, None, None)
+            .await?;
+
+        Ok(sequence_id)
+    }
+
+    /// Sends a message delivered to the consumers once the delay has elapsed.
+    ///
+    /// The delay is counted by the broker from the message publishing. The delayed delivery requires a reliable topic,
+    /// as the broker persists the message until it is due. Until then the message is held back from the subscriptions.
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: The message payload to be sent.
+    /// - `attributes`: Optional user-defined properties or attributes associated with the message.
+    /// - `deliver_after`: The delay before the delivery of the message to the consumers.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(u64)`: The sequence ID of the sent message if the operation is successful.
+    /// - `Err(e)`: An error if message sending fails, or if the topic is not reliable.
+    pub async fn send_delayed(
+        &self,
+        data: Vec<u8>,
+        attributes: Option<HashMap<String, String>>,
+        deliver_after: Duration,
+    ) -> Result<u64> {
+        let next_partition = self.next_partition();
+
+        let producers = self.producers.lock().await;
+
+        let sequence_id = producers[next_partition]
+            .send(
+                data,
+                attributes,
+                None,
+                None,
+                Some(deliver_after.as_millis() as u64),
+            )
+            .await?;
+
+        Ok(sequence_id)
+    }
+
+    /// Sends a message delivered to the consumers at the scheduled time.
+    ///
+    /// A scheduled time in the past delivers the message right away. As for `send_delayed`, the scheduled delivery requires a reliable topic.
+    ///
+    /// # Parameters
+    ///
+    /// - `data`: The message payload to be sent.
+    /// - `attributes`: Optional user-defined properties or attributes associated with the message.
+    /// - `deliver_at`: The time of the delivery of the message to the consumers.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(u64)`: The sequence ID of the sent message if the operation is successful.
+    /// - `Err(e)`: An error if message sending fails, or if the topic is not reliable.
+    pub async fn send_at(
+        &self,
+        data: Vec<u8>,
+        attributes: Option<HashMap<String, String>>,
+        deliver_at: SystemTime,
+    ) -> Result<u64> {
+        let deliver_at = deliver_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let next_partition = self.next_partition();
+
+        let producers = self.producers.lock().await;
+
+        let sequence_id = producers[next_partition]
+            .send(data, attributes, None, Some(deliver_at), None)
+            .await?;
+
+        Ok(sequence_id)
+    }
+
+    /// Sends a message with a key to the topic associated with this producer.
+    ///
+    /// All the messages with the same key are routed to the same partition.
+    /// On the topics with the `Compact` retention policy, only the latest message of each key is retained.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: The message key.
+    /// - `data`: The message payload to be sent. An empty payload is a tombstone, that deletes the key on compacted topics.
+    /// - `attributes`: Optional user-defined properties or attributes associated with the message.
+    ///
+    /// # Returns
+    ///
+    /// - `Ok(u64)`: The sequence ID of the sent message if the operation is successful.
+    /// - `Err(e)`: An error if message sending fails.
+    pub async fn send_with_key(
+        &self,
+        key: impl Into<String>,
+        data: Vec<u8>,
+        attributes: Option<HashMap<String, String>>,
+    ) -> Result<u64> {
+        let key = key.into();
+        let partition = match self.partitions {
             Some(_) => self
                 .message_router
                 .as_ref()
                 .expect("already initialized")
-                .round_robin(),
+                .route_key(&key),
 
             None => 0,
         };
 
         let producers = self.producers.lock().await;
 
-        let sequence_id = producers[next_partition].send(data, attributes).await?;
+        let sequence_id = producers[partition]
+            .send(data, attributes, Some(key), None, None)
+            .await?;
 
         Ok(sequence_id)
     }
+
+    /// Sends a tombstone for the key, deleting the key from the topics with the `Compact` retention policy.
+    pub async fn send_tombstone(&self, key: impl Into<String>) -> Result<u64> {
+        self.send_with_key(key, Vec::new(), None).await
+    }
+
+    // The partitions of the messages without key are assigned in round robin
+    fn next_partition(&self) -> usize {
+        match self.partitions {
+            Some(_) => self
+                .message_router
+                .as_ref()
+                .expect("already initialized")
+                .round_robin(),
+
+            None => 0,
+        }
+    }
 }
 
 /// A builder for creating a new `Producer` instance.
@@ -245,6 +372,19 @@ impl ProducerBuilder {
         self
     }
 
+    /// Sets the time to live of the messages sent by the producer.
+    ///
+    /// The messages not delivered within their time to live, counted from their publishing, are skipped by the broker
+    /// instead of delivered to the consumers. It overrides the `message_ttl` policy of the topic.
+    ///
+    /// # Parameters
+    ///
+    /// - `message_ttl`: The time to live of each message.
+    pub fn with_message_ttl(mut self, message_ttl: Duration) -> Self {
+        self.producer_options.message_ttl = Some(message_ttl);
+        self
+    }
+
     /// Sets the number of partitions for the topic.
     ///
     /// This method specifies how many partitions the topic should have. Partitions are used to distribute the load of messages across multiple Danube brokers, which can help with parallel processing and scalability.
@@ -300,4 +440,6 @@ impl ProducerBuilder {
 pub struct ProducerOptions {
     // schema used to encode the messages
     pub others: String,
+    // the time to live of the messages, the expired messages are not delivered to the consumers
+    pub message_ttl: Option<Duration>,
 }
@@ -1,10 +1,37 @@
-use danube_core::dispatch_strategy::{ReliableOptions, RetentionPolicy};
+use danube_core::{
+    dispatch_strategy::{ReliableOptions, RetentionLimitPolicy, RetentionPolicy},
+    storage::CompressionCodec,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ConfigRetentionPolicy {
     RetainUntilExpire,
     RetainUntilAck,
+    /// Keeps only the latest message of each message key, see `Producer::send_with_key`
+    Compact,
+}
+
+/// What happens when the topic reaches its retention size or max segments
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum ConfigRetentionLimitPolicy {
+    /// The oldest segments are dropped, even if not yet consumed by all the subscriptions
+    #[default]
+    DropOldest,
+    /// The messages are rejected with `TOPIC_RETENTION_LIMIT_REACHED`, until the subscriptions catch up
+    Backpressure,
+}
+
+/// The compression of the segments stored by the broker
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum ConfigSegmentCompression {
+    /// The compression configured for the broker storage
+    #[default]
+    BrokerDefault,
+    Uncompressed,
+    Lz4,
+    Zstd,
+    Snappy,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -12,6 +39,19 @@ pub struct ConfigReliableOptions {
     pub segment_size: u64,
     pub retention_policy: ConfigRetentionPolicy,
     pub retention_time: u64,
+    /// Maximum size of the topic in bytes, 0 for no limit
+    #[serde(default)]
+    pub retention_size: u64,
+    /// Maximum number of segments of the topic, 0 for no limit
+    #[serde(default)]
+    pub max_segments: u64,
+    #[serde(default)]
+    pub retention_limit_policy: ConfigRetentionLimitPolicy,
+    #[serde(default)]
+    pub compression: ConfigSegmentCompression,
+    /// Maximum age in seconds of the open segment, 0 for no limit
+    #[serde(default)]
+    pub segment_max_age: u64,
 }
 
 impl ConfigReliableOptions {
@@ -24,8 +64,39 @@ impl ConfigReliableOptions {
             segment_size,
             retention_policy,
             retention_time,
+            retention_size: 0,
+            max_segments: 0,
+            retention_limit_policy: ConfigRetentionLimitPolicy::default(),
+            compression: ConfigSegmentCompression::default(),
+            segment_max_age: 0,
         }
     }
+
+    /// Limits the size and the number of segments of the topic, a limit set to 0 is not enforced
+    pub fn with_retention_limits(
+        mut self,
+        retention_size: u64,
+        max_segments: u64,
+        retention_limit_policy: ConfigRetentionLimitPolicy,
+    ) -> Self {
+        self.retention_size = retention_size;
+        self.max_segments = max_segments;
+        self.retention_limit_policy = retention_limit_policy;
+        self
+    }
+
+    /// Sets the compression of the stored segments, the JSON payloads compress well
+    pub fn with_compression(mut self, compression: ConfigSegmentCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Closes the open segment once it is older than `segment_max_age` seconds, even if not full,
+    /// so the messages of a low traffic topic are persisted and expired in due time
+    pub fn with_segment_max_age(mut self, segment_max_age: u64) -> Self {
+        self.segment_max_age = segment_max_age;
+        self
+    }
 }
 
 impl From<ConfigReliableOptions> for ReliableOptions {
@@ -33,12 +104,34 @@ impl From<ConfigReliableOptions> for ReliableOptions {
         let retention_policy = match config.retention_policy {
             ConfigRetentionPolicy::RetainUntilExpire => RetentionPolicy::RetainUntilExpire,
             ConfigRetentionPolicy::RetainUntilAck => RetentionPolicy::RetainUntilAck,
+            ConfigRetentionPolicy::Compact => RetentionPolicy::Compact,
         };
 
-        ReliableOptions {
-            segment_size: config.segment_size as usize,
+        let retention_limit_policy = match config.retention_limit_policy {
+            ConfigRetentionLimitPolicy::DropOldest => RetentionLimitPolicy::DropOldest,
+            ConfigRetentionLimitPolicy::Backpressure => RetentionLimitPolicy::Backpressure,
+        };
+
+        let options = ReliableOptions::new(
+            config.segment_size as usize,
             retention_policy,
-            retention_period: config.retention_time,
+            config.retention_time,
+        )
+        .with_retention_limits(
+            config.retention_size,
+            config.max_segments,
+            retention_limit_policy,
+        )
+        .with_segment_max_age(config.segment_max_age);
+
+        match config.compression {
+            ConfigSegmentCompression::BrokerDefault => options,
+            ConfigSegmentCompression::Uncompressed => {
+                options.with_compression(CompressionCodec::None)
+            }
+            ConfigSegmentCompression::Lz4 => options.with_compression(CompressionCodec::Lz4),
+            ConfigSegmentCompression::Zstd => options.with_compression(CompressionCodec::Zstd),
+            ConfigSegmentCompression::Snappy => options.with_compression(CompressionCodec::Snappy),
         }
     }
 }
@@ -6,7 +6,8 @@ use crate::{
 use danube_core::message::MessageID;
 use danube_core::proto::{
     consumer_service_client::ConsumerServiceClient, AckRequest, AckResponse, ConsumerRequest,
-    ConsumerResponse, ReceiveRequest, StreamMessage,
+    ConsumerResponse, DeadLetterPolicy as ProtoDeadLetterPolicy, FlowRequest, FlowResponse,
+    NackRequest, NackResponse, ReceiveRequest, StreamMessage,
 };
 
 use futures_core::Stream;
@@ -15,6 +16,7 @@ use std::sync::{
     atomic::{AtomicBool, AtomicU64, Ordering},
     Arc,
 };
+use std::time::Duration;
 use tonic::metadata::MetadataValue;
 use tonic::{transport::Uri, Code, Response, Status};
 use tracing::warn;
@@ -115,12 +117,33 @@ impl TopicConsumer {
             }
         }
 
+        let dead_letter_policy = self
+            .consumer_options
+            .dead_letter_policy
+            .as_ref()
+            .map(|policy| ProtoDeadLetterPolicy {
+                max_redeliveries: policy.max_redeliveries,
+                dead_letter_topic: policy.dead_letter_topic.clone(),
+            });
+
         let req = ConsumerRequest {
             request_id: self.request_id.fetch_add(1, Ordering::SeqCst),
             topic_name: self.topic_name.clone(),
             consumer_name: self.consumer_name.clone(),
             subscription: self.subscription.clone(),
             subscription_type: self.subscription_type.clone() as i32,
+            dead_letter_policy,
+            ack_timeout_ms: self
+                .consumer_options
+                .ack_timeout
+                .map(|timeout| timeout.as_millis() as u64),
+            backoff_multiplier: self.consumer_options.backoff_multiplier,
+            max_backoff_ms: self
+                .consumer_options
+                .max_backoff
+                .map(|backoff| backoff.as_millis() as u64),
+            max_unacked_messages: self.consumer_options.max_unacked_messages,
+            priority_level: self.consumer_options.priority_level.unwrap_or_default(),
         };
 
         let mut request = tonic::Request::new(req);
@@ -169,13 +192,15 @@ impl TopicConsumer {
         }
     }
 
-    // receive messages
+    // receive messages, the broker sends up to the permits before the consumer grants more
     pub(crate) async fn receive(
         &mut self,
+        permits: u32,
     ) -> Result<impl Stream<Item = std::result::Result<StreamMessage, Status>>> {
         let receive_request = ReceiveRequest {
             request_id: self.request_id.fetch_add(1, Ordering::SeqCst),
             consumer_id: self.consumer_id.unwrap(),
+            permits,
         };
 
         let mut request = tonic::Request::new(receive_request);
@@ -232,6 +257,70 @@ impl TopicConsumer {
         Ok(response.into_inner())
     }
 
+    pub(crate) async fn send_nack(
+        &mut self,
+        req_id: u64,
+        msg_id: MessageID,
+        subscription_name: &str,
+        redelivery_delay: Option<Duration>,
+    ) -> Result<NackResponse> {
+        let nack_request = NackRequest {
+            request_id: req_id,
+            msg_id: Some(msg_id.into()),
+            subscription_name: subscription_name.to_string(),
+            redelivery_delay_ms: redelivery_delay.map(|delay| delay.as_millis() as u64),
+        };
+
+        let mut request = tonic::Request::new(nack_request);
+
+        if let Some(api_key) = &self.client.cnx_manager.connection_options.api_key {
+            self.insert_auth_token(&mut request, &self.client.uri, api_key)
+                .await?;
+        }
+
+        let stream_client = self.stream_client.as_mut().ok_or_else(|| {
+            DanubeError::Unrecoverable("SendNack: Stream client is not initialized".to_string())
+        })?;
+
+        let response = match stream_client.nack(request).await {
+            Ok(response) => response,
+            Err(status) => {
+                let decoded_message = decode_error_details(&status);
+                return Err(DanubeError::FromStatus(status, decoded_message));
+            }
+        };
+        Ok(response.into_inner())
+    }
+
+    // grants the broker permits to send more messages
+    pub(crate) async fn send_flow(&mut self, permits: u32) -> Result<FlowResponse> {
+        let flow_request = FlowRequest {
+            request_id: self.request_id.fetch_add(1, Ordering::SeqCst),
+            consumer_id: self.consumer_id.unwrap(),
+            permits,
+        };
+
+        let mut request = tonic::Request::new(flow_request);
+
+        if let Some(api_key) = &self.client.cnx_manager.connection_options.api_key {
+            self.insert_auth_token(&mut request, &self.client.uri, api_key)
+                .await?;
+        }
+
+        let stream_client = self.stream_client.as_mut().ok_or_else(|| {
+            DanubeError::Unrecoverable("SendFlow: Stream client is not initialized".to_string())
+        })?;
+
+        let response = match stream_client.flow(request).await {
+            Ok(response) => response,
+            Err(status) => {
+                let decoded_message = decode_error_details(&status);
+                return Err(DanubeError::FromStatus(status, decoded_message));
+            }
+        };
+        Ok(response.into_inner())
+    }
+
     pub(crate) fn get_topic_name(&self) -> &str {
         &self.topic_name
     }
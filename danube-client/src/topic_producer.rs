@@ -189,6 +189,9 @@ impl TopicProducer {
         &self,
         data: Vec<u8>,
         attributes: Option<HashMap<String, String>>,
+        key: Option<String>,
+        deliver_at: Option<u64>,
+        deliver_after: Option<u64>,
     ) -> Result<u64> {
         let publish_time = SystemTime::now()
             .duration_since(UNIX_EPOCH)
@@ -223,6 +226,14 @@ impl TopicProducer {
             producer_name: self.producer_name.clone(),
             subscription_name: None,
             attributes: attr,
+            key,
+            deliver_at,
+            deliver_after,
+            ttl: self
+                .producer_options
+                .message_ttl
+                .map(|ttl| ttl.as_millis() as u64),
+            expire_at: None,
         };
 
         let req: ProtoStreamMessage = send_message.into();
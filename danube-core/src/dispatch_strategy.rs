@@ -1,4 +1,5 @@
 use crate::proto::{ReliableOptions as ProtoReliableOptions, TopicDispatchStrategy};
+use crate::storage::CompressionCodec;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
 
@@ -16,10 +17,26 @@ pub enum ConfigDispatchStrategy {
 pub struct ReliableOptions {
     /// Segment size in bytes.
     pub segment_size: usize,
-    /// Retention policy for messages in the topic.Could be retain until ack or expire.
+    /// Retention policy for messages in the topic.Could be retain until ack, expire or compact.
     pub retention_policy: RetentionPolicy,
     /// Retention period in seconds.
+    /// For compacted topics, it is how long the tombstones are kept before being removed.
     pub retention_period: u64,
+    /// Maximum size in bytes of the topic segments, 0 for no limit.
+    #[serde(default)]
+    pub retention_size: u64,
+    /// Maximum number of segments of the topic, 0 for no limit.
+    #[serde(default)]
+    pub max_segments: u64,
+    /// What happens when the topic reaches the retention size or the max segments.
+    #[serde(default)]
+    pub retention_limit_policy: RetentionLimitPolicy,
+    /// Compression of the stored segments, None for the compression configured for the broker storage.
+    #[serde(default)]
+    pub compression: Option<CompressionCodec>,
+    /// Maximum age in seconds of the open segment, it is closed even if not full, 0 for no limit.
+    #[serde(default)]
+    pub segment_max_age: u64,
 }
 
 impl ReliableOptions {
@@ -32,8 +49,38 @@ impl ReliableOptions {
             segment_size,
             retention_policy,
             retention_period,
+            retention_size: 0,
+            max_segments: 0,
+            retention_limit_policy: RetentionLimitPolicy::default(),
+            compression: None,
+            segment_max_age: 0,
         }
     }
+
+    /// Bounds the topic by size and by number of segments, a limit set to 0 is not enforced.
+    pub fn with_retention_limits(
+        mut self,
+        retention_size: u64,
+        max_segments: u64,
+        retention_limit_policy: RetentionLimitPolicy,
+    ) -> Self {
+        self.retention_size = retention_size;
+        self.max_segments = max_segments;
+        self.retention_limit_policy = retention_limit_policy;
+        self
+    }
+
+    /// Stores the topic segments with the codec, instead of the broker storage compression.
+    pub fn with_compression(mut self, compression: CompressionCodec) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Closes the open segment once it is older than `segment_max_age` seconds, 0 for no limit.
+    pub fn with_segment_max_age(mut self, segment_max_age: u64) -> Self {
+        self.segment_max_age = segment_max_age;
+        self
+    }
 }
 
 /// Retention policy for messages in the topic.
@@ -41,6 +88,19 @@ impl ReliableOptions {
 pub enum RetentionPolicy {
     RetainUntilAck,
     RetainUntilExpire,
+    /// Keeps only the latest message of each message key, in the closed segments.
+    /// A message with a key and an empty payload (tombstone) deletes the key.
+    Compact,
+}
+
+/// The behaviour of a reliable topic that reached its retention size or max segments.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum RetentionLimitPolicy {
+    /// Drops the oldest segments, the lagging subscriptions continue with the first retained segment.
+    #[default]
+    DropOldest,
+    /// Rejects the published messages until the subscriptions catch up.
+    Backpressure,
 }
 
 impl Default for ConfigDispatchStrategy {
@@ -57,6 +117,7 @@ impl Display for ConfigDispatchStrategy {
                 let policy = match opts.retention_policy {
                     RetentionPolicy::RetainUntilAck => "Retain Until Ack",
                     RetentionPolicy::RetainUntilExpire => "Retain Until Expire",
+                    RetentionPolicy::Compact => "Compact",
                 };
                 write!(
                     f,
@@ -78,13 +139,32 @@ impl From<TopicDispatchStrategy> for ConfigDispatchStrategy {
                     let retention_policy = match reliable_opts.retention_policy {
                         0 => RetentionPolicy::RetainUntilAck,
                         1 => RetentionPolicy::RetainUntilExpire,
+                        2 => RetentionPolicy::Compact,
                         _ => RetentionPolicy::RetainUntilAck,
                     };
 
+                    let retention_limit_policy = match reliable_opts.retention_limit_policy {
+                        1 => RetentionLimitPolicy::Backpressure,
+                        _ => RetentionLimitPolicy::DropOldest,
+                    };
+
+                    let compression = match reliable_opts.compression {
+                        1 => Some(CompressionCodec::None),
+                        2 => Some(CompressionCodec::Lz4),
+                        3 => Some(CompressionCodec::Zstd),
+                        4 => Some(CompressionCodec::Snappy),
+                        _ => None,
+                    };
+
                     ConfigDispatchStrategy::Reliable(ReliableOptions {
                         segment_size: reliable_opts.segment_size as usize,
                         retention_policy,
                         retention_period: reliable_opts.retention_period,
+                        retention_size: reliable_opts.retention_size,
+                        max_segments: reliable_opts.max_segments,
+                        retention_limit_policy,
+                        compression,
+                        segment_max_age: reliable_opts.segment_max_age,
                     })
                 } else {
                     ConfigDispatchStrategy::NonReliable
@@ -107,6 +187,20 @@ impl From<ConfigDispatchStrategy> for TopicDispatchStrategy {
                 let retention_policy = match opts.retention_policy {
                     RetentionPolicy::RetainUntilAck => 0,
                     RetentionPolicy::RetainUntilExpire => 1,
+                    RetentionPolicy::Compact => 2,
+                };
+
+                let retention_limit_policy = match opts.retention_limit_policy {
+                    RetentionLimitPolicy::DropOldest => 0,
+                    RetentionLimitPolicy::Backpressure => 1,
+                };
+
+                let compression = match opts.compression {
+                    None => 0,
+                    Some(CompressionCodec::None) => 1,
+                    Some(CompressionCodec::Lz4) => 2,
+                    Some(CompressionCodec::Zstd) => 3,
+                    Some(CompressionCodec::Snappy) => 4,
                 };
 
                 TopicDispatchStrategy {
@@ -115,6 +209,11 @@ impl From<ConfigDispatchStrategy> for TopicDispatchStrategy {
                         segment_size: opts.segment_size as u64,
                         retention_policy,
                         retention_period: opts.retention_period,
+                        retention_size: opts.retention_size,
+                        max_segments: opts.max_segments,
+                        retention_limit_policy,
+                        compression,
+                        segment_max_age: opts.segment_max_age,
                     }),
                 }
             }
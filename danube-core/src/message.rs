@@ -52,12 +52,42 @@ pub struct StreamMessage {
     pub subscription_name: Option<String>,
     // User-defined properties/attributes
     pub attributes: HashMap<String, String>,
+    // Optional message key, the compacted topics keep only the latest message of each key
+    #[serde(default)]
+    pub key: Option<String>,
+    // Optional delivery time of the message, in milliseconds since the epoch
+    #[serde(default)]
+    pub deliver_at: Option<u64>,
+    // Optional delivery delay of the message, in milliseconds after its publishing,
+    // resolved by the broker into the delivery time
+    #[serde(default)]
+    pub deliver_after: Option<u64>,
+    // Optional time to live of the message, in milliseconds after its publishing,
+    // resolved by the broker into the expiration time
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    // Optional expiration time of the message, in milliseconds since the epoch, set by the broker,
+    // the expired messages are skipped instead of delivered
+    #[serde(default)]
+    pub expire_at: Option<u64>,
 }
 
 impl StreamMessage {
     pub fn size(&self) -> usize {
         self.payload.len()
     }
+    // A keyed message without payload marks the deletion of the key on compacted topics
+    pub fn is_tombstone(&self) -> bool {
+        self.key.is_some() && self.payload.is_empty()
+    }
+    // A message held back from the consumers until its delivery time
+    pub fn is_delayed(&self) -> bool {
+        self.deliver_at.is_some() || self.deliver_after.is_some()
+    }
+    // The message is no longer delivered past its expiration time
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expire_at.is_some_and(|expire_at| expire_at <= now)
+    }
     pub fn add_subscription_name(&mut self, subscription_name: &String) {
         self.subscription_name = Some(subscription_name.into());
     }
@@ -88,6 +118,11 @@ impl From<ProtoStreamMessage> for StreamMessage {
             producer_name: proto_stream_msg.producer_name,
             subscription_name: Some(proto_stream_msg.subscription_name),
             attributes: proto_stream_msg.attributes,
+            key: proto_stream_msg.key,
+            deliver_at: proto_stream_msg.deliver_at,
+            deliver_after: proto_stream_msg.deliver_after,
+            ttl: proto_stream_msg.ttl,
+            expire_at: proto_stream_msg.expire_at,
         }
     }
 }
@@ -114,6 +149,11 @@ impl From<StreamMessage> for ProtoStreamMessage {
             producer_name: stream_msg.producer_name,
             subscription_name: stream_msg.subscription_name.unwrap_or_default(),
             attributes: stream_msg.attributes,
+            key: stream_msg.key,
+            deliver_at: stream_msg.deliver_at,
+            deliver_after: stream_msg.deliver_after,
+            ttl: stream_msg.ttl,
+            expire_at: stream_msg.expire_at,
         }
     }
 }
@@ -4,12 +4,27 @@ pub struct ReliableOptions {
     /// in MB
     #[prost(uint64, tag = "1")]
     pub segment_size: u64,
-    /// RetainUntilAck or RetainUntilExpire
+    /// RetainUntilAck, RetainUntilExpire or Compact
     #[prost(enumeration = "RetentionPolicy", tag = "4")]
     pub retention_policy: i32,
     /// in seconds
     #[prost(uint64, tag = "5")]
     pub retention_period: u64,
+    /// in bytes, 0 for no limit
+    #[prost(uint64, tag = "6")]
+    pub retention_size: u64,
+    /// 0 for no limit
+    #[prost(uint64, tag = "7")]
+    pub max_segments: u64,
+    /// DropOldest or Backpressure
+    #[prost(enumeration = "RetentionLimitPolicy", tag = "8")]
+    pub retention_limit_policy: i32,
+    /// codec of the stored segments
+    #[prost(enumeration = "SegmentCompression", tag = "9")]
+    pub compression: i32,
+    /// in seconds, the open segment is closed once older, 0 for no limit
+    #[prost(uint64, tag = "10")]
+    pub segment_max_age: u64,
 }
 /// Message representing topic retention strategy
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
@@ -66,6 +81,26 @@ pub struct ConsumerRequest {
     pub subscription: ::prost::alloc::string::String,
     #[prost(enumeration = "consumer_request::SubscriptionType", tag = "5")]
     pub subscription_type: i32,
+    /// optional, for the subscriptions of the reliable topics
+    #[prost(message, optional, tag = "6")]
+    pub dead_letter_policy: ::core::option::Option<DeadLetterPolicy>,
+    /// The redelivery schedule of the unacknowledged messages, for the subscriptions of the reliable topics
+    ///
+    /// the first redelivery, after the delivery of the message
+    #[prost(uint64, optional, tag = "7")]
+    pub ack_timeout_ms: ::core::option::Option<u64>,
+    /// growth of the delay between the following redeliveries
+    #[prost(double, optional, tag = "8")]
+    pub backoff_multiplier: ::core::option::Option<f64>,
+    /// the longest delay between the redeliveries
+    #[prost(uint64, optional, tag = "9")]
+    pub max_backoff_ms: ::core::option::Option<u64>,
+    /// The messages delivered to a consumer and awaiting acknowledgment, for the subscriptions of the reliable topics
+    #[prost(uint32, optional, tag = "10")]
+    pub max_unacked_messages: ::core::option::Option<u32>,
+    /// The Shared subscriptions deliver first to the consumers of the highest priority, 0 is the highest
+    #[prost(uint32, tag = "11")]
+    pub priority_level: u32,
 }
 /// Nested message and enum types in `ConsumerRequest`.
 pub mod consumer_request {
@@ -88,6 +123,8 @@ pub mod consumer_request {
         Shared = 1,
         /// Only one consumer (the active consumer) receives messages at any given time.
         Failover = 2,
+        /// Multiple consumers, the messages with the same key go to the same consumer, in order.
+        KeyShared = 3,
     }
     impl SubscriptionType {
         /// String value of the enum field names used in the ProtoBuf definition.
@@ -99,6 +136,7 @@ pub mod consumer_request {
                 Self::Exclusive => "Exclusive",
                 Self::Shared => "Shared",
                 Self::Failover => "Failover",
+                Self::KeyShared => "Key_Shared",
             }
         }
         /// Creates an enum from field names used in the ProtoBuf definition.
@@ -107,11 +145,21 @@ pub mod consumer_request {
                 "Exclusive" => Some(Self::Exclusive),
                 "Shared" => Some(Self::Shared),
                 "Failover" => Some(Self::Failover),
+                "Key_Shared" => Some(Self::KeyShared),
                 _ => None,
             }
         }
     }
 }
+/// The messages not acknowledged after max_redeliveries redeliveries
+/// are published to the dead letter topic, and the subscription moves past them
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct DeadLetterPolicy {
+    #[prost(uint32, tag = "1")]
+    pub max_redeliveries: u32,
+    #[prost(string, tag = "2")]
+    pub dead_letter_topic: ::prost::alloc::string::String,
+}
 /// Create Consumer response
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct ConsumerResponse {
@@ -128,6 +176,9 @@ pub struct ReceiveRequest {
     pub request_id: u64,
     #[prost(uint64, tag = "2")]
     pub consumer_id: u64,
+    /// the initial permits of the consumer flow control, no flow control if 0
+    #[prost(uint32, tag = "3")]
+    pub permits: u32,
 }
 /// The message that is sent by producer to topic and then to consumer
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -156,6 +207,21 @@ pub struct StreamMessage {
         ::prost::alloc::string::String,
         ::prost::alloc::string::String,
     >,
+    /// Optional message key, the compacted topics keep only the latest message of each key
+    #[prost(string, optional, tag = "8")]
+    pub key: ::core::option::Option<::prost::alloc::string::String>,
+    /// Optional delivery time of the message, in milliseconds since the epoch
+    #[prost(uint64, optional, tag = "9")]
+    pub deliver_at: ::core::option::Option<u64>,
+    /// Optional delivery delay of the message, in milliseconds after its publishing
+    #[prost(uint64, optional, tag = "10")]
+    pub deliver_after: ::core::option::Option<u64>,
+    /// Optional time to live of the message, in milliseconds after its publishing
+    #[prost(uint64, optional, tag = "11")]
+    pub ttl: ::core::option::Option<u64>,
+    /// Optional expiration time of the message, in milliseconds since the epoch, set by the broker
+    #[prost(uint64, optional, tag = "12")]
+    pub expire_at: ::core::option::Option<u64>,
 }
 /// Unique ID of the message
 #[derive(Clone, PartialEq, ::prost::Message)]
@@ -193,6 +259,40 @@ pub struct AckResponse {
     pub request_id: u64,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
+pub struct NackRequest {
+    #[prost(uint64, tag = "1")]
+    pub request_id: u64,
+    /// Identifies the message, associated with a unique topic, subscription and the broker
+    #[prost(message, optional, tag = "2")]
+    pub msg_id: ::core::option::Option<MsgId>,
+    /// Subscription name the consumer is subscribed to
+    #[prost(string, tag = "3")]
+    pub subscription_name: ::prost::alloc::string::String,
+    /// Delay before the message is redelivered, redelivered right away if not set
+    #[prost(uint64, optional, tag = "4")]
+    pub redelivery_delay_ms: ::core::option::Option<u64>,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct NackResponse {
+    #[prost(uint64, tag = "1")]
+    pub request_id: u64,
+}
+/// The consumer is ready to receive more messages, as the application drained the received ones
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct FlowRequest {
+    #[prost(uint64, tag = "1")]
+    pub request_id: u64,
+    #[prost(uint64, tag = "2")]
+    pub consumer_id: u64,
+    #[prost(uint32, tag = "3")]
+    pub permits: u32,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct FlowResponse {
+    #[prost(uint64, tag = "1")]
+    pub request_id: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
 pub struct TopicLookupRequest {
     #[prost(uint64, tag = "1")]
     pub request_id: u64,
@@ -497,6 +597,7 @@ impl DispatchStrategy {
 pub enum RetentionPolicy {
     RetainUntilAck = 0,
     RetainUntilExpire = 1,
+    Compact = 2,
 }
 impl RetentionPolicy {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -507,6 +608,7 @@ impl RetentionPolicy {
         match self {
             Self::RetainUntilAck => "RetainUntilAck",
             Self::RetainUntilExpire => "RetainUntilExpire",
+            Self::Compact => "Compact",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -514,6 +616,73 @@ impl RetentionPolicy {
         match value {
             "RetainUntilAck" => Some(Self::RetainUntilAck),
             "RetainUntilExpire" => Some(Self::RetainUntilExpire),
+            "Compact" => Some(Self::Compact),
+            _ => None,
+        }
+    }
+}
+/// The compression of the stored segments of a reliable topic
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum SegmentCompression {
+    /// the compression configured for the broker storage
+    BrokerDefault = 0,
+    Uncompressed = 1,
+    Lz4 = 2,
+    Zstd = 3,
+    Snappy = 4,
+}
+impl SegmentCompression {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::BrokerDefault => "BrokerDefault",
+            Self::Uncompressed => "Uncompressed",
+            Self::Lz4 => "Lz4",
+            Self::Zstd => "Zstd",
+            Self::Snappy => "Snappy",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "BrokerDefault" => Some(Self::BrokerDefault),
+            "Uncompressed" => Some(Self::Uncompressed),
+            "Lz4" => Some(Self::Lz4),
+            "Zstd" => Some(Self::Zstd),
+            "Snappy" => Some(Self::Snappy),
+            _ => None,
+        }
+    }
+}
+/// What happens when a reliable topic reaches its retention size or max segments
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum RetentionLimitPolicy {
+    /// the oldest segments are dropped, the lagging subscriptions skip them
+    DropOldest = 0,
+    /// the messages are rejected until the subscriptions catch up
+    Backpressure = 1,
+}
+impl RetentionLimitPolicy {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::DropOldest => "DropOldest",
+            Self::Backpressure => "Backpressure",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "DropOldest" => Some(Self::DropOldest),
+            "Backpressure" => Some(Self::Backpressure),
             _ => None,
         }
     }
@@ -532,6 +701,8 @@ pub enum ErrorType {
     SubscribePermissionDenied = 5,
     /// Subscription not found
     SubscriptionNotFound = 6,
+    /// The reliable topic is full, retry once the subscriptions catch up
+    TopicRetentionLimitReached = 7,
 }
 impl ErrorType {
     /// String value of the enum field names used in the ProtoBuf definition.
@@ -547,6 +718,7 @@ impl ErrorType {
             Self::ProducerAlreadyExists => "PRODUCER_ALREADY_EXISTS",
             Self::SubscribePermissionDenied => "SUBSCRIBE_PERMISSION_DENIED",
             Self::SubscriptionNotFound => "SUBSCRIPTION_NOT_FOUND",
+            Self::TopicRetentionLimitReached => "TOPIC_RETENTION_LIMIT_REACHED",
         }
     }
     /// Creates an enum from field names used in the ProtoBuf definition.
@@ -559,6 +731,7 @@ impl ErrorType {
             "PRODUCER_ALREADY_EXISTS" => Some(Self::ProducerAlreadyExists),
             "SUBSCRIBE_PERMISSION_DENIED" => Some(Self::SubscribePermissionDenied),
             "SUBSCRIPTION_NOT_FOUND" => Some(Self::SubscriptionNotFound),
+            "TOPIC_RETENTION_LIMIT_REACHED" => Some(Self::TopicRetentionLimitReached),
             _ => None,
         }
     }
@@ -869,6 +1042,50 @@ pub mod consumer_service_client {
                 .insert(GrpcMethod::new("danube.ConsumerService", "Ack"));
             self.inner.unary(req, path, codec).await
         }
+        /// Negatively acknowledges a message, the broker redelivers it after the redelivery delay
+        pub async fn nack(
+            &mut self,
+            request: impl tonic::IntoRequest<super::NackRequest>,
+        ) -> std::result::Result<tonic::Response<super::NackResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/danube.ConsumerService/Nack",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("danube.ConsumerService", "Nack"));
+            self.inner.unary(req, path, codec).await
+        }
+        /// Grants the broker permits to send more messages to the Consumer
+        pub async fn flow(
+            &mut self,
+            request: impl tonic::IntoRequest<super::FlowRequest>,
+        ) -> std::result::Result<tonic::Response<super::FlowResponse>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/danube.ConsumerService/Flow",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("danube.ConsumerService", "Flow"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated client implementations.
@@ -1541,6 +1758,16 @@ pub mod consumer_service_server {
             &self,
             request: tonic::Request<super::AckRequest>,
         ) -> std::result::Result<tonic::Response<super::AckResponse>, tonic::Status>;
+        /// Negatively acknowledges a message, the broker redelivers it after the redelivery delay
+        async fn nack(
+            &self,
+            request: tonic::Request<super::NackRequest>,
+        ) -> std::result::Result<tonic::Response<super::NackResponse>, tonic::Status>;
+        /// Grants the broker permits to send more messages to the Consumer
+        async fn flow(
+            &self,
+            request: tonic::Request<super::FlowRequest>,
+        ) -> std::result::Result<tonic::Response<super::FlowResponse>, tonic::Status>;
     }
     #[derive(Debug)]
     pub struct ConsumerServiceServer<T> {
@@ -1754,6 +1981,94 @@ pub mod consumer_service_server {
                     };
                     Box::pin(fut)
                 }
+                "/danube.ConsumerService/Nack" => {
+                    #[allow(non_camel_case_types)]
+                    struct NackSvc<T: ConsumerService>(pub Arc<T>);
+                    impl<
+                        T: ConsumerService,
+                    > tonic::server::UnaryService<super::NackRequest> for NackSvc<T> {
+                        type Response = super::NackResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::NackRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ConsumerService>::nack(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = NackSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/danube.ConsumerService/Flow" => {
+                    #[allow(non_camel_case_types)]
+                    struct FlowSvc<T: ConsumerService>(pub Arc<T>);
+                    impl<
+                        T: ConsumerService,
+                    > tonic::server::UnaryService<super::FlowRequest> for FlowSvc<T> {
+                        type Response = super::FlowResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::FlowRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ConsumerService>::flow(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = FlowSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(empty_body());
@@ -18,6 +18,9 @@ pub struct SegmentChunk {
     pub chunk_index: u64,
     #[prost(bool, tag = "5")]
     pub is_last_chunk: bool,
+    /// codec of the serialized segment, the segment is stored with it
+    #[prost(enumeration = "Compression", tag = "6")]
+    pub compression: i32,
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct PutSegmentResponse {
@@ -33,6 +36,88 @@ pub struct RemoveSegmentRequest {
 }
 #[derive(Clone, Copy, PartialEq, ::prost::Message)]
 pub struct RemoveSegmentResponse {}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListSegmentsRequest {
+    #[prost(string, tag = "1")]
+    pub topic_name: ::prost::alloc::string::String,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ListSegmentsResponse {
+    /// in ascending order
+    #[prost(uint64, repeated, tag = "1")]
+    pub segment_ids: ::prost::alloc::vec::Vec<u64>,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct GetSegmentInfoRequest {
+    #[prost(string, tag = "1")]
+    pub topic_name: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub segment_id: u64,
+}
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct SegmentInfo {
+    #[prost(uint64, tag = "1")]
+    pub segment_id: u64,
+    #[prost(uint64, tag = "2")]
+    pub close_time: u64,
+    /// in bytes
+    #[prost(uint64, tag = "3")]
+    pub current_size: u64,
+    #[prost(uint64, tag = "4")]
+    pub message_count: u64,
+    #[prost(uint64, tag = "5")]
+    pub next_offset: u64,
+}
+/// Reads the messages with the offsets in [start_offset, end_offset)
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct ReadMessagesRequest {
+    #[prost(string, tag = "1")]
+    pub topic_name: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub segment_id: u64,
+    #[prost(uint64, tag = "3")]
+    pub start_offset: u64,
+    #[prost(uint64, tag = "4")]
+    pub end_offset: u64,
+}
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessagesChunk {
+    /// bincode serialized messages, in offset order
+    #[prost(bytes = "vec", tag = "1")]
+    pub messages_data: ::prost::alloc::vec::Vec<u8>,
+}
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum Compression {
+    None = 0,
+    Lz4 = 1,
+    Zstd = 2,
+    Snappy = 3,
+}
+impl Compression {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::None => "NONE",
+            Self::Lz4 => "LZ4",
+            Self::Zstd => "ZSTD",
+            Self::Snappy => "SNAPPY",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "NONE" => Some(Self::None),
+            "LZ4" => Some(Self::Lz4),
+            "ZSTD" => Some(Self::Zstd),
+            "SNAPPY" => Some(Self::Snappy),
+            _ => None,
+        }
+    }
+}
 /// Generated client implementations.
 pub mod managed_storage_client {
     #![allow(
@@ -198,6 +283,81 @@ pub mod managed_storage_client {
                 );
             self.inner.unary(req, path, codec).await
         }
+        pub async fn list_segments(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ListSegmentsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListSegmentsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/managed_storage.ManagedStorage/ListSegments",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("managed_storage.ManagedStorage", "ListSegments"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn get_segment_info(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetSegmentInfoRequest>,
+        ) -> std::result::Result<tonic::Response<super::SegmentInfo>, tonic::Status> {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/managed_storage.ManagedStorage/GetSegmentInfo",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("managed_storage.ManagedStorage", "GetSegmentInfo"),
+                );
+            self.inner.unary(req, path, codec).await
+        }
+        pub async fn read_messages(
+            &mut self,
+            request: impl tonic::IntoRequest<super::ReadMessagesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<tonic::codec::Streaming<super::MessagesChunk>>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic::codec::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/managed_storage.ManagedStorage/ReadMessages",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(
+                    GrpcMethod::new("managed_storage.ManagedStorage", "ReadMessages"),
+                );
+            self.inner.server_streaming(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -237,6 +397,30 @@ pub mod managed_storage_server {
             tonic::Response<super::RemoveSegmentResponse>,
             tonic::Status,
         >;
+        async fn list_segments(
+            &self,
+            request: tonic::Request<super::ListSegmentsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::ListSegmentsResponse>,
+            tonic::Status,
+        >;
+        async fn get_segment_info(
+            &self,
+            request: tonic::Request<super::GetSegmentInfoRequest>,
+        ) -> std::result::Result<tonic::Response<super::SegmentInfo>, tonic::Status>;
+        /// Server streaming response type for the ReadMessages method.
+        type ReadMessagesStream: tonic::codegen::tokio_stream::Stream<
+                Item = std::result::Result<super::MessagesChunk, tonic::Status>,
+            >
+            + std::marker::Send
+            + 'static;
+        async fn read_messages(
+            &self,
+            request: tonic::Request<super::ReadMessagesRequest>,
+        ) -> std::result::Result<
+            tonic::Response<Self::ReadMessagesStream>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
     pub struct ManagedStorageServer<T> {
@@ -452,6 +636,143 @@ pub mod managed_storage_server {
                     };
                     Box::pin(fut)
                 }
+                "/managed_storage.ManagedStorage/ListSegments" => {
+                    #[allow(non_camel_case_types)]
+                    struct ListSegmentsSvc<T: ManagedStorage>(pub Arc<T>);
+                    impl<
+                        T: ManagedStorage,
+                    > tonic::server::UnaryService<super::ListSegmentsRequest>
+                    for ListSegmentsSvc<T> {
+                        type Response = super::ListSegmentsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ListSegmentsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ManagedStorage>::list_segments(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ListSegmentsSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/managed_storage.ManagedStorage/GetSegmentInfo" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetSegmentInfoSvc<T: ManagedStorage>(pub Arc<T>);
+                    impl<
+                        T: ManagedStorage,
+                    > tonic::server::UnaryService<super::GetSegmentInfoRequest>
+                    for GetSegmentInfoSvc<T> {
+                        type Response = super::SegmentInfo;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetSegmentInfoRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ManagedStorage>::get_segment_info(&inner, request)
+                                    .await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetSegmentInfoSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
+                "/managed_storage.ManagedStorage/ReadMessages" => {
+                    #[allow(non_camel_case_types)]
+                    struct ReadMessagesSvc<T: ManagedStorage>(pub Arc<T>);
+                    impl<
+                        T: ManagedStorage,
+                    > tonic::server::ServerStreamingService<super::ReadMessagesRequest>
+                    for ReadMessagesSvc<T> {
+                        type Response = super::MessagesChunk;
+                        type ResponseStream = T::ReadMessagesStream;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::ResponseStream>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::ReadMessagesRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as ManagedStorage>::read_messages(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = ReadMessagesSvc(inner);
+                        let codec = tonic::codec::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.server_streaming(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(empty_body());
@@ -2,6 +2,7 @@ use crate::message::StreamMessage;
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::RwLock;
@@ -19,7 +20,79 @@ pub trait StorageBackend: Send + Sync + std::fmt::Debug + 'static {
         id: usize,
         segment: Arc<RwLock<Segment>>,
     ) -> Result<(), StorageBackendError>;
+    // Stores the segment compressed with the codec
+    // The backends without compression support store the segment as with put_segment
+    async fn put_segment_with_codec(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+        _codec: CompressionCodec,
+    ) -> Result<(), StorageBackendError> {
+        self.put_segment(topic_name, id, segment).await
+    }
     async fn remove_segment(&self, topic_name: &str, id: usize) -> Result<(), StorageBackendError>;
+    // Lists the ids of the segments stored for the topic, in ascending order
+    async fn list_segments(&self, topic_name: &str) -> Result<Vec<usize>, StorageBackendError>;
+    // Returns the metadata of the segment, None if the segment doesn't exist
+    // The backends able to read it without loading the whole segment should override it
+    async fn segment_info(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<SegmentInfo>, StorageBackendError> {
+        let segment = match self.get_segment(topic_name, id).await? {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let segment = segment.read().await;
+        Ok(Some(SegmentInfo::from(&*segment)))
+    }
+    // Reads the messages of the segment within the offset range, None if the segment doesn't exist
+    // The backends able to read a part of a segment should override it, to avoid loading the whole segment
+    async fn read_messages(
+        &self,
+        topic_name: &str,
+        id: usize,
+        offsets: Range<u64>,
+    ) -> Result<Option<Vec<StreamMessage>>, StorageBackendError> {
+        let segment = match self.get_segment(topic_name, id).await? {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+        let segment = segment.read().await;
+        Ok(Some(
+            segment
+                .messages
+                .iter()
+                .filter(|message| offsets.contains(&message.msg_id.segment_offset))
+                .cloned()
+                .collect(),
+        ))
+    }
+}
+
+/// The compression of the closed segments, recorded in the stored segment header
+/// so the segments written with different codecs remain readable.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionCodec {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+impl Display for CompressionCodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressionCodec::None => write!(f, "none"),
+            CompressionCodec::Lz4 => write!(f, "lz4"),
+            CompressionCodec::Zstd => write!(f, "zstd"),
+            CompressionCodec::Snappy => write!(f, "snappy"),
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -32,6 +105,12 @@ pub enum StorageBackendError {
 
     #[error("Managed storage error: {0}")]
     Managed(String),
+
+    #[error("Storage unavailable: {0}")]
+    Unavailable(String),
+
+    #[error("Storage encryption error: {0}")]
+    Encryption(String),
 }
 
 /// Segment is a collection of messages, the segment is closed for writing when it's capacity is reached
@@ -74,6 +153,29 @@ impl Segment {
     }
 }
 
+/// The metadata of a stored segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentInfo {
+    pub id: usize,
+    pub close_time: u64,
+    // size of the messages in bytes
+    pub current_size: usize,
+    pub message_count: u64,
+    pub next_offset: u64,
+}
+
+impl From<&Segment> for SegmentInfo {
+    fn from(segment: &Segment) -> Self {
+        SegmentInfo {
+            id: segment.id,
+            close_time: segment.close_time,
+            current_size: segment.current_size,
+            message_count: segment.messages.len() as u64,
+            next_offset: segment.next_offset,
+        }
+    }
+}
+
 // --- StorageConfig section ---
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -87,36 +189,159 @@ impl Display for DiskConfig {
     }
 }
 
+/// The cold tier of the tiered storage, a filesystem directory used as an object store bucket.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ColdStorageConfig {
+    pub path: String,
+    /// Seconds since the segment was closed, after which it is moved to the cold tier
+    pub offload_after: u64,
+}
+
+impl Display for ColdStorageConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "ColdStorageConfig(path: {}, offload_after: {}s)",
+            self.path, self.offload_after
+        )
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RemoteStorageConfig {
     pub endpoint: String,
     pub use_tls: bool,
     pub ca_file: String,
+    /// Milliseconds allowed to establish the connection
     pub connection_timeout: usize,
+    /// Milliseconds allowed for each call, including the streaming of the segment
+    #[serde(default = "default_request_timeout")]
+    pub request_timeout: u64,
+    /// Retries of the failed calls, on transport errors and timeouts
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+    /// Milliseconds before the first retry, doubled on each following retry
+    #[serde(default = "default_retry_backoff")]
+    pub retry_backoff: u64,
+    /// Consecutive failed calls after which the calls fail fast, without reaching the service
+    #[serde(default = "default_circuit_breaker_threshold")]
+    pub circuit_breaker_threshold: u32,
+    /// Milliseconds the calls fail fast, before a trial call is let through
+    #[serde(default = "default_circuit_breaker_reset")]
+    pub circuit_breaker_reset: u64,
+}
+
+fn default_request_timeout() -> u64 {
+    10_000
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+fn default_retry_backoff() -> u64 {
+    100
+}
+
+fn default_circuit_breaker_threshold() -> u32 {
+    5
+}
+
+fn default_circuit_breaker_reset() -> u64 {
+    30_000
 }
 
 impl Display for RemoteStorageConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "RemoteStorageConfig(endpoint: {}, connection_timeout: {})",
-            self.endpoint, self.connection_timeout
+            "RemoteStorageConfig(endpoint: {}, connection_timeout: {}, request_timeout: {}, max_retries: {})",
+            self.endpoint, self.connection_timeout, self.request_timeout, self.max_retries
+        )
+    }
+}
+
+/// Encryption at rest of the stored segments, with AES-256-GCM.
+/// The key file lists a `<key_id> <base64 key>` pair per line, the keys of the previous
+/// rotations are kept in the file so the segments encrypted with them remain readable.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EncryptionConfig {
+    pub key_file: String,
+    /// The key encrypting the new segments, the last key of the file if not set
+    #[serde(default)]
+    pub key_id: Option<String>,
+}
+
+impl Display for EncryptionConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "EncryptionConfig(key_file: {}, key_id: {})",
+            self.key_file,
+            self.key_id.as_deref().unwrap_or("latest")
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WalConfig {
+    pub path: String,
+    #[serde(default)]
+    pub fsync: WalFsyncPolicy,
+    #[serde(default = "default_wal_fsync_interval")]
+    pub fsync_interval_ms: u64,
+}
+
+// Custom function to return the default 1 second fsync interval
+fn default_wal_fsync_interval() -> u64 {
+    1000
+}
+
+/// Controls when the write-ahead log is flushed to stable storage.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WalFsyncPolicy {
+    /// fsync after every appended message, no acknowledged message can be lost
+    #[default]
+    Always,
+    /// fsync every `fsync_interval_ms` while messages are appended, bounded loss on power failure
+    Interval,
+    /// leave flushing to the operating system, survives a process crash only
+    Never,
+}
+
+impl Display for WalConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WalConfig(path: {}, fsync: {:?}, fsync_interval: {}ms)",
+            self.path, self.fsync, self.fsync_interval_ms
         )
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CacheConfig {
-    pub max_capacity: u64,
+    /// Broker wide budget of the cached segments, in bytes.
+    /// The open segments of the topics are not evicted and are not bounded by the budget.
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: u64,
+    /// Deprecated, the cache was bounded by the number of segments, it is ignored in favor of max_bytes
+    #[serde(default)]
+    pub max_capacity: Option<u64>,
     pub time_to_idle: u64,
 }
 
+fn default_cache_max_bytes() -> u64 {
+    512 * 1024 * 1024
+}
+
 impl Display for CacheConfig {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "CacheConfig(max_capacity: {}, time_to_idle: {})",
-            self.max_capacity, self.time_to_idle
+            "CacheConfig(max_bytes: {}, time_to_idle: {})",
+            self.max_bytes, self.time_to_idle
         )
     }
 }
@@ -130,12 +355,69 @@ pub enum StorageConfig {
     Local {
         local_config: DiskConfig,
         cache: CacheConfig,
+        #[serde(default)]
+        wal: Option<WalConfig>,
+        #[serde(default)]
+        compression: CompressionCodec,
+        #[serde(default)]
+        encryption: Option<EncryptionConfig>,
     },
     #[serde(rename = "remote")]
     Remote {
         remote_config: RemoteStorageConfig,
         cache: CacheConfig,
+        #[serde(default)]
+        wal: Option<WalConfig>,
+        #[serde(default)]
+        compression: CompressionCodec,
+        #[serde(default)]
+        encryption: Option<EncryptionConfig>,
     },
+    /// The closed segments are stored on the local disk, and moved to the cold tier once old enough
+    #[serde(rename = "tiered")]
+    Tiered {
+        local_config: DiskConfig,
+        cold_config: ColdStorageConfig,
+        cache: CacheConfig,
+        #[serde(default)]
+        wal: Option<WalConfig>,
+        #[serde(default)]
+        compression: CompressionCodec,
+        #[serde(default)]
+        encryption: Option<EncryptionConfig>,
+    },
+}
+
+impl StorageConfig {
+    /// The write-ahead log configuration, only the persistent storage types support it
+    pub fn wal_config(&self) -> Option<&WalConfig> {
+        match self {
+            StorageConfig::InMemory { .. } => None,
+            StorageConfig::Local { wal, .. } => wal.as_ref(),
+            StorageConfig::Remote { wal, .. } => wal.as_ref(),
+            StorageConfig::Tiered { wal, .. } => wal.as_ref(),
+        }
+    }
+
+    /// The default compression of the closed segments, the topics may choose another codec
+    pub fn compression(&self) -> CompressionCodec {
+        match self {
+            StorageConfig::InMemory { .. } => CompressionCodec::None,
+            StorageConfig::Local { compression, .. } => *compression,
+            StorageConfig::Remote { compression, .. } => *compression,
+            StorageConfig::Tiered { compression, .. } => *compression,
+        }
+    }
+
+    /// The encryption of the stored segments, only the persistent storage types support it
+    pub fn encryption_config(&self) -> Option<&EncryptionConfig> {
+        match self {
+            StorageConfig::InMemory { .. } => None,
+            StorageConfig::Local { encryption, .. } => encryption.as_ref(),
+            StorageConfig::Remote { encryption, .. } => encryption.as_ref(),
+            StorageConfig::Tiered { encryption, .. } => encryption.as_ref(),
+        }
+    }
 }
 
 impl Display for StorageConfig {
@@ -144,28 +426,46 @@ impl Display for StorageConfig {
             StorageConfig::InMemory { cache } => {
                 write!(
                     f,
-                    "In-Memory Storage (Cache entries: {}, TTL: {}min)",
-                    cache.max_capacity, cache.time_to_idle
+                    "In-Memory Storage (Cache: {} bytes, TTL: {}min)",
+                    cache.max_bytes, cache.time_to_idle
                 )
             }
             StorageConfig::Local {
                 local_config,
                 cache,
+                ..
             } => {
                 write!(
                     f,
-                    "Local Disk Storage at '{}' (Cache entries: {}, TTL: {}min)",
-                    local_config.path, cache.max_capacity, cache.time_to_idle
+                    "Local Disk Storage at '{}' (Cache: {} bytes, TTL: {}min)",
+                    local_config.path, cache.max_bytes, cache.time_to_idle
                 )
             }
             StorageConfig::Remote {
                 remote_config,
                 cache,
+                ..
+            } => {
+                write!(
+                    f,
+                    "Remote Storage at '{}' (Cache: {} bytes, TTL: {}min)",
+                    remote_config.endpoint, cache.max_bytes, cache.time_to_idle
+                )
+            }
+            StorageConfig::Tiered {
+                local_config,
+                cold_config,
+                cache,
+                ..
             } => {
                 write!(
                     f,
-                    "Remote Storage at '{}' (Cache entries: {}, TTL: {}min)",
-                    remote_config.endpoint, cache.max_capacity, cache.time_to_idle
+                    "Tiered Storage at '{}', offloaded after {}s to '{}' (Cache: {} bytes, TTL: {}min)",
+                    local_config.path,
+                    cold_config.offload_after,
+                    cold_config.path,
+                    cache.max_bytes,
+                    cache.time_to_idle
                 )
             }
         }
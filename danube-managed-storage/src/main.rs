@@ -0,0 +1,75 @@
+mod storage_service;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use danube_core::managed_storage_proto::managed_storage_server::ManagedStorageServer;
+use danube_persistent_storage::DiskStorage;
+use std::net::SocketAddr;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tracing::{info, warn};
+
+use crate::storage_service::ManagedStorageService;
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "danube-managed-storage",
+    about = "Stores the segments of the Danube brokers configured with the remote storage"
+)]
+struct Args {
+    /// Address of the gRPC service, used as the endpoint of the brokers remote_config
+    #[arg(long, default_value = "0.0.0.0:50060")]
+    addr: SocketAddr,
+
+    /// Directory where the segments are stored
+    #[arg(long)]
+    path: String,
+
+    /// Server certificate (PEM), enables TLS together with --key-file.
+    /// The brokers verify it with the ca_file of their remote_config.
+    #[arg(long, requires = "key_file")]
+    cert_file: Option<String>,
+
+    /// Server private key (PEM)
+    #[arg(long, requires = "cert_file")]
+    key_file: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    // Initialize logging
+    tracing_subscriber::fmt::init();
+
+    let args = Args::parse();
+
+    let mut server = Server::builder();
+    if let (Some(cert_file), Some(key_file)) = (&args.cert_file, &args.key_file) {
+        if let Err(e) = rustls::crypto::ring::default_provider().install_default() {
+            warn!("Failed to install crypto provider: {:?}", e);
+        }
+
+        let cert = tokio::fs::read(cert_file)
+            .await
+            .with_context(|| format!("Failed to read the certificate {}", cert_file))?;
+        let key = tokio::fs::read(key_file)
+            .await
+            .with_context(|| format!("Failed to read the private key {}", key_file))?;
+        server =
+            server.tls_config(ServerTlsConfig::new().identity(Identity::from_pem(cert, key)))?;
+    }
+
+    let service = ManagedStorageService::new(DiskStorage::new(&args.path));
+
+    info!(
+        "Managed storage is serving the segments stored at '{}' on {} (TLS: {})",
+        args.path,
+        args.addr,
+        args.cert_file.is_some()
+    );
+
+    server
+        .add_service(ManagedStorageServer::new(service))
+        .serve(args.addr)
+        .await?;
+
+    Ok(())
+}
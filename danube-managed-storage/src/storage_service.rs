@@ -0,0 +1,358 @@
+use danube_core::{
+    managed_storage_proto::{
+        managed_storage_server::ManagedStorage, GetSegmentInfoRequest, GetSegmentRequest,
+        ListSegmentsRequest, ListSegmentsResponse, MessagesChunk, PutSegmentResponse,
+        ReadMessagesRequest, RemoveSegmentRequest, RemoveSegmentResponse, SegmentChunk,
+        SegmentInfo,
+    },
+    storage::{CompressionCodec, StorageBackend, StorageBackendError},
+};
+use danube_persistent_storage::{
+    segment_chunks::{messages_to_chunks, segment_to_chunks, SegmentAssembler},
+    DiskStorage,
+};
+use std::{pin::Pin, sync::Arc};
+use tokio::sync::RwLock;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::trace;
+
+// ManagedStorageService serves the segments of the brokers from a local directory,
+// using the same segment files as the local storage of the brokers.
+#[derive(Debug)]
+pub(crate) struct ManagedStorageService {
+    storage: DiskStorage,
+}
+
+impl ManagedStorageService {
+    pub(crate) fn new(storage: DiskStorage) -> Self {
+        ManagedStorageService { storage }
+    }
+}
+
+fn internal_status(error: StorageBackendError) -> Status {
+    Status::internal(error.to_string())
+}
+
+// the brokers expect NotFound for the missing segments
+fn segment_not_found(topic_name: &str, segment_id: usize) -> Status {
+    Status::not_found(format!(
+        "The segment {} of topic {} is not found",
+        segment_id, topic_name
+    ))
+}
+
+#[tonic::async_trait]
+impl ManagedStorage for ManagedStorageService {
+    type GetSegmentStream = Pin<Box<dyn Stream<Item = Result<SegmentChunk, Status>> + Send>>;
+    type ReadMessagesStream = Pin<Box<dyn Stream<Item = Result<MessagesChunk, Status>> + Send>>;
+
+    async fn get_segment(
+        &self,
+        request: Request<GetSegmentRequest>,
+    ) -> Result<Response<Self::GetSegmentStream>, Status> {
+        let req = request.into_inner();
+        let segment_id = req.segment_id as usize;
+
+        let segment = self
+            .storage
+            .get_segment(&req.topic_name, segment_id)
+            .await
+            .map_err(internal_status)?
+            .ok_or_else(|| segment_not_found(&req.topic_name, segment_id))?;
+
+        let chunks = {
+            let segment = segment.read().await;
+            // the segment is decoded from its stored format, so it is sent uncompressed
+            segment_to_chunks(
+                &req.topic_name,
+                segment_id,
+                &segment,
+                CompressionCodec::None,
+            )
+            .map_err(|e| Status::internal(e.to_string()))?
+        };
+
+        trace!(
+            "Sending the segment {} of topic {} in {} chunks",
+            segment_id,
+            req.topic_name,
+            chunks.len()
+        );
+
+        Ok(Response::new(Box::pin(tokio_stream::iter(
+            chunks.into_iter().map(Ok),
+        ))))
+    }
+
+    async fn put_segment(
+        &self,
+        request: Request<Streaming<SegmentChunk>>,
+    ) -> Result<Response<PutSegmentResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut assembler = SegmentAssembler::default();
+
+        while let Some(chunk) = stream.next().await {
+            assembler.push(chunk?).map_err(Status::invalid_argument)?;
+        }
+
+        let total_chunks_received = assembler.chunks_received();
+        let codec = assembler.compression().map_err(Status::invalid_argument)?;
+        let (topic_name, segment_id, segment) =
+            assembler.finish().map_err(Status::invalid_argument)?;
+
+        // the segment is stored with the codec chosen by the broker
+        self.storage
+            .put_segment_with_codec(
+                &topic_name,
+                segment_id,
+                Arc::new(RwLock::new(segment)),
+                codec,
+            )
+            .await
+            .map_err(internal_status)?;
+
+        trace!(
+            "Stored the segment {} of topic {} from {} chunks",
+            segment_id,
+            topic_name,
+            total_chunks_received
+        );
+
+        Ok(Response::new(PutSegmentResponse {
+            total_chunks_received,
+        }))
+    }
+
+    async fn remove_segment(
+        &self,
+        request: Request<RemoveSegmentRequest>,
+    ) -> Result<Response<RemoveSegmentResponse>, Status> {
+        let req = request.into_inner();
+
+        self.storage
+            .remove_segment(&req.topic_name, req.segment_id as usize)
+            .await
+            .map_err(internal_status)?;
+
+        Ok(Response::new(RemoveSegmentResponse {}))
+    }
+
+    async fn list_segments(
+        &self,
+        request: Request<ListSegmentsRequest>,
+    ) -> Result<Response<ListSegmentsResponse>, Status> {
+        let req = request.into_inner();
+
+        let segment_ids = self
+            .storage
+            .list_segments(&req.topic_name)
+            .await
+            .map_err(internal_status)?;
+
+        Ok(Response::new(ListSegmentsResponse {
+            segment_ids: segment_ids.into_iter().map(|id| id as u64).collect(),
+        }))
+    }
+
+    async fn get_segment_info(
+        &self,
+        request: Request<GetSegmentInfoRequest>,
+    ) -> Result<Response<SegmentInfo>, Status> {
+        let req = request.into_inner();
+        let segment_id = req.segment_id as usize;
+
+        let info = self
+            .storage
+            .segment_info(&req.topic_name, segment_id)
+            .await
+            .map_err(internal_status)?
+            .ok_or_else(|| segment_not_found(&req.topic_name, segment_id))?;
+
+        Ok(Response::new(SegmentInfo {
+            segment_id: info.id as u64,
+            close_time: info.close_time,
+            current_size: info.current_size as u64,
+            message_count: info.message_count,
+            next_offset: info.next_offset,
+        }))
+    }
+
+    async fn read_messages(
+        &self,
+        request: Request<ReadMessagesRequest>,
+    ) -> Result<Response<Self::ReadMessagesStream>, Status> {
+        let req = request.into_inner();
+        let segment_id = req.segment_id as usize;
+
+        let messages = self
+            .storage
+            .read_messages(
+                &req.topic_name,
+                segment_id,
+                req.start_offset..req.end_offset,
+            )
+            .await
+            .map_err(internal_status)?
+            .ok_or_else(|| segment_not_found(&req.topic_name, segment_id))?;
+
+        let chunks = messages_to_chunks(&messages).map_err(|e| Status::internal(e.to_string()))?;
+
+        trace!(
+            "Sending {} messages of the segment {} of topic {}",
+            messages.len(),
+            segment_id,
+            req.topic_name
+        );
+
+        Ok(Response::new(Box::pin(tokio_stream::iter(
+            chunks.into_iter().map(Ok),
+        ))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use danube_core::{
+        managed_storage_proto::managed_storage_server::ManagedStorageServer,
+        message::{MessageID, StreamMessage},
+        storage::{RemoteStorageConfig, Segment},
+    };
+    use danube_persistent_storage::RemoteStorage;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::transport::Server;
+
+    // Runs the service on the local address, port 0 picks a random port, returns its endpoint
+    async fn start_service(path: &str, addr: &str) -> String {
+        let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let service = ManagedStorageService::new(DiskStorage::new(path));
+
+        tokio::spawn(
+            Server::builder()
+                .add_service(ManagedStorageServer::new(service))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+
+        endpoint
+    }
+
+    #[tokio::test]
+    async fn test_remote_storage_end_to_end() {
+        let temp_dir = tempdir().unwrap();
+        let endpoint = start_service(temp_dir.path().to_str().unwrap(), "127.0.0.1:0").await;
+
+        let storage = RemoteStorage::new(RemoteStorageConfig {
+            endpoint,
+            use_tls: false,
+            ca_file: String::new(),
+            connection_timeout: 5000,
+            request_timeout: 10_000,
+            max_retries: 3,
+            retry_backoff: 100,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_reset: 30_000,
+        });
+        let topic_name = "/default/test_topic";
+
+        let mut segment = Segment::new(1, 1024);
+        for offset in 0..3 {
+            segment.add_message(StreamMessage {
+                request_id: offset,
+                msg_id: MessageID {
+                    producer_id: 1,
+                    topic_name: topic_name.to_string(),
+                    broker_addr: "localhost:6650".to_string(),
+                    segment_id: 1,
+                    segment_offset: offset,
+                },
+                // spans several chunks
+                payload: vec![offset as u8; 1_500_000],
+                publish_time: 123456789,
+                producer_name: "test_producer".to_string(),
+                subscription_name: None,
+                attributes: HashMap::new(),
+                key: None,
+                deliver_at: None,
+                deliver_after: None,
+                ttl: None,
+                expire_at: None,
+            });
+        }
+        segment.close_time = 123456790;
+
+        storage
+            .put_segment(topic_name, 1, Arc::new(RwLock::new(segment)))
+            .await
+            .unwrap();
+
+        {
+            let stored = storage.get_segment(topic_name, 1).await.unwrap().unwrap();
+            let stored = stored.read().await;
+            assert_eq!(stored.id, 1);
+            assert_eq!(stored.close_time, 123456790);
+            assert_eq!(stored.messages.len(), 3);
+            assert_eq!(stored.messages[2].payload, vec![2u8; 1_500_000]);
+        }
+
+        assert!(storage.get_segment(topic_name, 2).await.unwrap().is_none());
+
+        assert_eq!(storage.list_segments(topic_name).await.unwrap(), vec![1]);
+        let info = storage.segment_info(topic_name, 1).await.unwrap().unwrap();
+        assert_eq!(info.close_time, 123456790);
+        assert_eq!(info.message_count, 3);
+        assert_eq!(info.current_size, 4_500_000);
+        assert!(storage.segment_info(topic_name, 2).await.unwrap().is_none());
+
+        let messages = storage
+            .read_messages(topic_name, 1, 1..3)
+            .await
+            .unwrap()
+            .unwrap();
+        let offsets: Vec<u64> = messages
+            .iter()
+            .map(|message| message.msg_id.segment_offset)
+            .collect();
+        assert_eq!(offsets, vec![1, 2]);
+        assert!(storage
+            .read_messages(topic_name, 2, 0..1)
+            .await
+            .unwrap()
+            .is_none());
+
+        storage.remove_segment(topic_name, 1).await.unwrap();
+        assert!(storage.get_segment(topic_name, 1).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_remote_storage_reconnects() {
+        let temp_dir = tempdir().unwrap();
+        // reserve a port, the service is started on it after the first call
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+
+        let storage = RemoteStorage::new(RemoteStorageConfig {
+            endpoint: format!("http://{}", addr),
+            use_tls: false,
+            ca_file: String::new(),
+            connection_timeout: 1000,
+            request_timeout: 1000,
+            max_retries: 0,
+            retry_backoff: 10,
+            circuit_breaker_threshold: 5,
+            circuit_breaker_reset: 30_000,
+        });
+        let topic_name = "/default/test_topic";
+
+        assert!(storage.list_segments(topic_name).await.is_err());
+
+        start_service(temp_dir.path().to_str().unwrap(), &addr).await;
+        assert!(storage.list_segments(topic_name).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// CircuitBreaker stops the calls to an unavailable service, so the callers fail fast
+// instead of waiting for the timeouts and retries of each call.
+//
+// Closed   -> the calls are let through, the consecutive failures are counted
+// Open     -> the calls are rejected, until reset_after is elapsed
+// HalfOpen -> a single trial call is let through, its outcome closes or reopens the circuit,
+//             another trial is let through after reset_after if the outcome is never recorded,
+//             as the trial call was dropped
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BreakerState {
+    Closed { failures: u32 },
+    Open { since: Instant },
+    HalfOpen { since: Instant },
+}
+
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    state: Mutex<BreakerState>,
+    // consecutive failures opening the circuit, 0 disables the breaker
+    threshold: u32,
+    reset_after: Duration,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(threshold: u32, reset_after: Duration) -> Self {
+        CircuitBreaker {
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+            threshold,
+            reset_after,
+        }
+    }
+
+    /// Returns false if the call should be rejected
+    pub(crate) fn allow_call(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { since } | BreakerState::HalfOpen { since }
+                if since.elapsed() >= self.reset_after =>
+            {
+                *state = BreakerState::HalfOpen {
+                    since: Instant::now(),
+                };
+                true
+            }
+            // the trial call is in progress
+            BreakerState::Open { .. } | BreakerState::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Returns true if the call closed the circuit
+    pub(crate) fn record_success(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let was_open = !matches!(*state, BreakerState::Closed { .. });
+        *state = BreakerState::Closed { failures: 0 };
+        was_open
+    }
+
+    /// Returns true if the call opened the circuit
+    pub(crate) fn record_failure(&self) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { failures } if failures + 1 < self.threshold => {
+                *state = BreakerState::Closed {
+                    failures: failures + 1,
+                };
+                false
+            }
+            BreakerState::Closed { .. } | BreakerState::HalfOpen { .. } => {
+                *state = BreakerState::Open {
+                    since: Instant::now(),
+                };
+                true
+            }
+            // a call started before the circuit was opened
+            BreakerState::Open { .. } => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+
+        assert!(breaker.allow_call());
+        assert!(!breaker.record_failure());
+        // a success resets the consecutive failures
+        assert!(!breaker.record_success());
+        assert!(!breaker.record_failure());
+        assert!(breaker.record_failure());
+        assert!(!breaker.allow_call());
+
+        // a single trial call once reset_after is elapsed
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow_call());
+        assert!(!breaker.allow_call());
+        assert!(breaker.record_failure());
+        assert!(!breaker.allow_call());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow_call());
+        assert!(breaker.record_success());
+        assert!(breaker.allow_call());
+
+        // the trial call dropped without an outcome, another trial once reset_after is elapsed
+        assert!(!breaker.record_failure());
+        assert!(breaker.record_failure());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow_call());
+        assert!(!breaker.allow_call());
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow_call());
+        assert!(breaker.record_success());
+
+        // disabled breaker
+        let breaker = CircuitBreaker::new(0, Duration::from_millis(50));
+        for _ in 0..10 {
+            assert!(!breaker.record_failure());
+        }
+        assert!(breaker.allow_call());
+    }
+}
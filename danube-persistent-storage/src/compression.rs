@@ -0,0 +1,88 @@
+use danube_core::storage::CompressionCodec;
+use std::io::Read;
+
+// The compression of the stored segments, the codec is recorded with the compressed data
+// (the segment header flags, the segment chunks) so each segment is read back with its own codec.
+
+const ZSTD_LEVEL: i32 = 3;
+
+// The codec id stored in the low bits of the segment header flags
+pub(crate) fn codec_id(codec: CompressionCodec) -> u16 {
+    match codec {
+        CompressionCodec::None => 0,
+        CompressionCodec::Lz4 => 1,
+        CompressionCodec::Zstd => 2,
+        CompressionCodec::Snappy => 3,
+    }
+}
+
+pub(crate) fn codec_from_id(id: u16) -> Result<CompressionCodec, String> {
+    match id {
+        0 => Ok(CompressionCodec::None),
+        1 => Ok(CompressionCodec::Lz4),
+        2 => Ok(CompressionCodec::Zstd),
+        3 => Ok(CompressionCodec::Snappy),
+        _ => Err(format!("unknown compression codec {}", id)),
+    }
+}
+
+pub(crate) fn compress(codec: CompressionCodec, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    match codec {
+        CompressionCodec::None => Ok(bytes.to_vec()),
+        CompressionCodec::Lz4 => Ok(lz4_flex::compress_prepend_size(bytes)),
+        CompressionCodec::Zstd => zstd::encode_all(bytes, ZSTD_LEVEL),
+        CompressionCodec::Snappy => {
+            let mut compressed = Vec::new();
+            snap::read::FrameEncoder::new(bytes).read_to_end(&mut compressed)?;
+            Ok(compressed)
+        }
+    }
+}
+
+// Returns the decompressed data, or the reason the data is invalid
+pub(crate) fn decompress(codec: CompressionCodec, bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let decompressed = match codec {
+        CompressionCodec::None => return Ok(bytes.to_vec()),
+        CompressionCodec::Lz4 => {
+            lz4_flex::decompress_size_prepended(bytes).map_err(|e| e.to_string())
+        }
+        CompressionCodec::Zstd => zstd::decode_all(bytes).map_err(|e| e.to_string()),
+        CompressionCodec::Snappy => {
+            let mut decompressed = Vec::new();
+            snap::read::FrameDecoder::new(bytes)
+                .read_to_end(&mut decompressed)
+                .map(|_| decompressed)
+                .map_err(|e| e.to_string())
+        }
+    };
+
+    decompressed.map_err(|e| format!("unable to decompress the {} data: {}", codec, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_roundtrip() {
+        let bytes = br#"{"sensor": "temperature", "value": 21.5}"#.repeat(100);
+
+        for codec in [
+            CompressionCodec::None,
+            CompressionCodec::Lz4,
+            CompressionCodec::Zstd,
+            CompressionCodec::Snappy,
+        ] {
+            assert_eq!(codec_from_id(codec_id(codec)).unwrap(), codec);
+
+            let compressed = compress(codec, &bytes).unwrap();
+            if codec != CompressionCodec::None {
+                assert!(compressed.len() < bytes.len() / 5, "{}", codec);
+            }
+            assert_eq!(decompress(codec, &compressed).unwrap(), bytes);
+        }
+
+        assert!(decompress(CompressionCodec::Zstd, b"not compressed").is_err());
+        assert!(codec_from_id(7).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tonic::transport::{Channel, ClientTlsConfig, Uri};
 use tracing::info;
 
@@ -15,6 +15,7 @@ enum ConnectionStatus {
 pub(crate) struct ConnectionOptions {
     pub(crate) tls_config: Option<ClientTlsConfig>,
     pub(crate) use_tls: bool,
+    pub(crate) connect_timeout: Option<Duration>,
 }
 
 #[derive(Debug, Clone)]
@@ -28,12 +29,15 @@ pub(crate) async fn new_rpc_connection(
 ) -> Result<RpcConnection, PersistentStorageError> {
     info!("Establishing new RPC connection to {}", connect_url);
 
+    let mut endpoint = Channel::from_shared(connect_url.to_string())?;
+    if let Some(connect_timeout) = cnx_options.connect_timeout {
+        endpoint = endpoint.connect_timeout(connect_timeout);
+    }
+
     let channel = match cnx_options.use_tls {
         false => {
             // Plain TCP connection
-            Channel::from_shared(connect_url.to_string())?
-                .connect()
-                .await?
+            endpoint.connect().await?
         }
         true => {
             // TLS is enabled, tls_config must be present
@@ -42,10 +46,7 @@ pub(crate) async fn new_rpc_connection(
                 .as_ref()
                 .expect("TLS config must be present when TLS is enabled");
 
-            Channel::from_shared(connect_url.to_string())?
-                .tls_config(tls_config.clone())?
-                .connect()
-                .await?
+            endpoint.tls_config(tls_config.clone())?.connect().await?
         }
     };
 
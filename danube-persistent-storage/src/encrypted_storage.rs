@@ -0,0 +1,522 @@
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use danube_core::{
+    message::StreamMessage,
+    storage::{
+        CompressionCodec, EncryptionConfig, Segment, SegmentInfo, StorageBackend,
+        StorageBackendError,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, ops::Range, sync::Arc};
+use tokio::sync::RwLock;
+
+use crate::compression::{codec_from_id, codec_id, compress, decompress};
+
+// EncryptedStorage is a storage backend wrapper that encrypts the messages of the segments
+// before they reach the wrapped backend, and decrypts them once read back.
+//
+// The payload, the attributes and the key of each message are encrypted with AES-256-GCM,
+// the message id, the producer and the publish time are kept in clear for the storage.
+// The stored message carries the id of the key in its attributes and as payload:
+//     [version: u8][codec: u8][nonce: 12 bytes][ciphertext + tag]
+// The message position (topic, segment, offset) is authenticated, so the stored messages
+// can't be swapped. The segments are compressed before the encryption, with the codec
+// requested by the topic, as the encrypted data doesn't compress.
+//
+// The messages stored without encryption, before it was enabled, are read as they are.
+//
+// The records of the write-ahead log, holding the messages of the open segment,
+// are encrypted as a whole with the same keys, see `encrypt_record`.
+
+// The attribute of the stored message holding the encryption key id
+const KEY_ID_ATTRIBUTE: &str = "danube.encryption_key_id";
+const ENVELOPE_VERSION: u8 = 1;
+const NONCE_SIZE: usize = 12;
+const KEY_SIZE: usize = 32;
+
+/// Provides the encryption keys, the implementations may fetch them from a key management service.
+/// The keys are never removed on rotation, the old segments are decrypted with the key of their id.
+pub trait KeyProvider: Send + Sync + std::fmt::Debug + 'static {
+    /// The id and the key encrypting the new segments
+    fn current_key(&self) -> (String, [u8; KEY_SIZE]);
+    /// The key with the id, None if unknown
+    fn key(&self, key_id: &str) -> Option<[u8; KEY_SIZE]>;
+}
+
+/// A KeyProvider reading the keys from a file, a `<key_id> <base64 key>` pair per line.
+/// Empty lines and the lines starting with `#` are ignored.
+#[derive(Debug)]
+pub struct FileKeyProvider {
+    keys: HashMap<String, [u8; KEY_SIZE]>,
+    current_key_id: String,
+}
+
+impl FileKeyProvider {
+    /// Loads the keys, the current key is key_id or the last key of the file
+    pub fn load(config: &EncryptionConfig) -> Result<Self, StorageBackendError> {
+        let invalid = |reason: String| {
+            StorageBackendError::Encryption(format!("{}: {}", config.key_file, reason))
+        };
+        let content = std::fs::read_to_string(&config.key_file)
+            .map_err(|e| invalid(format!("unable to read the key file: {}", e)))?;
+
+        let mut keys = HashMap::new();
+        let mut last_key_id = None;
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key_id, encoded) = line
+                .split_once(char::is_whitespace)
+                .ok_or_else(|| invalid(format!("line {} is not `<key_id> <key>`", number + 1)))?;
+            let key: [u8; KEY_SIZE] = STANDARD
+                .decode(encoded.trim())
+                .ok()
+                .and_then(|key| key.try_into().ok())
+                .ok_or_else(|| {
+                    invalid(format!(
+                        "the key {} is not a base64 encoded {} bytes key",
+                        key_id, KEY_SIZE
+                    ))
+                })?;
+
+            keys.insert(key_id.to_string(), key);
+            last_key_id = Some(key_id.to_string());
+        }
+
+        let current_key_id = config
+            .key_id
+            .clone()
+            .or(last_key_id)
+            .ok_or_else(|| invalid("no key found".to_string()))?;
+        if !keys.contains_key(&current_key_id) {
+            return Err(invalid(format!("the key {} is not found", current_key_id)));
+        }
+
+        Ok(FileKeyProvider {
+            keys,
+            current_key_id,
+        })
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn current_key(&self) -> (String, [u8; KEY_SIZE]) {
+        (self.current_key_id.clone(), self.keys[&self.current_key_id])
+    }
+
+    fn key(&self, key_id: &str) -> Option<[u8; KEY_SIZE]> {
+        self.keys.get(key_id).copied()
+    }
+}
+
+// The message fields encrypted in the stored message
+#[derive(Serialize, Deserialize)]
+struct SealedFields {
+    payload: Vec<u8>,
+    attributes: HashMap<String, String>,
+    key: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct EncryptedStorage {
+    inner: Arc<dyn StorageBackend>,
+    keys: Arc<dyn KeyProvider>,
+}
+
+impl EncryptedStorage {
+    pub fn new(inner: Arc<dyn StorageBackend>, keys: Arc<dyn KeyProvider>) -> Self {
+        EncryptedStorage { inner, keys }
+    }
+
+    fn encrypt_segment(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: &Segment,
+        codec: CompressionCodec,
+    ) -> Result<Segment, StorageBackendError> {
+        let (key_id, key) = self.keys.current_key();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+
+        let mut encrypted = segment.clone();
+        for message in encrypted.messages.iter_mut() {
+            let fields = SealedFields {
+                payload: std::mem::take(&mut message.payload),
+                attributes: std::mem::take(&mut message.attributes),
+                key: message.key.take(),
+            };
+            let plaintext = bincode::serialize(&fields).map_err(encryption_error)?;
+            let plaintext = compress(codec, &plaintext).map_err(encryption_error)?;
+
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let aad = associated_data(topic_name, id, message.msg_id.segment_offset);
+            let ciphertext = cipher
+                .encrypt(
+                    &nonce,
+                    Payload {
+                        msg: &plaintext,
+                        aad: &aad,
+                    },
+                )
+                .map_err(encryption_error)?;
+
+            let mut payload = Vec::with_capacity(2 + NONCE_SIZE + ciphertext.len());
+            payload.push(ENVELOPE_VERSION);
+            payload.push(codec_id(codec) as u8);
+            payload.extend_from_slice(&nonce);
+            payload.extend_from_slice(&ciphertext);
+
+            message.payload = payload;
+            message
+                .attributes
+                .insert(KEY_ID_ATTRIBUTE.to_string(), key_id.clone());
+        }
+
+        Ok(encrypted)
+    }
+
+    fn decrypt_message(
+        &self,
+        topic_name: &str,
+        id: usize,
+        mut message: StreamMessage,
+    ) -> Result<StreamMessage, StorageBackendError> {
+        // stored before the encryption was enabled
+        let Some(key_id) = message.attributes.get(KEY_ID_ATTRIBUTE) else {
+            return Ok(message);
+        };
+
+        let key = self.keys.key(key_id).ok_or_else(|| {
+            StorageBackendError::Encryption(format!(
+                "the key {} of the segment {} of topic {} is not found",
+                key_id, id, topic_name
+            ))
+        })?;
+
+        let payload = &message.payload;
+        if payload.len() < 2 + NONCE_SIZE || payload[0] != ENVELOPE_VERSION {
+            return Err(StorageBackendError::Encryption(format!(
+                "invalid encrypted message in the segment {} of topic {}",
+                id, topic_name
+            )));
+        }
+        let codec = codec_from_id(payload[1] as u16).map_err(StorageBackendError::Encryption)?;
+        let nonce = Nonce::from_slice(&payload[2..2 + NONCE_SIZE]);
+        let aad = associated_data(topic_name, id, message.msg_id.segment_offset);
+
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let plaintext = cipher
+            .decrypt(
+                nonce,
+                Payload {
+                    msg: &payload[2 + NONCE_SIZE..],
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| {
+                StorageBackendError::Encryption(format!(
+                    "unable to decrypt the message {} of the segment {} of topic {}",
+                    message.msg_id.segment_offset, id, topic_name
+                ))
+            })?;
+
+        let plaintext = decompress(codec, &plaintext).map_err(StorageBackendError::Encryption)?;
+        let fields: SealedFields = bincode::deserialize(&plaintext).map_err(encryption_error)?;
+
+        message.payload = fields.payload;
+        message.attributes = fields.attributes;
+        message.key = fields.key;
+        Ok(message)
+    }
+}
+
+// Encrypts a record of the write-ahead log with the current key, the record is stored as:
+//     [key id length: u8][key id][nonce: 12 bytes][ciphertext + tag]
+// The associated data binds the record to its log.
+pub(crate) fn encrypt_record(
+    keys: &dyn KeyProvider,
+    aad: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, StorageBackendError> {
+    let (key_id, key) = keys.current_key();
+    let key_id_len = u8::try_from(key_id.len()).map_err(|_| {
+        StorageBackendError::Encryption(format!("the key id {} is too long", key_id))
+    })?;
+
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(encryption_error)?;
+
+    let mut record = Vec::with_capacity(1 + key_id.len() + NONCE_SIZE + ciphertext.len());
+    record.push(key_id_len);
+    record.extend_from_slice(key_id.as_bytes());
+    record.extend_from_slice(&nonce);
+    record.extend_from_slice(&ciphertext);
+    Ok(record)
+}
+
+// Decrypts a record of the write-ahead log encrypted by `encrypt_record`
+pub(crate) fn decrypt_record(
+    keys: &dyn KeyProvider,
+    aad: &[u8],
+    record: &[u8],
+) -> Result<Vec<u8>, StorageBackendError> {
+    let invalid = || StorageBackendError::Encryption("invalid encrypted record".to_string());
+
+    let (&key_id_len, rest) = record.split_first().ok_or_else(invalid)?;
+    let key_id_len = key_id_len as usize;
+    if rest.len() < key_id_len + NONCE_SIZE {
+        return Err(invalid());
+    }
+    let key_id = std::str::from_utf8(&rest[..key_id_len]).map_err(|_| invalid())?;
+    let nonce = Nonce::from_slice(&rest[key_id_len..key_id_len + NONCE_SIZE]);
+
+    let key = keys.key(key_id).ok_or_else(|| {
+        StorageBackendError::Encryption(format!("the key {} of the record is not found", key_id))
+    })?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    cipher
+        .decrypt(
+            nonce,
+            Payload {
+                msg: &rest[key_id_len + NONCE_SIZE..],
+                aad,
+            },
+        )
+        .map_err(|_| {
+            StorageBackendError::Encryption(format!(
+                "unable to decrypt the record with the key {}",
+                key_id
+            ))
+        })
+}
+
+fn associated_data(topic_name: &str, id: usize, offset: u64) -> Vec<u8> {
+    format!("{}:{}:{}", topic_name, id, offset).into_bytes()
+}
+
+fn encryption_error(error: impl ToString) -> StorageBackendError {
+    StorageBackendError::Encryption(error.to_string())
+}
+
+#[async_trait]
+impl StorageBackend for EncryptedStorage {
+    async fn get_segment(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<Arc<RwLock<Segment>>>, StorageBackendError> {
+        let stored = match self.inner.get_segment(topic_name, id).await? {
+            Some(segment) => segment,
+            None => return Ok(None),
+        };
+
+        // the stored segment may be shared by the wrapped backend, it is decrypted as a copy
+        let mut segment = stored.read().await.clone();
+        segment.messages = std::mem::take(&mut segment.messages)
+            .into_iter()
+            .map(|message| self.decrypt_message(topic_name, id, message))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Some(Arc::new(RwLock::new(segment))))
+    }
+
+    async fn put_segment(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+    ) -> Result<(), StorageBackendError> {
+        self.put_segment_with_codec(topic_name, id, segment, CompressionCodec::None)
+            .await
+    }
+
+    async fn put_segment_with_codec(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+        codec: CompressionCodec,
+    ) -> Result<(), StorageBackendError> {
+        let encrypted = {
+            let segment = segment.read().await;
+            self.encrypt_segment(topic_name, id, &segment, codec)?
+        };
+
+        self.inner
+            .put_segment(topic_name, id, Arc::new(RwLock::new(encrypted)))
+            .await
+    }
+
+    async fn remove_segment(&self, topic_name: &str, id: usize) -> Result<(), StorageBackendError> {
+        self.inner.remove_segment(topic_name, id).await
+    }
+
+    async fn list_segments(&self, topic_name: &str) -> Result<Vec<usize>, StorageBackendError> {
+        self.inner.list_segments(topic_name).await
+    }
+
+    async fn segment_info(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<SegmentInfo>, StorageBackendError> {
+        self.inner.segment_info(topic_name, id).await
+    }
+
+    async fn read_messages(
+        &self,
+        topic_name: &str,
+        id: usize,
+        offsets: Range<u64>,
+    ) -> Result<Option<Vec<StreamMessage>>, StorageBackendError> {
+        let messages = match self.inner.read_messages(topic_name, id, offsets).await? {
+            Some(messages) => messages,
+            None => return Ok(None),
+        };
+
+        messages
+            .into_iter()
+            .map(|message| self.decrypt_message(topic_name, id, message))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::local_disk::DiskStorage;
+    use danube_core::message::MessageID;
+    use tempfile::tempdir;
+
+    fn create_test_segment(id: usize) -> Segment {
+        let mut segment = Segment::new(id, 1024);
+        for offset in 0..3 {
+            segment.add_message(StreamMessage {
+                request_id: offset,
+                msg_id: MessageID {
+                    producer_id: 1,
+                    topic_name: "/default/test_topic".to_string(),
+                    broker_addr: "localhost:6650".to_string(),
+                    segment_id: id as u64,
+                    segment_offset: offset,
+                },
+                payload: format!("customer record {}", offset).into_bytes(),
+                publish_time: 123456789,
+                producer_name: "test_producer".to_string(),
+                subscription_name: None,
+                attributes: HashMap::from([("email".to_string(), "a@b.c".to_string())]),
+                key: Some("customer-1".to_string()),
+                deliver_at: None,
+                deliver_after: None,
+                ttl: None,
+                expire_at: None,
+            });
+        }
+        segment.close_time = 42;
+        segment
+    }
+
+    fn key_provider(dir: &std::path::Path, keys: &[(&str, u8)]) -> Arc<FileKeyProvider> {
+        let key_file = dir.join("keys");
+        let content: String = keys
+            .iter()
+            .map(|(key_id, byte)| format!("{} {}\n", key_id, STANDARD.encode([*byte; KEY_SIZE])))
+            .collect();
+        std::fs::write(&key_file, format!("# storage keys\n{}", content)).unwrap();
+
+        Arc::new(
+            FileKeyProvider::load(&EncryptionConfig {
+                key_file: key_file.to_str().unwrap().to_string(),
+                key_id: None,
+            })
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_storage_with_key_rotation() {
+        let temp_dir = tempdir().unwrap();
+        let disk = Arc::new(DiskStorage::new(temp_dir.path().to_str().unwrap()));
+        let topic_name = "/default/test_topic";
+
+        // a segment stored before the encryption was enabled
+        disk.put_segment(topic_name, 0, Arc::new(RwLock::new(create_test_segment(0))))
+            .await
+            .unwrap();
+
+        let storage =
+            EncryptedStorage::new(disk.clone(), key_provider(temp_dir.path(), &[("k1", 1)]));
+        storage
+            .put_segment_with_codec(
+                topic_name,
+                1,
+                Arc::new(RwLock::new(create_test_segment(1))),
+                CompressionCodec::Zstd,
+            )
+            .await
+            .unwrap();
+
+        // nothing of the message content is stored in clear
+        let stored = disk.get_segment(topic_name, 1).await.unwrap().unwrap();
+        for message in stored.read().await.messages.iter() {
+            assert!(!message.payload.windows(8).any(|w| w == b"customer"));
+            assert_eq!(message.attributes.get(KEY_ID_ATTRIBUTE).unwrap(), "k1");
+            assert!(!message.attributes.contains_key("email"));
+            assert!(message.key.is_none());
+        }
+
+        // after the rotation, the new segments use k2 and the old ones remain readable
+        let storage = EncryptedStorage::new(
+            disk.clone(),
+            key_provider(temp_dir.path(), &[("k1", 1), ("k2", 2)]),
+        );
+        storage
+            .put_segment(topic_name, 2, Arc::new(RwLock::new(create_test_segment(2))))
+            .await
+            .unwrap();
+
+        for id in 0..3 {
+            let segment = storage.get_segment(topic_name, id).await.unwrap().unwrap();
+            let segment = segment.read().await;
+            assert_eq!(segment.messages.len(), 3);
+            assert_eq!(segment.messages[1].payload, b"customer record 1");
+            assert_eq!(
+                segment.messages[1].attributes.get("email").unwrap(),
+                "a@b.c"
+            );
+            assert_eq!(segment.messages[1].key.as_deref(), Some("customer-1"));
+        }
+
+        let messages = storage
+            .read_messages(topic_name, 2, 2..3)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages[0].payload, b"customer record 2");
+
+        // without the key of the segment it can't be read
+        let storage = EncryptedStorage::new(disk, key_provider(temp_dir.path(), &[("k2", 2)]));
+        assert!(matches!(
+            storage.get_segment(topic_name, 1).await,
+            Err(StorageBackendError::Encryption(_))
+        ));
+    }
+}
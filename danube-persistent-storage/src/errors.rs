@@ -13,6 +13,8 @@ pub enum PersistentStorageError {
     InvalidPath(String),
     #[error("Bincode error: {0}")]
     Bincode(#[from] bincode::Error),
+    #[error("Corrupted segment: {0}")]
+    Corrupted(String),
     #[error("Transport error: {0}")]
     Transport(#[from] tonic::transport::Error),
     #[error("unable to parse the address: {0}")]
@@ -27,6 +29,7 @@ impl From<PersistentStorageError> for StorageBackendError {
             PersistentStorageError::Io(e) => StorageBackendError::Disk(e.to_string()),
             PersistentStorageError::InvalidPath(e) => StorageBackendError::Disk(e),
             PersistentStorageError::Bincode(e) => StorageBackendError::Disk(e.to_string()),
+            PersistentStorageError::Corrupted(e) => StorageBackendError::Disk(e),
             PersistentStorageError::Transport(e) => StorageBackendError::Managed(e.to_string()),
             PersistentStorageError::UrlParseError(e) => StorageBackendError::Managed(e.to_string()),
             PersistentStorageError::Status(e) => StorageBackendError::Managed(e.to_string()),
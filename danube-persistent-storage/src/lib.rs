@@ -6,4 +6,22 @@ pub use local_disk::DiskStorage;
 mod managed_storage;
 pub use managed_storage::RemoteStorage;
 
+mod encrypted_storage;
+pub use encrypted_storage::{EncryptedStorage, FileKeyProvider, KeyProvider};
+
+mod object_store;
+pub use object_store::{FilesystemObjectStore, ObjectStore};
+
+mod tiered_storage;
+pub use tiered_storage::TieredStorage;
+
+mod record;
+pub mod segment_chunks;
+mod segment_file;
+
+mod wal;
+pub use wal::{WalRecord, WriteAheadLog};
+
+mod circuit_breaker;
+mod compression;
 mod connection;
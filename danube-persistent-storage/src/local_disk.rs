@@ -1,21 +1,42 @@
 use async_trait::async_trait;
 use bincode;
-use danube_core::storage::{Segment, StorageBackend, StorageBackendError};
-use std::{path::PathBuf, sync::Arc};
+use danube_core::{
+    message::{MessageID, StreamMessage},
+    storage::{CompressionCodec, Segment, SegmentInfo, StorageBackend, StorageBackendError},
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio::{fs, sync::RwLock};
+use tracing::info;
 
-use crate::errors::PersistentStorageError;
+use crate::{errors::PersistentStorageError, segment_file};
 
 // DiskStorage is a storage backend that stores segments on disk.
 // This creates a directory structure like:
 // base_path/
 //     default/
 //         some_topic/
-//             segment_0.bin
-//             segment_1.bin
+//             segment_0.log
+//             segment_0.idx
+//             segment_1.log
+//             segment_1.idx
 //     other_namespace/
 //         another_topic/
-//             segment_0.bin
+//             segment_0.log
+//             segment_0.idx
+//
+// The .log and .idx file formats are described in the segment_file module.
+// The segment_N.bin files, written by the previous releases as a bincode serialized Segment,
+// are migrated to the new format the first time they are read.
+
+const SEGMENT_LOG_EXTENSION: &str = "log";
+const SEGMENT_INDEX_EXTENSION: &str = "idx";
+const LEGACY_SEGMENT_EXTENSION: &str = "bin";
 
 #[derive(Debug)]
 pub struct DiskStorage {
@@ -29,38 +50,167 @@ impl DiskStorage {
         std::fs::create_dir_all(&base_path).expect("Failed to create storage directory");
         DiskStorage { base_path }
     }
-    fn resolve_segment_path(&self, topic_name: &str, id: usize) -> Option<PathBuf> {
-        // Add validation
-        if topic_name.contains("//") {
-            return None;
-        }
+    fn resolve_segment_path(
+        &self,
+        topic_name: &str,
+        id: usize,
+        extension: &str,
+    ) -> Option<PathBuf> {
+        resolve_topic_dir(&self.base_path, topic_name)
+            .map(|topic_dir| topic_dir.join(format!("segment_{}.{}", id, extension)))
+    }
 
-        let topic_parts = topic_name.trim_start_matches('/').split('/');
-        let mut full_path = self.base_path.clone();
-        for part in topic_parts {
-            full_path = full_path.join(part);
+    fn segment_paths(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<(PathBuf, PathBuf), StorageBackendError> {
+        match (
+            self.resolve_segment_path(topic_name, id, SEGMENT_LOG_EXTENSION),
+            self.resolve_segment_path(topic_name, id, SEGMENT_INDEX_EXTENSION),
+        ) {
+            (Some(log_path), Some(index_path)) => Ok((log_path, index_path)),
+            _ => Err(StorageBackendError::Disk("Invalid topic path".to_string())),
         }
-        Some(full_path.join(format!("segment_{}.bin", id)))
     }
 
-    async fn segment_path(
+    // Returns the paths of the segment files, once the segment is present in the current format
+    pub(crate) async fn existing_segment_paths(
         &self,
         topic_name: &str,
         id: usize,
-    ) -> Result<PathBuf, PersistentStorageError> {
-        let path = self
-            .resolve_segment_path(topic_name, id)
-            .ok_or_else(|| PersistentStorageError::InvalidPath("Invalid topic name".to_string()))?;
+    ) -> Result<Option<(PathBuf, PathBuf)>, StorageBackendError> {
+        let (log_path, index_path) = self.segment_paths(topic_name, id)?;
+        if log_path.exists() {
+            return Ok(Some((log_path, index_path)));
+        }
 
-        // Ensure parent directory exists
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)
-                .await
-                .map_err(PersistentStorageError::from)?;
+        let legacy_path = self
+            .resolve_segment_path(topic_name, id, LEGACY_SEGMENT_EXTENSION)
+            .ok_or_else(|| StorageBackendError::Disk("Invalid topic path".to_string()))?;
+        if !legacy_path.exists() {
+            return Ok(None);
+        }
+
+        migrate_legacy_segment(&legacy_path, &log_path, &index_path).await?;
+        Ok(Some((log_path, index_path)))
+    }
+
+    // Lists the topics with segments on disk, from the base_path/{namespace}/{topic} directories
+    pub(crate) async fn list_topics(&self) -> Result<Vec<String>, PersistentStorageError> {
+        let mut topics = Vec::new();
+
+        let mut namespaces = fs::read_dir(&self.base_path).await?;
+        while let Some(namespace) = namespaces.next_entry().await? {
+            if !namespace.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let mut topic_dirs = fs::read_dir(namespace.path()).await?;
+            while let Some(topic) = topic_dirs.next_entry().await? {
+                if !topic.file_type().await?.is_dir() {
+                    continue;
+                }
+                if let (Some(namespace), Some(topic)) =
+                    (namespace.file_name().to_str(), topic.file_name().to_str())
+                {
+                    topics.push(format!("/{}/{}", namespace, topic));
+                }
+            }
+        }
+
+        topics.sort_unstable();
+        Ok(topics)
+    }
+}
+
+// The layout of the segments written by the previous releases.
+// bincode relies on the exact field order, so it is frozen here, independently of Segment and StreamMessage.
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct LegacySegment {
+    id: usize,
+    close_time: u64,
+    messages: Vec<LegacyStreamMessage>,
+    current_size: usize,
+    next_offset: u64,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(serde::Serialize))]
+struct LegacyStreamMessage {
+    request_id: u64,
+    msg_id: MessageID,
+    payload: Vec<u8>,
+    publish_time: u64,
+    producer_name: String,
+    subscription_name: Option<String>,
+    attributes: HashMap<String, String>,
+}
+
+impl From<LegacySegment> for Segment {
+    fn from(legacy: LegacySegment) -> Self {
+        Segment {
+            id: legacy.id,
+            close_time: legacy.close_time,
+            messages: legacy
+                .messages
+                .into_iter()
+                .map(|message| StreamMessage {
+                    request_id: message.request_id,
+                    msg_id: message.msg_id,
+                    payload: message.payload,
+                    publish_time: message.publish_time,
+                    producer_name: message.producer_name,
+                    subscription_name: message.subscription_name,
+                    attributes: message.attributes,
+                    key: None,
+                    deliver_at: None,
+                    deliver_after: None,
+                    ttl: None,
+                    expire_at: None,
+                })
+                .collect(),
+            current_size: legacy.current_size,
+            next_offset: legacy.next_offset,
         }
+    }
+}
+
+// Rewrites a bincode serialized segment, written by the previous releases, into the current format
+async fn migrate_legacy_segment(
+    legacy_path: &Path,
+    log_path: &Path,
+    index_path: &Path,
+) -> Result<(), PersistentStorageError> {
+    let bytes = fs::read(legacy_path).await?;
+    let segment: Segment = bincode::deserialize::<LegacySegment>(&bytes)?.into();
+
+    segment_file::write_segment(log_path, index_path, &segment, CompressionCodec::None).await?;
+    fs::remove_file(legacy_path).await?;
+
+    info!(
+        "Migrated the segment {} to the format version {}",
+        legacy_path.display(),
+        segment_file::SEGMENT_FORMAT_VERSION
+    );
 
-        Ok(path)
+    Ok(())
+}
+
+// Maps the topic name /{namespace}/{topic} to the base_path/{namespace}/{topic} directory
+pub(crate) fn resolve_topic_dir(base_path: &Path, topic_name: &str) -> Option<PathBuf> {
+    // Add validation
+    if topic_name.contains("//") {
+        return None;
+    }
+
+    let topic_parts = topic_name.trim_start_matches('/').split('/');
+    let mut full_path = base_path.to_path_buf();
+    for part in topic_parts {
+        full_path = full_path.join(part);
     }
+    Some(full_path)
 }
 
 #[async_trait]
@@ -70,23 +220,15 @@ impl StorageBackend for DiskStorage {
         topic_name: &str,
         id: usize,
     ) -> std::result::Result<Option<Arc<RwLock<Segment>>>, StorageBackendError> {
-        let path = match self.resolve_segment_path(topic_name, id) {
-            Some(path) => path,
-            None => return Err(StorageBackendError::Disk("Invalid topic path".to_string())),
+        let (log_path, _) = match self.existing_segment_paths(topic_name, id).await? {
+            Some(paths) => paths,
+            None => return Ok(None),
         };
 
-        if !path.exists() {
-            return Ok(None);
-        }
-
-        let bytes = fs::read(path).await.map_err(|e| {
+        let segment = segment_file::read_segment(&log_path).await.map_err(|e| {
             StorageBackendError::Disk(format!("Failed to read segment file: {}", e))
         })?;
 
-        let segment: Segment = bincode::deserialize(&bytes).map_err(|e| {
-            StorageBackendError::Disk(format!("Failed to deserialize segment: {}", e))
-        })?;
-
         Ok(Some(Arc::new(RwLock::new(segment))))
     }
 
@@ -96,13 +238,28 @@ impl StorageBackend for DiskStorage {
         id: usize,
         segment: Arc<RwLock<Segment>>,
     ) -> std::result::Result<(), StorageBackendError> {
-        let path = self.segment_path(topic_name, id).await?;
+        self.put_segment_with_codec(topic_name, id, segment, CompressionCodec::None)
+            .await
+    }
+
+    async fn put_segment_with_codec(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+        codec: CompressionCodec,
+    ) -> std::result::Result<(), StorageBackendError> {
+        let (log_path, index_path) = self.segment_paths(topic_name, id)?;
+
+        // Ensure parent directory exists
+        if let Some(parent) = log_path.parent() {
+            fs::create_dir_all(parent)
+                .await
+                .map_err(PersistentStorageError::from)?;
+        }
 
         let segment_data = segment.read().await;
-        let bytes = bincode::serialize(&*segment_data).map_err(PersistentStorageError::from)?;
-        fs::write(path, bytes)
-            .await
-            .map_err(PersistentStorageError::from)?;
+        segment_file::write_segment(&log_path, &index_path, &segment_data, codec).await?;
         Ok(())
     }
 
@@ -111,18 +268,96 @@ impl StorageBackend for DiskStorage {
         topic_name: &str,
         id: usize,
     ) -> std::result::Result<(), StorageBackendError> {
-        let path = match self.resolve_segment_path(topic_name, id) {
+        for extension in [
+            SEGMENT_LOG_EXTENSION,
+            SEGMENT_INDEX_EXTENSION,
+            LEGACY_SEGMENT_EXTENSION,
+        ] {
+            let path = match self.resolve_segment_path(topic_name, id, extension) {
+                Some(path) => path,
+                None => return Err(StorageBackendError::Disk("Invalid topic path".to_string())),
+            };
+
+            if path.exists() {
+                fs::remove_file(path).await.map_err(|e| {
+                    StorageBackendError::Disk(format!("Failed to remove segment file: {}", e))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn list_segments(
+        &self,
+        topic_name: &str,
+    ) -> std::result::Result<Vec<usize>, StorageBackendError> {
+        let topic_dir = match resolve_topic_dir(&self.base_path, topic_name) {
             Some(path) => path,
             None => return Err(StorageBackendError::Disk("Invalid topic path".to_string())),
         };
 
-        if path.exists() {
-            fs::remove_file(path).await.map_err(|e| {
-                StorageBackendError::Disk(format!("Failed to remove segment file: {}", e))
-            })?;
+        if !topic_dir.exists() {
+            return Ok(Vec::new());
         }
 
-        Ok(())
+        let mut entries = fs::read_dir(topic_dir)
+            .await
+            .map_err(PersistentStorageError::from)?;
+
+        let mut segment_ids = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(PersistentStorageError::from)?
+        {
+            let file_name = entry.file_name();
+            let segment_id = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix("segment_"))
+                .and_then(|name| {
+                    name.strip_suffix(&format!(".{}", SEGMENT_LOG_EXTENSION))
+                        .or_else(|| name.strip_suffix(&format!(".{}", LEGACY_SEGMENT_EXTENSION)))
+                })
+                .and_then(|id| id.parse::<usize>().ok());
+
+            if let Some(segment_id) = segment_id {
+                segment_ids.push(segment_id);
+            }
+        }
+
+        segment_ids.sort_unstable();
+        segment_ids.dedup();
+        Ok(segment_ids)
+    }
+
+    async fn segment_info(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> std::result::Result<Option<SegmentInfo>, StorageBackendError> {
+        let (log_path, _) = match self.existing_segment_paths(topic_name, id).await? {
+            Some(paths) => paths,
+            None => return Ok(None),
+        };
+
+        let header = segment_file::read_header(&log_path).await?;
+        Ok(Some(header.into()))
+    }
+
+    async fn read_messages(
+        &self,
+        topic_name: &str,
+        id: usize,
+        offsets: Range<u64>,
+    ) -> std::result::Result<Option<Vec<StreamMessage>>, StorageBackendError> {
+        let (log_path, index_path) = match self.existing_segment_paths(topic_name, id).await? {
+            Some(paths) => paths,
+            None => return Ok(None),
+        };
+
+        let messages = segment_file::read_messages(&log_path, &index_path, offsets).await?;
+        Ok(Some(messages))
     }
 }
 
@@ -148,6 +383,11 @@ mod tests {
             producer_name: "test_producer".to_string(),
             subscription_name: Some("test_subscription".to_string()),
             attributes: HashMap::new(),
+            key: None,
+            deliver_at: None,
+            deliver_after: None,
+            ttl: None,
+            expire_at: None,
         }
     }
 
@@ -177,6 +417,10 @@ mod tests {
             .await
             .unwrap();
 
+        // Verify the segment is listed
+        let segment_ids = storage.list_segments(topic_name).await.unwrap();
+        assert_eq!(segment_ids, vec![1]);
+
         // Verify segment exists and content matches
         let retrieved = storage.get_segment(topic_name, 1).await.unwrap().unwrap();
         assert_eq!(retrieved.read().await.id, 1);
@@ -193,4 +437,52 @@ mod tests {
         let result = storage.remove_segment(topic_name, 999).await;
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_disk_storage_migrates_legacy_segment() {
+        let temp_dir = tempdir().unwrap();
+        let storage = DiskStorage::new(temp_dir.path().to_str().unwrap());
+        let topic_name = "/default/test_topic";
+
+        // A segment written by the previous releases, as a bincode serialized Segment
+        let message = create_test_message();
+        let segment = LegacySegment {
+            id: 2,
+            close_time: 42,
+            messages: vec![LegacyStreamMessage {
+                request_id: message.request_id,
+                msg_id: message.msg_id,
+                payload: message.payload,
+                publish_time: message.publish_time,
+                producer_name: message.producer_name,
+                subscription_name: message.subscription_name,
+                attributes: message.attributes,
+            }],
+            current_size: 3,
+            next_offset: 1,
+        };
+        let legacy_path = storage
+            .resolve_segment_path(topic_name, 2, LEGACY_SEGMENT_EXTENSION)
+            .unwrap();
+        fs::create_dir_all(legacy_path.parent().unwrap())
+            .await
+            .unwrap();
+        fs::write(&legacy_path, bincode::serialize(&segment).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(storage.list_segments(topic_name).await.unwrap(), vec![2]);
+
+        let messages = storage
+            .read_messages(topic_name, 2, 0..10)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages.len(), 1);
+        assert!(!legacy_path.exists());
+
+        let retrieved = storage.get_segment(topic_name, 2).await.unwrap().unwrap();
+        assert_eq!(retrieved.read().await.close_time, 42);
+        assert_eq!(storage.list_segments(topic_name).await.unwrap(), vec![2]);
+    }
 }
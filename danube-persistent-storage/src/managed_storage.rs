@@ -1,57 +1,213 @@
 use async_trait::async_trait;
-use bytes::Bytes;
-use danube_core::storage::{RemoteStorageConfig, Segment, StorageBackend, StorageBackendError};
-use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex, RwLock};
-use tokio_stream::wrappers::ReceiverStream;
+use danube_core::{
+    message::StreamMessage,
+    storage::{
+        CompressionCodec, RemoteStorageConfig, Segment, SegmentInfo, StorageBackend,
+        StorageBackendError,
+    },
+};
+use std::{future::Future, ops::Range, sync::Arc, time::Duration};
+use tokio::sync::{Mutex, RwLock};
 use tokio_stream::StreamExt;
-use tonic::transport::{Certificate, Channel, ClientTlsConfig, Uri};
+use tonic::{
+    transport::{Certificate, Channel, ClientTlsConfig, Uri},
+    Code, Status,
+};
+use tracing::{info, warn};
 
 use crate::{
+    circuit_breaker::CircuitBreaker,
     connection::{new_rpc_connection, ConnectionOptions, RpcConnection},
     errors::PersistentStorageError,
+    segment_chunks::{messages_from_chunk, segment_to_chunks, SegmentAssembler},
 };
 
 // Generated gRPC client code
 use danube_core::managed_storage_proto::{
-    managed_storage_client::ManagedStorageClient, GetSegmentRequest, RemoveSegmentRequest,
-    SegmentChunk,
+    managed_storage_client::ManagedStorageClient, GetSegmentInfoRequest, GetSegmentRequest,
+    ListSegmentsRequest, ReadMessagesRequest, RemoveSegmentRequest,
 };
 
+// The upper bound of the delay between the retries
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
+
+// All the calls are idempotent, the segments are addressed by topic and id,
+// so a call failed on a transport error or a timeout is retried with an exponential backoff.
+// The failed calls drop the cached client, the next attempt establishes a new connection.
+// Once the service is unavailable for circuit_breaker_threshold calls in a row, the calls
+// fail fast with StorageBackendError::Unavailable, until circuit_breaker_reset is elapsed.
 #[derive(Debug, Clone)]
 pub struct RemoteStorage {
     client: Arc<Mutex<Option<ManagedStorageClient<Channel>>>>,
     connection_config: RemoteStorageConfig,
+    breaker: Arc<CircuitBreaker>,
+}
+
+// The failure of a single call attempt
+#[derive(Debug)]
+struct CallError {
+    code: Code,
+    message: String,
+}
+
+impl CallError {
+    // The transport errors and the timeouts may succeed on another attempt
+    fn is_retryable(&self) -> bool {
+        matches!(
+            self.code,
+            Code::Unavailable | Code::DeadlineExceeded | Code::Aborted | Code::Unknown
+        )
+    }
+}
+
+impl From<Status> for CallError {
+    fn from(status: Status) -> Self {
+        CallError {
+            code: status.code(),
+            message: status.message().to_string(),
+        }
+    }
 }
 
 impl RemoteStorage {
     pub fn new(config: RemoteStorageConfig) -> Self {
+        let breaker = CircuitBreaker::new(
+            config.circuit_breaker_threshold,
+            Duration::from_millis(config.circuit_breaker_reset),
+        );
+
         Self {
             client: Arc::new(Mutex::new(None)),
             connection_config: config,
+            breaker: Arc::new(breaker),
         }
     }
 
-    async fn ensure_connected(&self) -> Result<(), PersistentStorageError> {
+    // Returns the cached client, or connects to the service
+    async fn connect(&self) -> Result<ManagedStorageClient<Channel>, PersistentStorageError> {
         let mut client_guard = self.client.lock().await;
-        if client_guard.is_none() {
-            let tls_config = if self.connection_config.use_tls {
-                let ca_cert = std::fs::read(&self.connection_config.ca_file)?;
-                Some(ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_cert)))
-            } else {
-                None
-            };
+        if let Some(client) = client_guard.as_ref() {
+            return Ok(client.clone());
+        }
+
+        let tls_config = if self.connection_config.use_tls {
+            let ca_cert = std::fs::read(&self.connection_config.ca_file)?;
+            Some(ClientTlsConfig::new().ca_certificate(Certificate::from_pem(ca_cert)))
+        } else {
+            None
+        };
+
+        let connect_timeout = match self.connection_config.connection_timeout {
+            0 => None,
+            timeout => Some(Duration::from_millis(timeout as u64)),
+        };
+
+        let cnx_options = ConnectionOptions {
+            tls_config,
+            use_tls: self.connection_config.use_tls,
+            connect_timeout,
+        };
 
-            let cnx_options = ConnectionOptions {
-                tls_config,
-                use_tls: self.connection_config.use_tls,
+        let uri: Uri = self.connection_config.endpoint.parse()?;
+        let RpcConnection { grpc_cnx } = new_rpc_connection(&cnx_options, &uri).await?;
+        let client = ManagedStorageClient::new(grpc_cnx);
+        *client_guard = Some(client.clone());
+
+        Ok(client)
+    }
+
+    // Runs the call with the retries, the deadline is applied to each attempt
+    async fn call<T, F, Fut>(&self, operation: &str, call: F) -> Result<T, StorageBackendError>
+    where
+        F: Fn(ManagedStorageClient<Channel>) -> Fut + Send + Sync,
+        Fut: Future<Output = Result<T, Status>> + Send,
+        T: Send,
+    {
+        if !self.breaker.allow_call() {
+            return Err(StorageBackendError::Unavailable(format!(
+                "the managed storage {} is unavailable, {} is rejected",
+                self.connection_config.endpoint, operation
+            )));
+        }
+
+        let mut attempt = 0;
+        loop {
+            let error = match self.attempt(&call).await {
+                Ok(value) => {
+                    if self.breaker.record_success() {
+                        info!(
+                            "The managed storage {} is available again",
+                            self.connection_config.endpoint
+                        );
+                    }
+                    return Ok(value);
+                }
+                Err(error) => error,
             };
 
-            let uri: Uri = self.connection_config.endpoint.parse()?;
-            let RpcConnection { grpc_cnx } = new_rpc_connection(&cnx_options, &uri).await?;
-            *client_guard = Some(ManagedStorageClient::new(grpc_cnx));
+            if !error.is_retryable() {
+                // the service has answered, so it is available
+                self.breaker.record_success();
+                return Err(StorageBackendError::Managed(format!(
+                    "{} failed: {}",
+                    operation, error.message
+                )));
+            }
+
+            // the channel may be broken, the next attempt reconnects
+            *self.client.lock().await = None;
+
+            if attempt >= self.connection_config.max_retries {
+                if self.breaker.record_failure() {
+                    warn!(
+                        "The managed storage {} is unavailable, the calls are rejected for {} ms",
+                        self.connection_config.endpoint,
+                        self.connection_config.circuit_breaker_reset
+                    );
+                }
+                return Err(StorageBackendError::Managed(format!(
+                    "{} failed after {} attempts: {}",
+                    operation,
+                    attempt + 1,
+                    error.message
+                )));
+            }
+
+            let backoff = Duration::from_millis(
+                self.connection_config
+                    .retry_backoff
+                    .saturating_mul(1 << attempt.min(16)),
+            )
+            .min(MAX_RETRY_BACKOFF);
+
+            warn!(
+                "{} to the managed storage failed, retrying in {:?}: {}",
+                operation, backoff, error.message
+            );
+
+            tokio::time::sleep(backoff).await;
+            attempt += 1;
+        }
+    }
+
+    async fn attempt<T, F, Fut>(&self, call: &F) -> Result<T, CallError>
+    where
+        F: Fn(ManagedStorageClient<Channel>) -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let client = self.connect().await.map_err(|e| CallError {
+            code: Code::Unavailable,
+            message: e.to_string(),
+        })?;
+
+        let request_timeout = Duration::from_millis(self.connection_config.request_timeout);
+        match tokio::time::timeout(request_timeout, call(client)).await {
+            Ok(result) => result.map_err(CallError::from),
+            Err(_) => Err(CallError {
+                code: Code::DeadlineExceeded,
+                message: format!("no response within {:?}", request_timeout),
+            }),
         }
-        Ok(())
     }
 }
 
@@ -62,33 +218,35 @@ impl StorageBackend for RemoteStorage {
         topic_name: &str,
         id: usize,
     ) -> Result<Option<Arc<RwLock<Segment>>>, StorageBackendError> {
-        self.ensure_connected().await?;
-
-        let mut client_guard = self.client.lock().await;
-        let client = client_guard.as_mut().unwrap();
-
         let request = GetSegmentRequest {
             topic_name: topic_name.to_string(),
             segment_id: id as u64,
         };
 
-        let response = match client.get_segment(request).await {
-            Ok(response) => response,
-            Err(status) if status.code() == tonic::Code::NotFound => return Ok(None),
-            Err(e) => return Err(StorageBackendError::Managed(e.to_string())),
-        };
+        let segment = self
+            .call("get_segment", |mut client| {
+                let request = request.clone();
+                async move {
+                    let response = match client.get_segment(request).await {
+                        Ok(response) => response,
+                        Err(status) if status.code() == Code::NotFound => return Ok(None),
+                        Err(status) => return Err(status),
+                    };
 
-        let mut stream = response.into_inner();
-        let mut segment_data = Vec::new();
+                    let mut stream = response.into_inner();
+                    let mut assembler = SegmentAssembler::default();
 
-        while let Some(chunk) = stream.next().await {
-            let chunk = chunk.map_err(|e| StorageBackendError::Managed(e.to_string()))?;
-            segment_data.extend_from_slice(&chunk.chunk_data);
-        }
+                    while let Some(chunk) = stream.next().await {
+                        assembler.push(chunk?).map_err(Status::data_loss)?;
+                    }
 
-        let segment: Segment = bincode::deserialize(&segment_data)
-            .map_err(|e| StorageBackendError::Managed(e.to_string()))?;
-        Ok(Some(Arc::new(RwLock::new(segment))))
+                    let (_, _, segment) = assembler.finish().map_err(Status::data_loss)?;
+                    Ok(Some(segment))
+                }
+            })
+            .await?;
+
+        Ok(segment.map(|segment| Arc::new(RwLock::new(segment))))
     }
 
     async fn put_segment(
@@ -97,45 +255,36 @@ impl StorageBackend for RemoteStorage {
         id: usize,
         segment: Arc<RwLock<Segment>>,
     ) -> Result<(), StorageBackendError> {
-        self.ensure_connected().await?;
-
-        let mut client_guard = self.client.lock().await;
-        let client = client_guard.as_mut().unwrap();
-
-        const CHUNK_SIZE: usize = 2_097_152; // 2MB chunks
-        let (tx, rx) = mpsc::channel(10);
-
-        let segment_data = segment.read().await;
-        let serialized = bincode::serialize(&*segment_data)
-            .map_err(|e| StorageBackendError::Managed(e.to_string()))?;
-
-        let topic_name = topic_name.to_string();
-        let total_chunks = (serialized.len() + CHUNK_SIZE - 1) / CHUNK_SIZE;
-
-        tokio::spawn(async move {
-            for (chunk_index, chunk) in serialized.chunks(CHUNK_SIZE).enumerate() {
-                let is_last_chunk = chunk_index == total_chunks - 1;
+        self.put_segment_with_codec(topic_name, id, segment, CompressionCodec::None)
+            .await
+    }
 
-                let chunk_request = SegmentChunk {
-                    topic_name: topic_name.clone(),
-                    segment_id: id as u64,
-                    chunk_data: Bytes::copy_from_slice(chunk).to_vec(),
-                    chunk_index: chunk_index as u64,
-                    is_last_chunk,
-                };
+    async fn put_segment_with_codec(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+        codec: CompressionCodec,
+    ) -> Result<(), StorageBackendError> {
+        // the segment is sent compressed, and stored compressed by the service
+        let chunks = {
+            let segment_data = segment.read().await;
+            segment_to_chunks(topic_name, id, &segment_data, codec)
+                .map_err(|e| StorageBackendError::Managed(e.to_string()))?
+        };
+        let total_chunks = chunks.len();
 
-                if tx.send(chunk_request).await.is_err() {
-                    break;
+        let chunks_received = self
+            .call("put_segment", |mut client| {
+                let chunks = chunks.clone();
+                async move {
+                    let response = client.put_segment(tokio_stream::iter(chunks)).await?;
+                    Ok(response.get_ref().total_chunks_received as usize)
                 }
-            }
-        });
-
-        let response = client
-            .put_segment(ReceiverStream::new(rx))
-            .await
-            .map_err(|e| StorageBackendError::Managed(e.to_string()))?;
+            })
+            .await?;
 
-        if response.get_ref().total_chunks_received as usize != total_chunks {
+        if chunks_received != total_chunks {
             return Err(StorageBackendError::Managed(
                 "Incomplete segment transmission".to_string(),
             ));
@@ -145,19 +294,150 @@ impl StorageBackend for RemoteStorage {
     }
 
     async fn remove_segment(&self, topic_name: &str, id: usize) -> Result<(), StorageBackendError> {
-        self.ensure_connected().await?;
+        let request = RemoveSegmentRequest {
+            topic_name: topic_name.to_string(),
+            segment_id: id as u64,
+        };
 
-        let mut client_guard = self.client.lock().await;
-        let client = client_guard.as_mut().unwrap();
+        self.call("remove_segment", |mut client| {
+            let request = request.clone();
+            async move {
+                client.remove_segment(request).await?;
+                Ok(())
+            }
+        })
+        .await
+    }
 
-        let request = RemoveSegmentRequest {
+    async fn list_segments(&self, topic_name: &str) -> Result<Vec<usize>, StorageBackendError> {
+        let request = ListSegmentsRequest {
+            topic_name: topic_name.to_string(),
+        };
+
+        let segment_ids = self
+            .call("list_segments", |mut client| {
+                let request = request.clone();
+                async move {
+                    Ok(client
+                        .list_segments(request)
+                        .await?
+                        .into_inner()
+                        .segment_ids)
+                }
+            })
+            .await?;
+
+        Ok(segment_ids.into_iter().map(|id| id as usize).collect())
+    }
+
+    async fn segment_info(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<SegmentInfo>, StorageBackendError> {
+        let request = GetSegmentInfoRequest {
+            topic_name: topic_name.to_string(),
+            segment_id: id as u64,
+        };
+
+        let info = self
+            .call("get_segment_info", |mut client| {
+                let request = request.clone();
+                async move {
+                    match client.get_segment_info(request).await {
+                        Ok(response) => Ok(Some(response.into_inner())),
+                        Err(status) if status.code() == Code::NotFound => Ok(None),
+                        Err(status) => Err(status),
+                    }
+                }
+            })
+            .await?;
+
+        Ok(info.map(|info| SegmentInfo {
+            id: info.segment_id as usize,
+            close_time: info.close_time,
+            current_size: info.current_size as usize,
+            message_count: info.message_count,
+            next_offset: info.next_offset,
+        }))
+    }
+
+    async fn read_messages(
+        &self,
+        topic_name: &str,
+        id: usize,
+        offsets: Range<u64>,
+    ) -> Result<Option<Vec<StreamMessage>>, StorageBackendError> {
+        let request = ReadMessagesRequest {
             topic_name: topic_name.to_string(),
             segment_id: id as u64,
+            start_offset: offsets.start,
+            end_offset: offsets.end,
         };
 
-        match client.remove_segment(request).await {
-            Ok(_) => Ok(()),
-            Err(e) => Err(StorageBackendError::Managed(e.to_string())),
+        self.call("read_messages", |mut client| {
+            let request = request.clone();
+            async move {
+                let response = match client.read_messages(request).await {
+                    Ok(response) => response,
+                    Err(status) if status.code() == Code::NotFound => return Ok(None),
+                    Err(status) => return Err(status),
+                };
+
+                let mut stream = response.into_inner();
+                let mut messages = Vec::new();
+
+                while let Some(chunk) = stream.next().await {
+                    messages.extend(
+                        messages_from_chunk(&chunk?)
+                            .map_err(|e| Status::data_loss(e.to_string()))?,
+                    );
+                }
+
+                Ok(Some(messages))
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_unavailable_service_opens_the_circuit() {
+        // nothing listens on the port
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let storage = RemoteStorage::new(RemoteStorageConfig {
+            endpoint,
+            use_tls: false,
+            ca_file: String::new(),
+            connection_timeout: 1000,
+            request_timeout: 1000,
+            max_retries: 1,
+            retry_backoff: 10,
+            circuit_breaker_threshold: 2,
+            circuit_breaker_reset: 60_000,
+        });
+        let topic_name = "/default/test_topic";
+
+        for _ in 0..2 {
+            let result = storage.list_segments(topic_name).await;
+            assert!(
+                matches!(result, Err(StorageBackendError::Managed(ref e)) if e.contains("after 2 attempts")),
+                "{:?}",
+                result
+            );
         }
+
+        // fails fast, without reaching the service
+        assert!(matches!(
+            storage.segment_info(topic_name, 0).await,
+            Err(StorageBackendError::Unavailable(_))
+        ));
     }
 }
@@ -0,0 +1,189 @@
+use async_trait::async_trait;
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::errors::PersistentStorageError;
+
+/// The object interface of the cold storage tier, modelled after the S3 API.
+/// The keys are `/` separated paths, like `default/some_topic/segment_0.log`.
+#[async_trait]
+pub trait ObjectStore: Send + Sync + std::fmt::Debug + 'static {
+    async fn put_object(&self, key: &str, bytes: Vec<u8>) -> Result<(), PersistentStorageError>;
+    // Returns None if the object doesn't exist
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, PersistentStorageError>;
+    // Lists the keys starting with the prefix, in ascending order
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<String>, PersistentStorageError>;
+    // Deleting a missing object is not an error
+    async fn delete_object(&self, key: &str) -> Result<(), PersistentStorageError>;
+}
+
+// FilesystemObjectStore is an ObjectStore that stores each object as a file under the bucket directory.
+// It allows to run and test the tiered storage without an object store service.
+#[derive(Debug)]
+pub struct FilesystemObjectStore {
+    bucket_path: PathBuf,
+}
+
+impl FilesystemObjectStore {
+    pub fn new(path: impl Into<String>) -> Self {
+        let bucket_path = PathBuf::from(path.into());
+        std::fs::create_dir_all(&bucket_path).expect("Failed to create the bucket directory");
+        FilesystemObjectStore { bucket_path }
+    }
+
+    fn object_path(&self, key: &str) -> std::io::Result<PathBuf> {
+        let key_path = Path::new(key);
+        // the key must stay within the bucket directory
+        if key.is_empty()
+            || !key_path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid object key: {}", key),
+            ));
+        }
+        Ok(self.bucket_path.join(key_path))
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FilesystemObjectStore {
+    async fn put_object(&self, key: &str, bytes: Vec<u8>) -> Result<(), PersistentStorageError> {
+        let path = self.object_path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // the object is visible only once fully written, as with a completed upload
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".upload");
+        let mut file = fs::File::create(&tmp_path).await?;
+        file.write_all(&bytes).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, &path).await?;
+
+        Ok(())
+    }
+
+    async fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, PersistentStorageError> {
+        match fs::read(self.object_path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<String>, PersistentStorageError> {
+        // start from the deepest directory of the prefix, the keys below are filtered by the prefix
+        let prefix_dir = match prefix.rfind('/') {
+            Some(position) => self.object_path(&prefix[..position])?,
+            None => self.bucket_path.clone(),
+        };
+
+        let mut keys = Vec::new();
+        let mut directories = vec![prefix_dir];
+        while let Some(directory) = directories.pop() {
+            let mut entries = match fs::read_dir(&directory).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    directories.push(path);
+                    continue;
+                }
+
+                let key = path
+                    .strip_prefix(&self.bucket_path)
+                    .ok()
+                    .and_then(|relative| relative.to_str())
+                    .map(|relative| relative.replace(std::path::MAIN_SEPARATOR, "/"));
+                if let Some(key) = key {
+                    if key.starts_with(prefix) && !key.ends_with(".upload") {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+
+        keys.sort_unstable();
+        Ok(keys)
+    }
+
+    async fn delete_object(&self, key: &str) -> Result<(), PersistentStorageError> {
+        match fs::remove_file(self.object_path(key)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_filesystem_object_store() {
+        let temp_dir = tempdir().unwrap();
+        let store = FilesystemObjectStore::new(temp_dir.path().to_str().unwrap());
+
+        store
+            .put_object("default/topic/segment_1.log", vec![1])
+            .await
+            .unwrap();
+        store
+            .put_object("default/topic/segment_0.log", vec![0])
+            .await
+            .unwrap();
+        store
+            .put_object("default/other/segment_0.log", vec![2])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store
+                .get_object("default/topic/segment_1.log")
+                .await
+                .unwrap(),
+            Some(vec![1])
+        );
+        assert_eq!(
+            store
+                .get_object("default/topic/segment_2.log")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            store.list_objects("default/topic/").await.unwrap(),
+            vec!["default/topic/segment_0.log", "default/topic/segment_1.log"]
+        );
+        assert_eq!(store.list_objects("default/").await.unwrap().len(), 3);
+        assert!(store.list_objects("missing/").await.unwrap().is_empty());
+
+        store
+            .delete_object("default/topic/segment_0.log")
+            .await
+            .unwrap();
+        store
+            .delete_object("default/topic/segment_0.log")
+            .await
+            .unwrap();
+        assert_eq!(
+            store.list_objects("default/topic/").await.unwrap(),
+            vec!["default/topic/segment_1.log"]
+        );
+
+        assert!(store.get_object("../outside").await.is_err());
+    }
+}
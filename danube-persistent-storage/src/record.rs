@@ -0,0 +1,34 @@
+// Framing shared by the on-disk files of the storage (write-ahead log, segment files).
+// Each record is framed as [length: u32 LE][crc32: u32 LE][payload].
+
+pub(crate) const RECORD_HEADER_SIZE: usize = 8;
+
+// Frames the payload with its length and checksum
+pub(crate) fn frame_record(payload: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    buffer.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    buffer.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    buffer.extend_from_slice(payload);
+    buffer
+}
+
+// Parses the record header, returns the payload length and checksum
+pub(crate) fn parse_record_header(header: &[u8; RECORD_HEADER_SIZE]) -> (usize, u32) {
+    let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    (length, checksum)
+}
+
+// Returns the payload of the first record and the number of bytes the record occupies,
+// or None if the record is incomplete or fails the checksum
+pub(crate) fn unframe_record(bytes: &[u8]) -> Option<(&[u8], usize)> {
+    let header: &[u8; RECORD_HEADER_SIZE] = bytes.get(..RECORD_HEADER_SIZE)?.try_into().ok()?;
+    let (length, checksum) = parse_record_header(header);
+    let payload = bytes.get(RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + length)?;
+
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+
+    Some((payload, RECORD_HEADER_SIZE + length))
+}
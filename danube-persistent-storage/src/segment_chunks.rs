@@ -0,0 +1,243 @@
+use danube_core::{
+    managed_storage_proto::{MessagesChunk, SegmentChunk},
+    message::StreamMessage,
+    storage::{CompressionCodec, Segment},
+};
+
+use crate::compression::{codec_from_id, codec_id, compress, decompress};
+
+// The segments are transferred to and from the ManagedStorage service as a stream of chunks,
+// of the bincode serialized segment, compressed with the codec set on the chunks.
+// The chunks are sent in order, the last one is flagged.
+// The messages read from a segment are streamed as chunks of bincode serialized messages.
+
+/// The maximum size of the chunk data
+pub const SEGMENT_CHUNK_SIZE: usize = 2_097_152; // 2MB chunks
+
+/// Splits the serialized and compressed segment into the chunks to be streamed
+pub fn segment_to_chunks(
+    topic_name: &str,
+    id: usize,
+    segment: &Segment,
+    codec: CompressionCodec,
+) -> Result<Vec<SegmentChunk>, bincode::Error> {
+    let serialized =
+        compress(codec, &bincode::serialize(segment)?).map_err(bincode::ErrorKind::Io)?;
+    let total_chunks = serialized.len().div_ceil(SEGMENT_CHUNK_SIZE);
+
+    Ok(serialized
+        .chunks(SEGMENT_CHUNK_SIZE)
+        .enumerate()
+        .map(|(chunk_index, chunk)| SegmentChunk {
+            topic_name: topic_name.to_string(),
+            segment_id: id as u64,
+            chunk_data: chunk.to_vec(),
+            chunk_index: chunk_index as u64,
+            is_last_chunk: chunk_index == total_chunks - 1,
+            compression: codec_id(codec) as i32,
+        })
+        .collect())
+}
+
+/// Groups the messages into chunks of about SEGMENT_CHUNK_SIZE, a larger message gets its own chunk
+pub fn messages_to_chunks(
+    messages: &[StreamMessage],
+) -> Result<Vec<MessagesChunk>, bincode::Error> {
+    let mut chunks = Vec::new();
+    let mut chunk_start = 0;
+    let mut chunk_size = 0;
+
+    for (position, message) in messages.iter().enumerate() {
+        if position > chunk_start && chunk_size + message.size() > SEGMENT_CHUNK_SIZE {
+            chunks.push(MessagesChunk {
+                messages_data: bincode::serialize(&messages[chunk_start..position])?,
+            });
+            chunk_start = position;
+            chunk_size = 0;
+        }
+        chunk_size += message.size();
+    }
+
+    if chunk_start < messages.len() {
+        chunks.push(MessagesChunk {
+            messages_data: bincode::serialize(&messages[chunk_start..])?,
+        });
+    }
+
+    Ok(chunks)
+}
+
+pub fn messages_from_chunk(chunk: &MessagesChunk) -> Result<Vec<StreamMessage>, bincode::Error> {
+    bincode::deserialize(&chunk.messages_data)
+}
+
+/// Rebuilds a segment from its streamed chunks
+#[derive(Debug, Default)]
+pub struct SegmentAssembler {
+    topic_name: String,
+    segment_id: u64,
+    compression: i32,
+    data: Vec<u8>,
+    chunks_received: u64,
+    complete: bool,
+}
+
+impl SegmentAssembler {
+    /// Appends the chunk, returns the reason if the chunk doesn't follow the previous ones
+    pub fn push(&mut self, chunk: SegmentChunk) -> Result<(), String> {
+        if self.complete {
+            return Err("chunk received after the last chunk".to_string());
+        }
+        if chunk.chunk_index != self.chunks_received {
+            return Err(format!(
+                "expected the chunk {}, received the chunk {}",
+                self.chunks_received, chunk.chunk_index
+            ));
+        }
+
+        if self.chunks_received == 0 {
+            self.topic_name = chunk.topic_name;
+            self.segment_id = chunk.segment_id;
+            self.compression = chunk.compression;
+        } else if chunk.topic_name != self.topic_name
+            || chunk.segment_id != self.segment_id
+            || chunk.compression != self.compression
+        {
+            return Err("the chunks belong to different segments".to_string());
+        }
+
+        self.data.extend_from_slice(&chunk.chunk_data);
+        self.chunks_received += 1;
+        self.complete = chunk.is_last_chunk;
+        Ok(())
+    }
+
+    pub fn chunks_received(&self) -> u64 {
+        self.chunks_received
+    }
+
+    /// The codec of the received segment
+    pub fn compression(&self) -> Result<CompressionCodec, String> {
+        u16::try_from(self.compression)
+            .map_err(|e| e.to_string())
+            .and_then(codec_from_id)
+    }
+
+    /// Returns the topic name, the segment id and the segment, once all the chunks are received
+    pub fn finish(self) -> Result<(String, usize, Segment), String> {
+        if !self.complete {
+            return Err(format!(
+                "the segment is incomplete, {} chunks received",
+                self.chunks_received
+            ));
+        }
+
+        let data = decompress(self.compression()?, &self.data)?;
+        let segment = bincode::deserialize(&data).map_err(|e| e.to_string())?;
+        Ok((self.topic_name, self.segment_id as usize, segment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use danube_core::message::MessageID;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_segment_chunks_roundtrip() {
+        let mut segment = Segment::new(3, 1024);
+        segment.add_message(StreamMessage {
+            request_id: 1,
+            msg_id: MessageID {
+                producer_id: 1,
+                topic_name: "/default/test_topic".to_string(),
+                broker_addr: "localhost:6650".to_string(),
+                segment_id: 3,
+                segment_offset: 0,
+            },
+            payload: vec![7; SEGMENT_CHUNK_SIZE + 10],
+            publish_time: 123456789,
+            producer_name: "test_producer".to_string(),
+            subscription_name: None,
+            attributes: HashMap::new(),
+            key: None,
+            deliver_at: None,
+            deliver_after: None,
+            ttl: None,
+            expire_at: None,
+        });
+
+        let chunks =
+            segment_to_chunks("/default/test_topic", 3, &segment, CompressionCodec::None).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1].is_last_chunk);
+
+        // the repeated payload fits in a single compressed chunk
+        let compressed =
+            segment_to_chunks("/default/test_topic", 3, &segment, CompressionCodec::Zstd).unwrap();
+        assert_eq!(compressed.len(), 1);
+        let mut assembler = SegmentAssembler::default();
+        assembler.push(compressed[0].clone()).unwrap();
+        assert_eq!(assembler.compression().unwrap(), CompressionCodec::Zstd);
+        let (_, _, received) = assembler.finish().unwrap();
+        assert_eq!(received.messages[0].payload, segment.messages[0].payload);
+
+        // the chunks must arrive in order
+        let mut assembler = SegmentAssembler::default();
+        assert!(assembler.push(chunks[1].clone()).is_err());
+
+        let mut assembler = SegmentAssembler::default();
+        for chunk in chunks {
+            assembler.push(chunk).unwrap();
+        }
+        assert_eq!(assembler.chunks_received(), 2);
+
+        let (topic_name, segment_id, received) = assembler.finish().unwrap();
+        assert_eq!(topic_name, "/default/test_topic");
+        assert_eq!(segment_id, 3);
+        assert_eq!(received.messages[0].payload, segment.messages[0].payload);
+    }
+
+    #[test]
+    fn test_messages_chunks() {
+        let message = |offset: u64, size: usize| StreamMessage {
+            request_id: offset,
+            msg_id: MessageID {
+                producer_id: 1,
+                topic_name: "/default/test_topic".to_string(),
+                broker_addr: "localhost:6650".to_string(),
+                segment_id: 0,
+                segment_offset: offset,
+            },
+            payload: vec![1; size],
+            publish_time: 123456789,
+            producer_name: "test_producer".to_string(),
+            subscription_name: None,
+            attributes: HashMap::new(),
+            key: None,
+            deliver_at: None,
+            deliver_after: None,
+            ttl: None,
+            expire_at: None,
+        };
+
+        assert!(messages_to_chunks(&[]).unwrap().is_empty());
+
+        let messages = vec![
+            message(0, 10),
+            message(1, SEGMENT_CHUNK_SIZE),
+            message(2, 10),
+            message(3, 10),
+        ];
+        let chunks = messages_to_chunks(&messages).unwrap();
+        assert_eq!(chunks.len(), 3);
+
+        let offsets: Vec<u64> = chunks
+            .iter()
+            .flat_map(|chunk| messages_from_chunk(chunk).unwrap())
+            .map(|message| message.msg_id.segment_offset)
+            .collect();
+        assert_eq!(offsets, vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,508 @@
+use danube_core::{
+    message::StreamMessage,
+    proto::StreamMessage as ProtoStreamMessage,
+    storage::{CompressionCodec, Segment, SegmentInfo},
+};
+use prost::Message;
+use std::{io::ErrorKind, ops::Range, path::Path};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufReader, SeekFrom},
+};
+
+use crate::{
+    compression::{codec_from_id, codec_id, compress, decompress},
+    errors::PersistentStorageError,
+    record::{frame_record, parse_record_header, unframe_record, RECORD_HEADER_SIZE},
+};
+
+// The on-disk format of the segments stored by DiskStorage.
+//
+// segment_{id}.log holds the segment, all the integers are little endian:
+//     header:  [magic "DSEG"][version: u16][flags: u16][segment_id: u64][close_time: u64]
+//              [next_offset: u64][current_size: u64][message_count: u64][crc32: u32]
+//     records: one per message, the protobuf StreamMessage framed as [length: u32][crc32: u32][payload]
+//
+// The compressed segments (version 2) store the codec in the low bits of the header flags.
+// Each record holds a block of INDEX_INTERVAL messages, the compressed concatenation of their
+// framed records, so a range read decompresses only the blocks around the range.
+//
+// segment_{id}.idx holds the sparse index of the log, the position of every INDEX_INTERVAL message:
+//     header:  [magic "DIDX"][version: u16][interval: u16]
+//     entries: [offset: u64][position: u64]
+//
+// The messages are encoded with protobuf, so new StreamMessage fields don't break the existing files.
+
+const SEGMENT_MAGIC: &[u8; 4] = b"DSEG";
+const INDEX_MAGIC: &[u8; 4] = b"DIDX";
+pub(crate) const SEGMENT_FORMAT_VERSION: u16 = 1;
+// the uncompressed segments keep the version 1, readable by the previous releases
+const COMPRESSED_SEGMENT_FORMAT_VERSION: u16 = 2;
+const CODEC_FLAGS_MASK: u16 = 0x000f;
+const SEGMENT_HEADER_SIZE: usize = 52;
+const INDEX_HEADER_SIZE: usize = 8;
+const INDEX_ENTRY_SIZE: usize = 16;
+const INDEX_INTERVAL: u16 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SegmentHeader {
+    pub(crate) version: u16,
+    // the low bits hold the compression codec, the others are reserved
+    pub(crate) flags: u16,
+    pub(crate) segment_id: usize,
+    pub(crate) close_time: u64,
+    pub(crate) next_offset: u64,
+    pub(crate) current_size: usize,
+    pub(crate) message_count: u64,
+}
+
+impl SegmentHeader {
+    fn new(segment: &Segment, codec: CompressionCodec) -> Self {
+        let version = match codec {
+            CompressionCodec::None => SEGMENT_FORMAT_VERSION,
+            _ => COMPRESSED_SEGMENT_FORMAT_VERSION,
+        };
+
+        SegmentHeader {
+            version,
+            flags: codec_id(codec),
+            segment_id: segment.id,
+            close_time: segment.close_time,
+            next_offset: segment.next_offset,
+            current_size: segment.current_size,
+            message_count: segment.messages.len() as u64,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(SEGMENT_HEADER_SIZE);
+        buffer.extend_from_slice(SEGMENT_MAGIC);
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        buffer.extend_from_slice(&self.flags.to_le_bytes());
+        buffer.extend_from_slice(&(self.segment_id as u64).to_le_bytes());
+        buffer.extend_from_slice(&self.close_time.to_le_bytes());
+        buffer.extend_from_slice(&self.next_offset.to_le_bytes());
+        buffer.extend_from_slice(&(self.current_size as u64).to_le_bytes());
+        buffer.extend_from_slice(&self.message_count.to_le_bytes());
+        let checksum = crc32fast::hash(&buffer);
+        buffer.extend_from_slice(&checksum.to_le_bytes());
+        buffer
+    }
+
+    // Returns the header, or the reason the header is invalid
+    fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let header = bytes
+            .get(..SEGMENT_HEADER_SIZE)
+            .ok_or("the segment header is truncated")?;
+
+        if &header[0..4] != SEGMENT_MAGIC {
+            return Err("not a segment file".to_string());
+        }
+        if crc32fast::hash(&header[..SEGMENT_HEADER_SIZE - 4]) != read_u32(header, 48) {
+            return Err("the segment header checksum doesn't match".to_string());
+        }
+
+        let version = read_u16(header, 4);
+        if version > COMPRESSED_SEGMENT_FORMAT_VERSION {
+            return Err(format!("unsupported segment format version {}", version));
+        }
+
+        let flags = read_u16(header, 6);
+        codec_from_id(flags & CODEC_FLAGS_MASK)?;
+
+        Ok(SegmentHeader {
+            version,
+            flags,
+            segment_id: read_u64(header, 8) as usize,
+            close_time: read_u64(header, 16),
+            next_offset: read_u64(header, 24),
+            current_size: read_u64(header, 32) as usize,
+            message_count: read_u64(header, 40),
+        })
+    }
+
+    pub(crate) fn codec(&self) -> CompressionCodec {
+        // validated by decode
+        codec_from_id(self.flags & CODEC_FLAGS_MASK).unwrap_or_default()
+    }
+}
+
+impl From<SegmentHeader> for SegmentInfo {
+    fn from(header: SegmentHeader) -> Self {
+        SegmentInfo {
+            id: header.segment_id,
+            close_time: header.close_time,
+            current_size: header.current_size,
+            message_count: header.message_count,
+            next_offset: header.next_offset,
+        }
+    }
+}
+
+/// Writes the segment, compressed with the codec, and its sparse index.
+/// Each file is replaced atomically
+pub(crate) async fn write_segment(
+    log_path: &Path,
+    index_path: &Path,
+    segment: &Segment,
+    codec: CompressionCodec,
+) -> Result<(), PersistentStorageError> {
+    let mut log = SegmentHeader::new(segment, codec).encode();
+
+    let mut index = Vec::new();
+    index.extend_from_slice(INDEX_MAGIC);
+    index.extend_from_slice(&SEGMENT_FORMAT_VERSION.to_le_bytes());
+    index.extend_from_slice(&INDEX_INTERVAL.to_le_bytes());
+
+    // the index points to the first message of each block
+    for block in segment.messages.chunks(INDEX_INTERVAL as usize) {
+        index.extend_from_slice(&block[0].msg_id.segment_offset.to_le_bytes());
+        index.extend_from_slice(&(log.len() as u64).to_le_bytes());
+
+        let records = block
+            .iter()
+            .map(|message| frame_record(&encode_message(message)));
+        match codec {
+            CompressionCodec::None => records.for_each(|record| log.extend_from_slice(&record)),
+            codec => {
+                let block_bytes: Vec<u8> = records.flatten().collect();
+                log.extend_from_slice(&frame_record(&compress(codec, &block_bytes)?));
+            }
+        }
+    }
+
+    write_atomically(log_path, &log).await?;
+    write_atomically(index_path, &index).await?;
+
+    Ok(())
+}
+
+/// Reads the whole segment, verifying the checksum of every record
+pub(crate) async fn read_segment(log_path: &Path) -> Result<Segment, PersistentStorageError> {
+    let bytes = fs::read(log_path).await?;
+    decode_segment(&bytes).map_err(PersistentStorageError::Corrupted)
+}
+
+/// Reads only the header of the segment
+pub(crate) async fn read_header(log_path: &Path) -> Result<SegmentHeader, PersistentStorageError> {
+    let mut file = File::open(log_path).await?;
+    let mut header = [0u8; SEGMENT_HEADER_SIZE];
+    file.read_exact(&mut header).await?;
+    SegmentHeader::decode(&header).map_err(PersistentStorageError::Corrupted)
+}
+
+/// Decodes the content of a segment log, returns the reason if the log is corrupted
+pub(crate) fn decode_segment(bytes: &[u8]) -> Result<Segment, String> {
+    let header = SegmentHeader::decode(bytes)?;
+    let codec = header.codec();
+
+    let mut messages = Vec::with_capacity(header.message_count as usize);
+    let mut position = SEGMENT_HEADER_SIZE;
+    while position < bytes.len() {
+        let (payload, consumed) = unframe_record(&bytes[position..])
+            .ok_or_else(|| format!("invalid record at byte {} of the segment", position))?;
+        messages.extend(decode_record(codec, payload)?);
+        position += consumed;
+    }
+
+    if messages.len() as u64 != header.message_count {
+        return Err(format!(
+            "expected {} messages in the segment, found {}",
+            header.message_count,
+            messages.len()
+        ));
+    }
+
+    Ok(Segment {
+        id: header.segment_id,
+        close_time: header.close_time,
+        messages,
+        current_size: header.current_size,
+        next_offset: header.next_offset,
+    })
+}
+
+/// Reads the messages of the segment within the offset range.
+/// The sparse index is used to seek close to the first requested offset,
+/// so only the records around the range are read.
+pub(crate) async fn read_messages(
+    log_path: &Path,
+    index_path: &Path,
+    offsets: Range<u64>,
+) -> Result<Vec<StreamMessage>, PersistentStorageError> {
+    let mut file = File::open(log_path).await?;
+
+    let mut header = [0u8; SEGMENT_HEADER_SIZE];
+    file.read_exact(&mut header).await?;
+    let codec = SegmentHeader::decode(&header)
+        .map_err(PersistentStorageError::Corrupted)?
+        .codec();
+
+    // a missing or invalid index only costs a scan from the first record
+    let position = match fs::read(index_path).await {
+        Ok(index) => lookup_index(&index, offsets.start),
+        Err(_) => None,
+    }
+    .unwrap_or(SEGMENT_HEADER_SIZE as u64);
+
+    file.seek(SeekFrom::Start(position)).await?;
+    let mut reader = BufReader::new(file);
+
+    let mut messages = Vec::new();
+    let mut record_header = [0u8; RECORD_HEADER_SIZE];
+    loop {
+        match reader.read_exact(&mut record_header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+
+        let (length, checksum) = parse_record_header(&record_header);
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload).await?;
+        if crc32fast::hash(&payload) != checksum {
+            return Err(corrupted("invalid record checksum in the segment"));
+        }
+
+        let record_messages =
+            decode_record(codec, &payload).map_err(PersistentStorageError::Corrupted)?;
+        for message in record_messages {
+            let offset = message.msg_id.segment_offset;
+            if offset >= offsets.end {
+                return Ok(messages);
+            }
+            if offset >= offsets.start {
+                messages.push(message);
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+// Returns the position of the last indexed message with an offset lower or equal to the requested one
+fn lookup_index(index: &[u8], offset: u64) -> Option<u64> {
+    if index.len() < INDEX_HEADER_SIZE || &index[0..4] != INDEX_MAGIC {
+        return None;
+    }
+
+    index[INDEX_HEADER_SIZE..]
+        .chunks_exact(INDEX_ENTRY_SIZE)
+        .map(|entry| (read_u64(entry, 0), read_u64(entry, 8)))
+        .take_while(|(entry_offset, _)| *entry_offset <= offset)
+        .last()
+        .map(|(_, position)| position)
+}
+
+async fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), PersistentStorageError> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp_path, path).await?;
+
+    Ok(())
+}
+
+pub(crate) fn encode_message(message: &StreamMessage) -> Vec<u8> {
+    ProtoStreamMessage::from(message.clone()).encode_to_vec()
+}
+
+// Returns the messages of the log record, a compressed record holds a block of framed messages
+fn decode_record(codec: CompressionCodec, payload: &[u8]) -> Result<Vec<StreamMessage>, String> {
+    if codec == CompressionCodec::None {
+        return Ok(vec![decode_message(payload)?]);
+    }
+
+    let block = decompress(codec, payload)?;
+    let mut messages = Vec::new();
+    let mut position = 0;
+    while position < block.len() {
+        let (message, consumed) = unframe_record(&block[position..])
+            .ok_or("invalid record in the compressed block of the segment")?;
+        messages.push(decode_message(message)?);
+        position += consumed;
+    }
+
+    Ok(messages)
+}
+
+// Returns the message, or the reason the record is invalid
+pub(crate) fn decode_message(payload: &[u8]) -> Result<StreamMessage, String> {
+    let proto_message = ProtoStreamMessage::decode(payload)
+        .map_err(|e| format!("unable to decode the stored message: {}", e))?;
+    if proto_message.msg_id.is_none() {
+        return Err("stored message without a message id".to_string());
+    }
+
+    let mut message = StreamMessage::from(proto_message);
+    if message.subscription_name.as_deref() == Some("") {
+        message.subscription_name = None;
+    }
+    Ok(message)
+}
+
+fn corrupted(reason: &str) -> PersistentStorageError {
+    PersistentStorageError::Corrupted(reason.to_string())
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use danube_core::message::MessageID;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn create_test_segment(message_count: u64) -> Segment {
+        let mut segment = Segment::new(7, 1024);
+        for offset in 0..message_count {
+            segment.add_message(StreamMessage {
+                request_id: offset,
+                msg_id: MessageID {
+                    producer_id: 1,
+                    topic_name: "/default/test_topic".to_string(),
+                    broker_addr: "localhost:6650".to_string(),
+                    segment_id: 7,
+                    segment_offset: offset,
+                },
+                payload: vec![offset as u8; 16],
+                publish_time: 123456789,
+                producer_name: "test_producer".to_string(),
+                subscription_name: None,
+                attributes: HashMap::new(),
+                key: None,
+                deliver_at: None,
+                deliver_after: None,
+                ttl: None,
+                expire_at: None,
+            });
+        }
+        segment.close_time = 42;
+        segment
+    }
+
+    #[tokio::test]
+    async fn test_segment_file_roundtrip_and_range_read() {
+        let temp_dir = tempdir().unwrap();
+        let log_path = temp_dir.path().join("segment_7.log");
+        let index_path = temp_dir.path().join("segment_7.idx");
+        let segment = create_test_segment(200);
+
+        write_segment(&log_path, &index_path, &segment, CompressionCodec::None)
+            .await
+            .unwrap();
+
+        let read = read_segment(&log_path).await.unwrap();
+        assert_eq!(read.id, 7);
+        assert_eq!(read.close_time, 42);
+        assert_eq!(read.next_offset, 200);
+        assert_eq!(read.messages.len(), 200);
+        assert_eq!(read.messages[10].payload, segment.messages[10].payload);
+        assert_eq!(read.messages[10].msg_id, segment.messages[10].msg_id);
+
+        // The range crosses the index entries at offsets 64 and 128
+        let messages = read_messages(&log_path, &index_path, 100..150)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 50);
+        assert_eq!(messages[0].msg_id.segment_offset, 100);
+        assert_eq!(messages[49].msg_id.segment_offset, 149);
+
+        // Without the index the range is found by scanning the records
+        fs::remove_file(&index_path).await.unwrap();
+        let messages = read_messages(&log_path, &index_path, 198..300)
+            .await
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_compressed_segment_file() {
+        let temp_dir = tempdir().unwrap();
+        let segment = create_test_segment(200);
+
+        let plain_path = temp_dir.path().join("segment_plain.log");
+        write_segment(
+            &plain_path,
+            &temp_dir.path().join("segment_plain.idx"),
+            &segment,
+            CompressionCodec::None,
+        )
+        .await
+        .unwrap();
+        let plain_size = fs::metadata(&plain_path).await.unwrap().len();
+
+        for codec in [
+            CompressionCodec::Lz4,
+            CompressionCodec::Zstd,
+            CompressionCodec::Snappy,
+        ] {
+            let log_path = temp_dir.path().join(format!("segment_{}.log", codec));
+            let index_path = temp_dir.path().join(format!("segment_{}.idx", codec));
+
+            write_segment(&log_path, &index_path, &segment, codec)
+                .await
+                .unwrap();
+            assert!(fs::metadata(&log_path).await.unwrap().len() < plain_size);
+
+            let header = read_header(&log_path).await.unwrap();
+            assert_eq!(header.version, COMPRESSED_SEGMENT_FORMAT_VERSION);
+            assert_eq!(header.codec(), codec);
+
+            let read = read_segment(&log_path).await.unwrap();
+            assert_eq!(read.messages.len(), 200);
+            assert_eq!(read.messages[150].payload, segment.messages[150].payload);
+
+            // The range starts within the second block and ends within the third one
+            let messages = read_messages(&log_path, &index_path, 100..150)
+                .await
+                .unwrap();
+            assert_eq!(messages.len(), 50);
+            assert_eq!(messages[0].msg_id.segment_offset, 100);
+            assert_eq!(messages[49].msg_id.segment_offset, 149);
+        }
+
+        // the uncompressed segments remain readable by the previous releases
+        let header = read_header(&plain_path).await.unwrap();
+        assert_eq!(header.version, SEGMENT_FORMAT_VERSION);
+        assert_eq!(header.codec(), CompressionCodec::None);
+    }
+
+    #[tokio::test]
+    async fn test_segment_file_detects_corruption() {
+        let temp_dir = tempdir().unwrap();
+        let log_path = temp_dir.path().join("segment_7.log");
+        let index_path = temp_dir.path().join("segment_7.idx");
+
+        write_segment(
+            &log_path,
+            &index_path,
+            &create_test_segment(3),
+            CompressionCodec::None,
+        )
+        .await
+        .unwrap();
+
+        let mut bytes = fs::read(&log_path).await.unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&log_path, &bytes).await.unwrap();
+
+        let result = read_segment(&log_path).await;
+        assert!(matches!(result, Err(PersistentStorageError::Corrupted(_))));
+    }
+}
@@ -0,0 +1,357 @@
+use async_trait::async_trait;
+use danube_core::{
+    message::StreamMessage,
+    storage::{CompressionCodec, Segment, SegmentInfo, StorageBackend, StorageBackendError},
+};
+use std::{
+    ops::Range,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{fs, sync::RwLock};
+use tracing::{info, trace, warn};
+
+use crate::{
+    errors::PersistentStorageError, local_disk::DiskStorage, object_store::ObjectStore,
+    segment_file,
+};
+
+// TieredStorage is a storage backend that keeps the segments on the local disk (hot tier)
+// and moves the segments closed for longer than offload_after to an object store (cold tier).
+// The cold segments keep the segment_file .log format, under the object keys:
+//     default/some_topic/segment_0.log
+//     default/some_topic/segment_1.log
+//
+// The segments are read from the hot tier first, the cold segments are fetched back on demand,
+// like when a lagging subscription reaches them, and are kept in memory by the TopicCache.
+
+// How often the hot tier is checked for segments to offload
+const OFFLOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct TieredStorage {
+    hot: DiskStorage,
+    cold: Arc<dyn ObjectStore>,
+    // seconds since the segment was closed, after which it is moved to the cold tier
+    offload_after: u64,
+}
+
+impl TieredStorage {
+    pub fn new(hot: DiskStorage, cold: Arc<dyn ObjectStore>, offload_after: u64) -> Self {
+        TieredStorage {
+            hot,
+            cold,
+            offload_after,
+        }
+    }
+
+    /// Starts the task moving periodically the old segments to the cold tier,
+    /// the task stops once the storage is dropped.
+    pub fn start_offload_task(self: &Arc<Self>) {
+        let storage = Arc::downgrade(self);
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(OFFLOAD_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(storage) = storage.upgrade() else {
+                    break;
+                };
+                if let Err(e) = storage.offload_segments().await {
+                    warn!("Failed to move the segments to the cold tier: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Moves the closed segments older than offload_after from the hot to the cold tier.
+    /// Returns the number of moved segments.
+    pub async fn offload_segments(&self) -> Result<usize, StorageBackendError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        let mut offloaded = 0;
+        for topic_name in self.hot.list_topics().await? {
+            for id in self.hot.list_segments(&topic_name).await? {
+                let log_path = match self.hot.existing_segment_paths(&topic_name, id).await? {
+                    Some((log_path, _)) => log_path,
+                    None => continue,
+                };
+
+                let header = segment_file::read_header(&log_path).await?;
+                if header.close_time == 0
+                    || now.saturating_sub(header.close_time) < self.offload_after
+                {
+                    continue;
+                }
+
+                // the segment is removed from the hot tier only once stored in the cold tier
+                let bytes = fs::read(&log_path)
+                    .await
+                    .map_err(PersistentStorageError::from)?;
+                self.cold
+                    .put_object(&object_key(&topic_name, id), bytes)
+                    .await?;
+                self.hot.remove_segment(&topic_name, id).await?;
+
+                trace!(
+                    "Moved the segment {} of topic {} to the cold tier",
+                    id,
+                    topic_name
+                );
+                offloaded += 1;
+            }
+        }
+
+        if offloaded > 0 {
+            info!("Moved {} segments to the cold tier", offloaded);
+        }
+
+        Ok(offloaded)
+    }
+
+    async fn get_cold_segment(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<Segment>, StorageBackendError> {
+        let bytes = match self.cold.get_object(&object_key(topic_name, id)).await? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+
+        let segment = segment_file::decode_segment(&bytes).map_err(|e| {
+            PersistentStorageError::Corrupted(format!(
+                "the segment {} of topic {} in the cold tier: {}",
+                id, topic_name, e
+            ))
+        })?;
+
+        trace!(
+            "Fetched the segment {} of topic {} from the cold tier",
+            id,
+            topic_name
+        );
+        Ok(Some(segment))
+    }
+}
+
+// The topic /{namespace}/{topic} is stored under the {namespace}/{topic}/ prefix
+fn topic_prefix(topic_name: &str) -> String {
+    format!("{}/", topic_name.trim_start_matches('/'))
+}
+
+fn object_key(topic_name: &str, id: usize) -> String {
+    format!("{}segment_{}.log", topic_prefix(topic_name), id)
+}
+
+#[async_trait]
+impl StorageBackend for TieredStorage {
+    async fn get_segment(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<Arc<RwLock<Segment>>>, StorageBackendError> {
+        // the segment may be moved to the cold tier while being read from the hot tier
+        let hot_segment = self.hot.get_segment(topic_name, id).await;
+        if let Ok(Some(segment)) = hot_segment {
+            return Ok(Some(segment));
+        }
+
+        match self.get_cold_segment(topic_name, id).await? {
+            Some(segment) => Ok(Some(Arc::new(RwLock::new(segment)))),
+            None => hot_segment,
+        }
+    }
+
+    async fn put_segment(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+    ) -> Result<(), StorageBackendError> {
+        self.hot.put_segment(topic_name, id, segment).await
+    }
+
+    async fn put_segment_with_codec(
+        &self,
+        topic_name: &str,
+        id: usize,
+        segment: Arc<RwLock<Segment>>,
+        codec: CompressionCodec,
+    ) -> Result<(), StorageBackendError> {
+        // the cold tier receives the hot segment files as they are, compressed
+        self.hot
+            .put_segment_with_codec(topic_name, id, segment, codec)
+            .await
+    }
+
+    async fn remove_segment(&self, topic_name: &str, id: usize) -> Result<(), StorageBackendError> {
+        self.hot.remove_segment(topic_name, id).await?;
+        self.cold.delete_object(&object_key(topic_name, id)).await?;
+        Ok(())
+    }
+
+    async fn list_segments(&self, topic_name: &str) -> Result<Vec<usize>, StorageBackendError> {
+        let mut segment_ids = self.hot.list_segments(topic_name).await?;
+
+        let prefix = topic_prefix(topic_name);
+        for key in self.cold.list_objects(&prefix).await? {
+            let segment_id = key
+                .strip_prefix(&prefix)
+                .and_then(|name| name.strip_prefix("segment_"))
+                .and_then(|name| name.strip_suffix(".log"))
+                .and_then(|id| id.parse::<usize>().ok());
+
+            if let Some(segment_id) = segment_id {
+                segment_ids.push(segment_id);
+            }
+        }
+
+        segment_ids.sort_unstable();
+        segment_ids.dedup();
+        Ok(segment_ids)
+    }
+
+    async fn segment_info(
+        &self,
+        topic_name: &str,
+        id: usize,
+    ) -> Result<Option<SegmentInfo>, StorageBackendError> {
+        let hot_info = self.hot.segment_info(topic_name, id).await;
+        if let Ok(Some(info)) = hot_info {
+            return Ok(Some(info));
+        }
+
+        match self.get_cold_segment(topic_name, id).await? {
+            Some(segment) => Ok(Some(SegmentInfo::from(&segment))),
+            None => hot_info,
+        }
+    }
+
+    async fn read_messages(
+        &self,
+        topic_name: &str,
+        id: usize,
+        offsets: Range<u64>,
+    ) -> Result<Option<Vec<StreamMessage>>, StorageBackendError> {
+        let hot_messages = self
+            .hot
+            .read_messages(topic_name, id, offsets.clone())
+            .await;
+        if let Ok(Some(messages)) = hot_messages {
+            return Ok(Some(messages));
+        }
+
+        match self.get_cold_segment(topic_name, id).await? {
+            Some(segment) => Ok(Some(
+                segment
+                    .messages
+                    .into_iter()
+                    .filter(|message| offsets.contains(&message.msg_id.segment_offset))
+                    .collect(),
+            )),
+            None => hot_messages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object_store::FilesystemObjectStore;
+    use danube_core::message::MessageID;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn create_test_segment(id: usize, close_time: u64) -> Segment {
+        let mut segment = Segment::new(id, 1024);
+        for offset in 0..3 {
+            segment.add_message(StreamMessage {
+                request_id: offset,
+                msg_id: MessageID {
+                    producer_id: 1,
+                    topic_name: "/default/test_topic".to_string(),
+                    broker_addr: "localhost:6650".to_string(),
+                    segment_id: id as u64,
+                    segment_offset: offset,
+                },
+                payload: vec![offset as u8],
+                publish_time: 123456789,
+                producer_name: "test_producer".to_string(),
+                subscription_name: None,
+                attributes: HashMap::new(),
+                key: None,
+                deliver_at: None,
+                deliver_after: None,
+                ttl: None,
+                expire_at: None,
+            });
+        }
+        segment.close_time = close_time;
+        segment
+    }
+
+    #[tokio::test]
+    async fn test_offload_and_fetch_back() {
+        let hot_dir = tempdir().unwrap();
+        let cold_dir = tempdir().unwrap();
+        let cold = Arc::new(FilesystemObjectStore::new(
+            cold_dir.path().to_str().unwrap(),
+        ));
+        let storage = TieredStorage::new(
+            DiskStorage::new(hot_dir.path().to_str().unwrap()),
+            cold.clone(),
+            3600,
+        );
+        let topic_name = "/default/test_topic";
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        // segment 0 was closed two hours ago, segment 1 just now
+        for (id, close_time) in [(0, now - 7200), (1, now)] {
+            storage
+                .put_segment(
+                    topic_name,
+                    id,
+                    Arc::new(RwLock::new(create_test_segment(id, close_time))),
+                )
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(storage.offload_segments().await.unwrap(), 1);
+        assert_eq!(
+            storage.hot.list_segments(topic_name).await.unwrap(),
+            vec![1]
+        );
+        assert_eq!(
+            cold.list_objects("default/test_topic/").await.unwrap(),
+            vec!["default/test_topic/segment_0.log"]
+        );
+
+        // the cold segment is read transparently
+        assert_eq!(storage.list_segments(topic_name).await.unwrap(), vec![0, 1]);
+        let segment = storage.get_segment(topic_name, 0).await.unwrap().unwrap();
+        assert_eq!(segment.read().await.messages.len(), 3);
+        assert_eq!(segment.read().await.close_time, now - 7200);
+        let messages = storage
+            .read_messages(topic_name, 0, 1..3)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].msg_id.segment_offset, 1);
+
+        // nothing left to move
+        assert_eq!(storage.offload_segments().await.unwrap(), 0);
+
+        storage.remove_segment(topic_name, 0).await.unwrap();
+        assert!(storage.get_segment(topic_name, 0).await.unwrap().is_none());
+        assert_eq!(storage.list_segments(topic_name).await.unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,552 @@
+use danube_core::{
+    message::StreamMessage,
+    storage::{StorageBackendError, WalConfig, WalFsyncPolicy},
+};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Weak},
+};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{trace, warn};
+
+use crate::{
+    encrypted_storage::{decrypt_record, encrypt_record, KeyProvider},
+    errors::PersistentStorageError,
+    local_disk::resolve_topic_dir,
+    record::{frame_record, unframe_record},
+    segment_file::{decode_message, encode_message},
+};
+
+// WriteAheadLog is an append-only log per topic, that records every message of the open segment
+// before it is acknowledged to the producer, so the open segment can be rebuilt after a crash.
+// It uses the same directory layout as DiskStorage:
+// base_path/
+//     default/
+//         some_topic/
+//             wal.log
+//
+// Each record is framed as [length: u32 LE][crc32: u32 LE][payload], the payload starts with its kind:
+//     [MESSAGE_RECORD][protobuf StreamMessage], as the segment files, so the new message fields
+//         don't break the existing logs
+//     [CHECKPOINT_RECORD][count: u32 LE]([segment_id: u64 LE][close_time: u64 LE] * count)
+//     [ENCRYPTED_RECORD][encrypted record], with the storage encryption
+// A torn or corrupted tail, left by a crash in the middle of an append, is truncated on replay.
+// A record passing the checksum that can't be decoded fails the replay instead, it is not dropped.
+
+const WAL_FILE_NAME: &str = "wal.log";
+const MESSAGE_RECORD: u8 = 0x10;
+const CHECKPOINT_RECORD: u8 = 0x11;
+const ENCRYPTED_RECORD: u8 = 0xE1;
+
+#[derive(Debug, Clone)]
+pub enum WalRecord {
+    // The closed segments, as (segment_id, close_time), known when the log was last truncated
+    Checkpoint(Vec<(usize, u64)>),
+    // A message stored in the open segment, with the segment_id and offset already assigned
+    Message(Box<StreamMessage>),
+}
+
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    topic_name: String,
+    // encrypts the records, as the closed segments of the topic
+    keys: Option<Arc<dyn KeyProvider>>,
+    fsync: WalFsyncPolicy,
+    fsync_interval: Duration,
+    writer: Mutex<WalWriter>,
+}
+
+#[derive(Debug)]
+struct WalWriter {
+    file: File,
+    last_sync: Instant,
+    // records were appended since the last sync
+    unsynced: bool,
+}
+
+impl WriteAheadLog {
+    /// Opens (or creates) the write-ahead log of the topic, in append mode
+    pub async fn open(config: &WalConfig, topic_name: &str) -> Result<Self, StorageBackendError> {
+        let topic_dir = resolve_topic_dir(&PathBuf::from(&config.path), topic_name)
+            .ok_or_else(|| PersistentStorageError::InvalidPath("Invalid topic name".to_string()))?;
+        fs::create_dir_all(&topic_dir)
+            .await
+            .map_err(PersistentStorageError::from)?;
+
+        let path = topic_dir.join(WAL_FILE_NAME);
+        let file = Self::open_append(&path)
+            .await
+            .map_err(PersistentStorageError::from)?;
+
+        Ok(WriteAheadLog {
+            path,
+            topic_name: topic_name.to_string(),
+            keys: None,
+            fsync: config.fsync,
+            fsync_interval: Duration::from_millis(config.fsync_interval_ms),
+            writer: Mutex::new(WalWriter {
+                file,
+                last_sync: Instant::now(),
+                unsynced: false,
+            }),
+        })
+    }
+
+    /// Encrypts the records appended to the log with the keys of the storage encryption
+    pub fn with_encryption(mut self, keys: Arc<dyn KeyProvider>) -> Self {
+        self.keys = Some(keys);
+        self
+    }
+
+    async fn open_append(path: &PathBuf) -> std::io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+    }
+
+    /// Appends the message to the log, it returns once the record is durable
+    /// according to the configured fsync policy
+    pub async fn append(&self, message: &StreamMessage) -> Result<(), StorageBackendError> {
+        let record = self.encode_record(&WalRecord::Message(Box::new(message.clone())))?;
+
+        let mut writer = self.writer.lock().await;
+        writer
+            .file
+            .write_all(&record)
+            .await
+            .map_err(PersistentStorageError::from)?;
+        writer
+            .file
+            .flush()
+            .await
+            .map_err(PersistentStorageError::from)?;
+
+        writer.unsynced = true;
+
+        let should_sync = match self.fsync {
+            WalFsyncPolicy::Always => true,
+            WalFsyncPolicy::Interval => writer.last_sync.elapsed() >= self.fsync_interval,
+            WalFsyncPolicy::Never => false,
+        };
+
+        if should_sync {
+            Self::sync_writer(&mut writer).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts the timer syncing the log with the Interval fsync policy, so the last messages
+    /// appended before a quiet period are durable too. It stops once the log is dropped.
+    pub fn start_sync_task(wal: &Arc<Self>) {
+        if wal.fsync != WalFsyncPolicy::Interval {
+            return;
+        }
+
+        let fsync_interval = wal.fsync_interval;
+        let wal: Weak<Self> = Arc::downgrade(wal);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(fsync_interval);
+            loop {
+                interval.tick().await;
+                let Some(wal) = wal.upgrade() else {
+                    break;
+                };
+                let mut writer = wal.writer.lock().await;
+                if writer.unsynced && writer.last_sync.elapsed() >= wal.fsync_interval {
+                    if let Err(e) = Self::sync_writer(&mut writer).await {
+                        warn!(
+                            "Unable to sync the write-ahead log {}: {}",
+                            wal.path.display(),
+                            e
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    async fn sync_writer(writer: &mut WalWriter) -> Result<(), StorageBackendError> {
+        writer
+            .file
+            .sync_data()
+            .await
+            .map_err(PersistentStorageError::from)?;
+        writer.last_sync = Instant::now();
+        writer.unsynced = false;
+        Ok(())
+    }
+
+    /// Replaces the content of the log with a single checkpoint record.
+    /// Called once a segment is closed and handed to the storage backend,
+    /// as its messages no longer need to be replayed.
+    pub async fn checkpoint(
+        &self,
+        closed_segments: Vec<(usize, u64)>,
+    ) -> Result<(), StorageBackendError> {
+        let record = self.encode_record(&WalRecord::Checkpoint(closed_segments))?;
+
+        let mut writer = self.writer.lock().await;
+
+        // write the new log aside and atomically swap it in, so a crash never leaves an empty log
+        let tmp_path = self.path.with_extension("log.tmp");
+        let mut tmp_file = File::create(&tmp_path)
+            .await
+            .map_err(PersistentStorageError::from)?;
+        tmp_file
+            .write_all(&record)
+            .await
+            .map_err(PersistentStorageError::from)?;
+        tmp_file
+            .sync_all()
+            .await
+            .map_err(PersistentStorageError::from)?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(PersistentStorageError::from)?;
+
+        writer.file = Self::open_append(&self.path)
+            .await
+            .map_err(PersistentStorageError::from)?;
+        writer.last_sync = Instant::now();
+        writer.unsynced = false;
+
+        trace!(
+            "Write-ahead log {} truncated to a checkpoint",
+            self.path.display()
+        );
+
+        Ok(())
+    }
+
+    /// Reads all the valid records of the log, in the order they were appended.
+    /// A partially written or corrupted tail is dropped from the file,
+    /// a complete record that can't be decoded fails the replay.
+    pub async fn replay(&self) -> Result<Vec<WalRecord>, StorageBackendError> {
+        let _writer = self.writer.lock().await;
+
+        let bytes = fs::read(&self.path)
+            .await
+            .map_err(PersistentStorageError::from)?;
+
+        let mut records = Vec::new();
+        let mut position = 0;
+
+        while position < bytes.len() {
+            match unframe_record(&bytes[position..]) {
+                Some((payload, consumed)) => {
+                    records.push(self.decode_record(payload)?);
+                    position += consumed;
+                }
+                None => {
+                    warn!(
+                        "Write-ahead log {} has an invalid record at byte {}, dropping the remaining {} bytes",
+                        self.path.display(),
+                        position,
+                        bytes.len() - position
+                    );
+                    let file = OpenOptions::new()
+                        .write(true)
+                        .open(&self.path)
+                        .await
+                        .map_err(PersistentStorageError::from)?;
+                    file.set_len(position as u64)
+                        .await
+                        .map_err(PersistentStorageError::from)?;
+                    file.sync_all()
+                        .await
+                        .map_err(PersistentStorageError::from)?;
+                    break;
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn encode_record(&self, record: &WalRecord) -> Result<Vec<u8>, StorageBackendError> {
+        let payload = match record {
+            WalRecord::Message(message) => {
+                let mut payload = vec![MESSAGE_RECORD];
+                payload.extend(encode_message(message));
+                payload
+            }
+            WalRecord::Checkpoint(closed_segments) => {
+                let mut payload = Vec::with_capacity(5 + closed_segments.len() * 16);
+                payload.push(CHECKPOINT_RECORD);
+                payload.extend_from_slice(&(closed_segments.len() as u32).to_le_bytes());
+                for (segment_id, close_time) in closed_segments {
+                    payload.extend_from_slice(&(*segment_id as u64).to_le_bytes());
+                    payload.extend_from_slice(&close_time.to_le_bytes());
+                }
+                payload
+            }
+        };
+
+        let payload = match &self.keys {
+            Some(keys) => {
+                let mut encrypted = vec![ENCRYPTED_RECORD];
+                encrypted.extend(encrypt_record(
+                    keys.as_ref(),
+                    self.topic_name.as_bytes(),
+                    &payload,
+                )?);
+                encrypted
+            }
+            None => payload,
+        };
+        Ok(frame_record(&payload))
+    }
+
+    fn decode_record(&self, payload: &[u8]) -> Result<WalRecord, StorageBackendError> {
+        let decrypted;
+        let payload = match payload.split_first() {
+            Some((&ENCRYPTED_RECORD, encrypted)) => {
+                let keys = self.keys.as_ref().ok_or_else(|| {
+                    StorageBackendError::Encryption(format!(
+                        "the write-ahead log {} is encrypted, the storage encryption is not configured",
+                        self.path.display()
+                    ))
+                })?;
+                decrypted = decrypt_record(keys.as_ref(), self.topic_name.as_bytes(), encrypted)?;
+                decrypted.as_slice()
+            }
+            _ => payload,
+        };
+
+        decode_plain_record(payload).map_err(|reason| {
+            StorageBackendError::Disk(format!(
+                "the write-ahead log {} has an undecodable record: {}",
+                self.path.display(),
+                reason
+            ))
+        })
+    }
+}
+
+fn decode_plain_record(payload: &[u8]) -> Result<WalRecord, String> {
+    match payload.split_first() {
+        Some((&MESSAGE_RECORD, message)) => {
+            Ok(WalRecord::Message(Box::new(decode_message(message)?)))
+        }
+        Some((&CHECKPOINT_RECORD, checkpoint)) => {
+            let invalid = || "invalid checkpoint record".to_string();
+            let (count, mut entries) = checkpoint.split_first_chunk::<4>().ok_or_else(invalid)?;
+            let count = u32::from_le_bytes(*count) as usize;
+            let mut closed_segments = Vec::with_capacity(count);
+            for _ in 0..count {
+                let (segment_id, rest) = entries.split_first_chunk::<8>().ok_or_else(invalid)?;
+                let (close_time, rest) = rest.split_first_chunk::<8>().ok_or_else(invalid)?;
+                closed_segments.push((
+                    u64::from_le_bytes(*segment_id) as usize,
+                    u64::from_le_bytes(*close_time),
+                ));
+                entries = rest;
+            }
+            Ok(WalRecord::Checkpoint(closed_segments))
+        }
+        _ => Err("unknown record".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use danube_core::message::MessageID;
+    use std::collections::HashMap;
+    use tempfile::tempdir;
+
+    fn create_test_message(segment_offset: u64) -> StreamMessage {
+        StreamMessage {
+            request_id: 1,
+            msg_id: MessageID {
+                producer_id: 1,
+                topic_name: "/default/test_topic".to_string(),
+                broker_addr: "localhost:6650".to_string(),
+                segment_id: 0,
+                segment_offset,
+            },
+            payload: vec![1, 2, 3],
+            publish_time: 123456789,
+            producer_name: "test_producer".to_string(),
+            subscription_name: None,
+            attributes: HashMap::new(),
+            key: None,
+            deliver_at: None,
+            deliver_after: None,
+            ttl: None,
+            expire_at: None,
+        }
+    }
+
+    fn create_test_config(path: &str) -> WalConfig {
+        WalConfig {
+            path: path.to_string(),
+            fsync: WalFsyncPolicy::Always,
+            fsync_interval_ms: 1000,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wal_append_replay_and_checkpoint() {
+        let temp_dir = tempdir().unwrap();
+        let config = create_test_config(temp_dir.path().to_str().unwrap());
+        let topic_name = "/default/test_topic";
+
+        let wal = WriteAheadLog::open(&config, topic_name).await.unwrap();
+        wal.append(&create_test_message(0)).await.unwrap();
+        wal.append(&create_test_message(1)).await.unwrap();
+
+        // Reopening the log returns the appended messages, in order
+        drop(wal);
+        let wal = WriteAheadLog::open(&config, topic_name).await.unwrap();
+        let records = wal.replay().await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(&records[1], WalRecord::Message(msg) if msg.msg_id.segment_offset == 1));
+
+        // The checkpoint replaces all the previous records
+        wal.checkpoint(vec![(0, 42)]).await.unwrap();
+        wal.append(&create_test_message(0)).await.unwrap();
+        let records = wal.replay().await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(&records[0], WalRecord::Checkpoint(closed) if closed == &vec![(0, 42)]));
+    }
+
+    #[tokio::test]
+    async fn test_wal_truncates_torn_tail() {
+        let temp_dir = tempdir().unwrap();
+        let config = create_test_config(temp_dir.path().to_str().unwrap());
+        let topic_name = "/default/test_topic";
+
+        let wal = WriteAheadLog::open(&config, topic_name).await.unwrap();
+        wal.append(&create_test_message(0)).await.unwrap();
+
+        // Simulate a crash in the middle of an append
+        let partial = wal
+            .encode_record(&WalRecord::Message(Box::new(create_test_message(1))))
+            .unwrap();
+        {
+            let mut writer = wal.writer.lock().await;
+            writer
+                .file
+                .write_all(&partial[..partial.len() / 2])
+                .await
+                .unwrap();
+            writer.file.flush().await.unwrap();
+        }
+
+        let records = wal.replay().await.unwrap();
+        assert_eq!(records.len(), 1);
+
+        // The torn record is gone, new appends are readable again
+        wal.append(&create_test_message(1)).await.unwrap();
+        let records = wal.replay().await.unwrap();
+        assert_eq!(records.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_wal_interval_sync_task() {
+        let temp_dir = tempdir().unwrap();
+        let mut config = create_test_config(temp_dir.path().to_str().unwrap());
+        config.fsync = WalFsyncPolicy::Interval;
+        config.fsync_interval_ms = 10;
+        let topic_name = "/default/test_topic";
+
+        let wal = Arc::new(WriteAheadLog::open(&config, topic_name).await.unwrap());
+        WriteAheadLog::start_sync_task(&wal);
+
+        // The last message appended is synced by the timer, without waiting for the next append
+        wal.append(&create_test_message(0)).await.unwrap();
+        wal.append(&create_test_message(1)).await.unwrap();
+        assert!(wal.writer.lock().await.unsynced);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!wal.writer.lock().await.unsynced);
+    }
+
+    #[tokio::test]
+    async fn test_wal_undecodable_record_is_not_truncated() {
+        let temp_dir = tempdir().unwrap();
+        let config = create_test_config(temp_dir.path().to_str().unwrap());
+        let topic_name = "/default/test_topic";
+
+        let wal = WriteAheadLog::open(&config, topic_name).await.unwrap();
+        {
+            let mut writer = wal.writer.lock().await;
+            writer
+                .file
+                .write_all(&frame_record(&[0x7F, 1, 2, 3]))
+                .await
+                .unwrap();
+            writer.file.flush().await.unwrap();
+        }
+        wal.append(&create_test_message(0)).await.unwrap();
+        let bytes = fs::read(&wal.path).await.unwrap();
+
+        // the record passes the checksum, it is reported instead of dropped with the following ones
+        assert!(matches!(
+            wal.replay().await,
+            Err(StorageBackendError::Disk(_))
+        ));
+        assert_eq!(fs::read(&wal.path).await.unwrap(), bytes);
+    }
+
+    #[derive(Debug)]
+    struct TestKeyProvider;
+
+    impl KeyProvider for TestKeyProvider {
+        fn current_key(&self) -> (String, [u8; 32]) {
+            ("k1".to_string(), [1; 32])
+        }
+
+        fn key(&self, key_id: &str) -> Option<[u8; 32]> {
+            (key_id == "k1").then_some([1; 32])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wal_encryption() {
+        let temp_dir = tempdir().unwrap();
+        let config = create_test_config(temp_dir.path().to_str().unwrap());
+        let topic_name = "/default/test_topic";
+
+        // a record appended before the encryption was enabled
+        let wal = WriteAheadLog::open(&config, topic_name).await.unwrap();
+        wal.append(&create_test_message(0)).await.unwrap();
+        drop(wal);
+
+        let mut message = create_test_message(1);
+        message.payload = b"customer record".to_vec();
+        message.key = Some("customer-1".to_string());
+        let wal = WriteAheadLog::open(&config, topic_name)
+            .await
+            .unwrap()
+            .with_encryption(Arc::new(TestKeyProvider));
+        wal.append(&message).await.unwrap();
+
+        // nothing of the message content is stored in clear
+        let bytes = fs::read(&wal.path).await.unwrap();
+        assert!(!bytes.windows(8).any(|w| w == b"customer"));
+
+        let records = wal.replay().await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(matches!(&records[1], WalRecord::Message(msg) if msg.payload == message.payload));
+
+        // without the keys the log can't be replayed, and it is not truncated
+        drop(wal);
+        let wal = WriteAheadLog::open(&config, topic_name).await.unwrap();
+        assert!(matches!(
+            wal.replay().await,
+            Err(StorageBackendError::Encryption(_))
+        ));
+        assert_eq!(fs::read(&wal.path).await.unwrap(), bytes);
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::delayed_delivery::DelayedDeliveryTracker;
+
+/// SubscriptionCursor is the durable position of a subscription within the topic
+/// It records the segment being consumed, the offset below which all the segment messages
+/// are acknowledged, and the messages acknowledged out of order above that offset.
+/// It also tracks the delayed messages held back until their delivery time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionCursor {
+    // the segment consumed by the subscription, all the previous segments are acknowledged
+    pub(crate) segment_id: usize,
+    // all the segment messages with a lower offset are acknowledged
+    pub(crate) offset: u64,
+    // the messages acknowledged above the offset, the bit i stands for the message at offset + i
+    #[serde(default)]
+    pub(crate) acked_bitmap: Vec<u64>,
+    // the delayed messages not yet delivered, they are acknowledged on the segment once tracked
+    #[serde(default)]
+    pub(crate) delayed: DelayedDeliveryTracker,
+}
+
+impl SubscriptionCursor {
+    #[cfg(test)]
+    pub(crate) fn new(segment_id: usize) -> Self {
+        SubscriptionCursor {
+            segment_id,
+            offset: 0,
+            acked_bitmap: Vec::new(),
+            delayed: DelayedDeliveryTracker::default(),
+        }
+    }
+
+    /// Moves the cursor to the start of the segment, the delayed messages are kept
+    pub(crate) fn move_to(&mut self, segment_id: usize) {
+        self.segment_id = segment_id;
+        self.offset = 0;
+        self.acked_bitmap.clear();
+    }
+
+    /// The segment consumed by the subscription
+    pub fn segment_id(&self) -> usize {
+        self.segment_id
+    }
+
+    /// The offset of the first unacknowledged message of the segment
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The last segment with all messages acknowledged, if any.
+    /// The segments holding delayed messages are not acknowledged until the messages are delivered.
+    pub fn last_acked_segment(&self) -> Option<usize> {
+        let last_acked = self.segment_id.checked_sub(1);
+        match self.delayed.oldest_segment() {
+            Some(oldest) => last_acked.min(oldest.checked_sub(1)),
+            None => last_acked,
+        }
+    }
+
+    /// The delayed messages not yet delivered
+    pub fn delayed_messages(&self) -> usize {
+        self.delayed.len()
+    }
+
+    pub(crate) fn is_acked(&self, offset: u64) -> bool {
+        if offset < self.offset {
+            return true;
+        }
+        let bit = (offset - self.offset) as usize;
+        self.acked_bitmap
+            .get(bit / 64)
+            .is_some_and(|word| word & (1 << (bit % 64)) != 0)
+    }
+
+    /// Marks the message at the offset as acknowledged,
+    /// the offset moves over the contiguous acknowledged messages
+    pub(crate) fn ack(&mut self, offset: u64) {
+        if self.is_acked(offset) {
+            return;
+        }
+
+        let bit = (offset - self.offset) as usize;
+        let word = bit / 64;
+        if self.acked_bitmap.len() <= word {
+            self.acked_bitmap.resize(word + 1, 0);
+        }
+        self.acked_bitmap[word] |= 1 << (bit % 64);
+
+        let mut contiguous = 0;
+        for word in &self.acked_bitmap {
+            if *word == u64::MAX {
+                contiguous += 64;
+            } else {
+                contiguous += word.trailing_ones() as usize;
+                break;
+            }
+        }
+
+        self.advance(contiguous);
+    }
+
+    // moves the offset forward by `count` messages, shifting the bitmap accordingly
+    fn advance(&mut self, count: usize) {
+        if count == 0 {
+            return;
+        }
+
+        self.offset += count as u64;
+
+        let words = (count / 64).min(self.acked_bitmap.len());
+        self.acked_bitmap.drain(..words);
+
+        let shift = count % 64;
+        if shift > 0 {
+            for i in 0..self.acked_bitmap.len() {
+                let carry = self
+                    .acked_bitmap
+                    .get(i + 1)
+                    .map_or(0, |next| next << (64 - shift));
+                self.acked_bitmap[i] = (self.acked_bitmap[i] >> shift) | carry;
+            }
+        }
+
+        while self.acked_bitmap.last() == Some(&0) {
+            self.acked_bitmap.pop();
+        }
+    }
+}
+
+/// AckedSegment is the last segment of a subscription with all messages acknowledged,
+/// shared with the retention of the topic. It is unset until the subscription acknowledges
+/// its first segment, so the segments of a new subscription are retained.
+#[derive(Debug, Default)]
+pub struct AckedSegment(
+    // the segment id + 1, 0 while no segment is acknowledged
+    AtomicUsize,
+);
+
+impl AckedSegment {
+    pub(crate) fn new(segment_id: Option<usize>) -> Self {
+        AckedSegment(AtomicUsize::new(segment_id.map_or(0, |id| id + 1)))
+    }
+
+    /// The last acknowledged segment, if any
+    pub fn get(&self) -> Option<usize> {
+        self.0.load(Ordering::Acquire).checked_sub(1)
+    }
+
+    pub(crate) fn set(&self, segment_id: usize) {
+        self.0.store(segment_id + 1, Ordering::Release);
+    }
+
+    /// Moves forward to the segment, unless a later segment is already acknowledged
+    pub(crate) fn advance(&self, segment_id: usize) {
+        self.0.fetch_max(segment_id + 1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,70 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// DelayedDeliveryTracker holds the delayed messages of a subscription back until their delivery time.
+/// The messages are indexed by their delivery time and identified by their segment and offset,
+/// so they are read back from the segments once due. It is persisted with the subscription cursor,
+/// the messages stay delayed as the topic is moved or the broker restarted.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct DelayedDeliveryTracker {
+    // delivery time, in milliseconds since the epoch -> (segment_id, segment_offset)
+    index: BTreeMap<u64, BTreeSet<(usize, u64)>>,
+}
+
+impl DelayedDeliveryTracker {
+    pub(crate) fn add(&mut self, deliver_at: u64, segment_id: usize, offset: u64) {
+        self.index
+            .entry(deliver_at)
+            .or_default()
+            .insert((segment_id, offset));
+    }
+
+    /// Removes the message once delivered and acknowledged, returns `false` if it was not tracked
+    pub(crate) fn remove(&mut self, deliver_at: u64, segment_id: usize, offset: u64) -> bool {
+        let Some(messages) = self.index.get_mut(&deliver_at) else {
+            return false;
+        };
+        let removed = messages.remove(&(segment_id, offset));
+        if messages.is_empty() {
+            self.index.remove(&deliver_at);
+        }
+        removed
+    }
+
+    /// The messages due at `now`, as (deliver_at, segment_id, offset), the earliest first
+    pub(crate) fn due(&self, now: u64) -> impl Iterator<Item = (u64, usize, u64)> + '_ {
+        self.index.range(..=now).flat_map(|(deliver_at, messages)| {
+            messages
+                .iter()
+                .map(move |(segment_id, offset)| (*deliver_at, *segment_id, *offset))
+        })
+    }
+
+    /// The delivery time of the next message not yet due at `now`
+    pub(crate) fn next_delivery_after(&self, now: u64) -> Option<u64> {
+        self.index
+            .range(now.saturating_add(1)..)
+            .next()
+            .map(|(deliver_at, _)| *deliver_at)
+    }
+
+    /// The oldest segment holding a delayed message, the segment is retained until the message is delivered
+    pub(crate) fn oldest_segment(&self) -> Option<usize> {
+        self.index
+            .values()
+            .filter_map(|messages| messages.first().map(|(segment_id, _)| *segment_id))
+            .min()
+    }
+
+    /// Keeps only the messages of the segments matching the predicate
+    pub(crate) fn retain_segments(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        self.index.retain(|_, messages| {
+            messages.retain(|(segment_id, _)| keep(*segment_id));
+            !messages.is_empty()
+        });
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.index.values().map(|messages| messages.len()).sum()
+    }
+}
@@ -1,16 +1,76 @@
 use danube_core::message::{MessageID, StreamMessage};
 use danube_core::storage::Segment;
-use std::collections::HashMap;
-use std::sync::atomic::AtomicUsize;
+use metrics::counter;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::trace;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+use tracing::{trace, warn};
 
 use crate::{
+    cursor::{AckedSegment, SubscriptionCursor},
     errors::{ReliableDispatchError, Result},
     topic_storage::TopicStore,
 };
 
+/// Counter of the expired messages, skipped instead of delivered to the subscriptions.
+pub const MESSAGES_EXPIRED_COUNTER: &str = "danube_topic_messages_expired_counter";
+
+// The redeliveries of an unacknowledged message, before the subscription reports MaxRetriesExceeded
+const DEFAULT_MAX_REDELIVERIES: u32 = 3;
+
+// The messages delivered and awaiting acknowledgment, one by default to preserve the strict ordering
+const DEFAULT_MAX_IN_FLIGHT: usize = 1;
+
+// The following segments are prefetched once this part of the current segment is consumed
+const READ_AHEAD_THRESHOLD_PERCENT: usize = 75;
+
+// The default redelivery schedule of an unacknowledged message: 10s, 20s, then 30s
+const DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_BACKOFF_MULTIPLIER: f64 = 2.0;
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The redelivery schedule of the unacknowledged messages of a subscription.
+/// The message is redelivered once `ack_timeout` elapsed since its delivery,
+/// the following redeliveries wait `backoff_multiplier` times longer, up to `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RedeliveryBackoff {
+    pub ack_timeout: Duration,
+    pub backoff_multiplier: f64,
+    pub max_backoff: Duration,
+}
+
+impl RedeliveryBackoff {
+    pub fn new(ack_timeout: Duration, backoff_multiplier: f64, max_backoff: Duration) -> Self {
+        Self {
+            ack_timeout,
+            backoff_multiplier,
+            max_backoff,
+        }
+    }
+
+    /// The time to wait for the acknowledgment, after the `retry_count` redeliveries of the message
+    pub fn delay(&self, retry_count: u32) -> Duration {
+        let delay = self.ack_timeout.as_secs_f64()
+            * self
+                .backoff_multiplier
+                .powi(retry_count.min(i32::MAX as u32) as i32);
+        // capped before the conversion, so the growing delays never overflow
+        Duration::try_from_secs_f64(delay.min(self.max_backoff.as_secs_f64()))
+            .unwrap_or(self.max_backoff)
+    }
+}
+
+impl Default for RedeliveryBackoff {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_ACK_TIMEOUT,
+            DEFAULT_BACKOFF_MULTIPLIER,
+            DEFAULT_MAX_BACKOFF,
+        )
+    }
+}
+
 /// SubscriptionDispatch is holding information about consumers and the messages within a segment
 /// It is used to dispatch messages to consumers and to track the progress of the consumer
 #[derive(Debug)]
@@ -20,35 +80,274 @@ pub struct SubscriptionDispatch {
     pub(crate) topic_store: TopicStore,
     // last acked segment is the last segment that has all messages acknowledged by the consumer
     // it is used to track the progress of the subscription
-    pub(crate) last_acked_segment: Arc<AtomicUsize>,
+    pub(crate) last_acked_segment: Arc<AckedSegment>,
+    // the durable position of the subscription, shared with the ReliableDispatch to be persisted
+    pub(crate) cursor: Arc<Mutex<SubscriptionCursor>>,
     // segment holds the messages to be sent to the consumer
     // segment is replaced when the consumer is done with the segment and if there is another available segment
     pub(crate) segment: Option<Arc<RwLock<Segment>>>,
     // Cached segment ID to avoid frequent locks
     pub(crate) current_segment_id: Option<usize>,
-    // single message awaiting acknowledgment from the consumer
-    pub(crate) pending_ack_message: Option<(u64, MessageID)>,
+    // the messages awaiting acknowledgment from the consumers, by segment id and offset,
+    // the delayed messages may belong to the previous segments
+    pub(crate) pending_acks: BTreeMap<(u64, u64), PendingAck>,
     // maps MessageID to request_id of segment acknowledged messages
     pub(crate) acked_messages: HashMap<MessageID, u64>,
-    // retry count for the pending ack message
-    retry_count: u8,
-    last_retry_timestamp: Option<tokio::time::Instant>,
+    // the segment whose following segments are already prefetched
+    prefetched_after: Option<usize>,
+    // maximum messages awaiting acknowledgment, the in-flight window
+    max_in_flight: usize,
+    // maximum redeliveries of a pending ack message
+    max_redeliveries: u32,
+    // the redelivery schedule of the pending ack messages
+    redelivery_backoff: RedeliveryBackoff,
+    // the delivery time of the next delayed message, in milliseconds since the epoch
+    next_delayed_delivery: Option<u64>,
+}
+
+// A message delivered to the consumers and awaiting acknowledgment
+#[derive(Debug)]
+pub(crate) struct PendingAck {
+    request_id: u64,
+    msg_id: MessageID,
+    // the redeliveries of the message
+    retry_count: u32,
+    // the last delivery of the message, its ack timeout starts there
+    last_delivery: tokio::time::Instant,
+    // redelivery delay requested by the consumer for the negatively acknowledged message
+    nack_delay: Option<Duration>,
+    // the delivery time of the delayed message, tracked until acknowledged
+    deliver_at: Option<u64>,
+    // the message is being handed over to the dead letter topic, it is not redelivered
+    dead_lettering: bool,
+}
+
+impl PendingAck {
+    // The message is redelivered after the delay requested by the nack, otherwise the backoff of the subscription
+    fn redelivery_deadline(&self, redelivery_backoff: &RedeliveryBackoff) -> tokio::time::Instant {
+        self.last_delivery
+            + self
+                .nack_delay
+                .unwrap_or_else(|| redelivery_backoff.delay(self.retry_count))
+    }
+
+    fn key(msg_id: &MessageID) -> (u64, u64) {
+        (msg_id.segment_id, msg_id.segment_offset)
+    }
 }
 
 impl SubscriptionDispatch {
-    pub(crate) fn new(topic_store: TopicStore, last_acked_segment: Arc<AtomicUsize>) -> Self {
+    pub(crate) fn new(topic_store: TopicStore, last_acked_segment: Arc<AckedSegment>) -> Self {
         Self {
             topic_store,
             last_acked_segment,
+            cursor: Arc::new(Mutex::new(SubscriptionCursor::default())),
             segment: None,
             current_segment_id: None,
-            pending_ack_message: None,
+            pending_acks: BTreeMap::new(),
             acked_messages: HashMap::new(),
-            retry_count: 0,
-            last_retry_timestamp: None,
+            prefetched_after: None,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            max_redeliveries: DEFAULT_MAX_REDELIVERIES,
+            redelivery_backoff: RedeliveryBackoff::default(),
+            next_delayed_delivery: None,
         }
     }
 
+    pub(crate) fn with_cursor(mut self, cursor: Arc<Mutex<SubscriptionCursor>>) -> Self {
+        self.cursor = cursor;
+        self
+    }
+
+    /// The cursor of the subscription, moved as the messages are acknowledged, to be persisted
+    pub fn cursor(&self) -> Arc<Mutex<SubscriptionCursor>> {
+        Arc::clone(&self.cursor)
+    }
+
+    /// Sets the redeliveries of an unacknowledged message, before `MaxRetriesExceeded` is returned
+    pub fn with_max_redeliveries(mut self, max_redeliveries: u32) -> Self {
+        self.max_redeliveries = max_redeliveries;
+        self
+    }
+
+    /// Sets the redelivery schedule of the unacknowledged messages
+    pub fn with_redelivery_backoff(mut self, redelivery_backoff: RedeliveryBackoff) -> Self {
+        self.redelivery_backoff = redelivery_backoff;
+        self
+    }
+
+    /// Sets the in-flight window, the messages delivered and awaiting acknowledgment
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.set_max_in_flight(max_in_flight);
+        self
+    }
+
+    /// Resizes the in-flight window, the messages already in flight are kept
+    pub fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.max_in_flight = max_in_flight.max(1);
+    }
+
+    /// The in-flight window, the messages delivered and awaiting acknowledgment
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+    }
+
+    /// Takes the pending message that exhausted its redeliveries, with the number of times it was delivered,
+    /// to be handed over to the dead letter topic. The message is no longer redelivered meanwhile,
+    /// the subscription moves past it with `skip_message`, or it is retried with `retry_dead_letter`.
+    pub async fn take_dead_letter(&mut self) -> Result<Option<(StreamMessage, u32)>> {
+        let max_redeliveries = self.max_redeliveries;
+        let Some(pending) = self
+            .pending_acks
+            .values_mut()
+            .find(|pending| !pending.dead_lettering && pending.retry_count >= max_redeliveries)
+        else {
+            return Ok(None);
+        };
+        pending.dead_lettering = true;
+        let deliveries = pending.retry_count + 1;
+        let msg_id = pending.msg_id.clone();
+
+        match self.find_message(&msg_id).await? {
+            Some(message) => Ok(Some((message, deliveries))),
+            None => {
+                self.pending_acks.remove(&PendingAck::key(&msg_id));
+                Err(ReliableDispatchError::SegmentError(format!(
+                    "The pending message {:?} is no longer stored",
+                    msg_id
+                )))
+            }
+        }
+    }
+
+    /// Moves the subscription past the pending message, as if it was acknowledged,
+    /// once the message is handed over to the dead letter topic
+    pub async fn skip_message(&mut self, msg_id: &MessageID) -> Result<()> {
+        let pending = self
+            .pending_acks
+            .remove(&PendingAck::key(msg_id))
+            .ok_or_else(|| {
+                ReliableDispatchError::AcknowledgmentError(format!(
+                    "The message {:?} is not awaiting acknowledgment",
+                    msg_id
+                ))
+            })?;
+
+        self.mark_acked(&pending).await;
+        trace!("Message with msg_id {:?} skipped", pending.msg_id);
+        Ok(())
+    }
+
+    /// The message was not stored by the dead letter topic, it is handed over again
+    /// once its redelivery is due
+    pub fn retry_dead_letter(&mut self, msg_id: &MessageID) {
+        if let Some(pending) = self.pending_acks.get_mut(&PendingAck::key(msg_id)) {
+            pending.dead_lettering = false;
+            pending.last_delivery = tokio::time::Instant::now();
+        }
+    }
+
+    // Records the acknowledgment of the message, on the segment and on the subscription cursor,
+    // the delayed message is no longer tracked, it was acknowledged on its segment once delayed
+    async fn mark_acked(&mut self, pending: &PendingAck) {
+        let msg_id = &pending.msg_id;
+        if pending.deliver_at.is_none() {
+            self.acked_messages
+                .insert(msg_id.clone(), pending.request_id);
+        }
+
+        let mut cursor = self.cursor.lock().await;
+        if cursor.segment_id as u64 == msg_id.segment_id {
+            cursor.ack(msg_id.segment_offset);
+        }
+        if let Some(deliver_at) = pending.deliver_at {
+            cursor.delayed.remove(
+                deliver_at,
+                msg_id.segment_id as usize,
+                msg_id.segment_offset,
+            );
+            // the segment of the delivered message may no longer be retained for the subscription
+            if let Some(last_acked) = cursor.last_acked_segment() {
+                self.last_acked_segment.advance(last_acked);
+            }
+        }
+    }
+
+    // Looks up the message within the current segment, then within its own segment,
+    // as the delayed messages may belong to the previous segments
+    async fn find_message(&self, msg_id: &MessageID) -> Result<Option<StreamMessage>> {
+        if let Some(segment) = &self.segment {
+            let segment_data = segment.read().await;
+            if let Some(msg) = segment_data
+                .messages
+                .iter()
+                .find(|msg| msg.msg_id == *msg_id)
+            {
+                return Ok(Some(msg.clone()));
+            }
+        }
+        self.find_message_at(msg_id.segment_id as usize, msg_id.segment_offset)
+            .await
+    }
+
+    async fn find_message_at(
+        &self,
+        segment_id: usize,
+        offset: u64,
+    ) -> Result<Option<StreamMessage>> {
+        let segment = match &self.segment {
+            Some(segment) if self.current_segment_id == Some(segment_id) => Arc::clone(segment),
+            _ => match self.topic_store.get_segment(segment_id).await? {
+                Some(segment) => segment,
+                None => return Ok(None),
+            },
+        };
+
+        let segment_data = segment.read().await;
+        Ok(segment_data
+            .messages
+            .iter()
+            .find(|msg| msg.msg_id.segment_offset == offset)
+            .cloned())
+    }
+
+    /// Resumes the delivery right after the last acknowledged message of the subscription cursor.
+    /// The messages acknowledged out of order are not delivered again.
+    pub(crate) async fn resume(&mut self) -> Result<()> {
+        let cursor = self.cursor.lock().await.clone();
+        self.clear_current_segment();
+
+        match self.topic_store.get_segment(cursor.segment_id).await? {
+            Some(segment) => {
+                {
+                    let segment_data = segment.read().await;
+                    for message in &segment_data.messages {
+                        if cursor.is_acked(message.msg_id.segment_offset) {
+                            self.acked_messages
+                                .insert(message.msg_id.clone(), message.request_id);
+                        }
+                    }
+                }
+                self.segment = Some(segment);
+                self.current_segment_id = Some(cursor.segment_id);
+            }
+            None => {
+                // The segment is already removed or not yet created,
+                // continue with the first segment following the acknowledged ones
+                self.current_segment_id = cursor.segment_id.checked_sub(1);
+            }
+        }
+        self.next_delayed_delivery = cursor.delayed.next_delivery_after(now_millis());
+
+        trace!(
+            "Subscription resumed on segment {} at offset {}",
+            cursor.segment_id,
+            cursor.offset
+        );
+
+        Ok(())
+    }
+
     /// Process the current segment and send the messages to the consumer
     pub async fn process_current_segment(&mut self) -> Result<StreamMessage> {
         // If we have a current segment, validate it
@@ -69,13 +368,17 @@ impl SubscriptionDispatch {
         }
 
         // Process the next message - continue even if no messages available
-        match self.process_next_message().await {
-            Ok(msg) => Ok(msg),
-            Err(ReliableDispatchError::NoMessagesAvailable) => {
-                Err(ReliableDispatchError::NoMessagesAvailable)
-            }
-            Err(e) => Err(e),
-        }
+        let result = self.process_next_message().await;
+
+        // the dispatchers wait for the next delayed message to be due
+        self.next_delayed_delivery = self
+            .cursor
+            .lock()
+            .await
+            .delayed
+            .next_delivery_after(now_millis());
+
+        result
     }
 
     /// Validates the current segment. Returns `true` if the segment was invalidated or closed.
@@ -99,10 +402,13 @@ impl SubscriptionDispatch {
         // The conditions to move to the next segment are:
         // 1. The segment is closed
         // 2. All messages in the segment are acknowledged
-        // 3. There is no pending ack message
+        // 3. There are no messages awaiting acknowledgment
         if segment_data.close_time > 0
             && self.acked_messages.len() == segment_data.messages.len()
-            && self.pending_ack_message.is_none()
+            && !self
+                .pending_acks
+                .keys()
+                .any(|(pending_segment_id, _)| *pending_segment_id == segment_id as u64)
         {
             trace!("The subscription dispatcher id moving to the next segment, the current segment is closed and all messages consumed");
             return Ok(true);
@@ -113,10 +419,16 @@ impl SubscriptionDispatch {
 
     /// Moves to the next segment in the `TopicStore`.
     pub(crate) async fn move_to_next_segment(&mut self) -> Result<()> {
-        // Update the last acknowledged segment
+        // Update the last acknowledged segment,
+        // the segments holding delayed messages are retained until the messages are delivered
         if let Some(current_segment_id) = self.current_segment_id {
-            self.last_acked_segment
-                .store(current_segment_id, std::sync::atomic::Ordering::Release);
+            let last_acked = match self.cursor.lock().await.delayed.oldest_segment() {
+                Some(oldest) if oldest <= current_segment_id => oldest.checked_sub(1),
+                _ => Some(current_segment_id),
+            };
+            if let Some(last_acked) = last_acked {
+                self.last_acked_segment.set(last_acked);
+            }
         }
 
         let next_segment = self
@@ -130,13 +442,21 @@ impl SubscriptionDispatch {
                 segment_data.id
             };
 
-            // Clear acknowledgments before switching to a new segment
+            // Clear acknowledgments before switching to a new segment,
+            // the delayed messages awaiting acknowledgment are kept
             self.acked_messages.clear();
+            self.pending_acks
+                .retain(|_, pending| pending.deliver_at.is_some());
+            self.cursor.lock().await.move_to(next_segment_id);
 
             self.segment = Some(next_segment);
             self.current_segment_id = Some(next_segment_id);
         } else {
-            self.clear_current_segment();
+            // No following segment yet, keep the segment id to continue after it
+            self.segment = None;
+            self.acked_messages.clear();
+            self.pending_acks
+                .retain(|_, pending| pending.deliver_at.is_some());
         }
 
         Ok(())
@@ -149,52 +469,284 @@ impl SubscriptionDispatch {
         self.acked_messages.clear();
     }
 
-    /// Processes the next unacknowledged message in the current segment.
+    /// Processes the next message of the current segment, the redeliveries due come first,
+    /// then the delayed messages due and the unacknowledged messages not yet delivered,
+    /// within the in-flight window. The expired messages are skipped, as if acknowledged.
     async fn process_next_message(&mut self) -> Result<StreamMessage> {
-        // Only process next message if there's no pending acknowledgment
-        match self.pending_ack_message {
-            None => return self.send_message().await,
-            Some(_) => {
-                if self.retry_count < 3 {
-                    let delay = match self.retry_count {
-                        0 => tokio::time::Duration::from_secs(10),
-                        1 => tokio::time::Duration::from_secs(20),
-                        2 => tokio::time::Duration::from_secs(30),
-                        _ => unreachable!(),
-                    };
-
-                    let now = tokio::time::Instant::now();
-                    if let Some(last_retry) = self.last_retry_timestamp {
-                        if now.duration_since(last_retry) < delay {
-                            return Err(ReliableDispatchError::NoMessagesAvailable);
-                        }
+        // the messages not acknowledged within their ack timeout, or nacked
+        let now = tokio::time::Instant::now();
+        let redelivery_backoff = self.redelivery_backoff;
+        while let Some(pending) = self.pending_acks.values_mut().find(|pending| {
+            !pending.dead_lettering && pending.redelivery_deadline(&redelivery_backoff) <= now
+        }) {
+            if pending.retry_count >= self.max_redeliveries {
+                return Err(ReliableDispatchError::MaxRetriesExceeded);
+            }
+
+            pending.retry_count += 1;
+            pending.last_delivery = now;
+            pending.nack_delay = None;
+            let msg_id = pending.msg_id.clone();
+
+            match self.find_message(&msg_id).await? {
+                Some(msg) if msg.is_expired(now_millis()) => {
+                    if let Some(pending) = self.pending_acks.remove(&PendingAck::key(&msg_id)) {
+                        self.mark_acked(&pending).await;
                     }
+                    self.count_expired(1);
+                    trace!("Expired message with id {:?} not redelivered", msg_id);
+                }
+                Some(msg) => {
+                    trace!("Redelivering message with id {:?}", msg.msg_id);
+                    return Ok(msg);
+                }
+                None => {
+                    self.pending_acks.remove(&PendingAck::key(&msg_id));
+                    return Err(ReliableDispatchError::SegmentError(format!(
+                        "The pending message {:?} is not in the current segment",
+                        msg_id
+                    )));
+                }
+            }
+        }
+
+        if self.pending_acks.len() >= self.max_in_flight {
+            return Err(ReliableDispatchError::NoMessagesAvailable);
+        }
+        if let Some(msg) = self.send_delayed_message().await? {
+            return Ok(msg);
+        }
+        self.send_message().await
+    }
+
+    // Delivers the earliest delayed message due, not yet awaiting acknowledgment
+    async fn send_delayed_message(&mut self) -> Result<Option<StreamMessage>> {
+        let now = now_millis();
+        loop {
+            let due = self
+                .cursor
+                .lock()
+                .await
+                .delayed
+                .due(now)
+                .find(|(_, segment_id, offset)| {
+                    !self
+                        .pending_acks
+                        .contains_key(&(*segment_id as u64, *offset))
+                });
+            let Some((deliver_at, segment_id, offset)) = due else {
+                return Ok(None);
+            };
 
-                    self.retry_count += 1;
-                    self.last_retry_timestamp = Some(now);
-                    self.send_message().await
-                } else {
-                    Err(ReliableDispatchError::MaxRetriesExceeded)
+            match self.find_message_at(segment_id, offset).await? {
+                Some(msg) if msg.is_expired(now) => {
+                    trace!(
+                        "Expired delayed message with id {:?} not delivered",
+                        msg.msg_id
+                    );
+                    self.cursor
+                        .lock()
+                        .await
+                        .delayed
+                        .remove(deliver_at, segment_id, offset);
+                    self.count_expired(1);
                 }
+                Some(msg) => {
+                    trace!("Sending delayed message with id {:?}", msg.msg_id);
+                    self.insert_pending(&msg, Some(deliver_at));
+                    return Ok(Some(msg));
+                }
+                None => {
+                    // the segment was dropped by the retention policies, or the message compacted
+                    warn!(
+                        "The delayed message at offset {} of segment {} is no longer stored, it is not delivered",
+                        offset, segment_id
+                    );
+                    self.cursor
+                        .lock()
+                        .await
+                        .delayed
+                        .remove(deliver_at, segment_id, offset);
+                }
+            }
+        }
+    }
+
+    fn insert_pending(&mut self, msg: &StreamMessage, deliver_at: Option<u64>) {
+        self.pending_acks.insert(
+            PendingAck::key(&msg.msg_id),
+            PendingAck {
+                request_id: msg.request_id,
+                msg_id: msg.msg_id.clone(),
+                retry_count: 0,
+                last_delivery: tokio::time::Instant::now(),
+                nack_delay: None,
+                deliver_at,
+                dead_lettering: false,
+            },
+        );
+    }
+
+    // Skips the expired messages, they are acknowledged on the segment as if delivered
+    async fn skip_expired_messages(&mut self, expired: Vec<(u64, MessageID)>) {
+        if expired.is_empty() {
+            return;
+        }
+
+        let mut cursor = self.cursor.lock().await;
+        let count = expired.len();
+        for (request_id, msg_id) in expired {
+            if cursor.segment_id as u64 == msg_id.segment_id {
+                cursor.ack(msg_id.segment_offset);
+            }
+            trace!("Expired message with id {:?} not delivered", msg_id);
+            self.acked_messages.insert(msg_id, request_id);
+        }
+        drop(cursor);
+        self.count_expired(count);
+    }
+
+    fn count_expired(&self, count: usize) {
+        counter!(MESSAGES_EXPIRED_COUNTER, "topic" => self.topic_store.topic_name.clone())
+            .increment(count as u64);
+    }
+
+    // Holds the delayed messages back until their delivery time, they are acknowledged on the segment
+    // and tracked on the subscription cursor, so the subscription moves on with the following messages
+    async fn defer_messages(&mut self, deferred: Vec<(u64, u64, MessageID)>) {
+        if deferred.is_empty() {
+            return;
+        }
+
+        let mut cursor = self.cursor.lock().await;
+        for (deliver_at, request_id, msg_id) in deferred {
+            cursor.delayed.add(
+                deliver_at,
+                msg_id.segment_id as usize,
+                msg_id.segment_offset,
+            );
+            if cursor.segment_id as u64 == msg_id.segment_id {
+                cursor.ack(msg_id.segment_offset);
             }
+            trace!(
+                "Message with id {:?} delayed until {}, {} delayed messages",
+                msg_id,
+                deliver_at,
+                cursor.delayed.len()
+            );
+            self.acked_messages.insert(msg_id, request_id);
+        }
+    }
+
+    /// Forgets the messages awaiting acknowledgment, so the delivery resumes from the last
+    /// acknowledged message, as another consumer takes over the subscription.
+    /// The messages are delivered again without counting as redeliveries.
+    /// The messages handed over to the dead letter topic are kept, until the topic stores them.
+    pub fn reset_pending(&mut self) {
+        let pending_count = self.pending_acks.len();
+        self.pending_acks
+            .retain(|_, pending| pending.dead_lettering);
+        if self.pending_acks.len() < pending_count {
+            trace!(
+                "Resuming the delivery before the {} unacknowledged messages",
+                pending_count - self.pending_acks.len()
+            );
+        }
+    }
+
+    /// Puts back the message taken for delivery but not sent, as none of the consumers could receive it.
+    /// It is the next message to be delivered again, without counting as a redelivery,
+    /// so the messages of a key are kept in order.
+    pub fn release_message(&mut self, msg_id: &MessageID) {
+        let key = PendingAck::key(msg_id);
+        let Some(pending) = self.pending_acks.get_mut(&key) else {
+            return;
+        };
+
+        if pending.retry_count == 0 {
+            // the first delivery, it is taken again from its segment, or from the delayed messages
+            self.pending_acks.remove(&key);
+        } else {
+            // the redelivery is due right away
+            pending.retry_count -= 1;
+            pending.nack_delay = Some(Duration::ZERO);
+            pending.last_delivery = tokio::time::Instant::now();
         }
+        trace!("Message with msg_id {:?} released, not delivered", msg_id);
+    }
+
+    /// Returns the instant the next pending message is due for redelivery, or the next delayed message
+    /// is due for delivery, if it is not yet due. The dispatchers wait for it,
+    /// so the messages are delivered without further notifications.
+    pub fn redelivery_deadline(&self) -> Option<tokio::time::Instant> {
+        let now = tokio::time::Instant::now();
+        let delayed_deadline = self
+            .next_delayed_delivery
+            .map(|deliver_at| now + Duration::from_millis(deliver_at.saturating_sub(now_millis())));
+        self.pending_acks
+            .values()
+            .filter(|pending| !pending.dead_lettering)
+            .map(|pending| pending.redelivery_deadline(&self.redelivery_backoff))
+            .chain(delayed_deadline)
+            .filter(|deadline| *deadline > now)
+            .min()
     }
 
     async fn send_message(&mut self) -> Result<StreamMessage> {
         if let Some(segment) = &self.segment {
-            let next_message = {
+            let now = now_millis();
+            let (next_message, deferred, expired, near_end) = {
                 let segment_data = segment.read().await;
-                segment_data
+                // the delayed messages not yet due are skipped, and held back,
+                // the expired messages are skipped for good
+                let mut deferred = Vec::new();
+                let mut expired = Vec::new();
+                let next_message = segment_data
                     .messages
                     .iter()
-                    .find(|msg| !self.acked_messages.contains_key(&msg.msg_id))
-                    .cloned()
+                    .filter(|msg| {
+                        !self.acked_messages.contains_key(&msg.msg_id)
+                            && !self
+                                .pending_acks
+                                .contains_key(&PendingAck::key(&msg.msg_id))
+                    })
+                    .find(|msg| match msg.deliver_at {
+                        _ if msg.is_expired(now) => {
+                            expired.push((msg.request_id, msg.msg_id.clone()));
+                            false
+                        }
+                        Some(deliver_at) if deliver_at > now => {
+                            deferred.push((deliver_at, msg.request_id, msg.msg_id.clone()));
+                            false
+                        }
+                        _ => true,
+                    })
+                    .cloned();
+                // the closed segment is consumed past the read-ahead threshold
+                let near_end = segment_data.close_time > 0
+                    && (self.acked_messages.len()
+                        + self.pending_acks.len()
+                        + deferred.len()
+                        + expired.len()
+                        + 1)
+                        * 100
+                        >= segment_data.messages.len() * READ_AHEAD_THRESHOLD_PERCENT;
+                (next_message, deferred, expired, near_end)
             };
+            self.defer_messages(deferred).await;
+            self.skip_expired_messages(expired).await;
+
+            if near_end && self.prefetched_after != self.current_segment_id {
+                if let Some(segment_id) = self.current_segment_id {
+                    self.topic_store.prefetch_segments_after(segment_id).await;
+                    self.prefetched_after = Some(segment_id);
+                }
+            }
 
             match next_message {
                 Some(msg) => {
                     trace!("Sending message with id {:?}", msg.msg_id);
-                    self.pending_ack_message = Some((msg.request_id, msg.msg_id.clone()));
+                    self.insert_pending(&msg, None);
                     Ok(msg)
                 }
                 None => Err(ReliableDispatchError::NoMessagesAvailable),
@@ -206,61 +758,17 @@ impl SubscriptionDispatch {
         }
     }
 
-    /// Handle the consumer message acknowledgement
-    pub async fn handle_message_acked(
-        &mut self,
-        request_id: u64,
-        msg_id: MessageID,
-    ) -> Result<Option<StreamMessage>> {
-        // Validate that the message belongs to current segment
-        // Not sure if needed
-        // if !self.segment.as_ref().map_or(false, |seg| {
-        //     seg.read()
-        //         .map_or(false, |s| s.messages.iter().any(|m| m.msg_id == msg_id))
-        // }) {
-        //     return Err(ReliableDispatchError::AcknowledgmentError(
-        //         "Acked message does not belong to current segment".to_string(),
-        //     ));
-        // }
-
-        if let Some((pending_request_id, pending_msg_id)) = &self.pending_ack_message {
-            if *pending_request_id == request_id && *pending_msg_id == msg_id {
-                self.pending_ack_message = None;
-                self.acked_messages.insert(msg_id.clone(), request_id);
-                trace!(
-                    "Message with request_id {} and msg_id {:?} acknowledged",
-                    request_id,
-                    msg_id
-                );
-                self.retry_count = 0;
-
-                // Try to fetch the next message after acknowledgment
-                match self.process_current_segment().await {
-                    Ok(message) => {
-                        return Ok(Some(message));
-                    }
-                    Err(ReliableDispatchError::NoMessagesAvailable) => {
-                        return Ok(None);
-                    }
-                    Err(e) => {
-                        trace!(
-                            "Error processing current segment after acknowledgment: {}",
-                            e
-                        );
+    // The message awaiting acknowledgment, matching the request
+    fn is_pending(&self, request_id: u64, msg_id: &MessageID) -> bool {
+        self.pending_acks
+            .get(&PendingAck::key(msg_id))
+            .is_some_and(|pending| pending.request_id == request_id && pending.msg_id == *msg_id)
+    }
 
-                        return Ok(None);
-                    }
-                }
-            } else {
-                // Received acknowledgment doesn't match the pending message
-                return Err(ReliableDispatchError::AcknowledgmentError(
-                format!(
-                    "Invalid acknowledgment: expected (request_id: {}, msg_id: {:?}), got (request_id: {}, msg_id: {:?})",
-                    pending_request_id, pending_msg_id, request_id, msg_id
-                ),
-            ));
-            }
-        } else {
+    /// Handle the consumer message acknowledgement, without fetching the next message,
+    /// so the dispatcher sends it once the consumer has a permit to receive it
+    pub async fn acknowledge(&mut self, request_id: u64, msg_id: MessageID) -> Result<()> {
+        if self.pending_acks.is_empty() {
             trace!(
                 "Stray acknowledgment received for request_id {} and msg_id {:?}",
                 request_id,
@@ -269,10 +777,82 @@ impl SubscriptionDispatch {
             return Err(ReliableDispatchError::AcknowledgmentError(
                 "No pending message to acknowledge".to_string(),
             ));
-            // Handle stray acknowledgments (when there is no pending message) ?
-            // self.acked_messages.insert(msg_id.clone(), request_id);
-            // No pending message to process; return None
-            //return Ok(None);
         }
+
+        if !self.is_pending(request_id, &msg_id) {
+            // Received acknowledgment doesn't match any of the pending messages
+            return Err(ReliableDispatchError::AcknowledgmentError(format!(
+                "Invalid acknowledgment: (request_id: {}, msg_id: {:?}) is not awaiting acknowledgment",
+                request_id, msg_id
+            )));
+        }
+
+        if let Some(pending) = self.pending_acks.remove(&PendingAck::key(&msg_id)) {
+            self.mark_acked(&pending).await;
+        }
+        trace!(
+            "Message with request_id {} and msg_id {:?} acknowledged",
+            request_id,
+            msg_id
+        );
+        Ok(())
+    }
+
+    /// Handle the consumer message acknowledgement, and fetch the next message to send
+    pub async fn handle_message_acked(
+        &mut self,
+        request_id: u64,
+        msg_id: MessageID,
+    ) -> Result<Option<StreamMessage>> {
+        self.acknowledge(request_id, msg_id).await?;
+
+        // Try to fetch the next message after acknowledgment
+        match self.process_current_segment().await {
+            Ok(message) => Ok(Some(message)),
+            Err(ReliableDispatchError::NoMessagesAvailable) => Ok(None),
+            Err(e) => {
+                trace!(
+                    "Error processing current segment after acknowledgment: {}",
+                    e
+                );
+                Ok(None)
+            }
+        }
+    }
+
+    /// Handle the consumer negative acknowledgement, the message is redelivered
+    /// once the redelivery delay elapsed, or right away if no delay was requested.
+    /// The redelivery counts toward the `max_redeliveries` of the subscription.
+    pub async fn handle_message_nacked(
+        &mut self,
+        request_id: u64,
+        msg_id: MessageID,
+        redelivery_delay: Option<Duration>,
+    ) -> Result<()> {
+        if !self.is_pending(request_id, &msg_id) {
+            return Err(ReliableDispatchError::AcknowledgmentError(format!(
+                "Invalid negative acknowledgment: (request_id: {}, msg_id: {:?}) is not awaiting acknowledgment",
+                request_id, msg_id
+            )));
+        }
+
+        if let Some(pending) = self.pending_acks.get_mut(&PendingAck::key(&msg_id)) {
+            pending.nack_delay = Some(redelivery_delay.unwrap_or(Duration::ZERO));
+            pending.last_delivery = tokio::time::Instant::now();
+        }
+        trace!(
+            "Message with request_id {} and msg_id {:?} negatively acknowledged, redelivery in {:?}",
+            request_id,
+            msg_id,
+            redelivery_delay
+        );
+        Ok(())
     }
 }
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
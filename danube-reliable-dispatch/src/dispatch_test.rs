@@ -1,11 +1,17 @@
 #[cfg(test)]
 use crate::{
-    dispatch::SubscriptionDispatch, errors::ReliableDispatchError,
-    storage_backend::InMemoryStorage, topic_storage::TopicStore,
+    dispatch::{RedeliveryBackoff, SubscriptionDispatch},
+    errors::ReliableDispatchError,
+    storage_backend::InMemoryStorage,
+    topic_storage::TopicStore,
 };
 
 #[cfg(test)]
-use crate::topic_cache::TopicCache;
+use crate::{
+    cursor::{AckedSegment, SubscriptionCursor},
+    topic_cache::TopicCache,
+    ReliableDispatch,
+};
 
 #[cfg(test)]
 use danube_core::{
@@ -16,13 +22,11 @@ use danube_core::{
 #[cfg(test)]
 use std::collections::HashMap;
 #[cfg(test)]
-use std::sync::atomic::AtomicUsize;
-#[cfg(test)]
 use std::sync::Arc;
 #[cfg(test)]
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 #[cfg(test)]
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, RwLock};
 
 /// Test helper to create a TopicStore with default settings
 #[cfg(test)]
@@ -34,7 +38,7 @@ fn create_test_topic_store(topic_name: &str) -> TopicStore {
         RetentionPolicy::RetainUntilAck,
         60, // 60s retention period
     );
-    let topic_cache = TopicCache::new(storage, 10, 10);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
     TopicStore::new(topic_name, topic_cache, reliable_options)
 }
 
@@ -73,6 +77,11 @@ fn create_test_message(
         producer_name: "test-producer".to_string(),
         subscription_name: Some("test-subscription".to_string()),
         attributes: HashMap::new(),
+        key: None,
+        deliver_at: None,
+        deliver_after: None,
+        ttl: None,
+        expire_at: None,
     }
 }
 
@@ -86,12 +95,12 @@ fn create_test_message(
 async fn test_new_subscription_dispatch() {
     let topic_name = "/default/test-topic";
     let topic_store = create_test_topic_store(topic_name);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     assert!(dispatch.segment.is_none());
     assert!(dispatch.current_segment_id.is_none());
-    assert!(dispatch.pending_ack_message.is_none());
+    assert!(dispatch.pending_acks.is_empty());
     assert!(dispatch.acked_messages.is_empty());
 }
 
@@ -101,7 +110,7 @@ async fn test_new_subscription_dispatch() {
 async fn test_process_empty_segment() {
     let topic_name = "/default/test-topic";
     let topic_store = create_test_topic_store(topic_name);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let mut dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     let result = dispatch.process_current_segment().await;
@@ -121,9 +130,9 @@ async fn test_message_acknowledgment() {
     let topic_name = "/default/test-topic";
     let storage = Arc::new(InMemoryStorage::new());
     let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 60);
-    let topic_cache = TopicCache::new(storage.clone(), 10, 10);
+    let topic_cache = TopicCache::new(storage.clone(), 1024 * 1024, 10);
     let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let mut dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     // Create and setup segment with message
@@ -151,13 +160,17 @@ async fn test_message_acknowledgment() {
 
     dispatch.segment = Some(segment);
     dispatch.current_segment_id = Some(1);
-    dispatch.pending_ack_message = Some((request_id, msg_id.clone()));
+    let delivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(delivered.msg_id, msg_id);
+    assert!(dispatch
+        .pending_acks
+        .contains_key(&(msg_id.segment_id, msg_id.segment_offset)));
 
     let result = dispatch
         .handle_message_acked(request_id, msg_id.clone())
         .await;
     assert!(result.is_ok());
-    assert!(dispatch.pending_ack_message.is_none());
+    assert!(dispatch.pending_acks.is_empty());
     assert!(dispatch.acked_messages.contains_key(&msg_id));
 }
 
@@ -168,7 +181,7 @@ async fn test_message_acknowledgment() {
 async fn test_invalid_acknowledgment() {
     let topic_name = "/default/test-topic";
     let topic_store = create_test_topic_store(topic_name);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let mut dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     let msg_id = create_test_message_id(topic_name, 0, 1);
@@ -188,7 +201,7 @@ async fn test_invalid_acknowledgment() {
 async fn test_segment_transition() {
     let topic_name = "/default/test-topic";
     let topic_store = create_test_topic_store(topic_name);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let mut dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     let segment = Arc::new(RwLock::new(Segment::new(1, 1024 * 1024)));
@@ -209,7 +222,7 @@ async fn test_segment_transition() {
 async fn test_clear_current_segment() {
     let topic_name = "/default/test-topic";
     let topic_store = create_test_topic_store(topic_name);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let mut dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     let segment = Arc::new(RwLock::new(Segment::new(1, 1024 * 1024)));
@@ -234,9 +247,9 @@ async fn test_validate_segment() {
     let topic_name = "/default/test-topic";
     let storage = Arc::new(InMemoryStorage::new());
     let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 60);
-    let topic_cache = TopicCache::new(storage.clone(), 10, 10);
+    let topic_cache = TopicCache::new(storage.clone(), 1024 * 1024, 10);
     let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
-    let last_acked = Arc::new(AtomicUsize::new(0));
+    let last_acked = Arc::new(AckedSegment::default());
     let mut dispatch = SubscriptionDispatch::new(topic_store, last_acked);
 
     let segment = Arc::new(RwLock::new(Segment::new(1, 1024 * 1024)));
@@ -270,3 +283,710 @@ async fn test_validate_segment() {
     let result = dispatch.validate_segment_state(1, &segment).await;
     assert!(matches!(result, Ok(true)));
 }
+
+/// Tests resuming a restored subscription after a topic reload
+/// Validates:
+/// - The segment index is recovered from the segments stored in the backend
+/// - The subscription continues with the segment after its last acknowledged one
+/// - The subscription keeps its position while no following segment is available
+#[tokio::test]
+async fn test_resume_restored_subscription() {
+    let topic_name = "/default/test-topic";
+    let storage = Arc::new(InMemoryStorage::new());
+    for segment_id in 0..3 {
+        let mut segment = Segment::new(segment_id, 1024 * 1024);
+        segment.close_time = 1;
+        storage
+            .put_segment(topic_name, segment_id, Arc::new(RwLock::new(segment)))
+            .await
+            .unwrap();
+    }
+
+    let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 60);
+    let topic_cache = TopicCache::new(storage.clone(), 1024 * 1024, 10);
+    let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
+    topic_store.recover().await.unwrap();
+    assert_eq!(
+        *topic_store.segments_index.read().await,
+        vec![(0, 1), (1, 1), (2, 1)]
+    );
+
+    // The cursor points to a removed segment, the dispatch continues with the following one
+    storage.remove_segment(topic_name, 1).await.unwrap();
+    topic_store
+        .segments_index
+        .write()
+        .await
+        .retain(|(id, _)| *id != 1);
+
+    let last_acked = Arc::new(AckedSegment::default());
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::new(1)));
+    let mut dispatch = SubscriptionDispatch::new(topic_store.clone(), last_acked.clone())
+        .with_cursor(cursor.clone());
+    dispatch.resume().await.unwrap();
+
+    dispatch.move_to_next_segment().await.unwrap();
+    assert_eq!(dispatch.current_segment_id, Some(2));
+    assert_eq!(last_acked.get(), Some(0));
+    assert_eq!(*cursor.lock().await, SubscriptionCursor::new(2));
+
+    // No segment after the last one, the subscription waits on its position
+    dispatch.segment = Some(Arc::new(RwLock::new(Segment::new(2, 1024 * 1024))));
+    dispatch.move_to_next_segment().await.unwrap();
+    assert!(dispatch.segment.is_none());
+    assert_eq!(dispatch.current_segment_id, Some(2));
+}
+
+/// Tests the subscription cursor with out of order acknowledgments
+/// Validates:
+/// - The offset moves only over the contiguous acknowledged messages
+/// - The messages acknowledged above the offset are tracked in the bitmap
+/// - The bitmap is shifted as the offset moves, across the 64 bit words
+#[test]
+fn test_subscription_cursor_out_of_order_acks() {
+    let mut cursor = SubscriptionCursor::new(3);
+
+    cursor.ack(1);
+    cursor.ack(70);
+    assert_eq!(cursor.offset(), 0);
+    assert!(cursor.is_acked(1));
+    assert!(cursor.is_acked(70));
+    assert!(!cursor.is_acked(2));
+
+    cursor.ack(0);
+    assert_eq!(cursor.offset(), 2);
+    assert!(cursor.is_acked(70));
+    assert!(!cursor.is_acked(69));
+
+    for offset in 2..70 {
+        cursor.ack(offset);
+    }
+    assert_eq!(cursor.offset(), 71);
+    assert!(cursor.acked_bitmap.is_empty());
+    assert_eq!(cursor.last_acked_segment(), Some(2));
+}
+
+/// Tests the precise redelivery after a consumer reconnect
+/// Validates:
+/// - The acknowledgments are recorded in the shared subscription cursor
+/// - A new dispatch resumes on the same segment, skipping the acknowledged messages
+#[tokio::test]
+async fn test_resume_after_last_acked_message() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    for _ in 0..3 {
+        topic_store
+            .store_message(create_test_message(topic_name, 0, 0, vec![1]))
+            .await
+            .unwrap();
+    }
+
+    let last_acked = Arc::new(AckedSegment::default());
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::default()));
+    let mut dispatch = SubscriptionDispatch::new(topic_store.clone(), last_acked.clone())
+        .with_cursor(cursor.clone());
+    dispatch.resume().await.unwrap();
+
+    let message = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(message.msg_id.segment_offset, 0);
+    let next = dispatch
+        .handle_message_acked(message.request_id, message.msg_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(next.msg_id.segment_offset, 1);
+    assert_eq!(cursor.lock().await.offset(), 1);
+
+    // The consumer reconnects before acknowledging the second message
+    drop(dispatch);
+    let mut dispatch =
+        SubscriptionDispatch::new(topic_store, last_acked).with_cursor(cursor.clone());
+    dispatch.resume().await.unwrap();
+    let message = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(message.msg_id.segment_offset, 1);
+}
+
+/// Tests the read-ahead of the segments following the one consumed by the subscription
+/// Validates:
+/// - Nothing is prefetched before the read-ahead threshold of the closed segment
+/// - The next two segments are loaded into the cache once the threshold is reached
+/// - The segments further ahead are not loaded
+#[tokio::test]
+async fn test_read_ahead_prefetch() {
+    let topic_name = "/default/test-topic";
+    let storage = Arc::new(InMemoryStorage::new());
+    for segment_id in 0..4 {
+        let mut segment = Segment::new(segment_id, 1024 * 1024);
+        for offset in 0..4 {
+            segment.add_message(create_test_message(
+                topic_name,
+                segment_id as u64,
+                offset,
+                vec![1],
+            ));
+        }
+        segment.close_time = 1;
+        storage
+            .put_segment(topic_name, segment_id, Arc::new(RwLock::new(segment)))
+            .await
+            .unwrap();
+    }
+
+    let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 60);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+    let topic_store = TopicStore::new(topic_name, topic_cache.clone(), reliable_options);
+    topic_store.recover().await.unwrap();
+
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()));
+    let mut message = dispatch.process_current_segment().await.unwrap();
+    for _ in 0..2 {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        assert!(!topic_cache.is_cached(topic_name, 1).await);
+        message = dispatch
+            .handle_message_acked(message.request_id, message.msg_id.clone())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    // the third of the four messages is sent, the following segments are prefetched
+    assert_eq!(message.msg_id.segment_offset, 2);
+    dispatch
+        .handle_message_acked(message.request_id, message.msg_id.clone())
+        .await
+        .unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    assert!(topic_cache.is_cached(topic_name, 1).await);
+    assert!(topic_cache.is_cached(topic_name, 2).await);
+    assert!(!topic_cache.is_cached(topic_name, 3).await);
+}
+
+/// Tests moving the subscription past a message exceeding its redeliveries
+/// Validates:
+/// - MaxRetriesExceeded is returned once the redeliveries are exhausted
+/// - The message is taken for the dead letter topic with its number of deliveries
+/// - The message is not redelivered while handed over, the following messages are dispatched meanwhile
+/// - The message not stored by the dead letter topic is taken again once due
+/// - The message handed over is kept across the reset of the pending messages, it is not delivered again
+/// - The skipped message is acknowledged on the cursor
+#[tokio::test]
+async fn test_skip_message_exceeding_redeliveries() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    for offset in 0..2 {
+        topic_store
+            .store_message(create_test_message(topic_name, 0, offset, vec![1]))
+            .await
+            .unwrap();
+    }
+
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::new(0)));
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_cursor(cursor.clone())
+        .with_max_in_flight(2)
+        .with_max_redeliveries(1)
+        .with_redelivery_backoff(RedeliveryBackoff::new(Duration::ZERO, 1.0, Duration::ZERO));
+
+    let message = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(message.msg_id.segment_offset, 0);
+    // the first redelivery is immediate, then the redeliveries are exhausted
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id, message.msg_id);
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::MaxRetriesExceeded)
+    ));
+
+    let (pending, deliveries) = dispatch.take_dead_letter().await.unwrap().unwrap();
+    assert_eq!(pending.msg_id, message.msg_id);
+    assert_eq!(deliveries, 2);
+    assert!(dispatch.take_dead_letter().await.unwrap().is_none());
+
+    // the message is handed over, the next message is dispatched
+    let next = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(next.msg_id.segment_offset, 1);
+    dispatch
+        .acknowledge(next.request_id, next.msg_id.clone())
+        .await
+        .unwrap();
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+
+    // the dead letter topic failed to store it, it is taken again
+    dispatch.retry_dead_letter(&message.msg_id);
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::MaxRetriesExceeded)
+    ));
+    let (pending, _) = dispatch.take_dead_letter().await.unwrap().unwrap();
+    assert_eq!(pending.msg_id, message.msg_id);
+
+    // another consumer takes over while the message is handed over
+    dispatch.reset_pending();
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+
+    dispatch.skip_message(&message.msg_id).await.unwrap();
+    assert!(dispatch.take_dead_letter().await.unwrap().is_none());
+    assert!(cursor.lock().await.is_acked(0));
+    assert!(dispatch.skip_message(&message.msg_id).await.is_err());
+}
+
+/// Tests the negative acknowledgment of the pending message
+/// Validates:
+/// - A nack of a message other than the pending one is rejected
+/// - The nacked message is not redelivered before the requested delay elapsed
+/// - The nacked message is redelivered right away without a delay
+/// - The redeliveries requested by nacks count toward max_redeliveries
+#[tokio::test]
+async fn test_message_negative_acknowledgment() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    for offset in 0..2 {
+        topic_store
+            .store_message(create_test_message(topic_name, 0, offset, vec![1]))
+            .await
+            .unwrap();
+    }
+
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_max_redeliveries(2);
+
+    let message = dispatch.process_current_segment().await.unwrap();
+    assert!(dispatch
+        .handle_message_nacked(
+            message.request_id,
+            create_test_message_id(topic_name, 0, 1),
+            None
+        )
+        .await
+        .is_err());
+
+    // redelivered once the delay elapsed
+    dispatch
+        .handle_message_nacked(
+            message.request_id,
+            message.msg_id.clone(),
+            Some(Duration::from_millis(50)),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id, message.msg_id);
+
+    // redelivered right away
+    dispatch
+        .handle_message_nacked(message.request_id, message.msg_id.clone(), None)
+        .await
+        .unwrap();
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id, message.msg_id);
+
+    dispatch
+        .handle_message_nacked(message.request_id, message.msg_id.clone(), None)
+        .await
+        .unwrap();
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::MaxRetriesExceeded)
+    ));
+}
+
+/// Tests the redelivery schedule of the unacknowledged messages
+/// Validates:
+/// - The delays grow by the backoff multiplier, capped at the max backoff
+/// - The message is not redelivered before the ack timeout elapsed since its delivery
+/// - The following redelivery waits for the backoff delay
+/// - The redelivery deadline of the pending message is reported to the dispatchers
+#[tokio::test]
+async fn test_redelivery_backoff() {
+    let backoff =
+        RedeliveryBackoff::new(Duration::from_millis(50), 2.0, Duration::from_millis(150));
+    assert_eq!(backoff.delay(0), Duration::from_millis(50));
+    assert_eq!(backoff.delay(1), Duration::from_millis(100));
+    assert_eq!(backoff.delay(2), Duration::from_millis(150));
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(150));
+
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    topic_store
+        .store_message(create_test_message(topic_name, 0, 0, vec![1]))
+        .await
+        .unwrap();
+
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_redelivery_backoff(backoff);
+
+    let message = dispatch.process_current_segment().await.unwrap();
+    assert!(dispatch.redelivery_deadline().is_some());
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+
+    tokio::time::sleep(Duration::from_millis(70)).await;
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id, message.msg_id);
+
+    // the second redelivery waits for 100ms
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id, message.msg_id);
+}
+
+/// Tests the pipelined delivery within the in-flight window
+/// Validates:
+/// - Up to max_in_flight messages are delivered before any acknowledgment
+/// - The messages are acknowledged individually and out of order
+/// - An acknowledgment frees a slot of the window for the next message
+/// - Only the unacknowledged messages are redelivered after the ack timeout
+#[tokio::test]
+async fn test_in_flight_window() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    for offset in 0..5 {
+        topic_store
+            .store_message(create_test_message(topic_name, 0, offset, vec![1]))
+            .await
+            .unwrap();
+    }
+
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::new(0)));
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_cursor(cursor.clone())
+        .with_max_in_flight(3)
+        .with_redelivery_backoff(RedeliveryBackoff::new(
+            Duration::from_millis(50),
+            1.0,
+            Duration::from_millis(50),
+        ));
+
+    let mut delivered = Vec::new();
+    for _ in 0..3 {
+        delivered.push(dispatch.process_current_segment().await.unwrap());
+    }
+    let offsets: Vec<u64> = delivered.iter().map(|m| m.msg_id.segment_offset).collect();
+    assert_eq!(offsets, vec![0, 1, 2]);
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+
+    // the out of order acknowledgment frees a slot for the next message
+    let next = dispatch
+        .handle_message_acked(delivered[1].request_id, delivered[1].msg_id.clone())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(next.msg_id.segment_offset, 3);
+    assert!(cursor.lock().await.is_acked(1));
+    assert!(!cursor.lock().await.is_acked(0));
+    assert!(dispatch
+        .handle_message_acked(delivered[1].request_id, delivered[1].msg_id.clone())
+        .await
+        .is_err());
+
+    // only the unacknowledged messages are redelivered
+    tokio::time::sleep(Duration::from_millis(70)).await;
+    let mut redelivered = Vec::new();
+    for _ in 0..3 {
+        let message = dispatch.process_current_segment().await.unwrap();
+        redelivered.push(message.msg_id.segment_offset);
+    }
+    assert_eq!(redelivered, vec![0, 2, 3]);
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+    assert!(dispatch.redelivery_deadline().is_some());
+}
+
+/// Tests the resume of the delivery as another consumer takes over the subscription
+/// Validates:
+/// - The unacknowledged messages are delivered again right away, in order
+/// - The acknowledged messages are not delivered again
+/// - The deliveries after the reset do not count as redeliveries
+#[tokio::test]
+async fn test_reset_pending() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    for offset in 0..4 {
+        topic_store
+            .store_message(create_test_message(topic_name, 0, offset, vec![1]))
+            .await
+            .unwrap();
+    }
+
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::new(0)));
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_cursor(cursor.clone())
+        .with_max_in_flight(3)
+        .with_max_redeliveries(1)
+        .with_redelivery_backoff(RedeliveryBackoff::new(
+            Duration::from_millis(50),
+            1.0,
+            Duration::from_millis(50),
+        ));
+
+    let mut delivered = Vec::new();
+    for _ in 0..3 {
+        delivered.push(dispatch.process_current_segment().await.unwrap());
+    }
+    dispatch
+        .acknowledge(delivered[0].request_id, delivered[0].msg_id.clone())
+        .await
+        .unwrap();
+
+    dispatch.reset_pending();
+    assert!(dispatch.redelivery_deadline().is_none());
+
+    let mut resumed = Vec::new();
+    for _ in 0..3 {
+        let message = dispatch.process_current_segment().await.unwrap();
+        resumed.push(message.msg_id.segment_offset);
+    }
+    assert_eq!(resumed, vec![1, 2, 3]);
+    assert!(cursor.lock().await.is_acked(0));
+
+    // the messages still have their redelivery
+    tokio::time::sleep(Duration::from_millis(70)).await;
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id.segment_offset, 1);
+}
+
+/// Tests the release of the messages taken for delivery but not sent to any consumer
+/// Validates:
+/// - The released message is the next one delivered, ahead of the following messages
+/// - The released message is not waiting for its ack timeout
+/// - The released redelivery is due right away, and doesn't count as a redelivery
+#[tokio::test]
+async fn test_release_message() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    for offset in 0..3 {
+        topic_store
+            .store_message(create_test_message(topic_name, 0, offset, vec![1]))
+            .await
+            .unwrap();
+    }
+
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_max_in_flight(3)
+        .with_max_redeliveries(1)
+        .with_redelivery_backoff(RedeliveryBackoff::new(
+            Duration::from_millis(50),
+            1.0,
+            Duration::from_millis(50),
+        ));
+
+    let first = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(first.msg_id.segment_offset, 0);
+    dispatch.release_message(&first.msg_id);
+    assert!(dispatch.pending_acks.is_empty());
+    assert!(dispatch.redelivery_deadline().is_none());
+
+    // the released message is delivered again, before the next ones
+    let message = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(message.msg_id.segment_offset, 0);
+    let next = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(next.msg_id.segment_offset, 1);
+    dispatch
+        .acknowledge(next.request_id, next.msg_id.clone())
+        .await
+        .unwrap();
+
+    // the redelivery of the message, released, is taken again right away
+    tokio::time::sleep(Duration::from_millis(70)).await;
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id.segment_offset, 0);
+    dispatch.release_message(&redelivered.msg_id);
+    let redelivered = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(redelivered.msg_id.segment_offset, 0);
+
+    // only the redelivery sent counts toward the redeliveries of the message
+    tokio::time::sleep(Duration::from_millis(70)).await;
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::MaxRetriesExceeded)
+    ));
+}
+
+/// Tests the delayed delivery of the messages
+/// Validates:
+/// - The delayed message is held back, the following messages are delivered meanwhile
+/// - The delayed message is tracked on the cursor and acknowledged on its segment
+/// - The delivery time of the delayed message is reported to the dispatchers
+/// - The delayed message is delivered once due, also after the subscription is resumed
+/// - The acknowledged delayed message is no longer tracked
+#[tokio::test]
+async fn test_delayed_delivery() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    let deliver_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+        + 150;
+    for offset in 0..3 {
+        let mut message = create_test_message(topic_name, 0, offset, vec![1]);
+        if offset == 0 {
+            message.deliver_at = Some(deliver_at);
+        }
+        topic_store.store_message(message).await.unwrap();
+    }
+
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::new(0)));
+    let mut dispatch =
+        SubscriptionDispatch::new(topic_store.clone(), Arc::new(AckedSegment::default()))
+            .with_cursor(cursor.clone());
+
+    for offset in 1..3 {
+        let message = dispatch.process_current_segment().await.unwrap();
+        assert_eq!(message.msg_id.segment_offset, offset);
+        dispatch
+            .acknowledge(message.request_id, message.msg_id)
+            .await
+            .unwrap();
+    }
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+    assert!(cursor.lock().await.is_acked(0));
+    assert_eq!(cursor.lock().await.delayed_messages(), 1);
+    assert!(dispatch.redelivery_deadline().is_some());
+
+    // the subscription is resumed from its cursor, as on another broker
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_cursor(cursor.clone());
+    dispatch.resume().await.unwrap();
+    assert!(dispatch.redelivery_deadline().is_some());
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let delayed = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(delayed.msg_id.segment_offset, 0);
+    assert_eq!(delayed.deliver_at, Some(deliver_at));
+    dispatch
+        .acknowledge(delayed.request_id, delayed.msg_id)
+        .await
+        .unwrap();
+    assert_eq!(cursor.lock().await.delayed_messages(), 0);
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+}
+
+/// Tests the expiration of the messages
+/// Validates:
+/// - The expired message is skipped and acknowledged on the cursor, the next message is delivered
+/// - The message expired while awaiting acknowledgment is not redelivered
+/// - The delayed message expired before its delivery time is no longer tracked
+#[tokio::test]
+async fn test_message_expiration() {
+    let topic_name = "/default/test-topic";
+    let topic_store = create_test_topic_store(topic_name);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    for offset in 0..4 {
+        let mut message = create_test_message(topic_name, 0, offset, vec![1]);
+        match offset {
+            0 => message.expire_at = Some(now - 1),
+            2 => message.expire_at = Some(now + 100),
+            3 => {
+                message.deliver_at = Some(now + 150);
+                message.expire_at = Some(now + 100);
+            }
+            _ => {}
+        }
+        topic_store.store_message(message).await.unwrap();
+    }
+
+    let cursor = Arc::new(Mutex::new(SubscriptionCursor::new(0)));
+    let mut dispatch = SubscriptionDispatch::new(topic_store, Arc::new(AckedSegment::default()))
+        .with_cursor(cursor.clone())
+        .with_max_in_flight(2)
+        .with_redelivery_backoff(RedeliveryBackoff::new(
+            Duration::from_millis(50),
+            1.0,
+            Duration::from_millis(50),
+        ));
+
+    let first = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(first.msg_id.segment_offset, 1);
+    assert!(cursor.lock().await.is_acked(0));
+    let second = dispatch.process_current_segment().await.unwrap();
+    assert_eq!(second.msg_id.segment_offset, 2);
+    dispatch
+        .acknowledge(first.request_id, first.msg_id)
+        .await
+        .unwrap();
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+    assert_eq!(cursor.lock().await.delayed_messages(), 1);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert!(matches!(
+        dispatch.process_current_segment().await,
+        Err(ReliableDispatchError::NoMessagesAvailable)
+    ));
+    assert!(dispatch.pending_acks.is_empty());
+    assert!(cursor.lock().await.is_acked(2));
+    assert_eq!(cursor.lock().await.delayed_messages(), 0);
+}
+
+/// Tests the removal of a subscription from the reliable dispatch
+/// Validates:
+/// - The restored and the new subscriptions are tracked with their cursors
+/// - The removed subscription no longer holds back the acknowledged segments
+/// - The removed subscription cursor is no longer persisted
+#[tokio::test]
+async fn test_remove_subscription() {
+    let topic_name = "/default/test-topic";
+    let storage = Arc::new(InMemoryStorage::new());
+    let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 60);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+    let subscription_cursors = HashMap::from([("sub-a".to_string(), SubscriptionCursor::new(2))]);
+    let reliable_dispatch = ReliableDispatch::new(
+        topic_name,
+        reliable_options,
+        topic_cache,
+        subscription_cursors,
+    )
+    .await
+    .unwrap();
+    reliable_dispatch.add_subscription("sub-b").await.unwrap();
+    reliable_dispatch
+        .new_subscription_dispatch("sub-b")
+        .await
+        .unwrap();
+    assert_eq!(reliable_dispatch.get_subscription_cursors().len(), 2);
+
+    reliable_dispatch.remove_subscription("sub-a");
+    assert!(reliable_dispatch.subscriptions.get("sub-a").is_none());
+    assert!(reliable_dispatch
+        .get_last_acknowledged_segment("sub-a")
+        .await
+        .is_err());
+    let cursors = reliable_dispatch.get_subscription_cursors();
+    assert_eq!(cursors.len(), 1);
+    assert_eq!(cursors[0].0, "sub-b");
+}
@@ -28,6 +28,9 @@ pub enum ReliableDispatchError {
 
     #[error("Max retries exceeded")]
     MaxRetriesExceeded,
+
+    #[error("Retention limit reached: {0}")]
+    RetentionLimitReached(String),
 }
 
 impl From<StorageBackendError> for ReliableDispatchError {
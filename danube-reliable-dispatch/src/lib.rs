@@ -2,19 +2,28 @@ mod topic_storage;
 mod topic_storage_test;
 pub use storage_backend::create_message_storage;
 use topic_storage::TopicStore;
+pub use topic_storage::SEGMENTS_DROPPED_COUNTER;
 mod errors;
 pub use errors::ReliableDispatchError;
 use errors::Result;
 mod dispatch;
 mod dispatch_test;
-pub use dispatch::SubscriptionDispatch;
+pub use dispatch::{RedeliveryBackoff, SubscriptionDispatch, MESSAGES_EXPIRED_COUNTER};
 mod storage_backend;
 mod topic_cache;
-pub use topic_cache::TopicCache;
+pub use topic_cache::{
+    TopicCache, CACHE_BYTES_GAUGE, CACHE_EVICTIONS_COUNTER, CACHE_HITS_COUNTER,
+    CACHE_MISSES_COUNTER, CACHE_PINNED_BYTES_GAUGE,
+};
+mod cursor;
+pub use cursor::{AckedSegment, SubscriptionCursor};
+mod delayed_delivery;
 
 use danube_core::{dispatch_strategy::ReliableOptions, message::StreamMessage};
 use dashmap::DashMap;
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
 /// ReliableDispatch is Topic bounded message queue for reliable delivery
 #[derive(Debug)]
@@ -22,35 +31,60 @@ pub struct ReliableDispatch {
     // Topic store is used to store messages in a queue for reliable delivery
     pub(crate) topic_store: TopicStore,
     // Map of subscription name to last acknowledged segment id
-    pub(crate) subscriptions: Arc<DashMap<String, Arc<AtomicUsize>>>,
+    pub(crate) subscriptions: Arc<DashMap<String, Arc<AckedSegment>>>,
+    // Map of subscription name to the subscription cursor, the exact position of the subscription
+    cursors: Arc<DashMap<String, Arc<Mutex<SubscriptionCursor>>>>,
     // Channel to send shutdown signal to the lifecycle management task
     shutdown_tx: tokio::sync::mpsc::Sender<()>,
 }
 
 impl ReliableDispatch {
-    pub fn new(
+    /// Creates the reliable dispatch of the topic, recovering the segments already stored for the topic.
+    /// The `subscription_cursors` are the cursors of the topic subscriptions,
+    /// as persisted before the topic was moved or the broker restarted.
+    pub async fn new(
         topic_name: &str,
         reliable_options: ReliableOptions,
         topic_cache: TopicCache,
-    ) -> Self {
-        let subscriptions: Arc<DashMap<String, Arc<AtomicUsize>>> = Arc::new(DashMap::new());
+        subscription_cursors: HashMap<String, SubscriptionCursor>,
+    ) -> Result<Self> {
+        let subscriptions: Arc<DashMap<String, Arc<AckedSegment>>> = Arc::new(DashMap::new());
+        let cursors = Arc::new(DashMap::new());
+        for (subscription_name, cursor) in subscription_cursors {
+            subscriptions.insert(
+                subscription_name.clone(),
+                Arc::new(AckedSegment::new(cursor.last_acked_segment())),
+            );
+            cursors.insert(subscription_name, Arc::new(Mutex::new(cursor)));
+        }
+
         let (shutdown_tx, shutdown_rx) = tokio::sync::mpsc::channel(1);
         let subscriptions_cloned = Arc::clone(&subscriptions);
 
         let retention_policy = reliable_options.retention_policy.clone();
-        let topic_store = TopicStore::new(&topic_name, topic_cache, reliable_options);
+        let wal = topic_cache.open_wal(topic_name).await?;
+        let mut topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
+
+        // Rebuild the segments index and the open segment, if the topic was served before
+        if let Some(wal) = wal {
+            topic_store = topic_store.with_wal(wal);
+        }
+        topic_store.recover().await?;
+
         // Start the lifecycle management task
         topic_store.start_lifecycle_management_task(
             shutdown_rx,
             subscriptions_cloned,
+            Arc::clone(&cursors),
             retention_policy,
         );
 
-        Self {
+        Ok(Self {
             topic_store,
             subscriptions,
+            cursors,
             shutdown_tx,
-        }
+        })
     }
 
     pub async fn new_subscription_dispatch(
@@ -61,8 +95,17 @@ impl ReliableDispatch {
             .get_last_acknowledged_segment(subscription_name)
             .await?;
 
-        let subscription_dispatch =
-            SubscriptionDispatch::new(self.topic_store.clone(), sub_last_acked_segment);
+        let cursor = self
+            .cursors
+            .entry(subscription_name.to_string())
+            .or_default()
+            .clone();
+
+        // Continue the delivery right after the last acknowledged message of the subscription
+        let mut subscription_dispatch =
+            SubscriptionDispatch::new(self.topic_store.clone(), sub_last_acked_segment)
+                .with_cursor(cursor);
+        subscription_dispatch.resume().await?;
 
         //self.subscription_dispatch.insert(subscription_name.to_string(), subscription_name.to_string());
 
@@ -75,15 +118,32 @@ impl ReliableDispatch {
     }
 
     pub async fn add_subscription(&self, subscription_name: &str) -> Result<()> {
+        // keep the progress of the subscriptions restored on topic load,
+        // the new subscriptions have acknowledged nothing yet
         self.subscriptions
-            .insert(subscription_name.to_string(), Arc::new(AtomicUsize::new(0)));
+            .entry(subscription_name.to_string())
+            .or_default();
         Ok(())
     }
 
+    /// Removes the subscription, its cursor no longer holds the acknowledged segments
+    pub fn remove_subscription(&self, subscription_name: &str) {
+        self.subscriptions.remove(subscription_name);
+        self.cursors.remove(subscription_name);
+    }
+
+    /// Returns the cursor of each subscription, to be persisted by the broker
+    pub fn get_subscription_cursors(&self) -> Vec<(String, Arc<Mutex<SubscriptionCursor>>)> {
+        self.cursors
+            .iter()
+            .map(|entry| (entry.key().clone(), Arc::clone(entry.value())))
+            .collect()
+    }
+
     pub async fn get_last_acknowledged_segment(
         &self,
         subscription_name: &str,
-    ) -> Result<Arc<AtomicUsize>> {
+    ) -> Result<Arc<AckedSegment>> {
         match self.subscriptions.get(subscription_name) {
             Some(subscription) => Ok(Arc::clone(subscription.value())),
             None => Err(ReliableDispatchError::SubscriptionError(
@@ -3,31 +3,67 @@ use danube_core::storage::{Segment, StorageBackend, StorageBackendError, Storage
 use dashmap::DashMap;
 use std::sync::Arc;
 use tokio::sync::RwLock;
+use tracing::warn;
 
 use crate::topic_cache::TopicCache;
-use danube_persistent_storage::{DiskStorage, RemoteStorage};
+use danube_persistent_storage::{
+    DiskStorage, EncryptedStorage, FileKeyProvider, FilesystemObjectStore, KeyProvider,
+    RemoteStorage, TieredStorage,
+};
 
-pub async fn create_message_storage(storage_config: &StorageConfig) -> TopicCache {
-    match storage_config {
-        StorageConfig::InMemory { cache } => {
-            let storage = Arc::new(InMemoryStorage::new());
-            return TopicCache::new(storage, cache.max_capacity, cache.time_to_idle);
-        }
+pub async fn create_message_storage(
+    storage_config: &StorageConfig,
+) -> Result<TopicCache, StorageBackendError> {
+    let (storage, cache): (Arc<dyn StorageBackend>, _) = match storage_config {
+        StorageConfig::InMemory { cache } => (Arc::new(InMemoryStorage::new()), cache),
         StorageConfig::Local {
             local_config,
             cache,
-        } => {
-            let storage = Arc::new(DiskStorage::new(&local_config.path));
-            return TopicCache::new(storage, cache.max_capacity, cache.time_to_idle);
-        }
+            ..
+        } => (Arc::new(DiskStorage::new(&local_config.path)), cache),
         StorageConfig::Remote {
             remote_config,
             cache,
+            ..
+        } => (Arc::new(RemoteStorage::new(remote_config.clone())), cache),
+        StorageConfig::Tiered {
+            local_config,
+            cold_config,
+            cache,
+            ..
         } => {
-            let storage = Arc::new(RemoteStorage::new(remote_config.clone()));
-            return TopicCache::new(storage, cache.max_capacity, cache.time_to_idle);
+            let storage = Arc::new(TieredStorage::new(
+                DiskStorage::new(&local_config.path),
+                Arc::new(FilesystemObjectStore::new(&cold_config.path)),
+                cold_config.offload_after,
+            ));
+            storage.start_offload_task();
+            (storage, cache)
         }
     };
+
+    // the encryption is layered over the configured storage, and covers the write-ahead log
+    let keys: Option<Arc<dyn KeyProvider>> = match storage_config.encryption_config() {
+        Some(encryption_config) => Some(Arc::new(FileKeyProvider::load(encryption_config)?)),
+        None => None,
+    };
+    let storage: Arc<dyn StorageBackend> = match &keys {
+        Some(keys) => Arc::new(EncryptedStorage::new(storage, keys.clone())),
+        None => storage,
+    };
+
+    if cache.max_capacity.is_some() {
+        warn!(
+            "The cache max_capacity is deprecated and ignored, the cache is bounded by max_bytes"
+        );
+    }
+
+    Ok(
+        TopicCache::new(storage, cache.max_bytes, cache.time_to_idle)
+            .with_wal(storage_config.wal_config().cloned())
+            .with_wal_encryption(keys)
+            .with_compression(storage_config.compression()),
+    )
 }
 
 #[derive(Debug)]
@@ -77,4 +113,14 @@ impl StorageBackend for InMemoryStorage {
         }
         Ok(())
     }
+
+    async fn list_segments(&self, topic_name: &str) -> Result<Vec<usize>, StorageBackendError> {
+        let mut segment_ids: Vec<usize> = self
+            .segments
+            .get(topic_name)
+            .map(|topic_segments| topic_segments.iter().map(|entry| *entry.key()).collect())
+            .unwrap_or_default();
+        segment_ids.sort_unstable();
+        Ok(segment_ids)
+    }
 }
@@ -1,32 +1,127 @@
-use danube_core::storage::{Segment, StorageBackend};
-use moka::future::Cache as MokaCache;
-use std::sync::Arc;
+use danube_core::{
+    message::StreamMessage,
+    storage::{CompressionCodec, Segment, SegmentInfo, StorageBackend, WalConfig},
+};
+use danube_persistent_storage::{KeyProvider, WriteAheadLog};
+use dashmap::DashMap;
+use metrics::{counter, gauge};
+use moka::{future::Cache as MokaCache, notification::RemovalCause};
+use std::{ops::Range, sync::Arc};
 use tokio::{sync::RwLock, time::Duration};
+use tracing::warn;
 
 use crate::errors::{ReliableDispatchError, Result};
 
+// The cache metrics are registered by the broker
+pub const CACHE_HITS_COUNTER: &str = "danube_cache_hits_counter";
+pub const CACHE_MISSES_COUNTER: &str = "danube_cache_misses_counter";
+pub const CACHE_EVICTIONS_COUNTER: &str = "danube_cache_evictions_counter";
+pub const CACHE_BYTES_GAUGE: &str = "danube_cache_bytes";
+pub const CACHE_PINNED_BYTES_GAUGE: &str = "danube_cache_pinned_bytes";
+
 #[derive(Debug, Clone)]
 pub struct TopicCache {
-    // Primary fast memory cache
+    // Primary fast memory cache of the closed segments, weighted by the segment size
     memory_cache: MokaCache<String, Arc<RwLock<Segment>>>,
+    // The open segments of the topics, pinned in memory as they are still written to
+    open_segments: Arc<DashMap<String, Arc<RwLock<Segment>>>>,
     // Storage backend for segments
     storage: Arc<dyn StorageBackend>,
+    // Write-ahead log configuration, each topic opens its own log under the configured path
+    wal_config: Option<WalConfig>,
+    // The keys of the storage encryption, encrypting the write-ahead logs as well
+    wal_keys: Option<Arc<dyn KeyProvider>>,
+    // Compression of the closed segments, for the topics without their own codec
+    compression: CompressionCodec,
+    // Codec chosen by the topics in their reliable options
+    topic_compression: Arc<DashMap<String, CompressionCodec>>,
 }
 
 impl TopicCache {
-    pub fn new(storage: Arc<dyn StorageBackend>, max_capacity: u64, idle_time: u64) -> Self {
+    /// Creates the broker cache, `max_bytes` is the budget of the cached segments
+    /// and `idle_time` (in minutes) expires the segments not accessed anymore.
+    pub fn new(storage: Arc<dyn StorageBackend>, max_bytes: u64, idle_time: u64) -> Self {
         let memory_cache = MokaCache::builder()
-            // Max 100 segment entries
-            .max_capacity(max_capacity)
+            // The cached segments are bounded by their size, not by their number
+            .weigher(|_key, segment: &Arc<RwLock<Segment>>| segment_weight(segment))
+            .max_capacity(max_bytes)
             // Time to idle (TTI):  10 minutes
             // A cached entry will be expired after the specified duration past from get or insert.
             .time_to_idle(Duration::from_secs(idle_time * 60))
+            .eviction_listener(|_key, _segment, cause| {
+                if cause == RemovalCause::Size {
+                    counter!(CACHE_EVICTIONS_COUNTER).increment(1);
+                }
+            })
             // Create the cache.
             .build();
 
         Self {
             memory_cache,
+            open_segments: Arc::new(DashMap::new()),
             storage,
+            wal_config: None,
+            wal_keys: None,
+            compression: CompressionCodec::None,
+            topic_compression: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Sets the compression of the closed segments, configured for the broker storage
+    pub fn with_compression(mut self, compression: CompressionCodec) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Stores the closed segments of the topic with the codec, None for the broker default
+    pub(crate) fn set_topic_compression(
+        &self,
+        topic_name: &str,
+        compression: Option<CompressionCodec>,
+    ) {
+        match compression {
+            Some(compression) => {
+                self.topic_compression
+                    .insert(topic_name.to_string(), compression);
+            }
+            None => {
+                self.topic_compression.remove(topic_name);
+            }
+        }
+    }
+
+    fn topic_codec(&self, topic_name: &str) -> CompressionCodec {
+        self.topic_compression
+            .get(topic_name)
+            .map(|codec| *codec)
+            .unwrap_or(self.compression)
+    }
+
+    /// Enables the write-ahead log for the open segments of the reliable topics
+    pub fn with_wal(mut self, wal_config: Option<WalConfig>) -> Self {
+        self.wal_config = wal_config;
+        self
+    }
+
+    /// Encrypts the write-ahead logs with the keys of the storage encryption, if enabled
+    pub fn with_wal_encryption(mut self, keys: Option<Arc<dyn KeyProvider>>) -> Self {
+        self.wal_keys = keys;
+        self
+    }
+
+    /// Opens the topic write-ahead log, if enabled
+    pub(crate) async fn open_wal(&self, topic_name: &str) -> Result<Option<Arc<WriteAheadLog>>> {
+        match &self.wal_config {
+            Some(wal_config) => {
+                let mut wal = WriteAheadLog::open(wal_config, topic_name).await?;
+                if let Some(keys) = &self.wal_keys {
+                    wal = wal.with_encryption(keys.clone());
+                }
+                let wal = Arc::new(wal);
+                WriteAheadLog::start_sync_task(&wal);
+                Ok(Some(wal))
+            }
+            None => Ok(None),
         }
     }
 
@@ -38,21 +133,58 @@ impl TopicCache {
         let key = format!("{}:{}", topic_name, id);
 
         // Try memory cache first
-        if let Some(segment) = self.memory_cache.get(&key).await {
+        if let Some(segment) = self.cached_segment(&key).await {
+            counter!(CACHE_HITS_COUNTER).increment(1);
             return Ok(Some(segment));
         }
+        counter!(CACHE_MISSES_COUNTER).increment(1);
 
-        // Try storage backend
-        match self.storage.get_segment(topic_name, id).await {
-            Ok(segment) => {
-                // Update memory cache
-                if let Some(ref segment) = segment {
-                    self.memory_cache.insert(key, segment.clone()).await;
+        // Try storage backend, the concurrent loads of the segment (like a prefetch in progress)
+        // are waiting for the same load
+        let mut load_error = None;
+        let segment = self
+            .memory_cache
+            .optionally_get_with(key.clone(), async {
+                match self.storage.get_segment(topic_name, id).await {
+                    Ok(segment) => segment,
+                    Err(e) => {
+                        load_error = Some(e);
+                        None
+                    }
                 }
-                Ok(segment)
+            })
+            .await;
+
+        if let Some(e) = load_error {
+            return Err(ReliableDispatchError::StorageError(e.to_string()));
+        }
+        if let Some(ref segment) = segment {
+            // the open segments recovered from the storage are pinned
+            if segment.read().await.close_time == 0 {
+                self.cache_segment(key, segment.clone()).await;
             }
-            Err(e) => Err(ReliableDispatchError::StorageError(e.to_string())),
         }
+        self.report_size();
+        Ok(segment)
+    }
+
+    /// Loads the segment into the cache in the background, if it is not already cached
+    pub(crate) fn prefetch_segment(&self, topic_name: &str, id: usize) {
+        let key = format!("{}:{}", topic_name, id);
+        if self.open_segments.contains_key(&key) || self.memory_cache.contains_key(&key) {
+            return;
+        }
+
+        let topic_cache = self.clone();
+        let topic_name = topic_name.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = topic_cache.get_segment(&topic_name, id).await {
+                warn!(
+                    "Unable to prefetch the segment {} of topic {}: {}",
+                    id, topic_name, e
+                );
+            }
+        });
     }
 
     pub async fn put_segment(
@@ -64,7 +196,8 @@ impl TopicCache {
         let key = format!("{}:{}", topic_name, id);
 
         // Always update memory cache
-        self.memory_cache.insert(key, segment.clone()).await;
+        self.cache_segment(key, segment.clone()).await;
+        self.report_size();
 
         // Only store in backend if segment is closed
         let is_closed = {
@@ -74,7 +207,9 @@ impl TopicCache {
 
         if is_closed {
             // Update storage backend only for closed segments
-            self.storage.put_segment(topic_name, id, segment).await?;
+            self.storage
+                .put_segment_with_codec(topic_name, id, segment, self.topic_codec(topic_name))
+                .await?;
         }
 
         Ok(())
@@ -84,10 +219,121 @@ impl TopicCache {
         let key = format!("{}:{}", topic_name, id);
 
         // Remove from memory cache
+        self.open_segments.remove(&key);
         self.memory_cache.remove(&key).await;
+        self.report_size();
 
         // Remove from storage backend
         self.storage.remove_segment(topic_name, id).await?;
         Ok(())
     }
+
+    pub async fn list_segments(&self, topic_name: &str) -> Result<Vec<usize>> {
+        Ok(self.storage.list_segments(topic_name).await?)
+    }
+
+    /// Returns the segment metadata, without loading the segment from the storage backend
+    pub async fn segment_info(&self, topic_name: &str, id: usize) -> Result<Option<SegmentInfo>> {
+        let key = format!("{}:{}", topic_name, id);
+
+        if let Some(segment) = self.cached_segment(&key).await {
+            return Ok(Some(SegmentInfo::from(&*segment.read().await)));
+        }
+
+        Ok(self.storage.segment_info(topic_name, id).await?)
+    }
+
+    /// Reads the messages of the segment within the offsets range,
+    /// only the requested messages are fetched if the segment is not cached
+    pub async fn read_messages(
+        &self,
+        topic_name: &str,
+        id: usize,
+        offsets: Range<u64>,
+    ) -> Result<Option<Vec<StreamMessage>>> {
+        let key = format!("{}:{}", topic_name, id);
+
+        if let Some(segment) = self.cached_segment(&key).await {
+            let segment = segment.read().await;
+            return Ok(Some(
+                segment
+                    .messages
+                    .iter()
+                    .filter(|message| offsets.contains(&message.msg_id.segment_offset))
+                    .cloned()
+                    .collect(),
+            ));
+        }
+
+        Ok(self.storage.read_messages(topic_name, id, offsets).await?)
+    }
+
+    /// Releases the open segments of the unloaded topic, these are evicted as the closed segments
+    pub(crate) async fn unpin_topic(&self, topic_name: &str) {
+        let prefix = format!("{}:", topic_name);
+        let keys: Vec<String> = self
+            .open_segments
+            .iter()
+            .filter(|entry| entry.key().starts_with(&prefix))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        for key in keys {
+            if let Some((key, segment)) = self.open_segments.remove(&key) {
+                self.memory_cache.insert(key, segment).await;
+            }
+        }
+        self.report_size();
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn is_cached(&self, topic_name: &str, id: usize) -> bool {
+        self.cached_segment(&format!("{}:{}", topic_name, id))
+            .await
+            .is_some()
+    }
+
+    #[cfg(test)]
+    pub(crate) async fn cached_bytes(&self) -> u64 {
+        self.memory_cache.run_pending_tasks().await;
+        self.memory_cache.weighted_size()
+    }
+
+    async fn cached_segment(&self, key: &str) -> Option<Arc<RwLock<Segment>>> {
+        if let Some(segment) = self.open_segments.get(key) {
+            return Some(segment.clone());
+        }
+        self.memory_cache.get(key).await
+    }
+
+    // The open segments are pinned, once closed they are weighted and may be evicted
+    async fn cache_segment(&self, key: String, segment: Arc<RwLock<Segment>>) {
+        let is_open = segment.read().await.close_time == 0;
+        if is_open {
+            self.memory_cache.remove(&key).await;
+            self.open_segments.insert(key, segment);
+        } else {
+            self.open_segments.remove(&key);
+            self.memory_cache.insert(key, segment).await;
+        }
+    }
+
+    fn report_size(&self) {
+        let pinned_bytes: usize = self
+            .open_segments
+            .iter()
+            .filter_map(|entry| entry.value().try_read().ok().map(|s| s.current_size))
+            .sum();
+        gauge!(CACHE_BYTES_GAUGE).set(self.memory_cache.weighted_size() as f64);
+        gauge!(CACHE_PINNED_BYTES_GAUGE).set(pinned_bytes as f64);
+    }
+}
+
+// The weight of a cached segment is its size in bytes, at the time it is cached
+fn segment_weight(segment: &Arc<RwLock<Segment>>) -> u32 {
+    match segment.try_read() {
+        Ok(segment) => segment.current_size.clamp(1, u32::MAX as usize) as u32,
+        // locked by a writer, the closed segments are not written to anymore
+        Err(_) => 1,
+    }
 }
@@ -1,23 +1,36 @@
 use danube_core::{
-    dispatch_strategy::{ReliableOptions, RetentionPolicy},
+    dispatch_strategy::{ReliableOptions, RetentionLimitPolicy, RetentionPolicy},
     message::StreamMessage,
     storage::Segment,
 };
+use danube_persistent_storage::{WalRecord, WriteAheadLog};
 use dashmap::DashMap;
-use std::sync::{atomic::AtomicUsize, Arc};
+use metrics::counter;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
 use tokio::sync::{Mutex, RwLock};
-use tracing::trace;
+use tracing::{info, trace, warn};
 
 use crate::{
+    cursor::{AckedSegment, SubscriptionCursor},
     errors::{ReliableDispatchError, Result},
     topic_cache::TopicCache,
 };
 
+// The number of segments loaded ahead of a subscription nearing the end of its segment
+const READ_AHEAD_SEGMENTS: usize = 2;
+
+/// Counter of the segments dropped from the topics that reached their retention limits.
+pub const SEGMENTS_DROPPED_COUNTER: &str = "danube_topic_segments_dropped_counter";
+
 // TopicStore is used only for reliable messaging
 // It stores the segments in memory until are acknowledged by every subscription
 #[derive(Debug, Clone)]
 pub(crate) struct TopicStore {
-    topic_name: String,
+    pub(crate) topic_name: String,
     // Storage backend for segments
     pub(crate) storage: TopicCache,
     // Index of segments store (segment_id, close_time) pairs
@@ -26,10 +39,24 @@ pub(crate) struct TopicStore {
     pub(crate) segment_size: usize,
     // Time to live for segments in seconds
     pub(crate) retention_period: u64,
+    // Maximum size of the topic segments in bytes, 0 for no limit
+    pub(crate) retention_size: u64,
+    // Maximum number of segments of the topic, 0 for no limit
+    pub(crate) max_segments: u64,
+    // What happens when the topic reaches the retention size or the max segments
+    pub(crate) retention_limit_policy: RetentionLimitPolicy,
+    // Set by the lifecycle task while the topic is over its limits, with the Backpressure policy
+    limit_reached: Arc<AtomicBool>,
     // ID of the current writable segment
     pub(crate) current_segment_id: Arc<RwLock<usize>>,
     // Cached segment, used to avoid expensive call to storage while storing a message
     cached_segment: Arc<Mutex<Option<Arc<RwLock<Segment>>>>>,
+    // Maximum age in seconds of the open segment before it is rolled, 0 for no limit
+    pub(crate) segment_max_age: u64,
+    // Time in seconds of the first message of the open segment, 0 while the segment is empty
+    pub(crate) segment_opened_at: Arc<AtomicU64>,
+    // Write-ahead log of the open segment, the messages are logged before being acknowledged
+    wal: Option<Arc<WriteAheadLog>>,
 }
 
 impl TopicStore {
@@ -40,41 +67,72 @@ impl TopicStore {
     ) -> Self {
         // Convert segment size from MB to Bytes
         let segment_size_bytes = reliable_options.segment_size * 1024 * 1024;
+        storage.set_topic_compression(topic_name, reliable_options.compression);
         Self {
             topic_name: topic_name.to_string(),
             storage,
             segments_index: Arc::new(RwLock::new(Vec::new())),
             segment_size: segment_size_bytes,
             retention_period: reliable_options.retention_period,
+            retention_size: reliable_options.retention_size,
+            max_segments: reliable_options.max_segments,
+            retention_limit_policy: reliable_options.retention_limit_policy,
+            limit_reached: Arc::new(AtomicBool::new(false)),
             current_segment_id: Arc::new(RwLock::new(0)),
             cached_segment: Arc::new(Mutex::new(None)),
+            segment_max_age: reliable_options.segment_max_age,
+            segment_opened_at: Arc::new(AtomicU64::new(0)),
+            wal: None,
         }
     }
 
+    pub(crate) fn with_wal(mut self, wal: Arc<WriteAheadLog>) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
     pub(crate) async fn store_message(&self, message: StreamMessage) -> Result<()> {
-        let segment_id = *self.current_segment_id.write().await;
-        let segment = self.get_or_create_segment(segment_id).await?;
+        if self.limit_reached.load(Ordering::Acquire) {
+            return Err(ReliableDispatchError::RetentionLimitReached(format!(
+                "the topic {} is over its retention size or max segments",
+                self.topic_name
+            )));
+        }
 
-        let close_time = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
+        let (segment_id, close_time, should_create_new_segment) = loop {
+            let segment_id = *self.current_segment_id.write().await;
+            let segment = self.get_or_create_segment(segment_id).await?;
 
-        // check if segment is full, if so mark it as closed and create a new segment
-        let should_create_new_segment = {
+            let close_time = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+
+            // check if segment is full, if so mark it as closed and create a new segment
             let mut writable_segment = segment.write().await;
+
+            // the segment rolled by the lifecycle task is replaced by the next open segment
+            if writable_segment.close_time > 0 && !writable_segment.is_full(self.segment_size) {
+                continue;
+            }
+
+            let segment_id = writable_segment.id;
             if writable_segment.is_full(self.segment_size) {
                 writable_segment.close_time = close_time;
-                true
-            } else {
-                // set the correct segment id and offset for the message
-                // the producer sets both to 0 as this is assigned by the broker once stored
-                let mut message = message.clone();
-                message.msg_id.segment_id = segment_id as u64;
-                message.msg_id.segment_offset = writable_segment.next_offset;
-                writable_segment.add_message(message);
-                false
+                break (segment_id, close_time, true);
             }
+
+            // set the correct segment id and offset for the message
+            // the producer sets both to 0 as this is assigned by the broker once stored
+            let mut message = message.clone();
+            message.msg_id.segment_id = segment_id as u64;
+            message.msg_id.segment_offset = writable_segment.next_offset;
+            self.append_to_wal(&message).await?;
+            if writable_segment.messages.is_empty() {
+                self.segment_opened_at.store(close_time, Ordering::Release);
+            }
+            writable_segment.add_message(message);
+            break (segment_id, close_time, false);
         };
 
         if should_create_new_segment {
@@ -88,6 +146,12 @@ impl TopicStore {
     // Checks if the segment is present in the cache, if not it fetches it from the storage
     // if no segment is found in the storage, it creates a new segment
     async fn get_or_create_segment(&self, segment_id: usize) -> Result<Arc<RwLock<Segment>>> {
+        if let Some(seg) = &*self.cached_segment.lock().await {
+            return Ok(seg.clone());
+        }
+
+        // the segments index is locked before the cached segment, as the readers of the topic do
+        let mut index = self.segments_index.write().await;
         let mut cached = self.cached_segment.lock().await;
         match &*cached {
             Some(seg) => Ok(seg.clone()),
@@ -106,7 +170,6 @@ impl TopicStore {
                             .put_segment(&self.topic_name, segment_id, new_segment.clone())
                             .await
                             .map_err(|e| ReliableDispatchError::StorageError(e.to_string()))?;
-                        let mut index = self.segments_index.write().await;
                         index.push((segment_id, 0));
                         new_segment
                     }
@@ -126,41 +189,288 @@ impl TopicStore {
         mut message: StreamMessage,
     ) -> Result<()> {
         // First write the current full segment to storage
-        if let Some(cached) = &*self.cached_segment.lock().await {
+        let full_segment = self.cached_segment.lock().await.clone();
+        if let Some(full_segment) = full_segment {
             self.storage
-                .put_segment(&self.topic_name, segment_id, cached.clone())
+                .put_segment(&self.topic_name, segment_id, full_segment)
                 .await
                 .map_err(|e| ReliableDispatchError::StorageError(e.to_string()))?;
         }
 
-        // Update segment index with close time
-        let mut index = self.segments_index.write().await;
-        if let Some(entry) = index.iter_mut().find(|(id, _)| *id == segment_id) {
-            entry.1 = close_time;
-        }
-
-        // Create new segment only in cache
+        // Update segment index with close time and the new segment
         let new_segment_id = segment_id + 1;
-        let new_segment = Arc::new(RwLock::new(Segment::new(new_segment_id, self.segment_size)));
+        let closed_segments = {
+            let mut index = self.segments_index.write().await;
+            if let Some(entry) = index.iter_mut().find(|(id, _)| *id == segment_id) {
+                entry.1 = close_time;
+            }
+            let closed_segments = index.clone();
+            index.push((new_segment_id, 0));
+            closed_segments
+        };
 
-        // Update index for new segment
-        index.push((new_segment_id, 0));
+        // Create new segment only in cache, locked until its initial message is added,
+        // so the messages stored meanwhile are logged after the checkpoint
+        let new_segment = Arc::new(RwLock::new(Segment::new(new_segment_id, self.segment_size)));
+        let mut new_writable_segment = new_segment.write().await;
 
         // Update cache and current segment id
         *self.cached_segment.lock().await = Some(new_segment.clone());
         *self.current_segment_id.write().await = new_segment_id;
 
+        // The closed segment is now owned by the storage backend, drop its messages from the log
+        if let Some(wal) = &self.wal {
+            wal.checkpoint(closed_segments).await?;
+        }
+
         // Add initial message to new segment
-        let mut new_writable_segment = new_segment.write().await;
         // set the correct segment id and offset for the message
         // the producer sets both to 0 as this is assigned by the broker once stored
         message.msg_id.segment_id = new_segment_id as u64;
         message.msg_id.segment_offset = new_writable_segment.next_offset;
+        self.append_to_wal(&message).await?;
+        self.segment_opened_at.store(close_time, Ordering::Release);
         new_writable_segment.add_message(message);
 
         Ok(())
     }
 
+    // Closes the open segment once it is older than segment_max_age, even if it is not full,
+    // so it is persisted and subject to the retention of the closed segments
+    pub(crate) async fn roll_aged_segment(&self) -> Result<()> {
+        let opened_at = self.segment_opened_at.load(Ordering::Acquire);
+        let close_time = now_secs();
+        if self.segment_max_age == 0
+            || opened_at == 0
+            || close_time.saturating_sub(opened_at) < self.segment_max_age
+        {
+            return Ok(());
+        }
+
+        let segment = match &*self.cached_segment.lock().await {
+            Some(segment) => segment.clone(),
+            None => return Ok(()),
+        };
+
+        // the messages stored meanwhile wait for the next open segment
+        let mut segment_data = segment.write().await;
+        if segment_data.close_time > 0 || segment_data.messages.is_empty() {
+            return Ok(());
+        }
+        segment_data.close_time = close_time;
+        let segment_id = segment_data.id;
+
+        let new_segment_id = segment_id + 1;
+        let closed_segments = {
+            let mut index = self.segments_index.write().await;
+            if let Some(entry) = index.iter_mut().find(|(id, _)| *id == segment_id) {
+                entry.1 = close_time;
+            }
+            let closed_segments = index.clone();
+            index.push((new_segment_id, 0));
+            closed_segments
+        };
+
+        // the new segment is locked until the log is checkpointed,
+        // so the messages stored meanwhile are logged after the checkpoint
+        let new_segment = Arc::new(RwLock::new(Segment::new(new_segment_id, self.segment_size)));
+        let new_writable_segment = new_segment.write().await;
+        *self.cached_segment.lock().await = Some(new_segment.clone());
+        *self.current_segment_id.write().await = new_segment_id;
+        self.segment_opened_at.store(0, Ordering::Release);
+        drop(segment_data);
+
+        self.storage
+            .put_segment(&self.topic_name, segment_id, segment)
+            .await?;
+
+        if let Some(wal) = &self.wal {
+            wal.checkpoint(closed_segments).await?;
+        }
+        drop(new_writable_segment);
+
+        trace!(
+            "Rolled the segment {} of topic {}, open for more than {}s",
+            segment_id,
+            self.topic_name,
+            self.segment_max_age
+        );
+
+        Ok(())
+    }
+
+    async fn append_to_wal(&self, message: &StreamMessage) -> Result<()> {
+        if let Some(wal) = &self.wal {
+            wal.append(message).await?;
+        }
+        Ok(())
+    }
+
+    // Rebuilds the segments index, the next segment id and the open segment of the topic,
+    // from the segments found in the storage backend and from the write-ahead log.
+    // It is called once, when the topic is loaded by the broker
+    pub(crate) async fn recover(&self) -> Result<()> {
+        let (mut index, stored) = self.load_stored_segments().await?;
+
+        let open_segment = match &self.wal {
+            Some(wal) => self.replay_wal(wal, &stored, &mut index).await?,
+            None => None,
+        };
+
+        index.sort_by_key(|(id, _)| *id);
+        index.dedup_by_key(|(id, _)| *id);
+
+        if let Some(wal) = &self.wal {
+            // Compact the log, so the next replay starts from the recovered state
+            wal.checkpoint(index.clone()).await?;
+            if let Some(segment) = &open_segment {
+                for message in &segment.messages {
+                    wal.append(message).await?;
+                }
+            }
+        }
+
+        if let Some(segment) = open_segment {
+            let segment_id = segment.id;
+
+            info!(
+                "Recovered the open segment {} with {} messages for topic {}",
+                segment_id,
+                segment.messages.len(),
+                self.topic_name
+            );
+
+            let segment = Arc::new(RwLock::new(segment));
+            self.storage
+                .put_segment(&self.topic_name, segment_id, segment.clone())
+                .await?;
+            index.push((segment_id, 0));
+            *self.cached_segment.lock().await = Some(segment);
+            *self.current_segment_id.write().await = segment_id;
+            // the recovered segment ages from the broker restart
+            self.segment_opened_at.store(now_secs(), Ordering::Release);
+        } else if let Some((last_segment_id, _)) = index.last() {
+            *self.current_segment_id.write().await = last_segment_id + 1;
+        }
+
+        if !index.is_empty() {
+            info!(
+                "Recovered {} segments for topic {}, the writable segment is {}",
+                index.len(),
+                self.topic_name,
+                *self.current_segment_id.read().await
+            );
+        }
+
+        *self.segments_index.write().await = index;
+
+        Ok(())
+    }
+
+    // Lists the closed segments persisted by the storage backend, as (segment_id, close_time) pairs,
+    // along with the ids of all the stored segments.
+    // Only the segments metadata is read, the segments are loaded once requested by the subscriptions
+    async fn load_stored_segments(&self) -> Result<(Vec<(usize, u64)>, HashSet<usize>)> {
+        let mut index = Vec::new();
+        let stored: HashSet<usize> = self
+            .storage
+            .list_segments(&self.topic_name)
+            .await?
+            .into_iter()
+            .collect();
+
+        for &segment_id in &stored {
+            if let Some(info) = self
+                .storage
+                .segment_info(&self.topic_name, segment_id)
+                .await?
+            {
+                if info.close_time > 0 {
+                    index.push((segment_id, info.close_time));
+                }
+            }
+        }
+
+        Ok((index, stored))
+    }
+
+    // Replays the write-ahead log on top of the stored segments, returns the open segment if any
+    async fn replay_wal(
+        &self,
+        wal: &WriteAheadLog,
+        stored: &HashSet<usize>,
+        index: &mut Vec<(usize, u64)>,
+    ) -> Result<Option<Segment>> {
+        let mut open_segment: Option<Segment> = None;
+
+        for record in wal.replay().await? {
+            match record {
+                WalRecord::Checkpoint(closed_segments) => {
+                    for (segment_id, close_time) in closed_segments {
+                        if index.iter().any(|(id, _)| *id == segment_id) {
+                            continue;
+                        }
+                        // skip the segments removed by the lifecycle task since the checkpoint
+                        if close_time > 0 && stored.contains(&segment_id) {
+                            index.push((segment_id, close_time));
+                        }
+                    }
+                }
+                WalRecord::Message(message) => {
+                    let segment_id = message.msg_id.segment_id as usize;
+                    if index.iter().any(|(id, _)| *id == segment_id) {
+                        continue;
+                    }
+
+                    match open_segment.as_mut() {
+                        Some(segment) if segment.id == segment_id => segment.add_message(*message),
+                        _ => {
+                            // the previous segment was filled up before the crash, persist it as closed
+                            if let Some(segment) = open_segment.take() {
+                                index.push(self.close_recovered_segment(segment).await?);
+                            }
+
+                            // the segment may have been persisted before the log was truncated
+                            if stored.contains(&segment_id) {
+                                if let Some(info) = self
+                                    .storage
+                                    .segment_info(&self.topic_name, segment_id)
+                                    .await?
+                                {
+                                    if info.close_time > 0 {
+                                        index.push((segment_id, info.close_time));
+                                        continue;
+                                    }
+                                }
+                            }
+
+                            let mut segment = Segment::new(segment_id, self.segment_size);
+                            segment.add_message(*message);
+                            open_segment = Some(segment);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(open_segment)
+    }
+
+    async fn close_recovered_segment(&self, mut segment: Segment) -> Result<(usize, u64)> {
+        let close_time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        segment.close_time = close_time;
+        let segment_id = segment.id;
+
+        self.storage
+            .put_segment(&self.topic_name, segment_id, Arc::new(RwLock::new(segment)))
+            .await?;
+
+        Ok((segment_id, close_time))
+    }
+
     // Get the next segment in the list based on the given segment ID
     // If the current_segment is None, it will return the first segment in the list
     // If the current_segment is the last segment in the list, it will return None
@@ -191,24 +501,55 @@ impl TopicStore {
                     .map_err(|e| ReliableDispatchError::StorageError(e.to_string()))?);
             }
             Some(segment_id) => {
-                if let Some(pos) = index.iter().position(|(id, _)| *id == segment_id) {
-                    if pos + 1 < index.len() {
-                        let next_segment_id = index[pos + 1].0;
-                        if next_segment_id == current_cached_id {
-                            return Ok(cached.clone());
-                        }
-                        return Ok(self
-                            .storage
-                            .get_segment(&self.topic_name, next_segment_id)
-                            .await
-                            .map_err(|e| ReliableDispatchError::StorageError(e.to_string()))?);
+                // The index is ordered by segment id, the requested segment may be already removed
+                // by the lifecycle task or it may be the last acked segment of a recovered subscription
+                if let Some((next_segment_id, _)) = index.iter().find(|(id, _)| *id > segment_id) {
+                    if *next_segment_id == current_cached_id {
+                        return Ok(cached.clone());
                     }
+                    return Ok(self
+                        .storage
+                        .get_segment(&self.topic_name, *next_segment_id)
+                        .await
+                        .map_err(|e| ReliableDispatchError::StorageError(e.to_string()))?);
                 }
                 Ok(None)
             }
         }
     }
 
+    // Loads the closed segments following the segment into the cache, in the background,
+    // so the subscriptions catching up do not wait for the storage at the segment boundaries
+    pub(crate) async fn prefetch_segments_after(&self, segment_id: usize) {
+        let current_segment_id = *self.current_segment_id.read().await;
+        let index = self.segments_index.read().await;
+        index
+            .iter()
+            .filter(|(id, _)| *id > segment_id && *id != current_segment_id)
+            .take(READ_AHEAD_SEGMENTS)
+            .for_each(|(id, _)| self.storage.prefetch_segment(&self.topic_name, *id));
+    }
+
+    // Returns the segment if it is still part of the topic
+    pub(crate) async fn get_segment(
+        &self,
+        segment_id: usize,
+    ) -> Result<Option<Arc<RwLock<Segment>>>> {
+        if !self.contains_segment(segment_id).await? {
+            return Ok(None);
+        }
+
+        let cached = self.cached_segment.lock().await;
+        if *self.current_segment_id.read().await == segment_id && cached.is_some() {
+            return Ok(cached.clone());
+        }
+
+        self.storage
+            .get_segment(&self.topic_name, segment_id)
+            .await
+            .map_err(|e| ReliableDispatchError::StorageError(e.to_string()))
+    }
+
     pub(crate) async fn contains_segment(&self, segment_id: usize) -> Result<bool> {
         let index = self.segments_index.read().await;
         Ok(index.iter().any(|(id, _)| *id == segment_id))
@@ -217,23 +558,33 @@ impl TopicStore {
     // Start the TopicStore lifecycle management task that have the following responsibilities:
     // - Clean up acknowledged segments
     // - Remove closed segments that are older than the TTL
+    // - Compact the closed segments, keeping the latest message of each key
+    // - Enforce the retention size and the max segments of the topic
     pub(crate) fn start_lifecycle_management_task(
         &self,
         mut shutdown_rx: tokio::sync::mpsc::Receiver<()>,
-        subscriptions: Arc<DashMap<String, Arc<AtomicUsize>>>,
+        subscriptions: Arc<DashMap<String, Arc<AckedSegment>>>,
+        cursors: Arc<DashMap<String, Arc<Mutex<SubscriptionCursor>>>>,
         retention_policy: RetentionPolicy,
     ) {
         let topic_name = self.topic_name.clone();
         let storage = self.storage.clone();
         let segments_index = self.segments_index.clone();
         let retention_period = self.retention_period;
+        let topic_store = self.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(10));
+            // sizes of the closed segments, these are read from the storage only once
+            let mut segment_sizes = HashMap::new();
+            let mut compaction = CompactionState::default();
 
             loop {
                 tokio::select! {
                     _ = interval.tick() => {
+                        if let Err(e) = topic_store.roll_aged_segment().await {
+                            warn!("Unable to roll the open segment of topic {}: {}", topic_name, e);
+                        }
                         match retention_policy {
                             RetentionPolicy::RetainUntilAck => {
                                 Self::cleanup_acknowledged_segments(&topic_name ,&storage, &segments_index, &subscriptions).await;
@@ -241,9 +592,20 @@ impl TopicStore {
                             RetentionPolicy::RetainUntilExpire => {
                                 Self::cleanup_expired_segments(&topic_name, &storage, &segments_index, retention_period).await;
                             }
+                            RetentionPolicy::Compact => {
+                                // the compacted segments shrink, their sizes are read again
+                                for segment_id in Self::compact_segments(&topic_name, &storage, &segments_index, retention_period, &mut compaction).await {
+                                    segment_sizes.remove(&segment_id);
+                                }
+                            }
                         }
+                        topic_store.enforce_retention_limits(&subscriptions, &cursors, &mut segment_sizes).await;
+                    }
+                    _ = shutdown_rx.recv() => {
+                        // the topic is unloaded, its open segment is not written to anymore
+                        storage.unpin_topic(&topic_name).await;
+                        break
                     }
-                    _ = shutdown_rx.recv() => break
                 }
             }
         });
@@ -253,13 +615,17 @@ impl TopicStore {
         topic_name: &str,
         storage: &TopicCache,
         segments_index: &Arc<RwLock<Vec<(usize, u64)>>>,
-        subscriptions: &Arc<DashMap<String, Arc<AtomicUsize>>>,
+        subscriptions: &Arc<DashMap<String, Arc<AckedSegment>>>,
     ) {
-        let min_acknowledged_id = subscriptions
+        // nothing is removed while a subscription has acknowledged nothing
+        let Some(min_acknowledged_id) = subscriptions
             .iter()
-            .map(|entry| entry.value().load(std::sync::atomic::Ordering::Acquire))
+            .map(|entry| entry.value().get())
             .min()
-            .unwrap_or(0);
+            .flatten()
+        else {
+            return;
+        };
 
         let mut index = segments_index.write().await;
         let segments_to_remove: Vec<usize> = index
@@ -314,4 +680,314 @@ impl TopicStore {
 
         index.retain(|(id, _)| !expired_segments.contains(id));
     }
+
+    // Enforces the retention size and the max segments of the topic, the open segment is always retained.
+    // With DropOldest the oldest closed segments are removed and the subscriptions still on them
+    // continue with the first retained segment, with Backpressure the messages are rejected
+    // until the other retention policies bring the topic back under its limits.
+    pub(crate) async fn enforce_retention_limits(
+        &self,
+        subscriptions: &DashMap<String, Arc<AckedSegment>>,
+        cursors: &DashMap<String, Arc<Mutex<SubscriptionCursor>>>,
+        segment_sizes: &mut HashMap<usize, u64>,
+    ) {
+        if self.retention_size == 0 && self.max_segments == 0 {
+            return;
+        }
+
+        let open_segment = self.cached_segment.lock().await.clone();
+        let open_segment_size = match open_segment {
+            Some(segment) => segment.read().await.current_size as u64,
+            None => 0,
+        };
+
+        // the sizes are read from the segments metadata, without holding the segments index lock
+        let index = self.segments_index.read().await.clone();
+        let closed_segments: Vec<usize> = index
+            .iter()
+            .filter(|(_, close_time)| *close_time > 0)
+            .map(|(id, _)| *id)
+            .collect();
+
+        segment_sizes.retain(|segment_id, _| closed_segments.contains(segment_id));
+        for segment_id in &closed_segments {
+            if segment_sizes.contains_key(segment_id) {
+                continue;
+            }
+            match self
+                .storage
+                .segment_info(&self.topic_name, *segment_id)
+                .await
+            {
+                Ok(Some(info)) => {
+                    segment_sizes.insert(*segment_id, info.current_size as u64);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    trace!("Failed to read the size of segment {}: {:?}", segment_id, e);
+                    return;
+                }
+            }
+        }
+
+        let mut segments_count = index.len() as u64;
+        let mut topic_size = open_segment_size + segment_sizes.values().sum::<u64>();
+        let over_limits = |segments_count: u64, topic_size: u64| {
+            (self.max_segments > 0 && segments_count > self.max_segments)
+                || (self.retention_size > 0 && topic_size > self.retention_size)
+        };
+
+        if self.retention_limit_policy == RetentionLimitPolicy::Backpressure {
+            let limit_reached = over_limits(segments_count, topic_size);
+            if self.limit_reached.swap(limit_reached, Ordering::AcqRel) != limit_reached {
+                info!(
+                    "Topic {} with {} segments and {} bytes, the producers are {}",
+                    self.topic_name,
+                    segments_count,
+                    topic_size,
+                    if limit_reached {
+                        "rejected"
+                    } else {
+                        "accepted again"
+                    }
+                );
+            }
+            return;
+        }
+
+        let mut dropped_segments = Vec::new();
+        for segment_id in closed_segments {
+            if !over_limits(segments_count, topic_size) {
+                break;
+            }
+            segments_count -= 1;
+            topic_size -= segment_sizes.remove(&segment_id).unwrap_or(0);
+            dropped_segments.push(segment_id);
+        }
+
+        let Some(&last_dropped_id) = dropped_segments.iter().max() else {
+            return;
+        };
+
+        let first_retained_id = {
+            let mut index = self.segments_index.write().await;
+            index.retain(|(id, _)| !dropped_segments.contains(id));
+            index
+                .first()
+                .map(|(id, _)| *id)
+                .unwrap_or(last_dropped_id + 1)
+        };
+
+        for segment_id in &dropped_segments {
+            if let Err(e) = self
+                .storage
+                .remove_segment(&self.topic_name, *segment_id)
+                .await
+            {
+                trace!("Failed to remove segment {}: {:?}", segment_id, e);
+            }
+        }
+
+        // the lagging subscriptions skip the dropped segments
+        for subscription in subscriptions.iter() {
+            subscription.value().advance(last_dropped_id);
+        }
+        let cursors: Vec<_> = cursors.iter().map(|entry| entry.value().clone()).collect();
+        for cursor in cursors {
+            let mut cursor = cursor.lock().await;
+            if cursor.segment_id <= last_dropped_id {
+                cursor.move_to(first_retained_id);
+            }
+            // the delayed messages of the dropped segments are lost
+            cursor
+                .delayed
+                .retain_segments(|segment_id| !dropped_segments.contains(&segment_id));
+        }
+
+        counter!(SEGMENTS_DROPPED_COUNTER, "topic" => self.topic_name.clone())
+            .increment(dropped_segments.len() as u64);
+        info!(
+            "Dropped {} segments of topic {}, the retention limits are reached",
+            dropped_segments.len(),
+            self.topic_name
+        );
+    }
+
+    // Keeps only the latest message of each key in the closed segments.
+    // The messages without a key are always retained, the tombstones (keyed messages with empty payload)
+    // delete the previous messages of the key and are removed once older than the retention period.
+    // The retained messages keep their offsets, the segments left empty are dropped.
+    // Each pass reads only the segments closed since the previous pass, and rewrites the segments
+    // holding superseded messages or expired tombstones. The segments index is not locked meanwhile.
+    // Returns the segments rewritten or dropped.
+    pub(crate) async fn compact_segments(
+        topic_name: &str,
+        storage: &TopicCache,
+        segments_index: &Arc<RwLock<Vec<(usize, u64)>>>,
+        retention_period: u64,
+        state: &mut CompactionState,
+    ) -> Vec<usize> {
+        let current_time = now_secs();
+
+        let closed_segments: Vec<(usize, u64)> = segments_index
+            .read()
+            .await
+            .iter()
+            .filter(|(_, close_time)| *close_time > 0)
+            .copied()
+            .collect();
+
+        // the segments closed since the previous pass supersede the messages of their keys
+        let last_scanned = state.last_scanned;
+        for (segment_id, close_time) in closed_segments
+            .iter()
+            .filter(|(id, _)| last_scanned.is_none_or(|last| *id > last))
+        {
+            let segment = match storage.get_segment(topic_name, *segment_id).await {
+                Ok(Some(segment)) => segment,
+                Ok(None) => {
+                    state.last_scanned = Some(*segment_id);
+                    continue;
+                }
+                Err(e) => {
+                    trace!(
+                        "Failed to read segment {} for compaction: {:?}",
+                        segment_id,
+                        e
+                    );
+                    break;
+                }
+            };
+
+            for message in segment.read().await.messages.iter() {
+                if let Some(key) = &message.key {
+                    let position = (*segment_id, message.msg_id.segment_offset);
+                    if let Some((superseded, _)) = state.latest.insert(key.clone(), position) {
+                        state.dirty.insert(superseded);
+                    }
+                    if message.is_tombstone() {
+                        state.tombstones.insert(*segment_id, *close_time);
+                    }
+                }
+            }
+            state.last_scanned = Some(*segment_id);
+        }
+
+        // the segments holding expired tombstones
+        state.tombstones.retain(|segment_id, close_time| {
+            let expired = current_time.saturating_sub(*close_time) >= retention_period;
+            if expired {
+                state.dirty.insert(*segment_id);
+            }
+            !expired
+        });
+
+        let mut compacted_segments = Vec::new();
+        let mut emptied_segments = Vec::new();
+        for segment_id in std::mem::take(&mut state.dirty) {
+            // the segment was dropped meanwhile
+            let Some((_, close_time)) = closed_segments.iter().find(|(id, _)| *id == segment_id)
+            else {
+                continue;
+            };
+            let tombstones_expired = current_time.saturating_sub(*close_time) >= retention_period;
+
+            let segment = match storage.get_segment(topic_name, segment_id).await {
+                Ok(Some(segment)) => segment,
+                Ok(None) => continue,
+                Err(e) => {
+                    trace!(
+                        "Failed to read segment {} for compaction: {:?}",
+                        segment_id,
+                        e
+                    );
+                    state.dirty.insert(segment_id);
+                    continue;
+                }
+            };
+
+            let compacted = {
+                let segment_data = segment.read().await;
+                let retained: Vec<StreamMessage> = segment_data
+                    .messages
+                    .iter()
+                    .filter(|message| match &message.key {
+                        None => true,
+                        Some(key) => {
+                            state.latest.get(key)
+                                == Some(&(segment_id, message.msg_id.segment_offset))
+                                && !(message.is_tombstone() && tombstones_expired)
+                        }
+                    })
+                    .cloned()
+                    .collect();
+
+                if retained.len() == segment_data.messages.len() {
+                    continue;
+                }
+
+                let mut compacted = segment_data.clone();
+                compacted.current_size = retained.iter().map(|message| message.size()).sum();
+                compacted.messages = retained;
+                compacted
+            };
+
+            // the emptied segments are removed once dropped from the index
+            if compacted.messages.is_empty() {
+                emptied_segments.push(segment_id);
+                continue;
+            }
+
+            match storage
+                .put_segment(topic_name, segment_id, Arc::new(RwLock::new(compacted)))
+                .await
+            {
+                Ok(_) => {
+                    compacted_segments.push(segment_id);
+                    trace!("Compacted segment {} of topic {}", segment_id, topic_name)
+                }
+                Err(e) => {
+                    trace!("Failed to compact segment {}: {:?}", segment_id, e);
+                    state.dirty.insert(segment_id);
+                }
+            }
+        }
+
+        if emptied_segments.is_empty() {
+            return compacted_segments;
+        }
+        segments_index
+            .write()
+            .await
+            .retain(|(id, _)| !emptied_segments.contains(id));
+
+        for segment_id in emptied_segments {
+            match storage.remove_segment(topic_name, segment_id).await {
+                Ok(_) => trace!("Compacted segment {} of topic {}", segment_id, topic_name),
+                Err(e) => trace!("Failed to remove compacted segment {}: {:?}", segment_id, e),
+            }
+            compacted_segments.push(segment_id);
+        }
+        compacted_segments
+    }
+}
+
+// The progress of the compaction of a topic, kept between the passes
+#[derive(Debug, Default)]
+pub(crate) struct CompactionState {
+    // the last closed segment read by the compaction
+    last_scanned: Option<usize>,
+    // the position (segment_id, offset) of the latest message of each key
+    latest: HashMap<String, (usize, u64)>,
+    // the segments holding tombstones not yet expired, with their close time
+    tombstones: BTreeMap<usize, u64>,
+    // the segments holding superseded messages or expired tombstones, to be rewritten
+    dirty: BTreeSet<usize>,
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
 }
@@ -1,22 +1,34 @@
 #[cfg(test)]
 use crate::topic_cache::TopicCache;
 #[cfg(test)]
-use crate::{storage_backend::InMemoryStorage, topic_storage::TopicStore};
+use crate::{
+    cursor::{AckedSegment, SubscriptionCursor},
+    errors::ReliableDispatchError,
+};
+#[cfg(test)]
+use crate::{
+    storage_backend::InMemoryStorage,
+    topic_storage::{CompactionState, TopicStore},
+};
 
 #[cfg(test)]
 use danube_core::{
-    dispatch_strategy::{ReliableOptions, RetentionPolicy},
+    dispatch_strategy::{ReliableOptions, RetentionLimitPolicy, RetentionPolicy},
     message::{MessageID, StreamMessage},
-    storage::Segment,
+    storage::{CompressionCodec, Segment, StorageBackend, WalConfig, WalFsyncPolicy},
 };
 #[cfg(test)]
+use danube_persistent_storage::{DiskStorage, WriteAheadLog};
+#[cfg(test)]
 use dashmap::DashMap;
 #[cfg(test)]
 use std::collections::HashMap;
 #[cfg(test)]
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::sync::Arc;
 #[cfg(test)]
 use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(test)]
+use tokio::sync::{Mutex, RwLock};
 
 #[cfg(test)]
 fn create_test_message(segment_id: u64, segment_offset: u64, payload: Vec<u8>) -> StreamMessage {
@@ -37,6 +49,11 @@ fn create_test_message(segment_id: u64, segment_offset: u64, payload: Vec<u8>) -
         producer_name: "test-producer".to_string(),
         subscription_name: Some("test-subscription".to_string()),
         attributes: HashMap::new(),
+        key: None,
+        deliver_at: None,
+        deliver_after: None,
+        ttl: None,
+        expire_at: None,
     }
 }
 
@@ -101,7 +118,7 @@ async fn test_topic_store_message_storage() {
         3600, // 3600s retention period
     );
     let topic_name = "/default/test_topic";
-    let topic_cache = TopicCache::new(storage, 10, 10);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
     let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
     let message = create_test_message(0, 0, vec![1, 2, 3]);
 
@@ -125,7 +142,7 @@ async fn test_topic_store_segment_transition() {
         3600, // 3600s retention period
     );
     let topic_name = "/default/test_topic";
-    let topic_cache = TopicCache::new(storage, 10, 10);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
     let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
     let large_message = create_test_message(0, 0, vec![0; 1024 * 1024]); // 1MB message
 
@@ -163,11 +180,11 @@ async fn test_topic_store_cleanup() {
         1, // 1s retention period
     );
     let topic_name = "/default/test_topic";
-    let topic_cache = TopicCache::new(storage, 10, 10);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
     let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
     let subscriptions = Arc::new(DashMap::new());
     let subscription_id = "test_sub".to_string();
-    subscriptions.insert(subscription_id.clone(), Arc::new(AtomicUsize::new(0)));
+    subscriptions.insert(subscription_id.clone(), Arc::new(AckedSegment::default()));
 
     let message = create_test_message(0, 0, vec![1, 2, 3]);
     topic_store.store_message(message).await.unwrap();
@@ -218,11 +235,14 @@ async fn test_topic_store_acknowledged_cleanup() {
         3600, // 3600s retention period
     );
     let topic_name = "/default/test_topic";
-    let topic_cache = TopicCache::new(storage, 10, 10);
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
     let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
     let subscriptions = Arc::new(DashMap::new());
     let subscription_id = "test_sub".to_string();
-    subscriptions.insert(subscription_id.clone(), Arc::new(AtomicUsize::new(1)));
+    subscriptions.insert(
+        subscription_id.clone(),
+        Arc::new(AckedSegment::new(Some(1))),
+    );
 
     let message = create_test_message(0, 0, vec![1, 2, 3]);
     topic_store.store_message(message).await.unwrap();
@@ -254,3 +274,581 @@ async fn test_topic_store_acknowledged_cleanup() {
 
     assert!(!topic_store.contains_segment(0).await.unwrap());
 }
+
+/// Tests the retention of the segments of a subscription with nothing acknowledged
+/// Validates:
+/// - The closed segment 0 is retained until the subscription acknowledges it
+/// - The segment is removed once acknowledged
+#[tokio::test]
+async fn test_topic_store_nothing_acknowledged() {
+    let storage = Arc::new(InMemoryStorage::new());
+    let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 3600);
+    let topic_name = "/default/test_topic";
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+    let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
+    let subscriptions = Arc::new(DashMap::new());
+    let last_acked = Arc::new(AckedSegment::default());
+    subscriptions.insert("new_sub".to_string(), last_acked.clone());
+
+    // segment 0 is filled up and closed, segment 1 is the open one
+    for payload in [vec![0; 1024 * 1024], vec![1]] {
+        topic_store
+            .store_message(create_test_message(0, 0, payload))
+            .await
+            .unwrap();
+    }
+
+    TopicStore::cleanup_acknowledged_segments(
+        topic_name,
+        &topic_store.storage,
+        &topic_store.segments_index,
+        &subscriptions,
+    )
+    .await;
+    assert!(topic_store.contains_segment(0).await.unwrap());
+
+    last_acked.set(0);
+    TopicStore::cleanup_acknowledged_segments(
+        topic_name,
+        &topic_store.storage,
+        &topic_store.segments_index,
+        &subscriptions,
+    )
+    .await;
+    assert!(!topic_store.contains_segment(0).await.unwrap());
+}
+
+/// Tests the recovery of a topic from the write-ahead log after a broker crash
+/// Validates:
+/// - Messages of the open segment are replayed
+/// - Closed segments are restored in the segments index
+/// - New messages continue in the recovered open segment
+#[tokio::test]
+async fn test_topic_store_wal_recovery() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let wal_config = WalConfig {
+        path: temp_dir.path().to_str().unwrap().to_string(),
+        fsync: WalFsyncPolicy::Always,
+        fsync_interval_ms: 1000,
+    };
+    let storage = Arc::new(InMemoryStorage::new());
+    let reliable_options = ReliableOptions::new(
+        1, // 1MB segment size
+        RetentionPolicy::RetainUntilAck,
+        3600, // 3600s retention period
+    );
+    let topic_name = "/default/test_topic";
+
+    {
+        let wal = WriteAheadLog::open(&wal_config, topic_name).await.unwrap();
+        let topic_cache = TopicCache::new(storage.clone(), 1024 * 1024, 10);
+        let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options.clone())
+            .with_wal(Arc::new(wal));
+
+        // Fill the first segment, so the next messages are stored in the second one
+        topic_store
+            .store_message(create_test_message(0, 0, vec![0; 1024 * 1024]))
+            .await
+            .unwrap();
+        topic_store
+            .store_message(create_test_message(0, 0, vec![1]))
+            .await
+            .unwrap();
+        topic_store
+            .store_message(create_test_message(0, 0, vec![2]))
+            .await
+            .unwrap();
+        // The topic store is dropped without closing the open segment, as on a crash
+    }
+
+    let wal = WriteAheadLog::open(&wal_config, topic_name).await.unwrap();
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+    let topic_store =
+        TopicStore::new(topic_name, topic_cache, reliable_options).with_wal(Arc::new(wal));
+    topic_store.recover().await.unwrap();
+
+    {
+        let index = topic_store.segments_index.read().await;
+        assert_eq!(index.len(), 2);
+        assert!(index[0].1 > 0);
+        assert_eq!(index[1], (1, 0));
+    }
+
+    topic_store
+        .store_message(create_test_message(0, 0, vec![3]))
+        .await
+        .unwrap();
+
+    let first_segment = topic_store.get_next_segment(None).await.unwrap().unwrap();
+    let open_segment = topic_store
+        .get_next_segment(Some(first_segment.read().await.id))
+        .await
+        .unwrap()
+        .unwrap();
+    let open_segment = open_segment.read().await;
+    assert_eq!(open_segment.id, 1);
+    let payloads: Vec<Vec<u8>> = open_segment
+        .messages
+        .iter()
+        .map(|msg| msg.payload.clone())
+        .collect();
+    assert_eq!(payloads, vec![vec![1], vec![2], vec![3]]);
+    assert_eq!(open_segment.messages[2].msg_id.segment_offset, 2);
+}
+
+/// Tests the compaction of the closed segments of a keyed topic
+/// Validates:
+/// - Only the latest message of each key is retained, with its original offset
+/// - The messages without a key are retained
+/// - A tombstone deletes the key, and is removed once older than the retention period
+/// - The segments left empty are dropped
+/// - Only the segments changed since the previous pass are rewritten
+#[tokio::test]
+async fn test_topic_store_compaction() {
+    let storage = Arc::new(InMemoryStorage::new());
+    let topic_name = "/default/test_topic";
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+
+    let keyed = |segment_id: u64, offset: u64, key: &str, payload: Vec<u8>| {
+        let mut message = create_test_message(segment_id, offset, payload);
+        message.key = Some(key.to_string());
+        message
+    };
+
+    // segment 0: a=1, b=1, unkeyed ; segment 1: a=2, b tombstone ; segment 2: c=1
+    let segments = vec![
+        vec![
+            keyed(0, 0, "a", vec![1]),
+            keyed(0, 1, "b", vec![1]),
+            create_test_message(0, 2, vec![9]),
+        ],
+        vec![keyed(1, 0, "a", vec![2]), keyed(1, 1, "b", vec![])],
+        vec![keyed(2, 0, "c", vec![1])],
+    ];
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut index = Vec::new();
+    for (segment_id, messages) in segments.into_iter().enumerate() {
+        let mut segment = Segment::new(segment_id, 1024);
+        for message in messages {
+            segment.add_message(message);
+        }
+        segment.close_time = now;
+        topic_cache
+            .put_segment(topic_name, segment_id, Arc::new(RwLock::new(segment)))
+            .await
+            .unwrap();
+        index.push((segment_id, now));
+    }
+    let segments_index = Arc::new(RwLock::new(index));
+    let mut compaction = CompactionState::default();
+
+    TopicStore::compact_segments(
+        topic_name,
+        &topic_cache,
+        &segments_index,
+        3600,
+        &mut compaction,
+    )
+    .await;
+
+    let segment = topic_cache
+        .get_segment(topic_name, 0)
+        .await
+        .unwrap()
+        .unwrap();
+    let offsets: Vec<u64> = segment
+        .read()
+        .await
+        .messages
+        .iter()
+        .map(|message| message.msg_id.segment_offset)
+        .collect();
+    assert_eq!(offsets, vec![2]);
+
+    let segment = topic_cache
+        .get_segment(topic_name, 1)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(segment.read().await.messages.len(), 2);
+
+    // Once the tombstone expired, it is removed as well
+    TopicStore::compact_segments(
+        topic_name,
+        &topic_cache,
+        &segments_index,
+        0,
+        &mut compaction,
+    )
+    .await;
+    let segment = topic_cache
+        .get_segment(topic_name, 1)
+        .await
+        .unwrap()
+        .unwrap();
+    let messages = segment.read().await.messages.clone();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].key.as_deref(), Some("a"));
+    assert_eq!(messages[0].payload, vec![2]);
+
+    // A segment left without messages is dropped, only the segment closed since is read
+    let mut segment = Segment::new(3, 1024);
+    segment.add_message(keyed(3, 0, "c", vec![]));
+    segment.close_time = now;
+    topic_cache
+        .put_segment(topic_name, 3, Arc::new(RwLock::new(segment)))
+        .await
+        .unwrap();
+    segments_index.write().await.push((3, now));
+    let compacted = TopicStore::compact_segments(
+        topic_name,
+        &topic_cache,
+        &segments_index,
+        0,
+        &mut compaction,
+    )
+    .await;
+    assert_eq!(compacted, vec![2, 3]);
+    assert_eq!(
+        segments_index
+            .read()
+            .await
+            .iter()
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    assert!(topic_cache
+        .get_segment(topic_name, 2)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Nothing changed since the previous pass, no segment is rewritten
+    let compacted = TopicStore::compact_segments(
+        topic_name,
+        &topic_cache,
+        &segments_index,
+        0,
+        &mut compaction,
+    )
+    .await;
+    assert!(compacted.is_empty());
+}
+
+/// Tests the max segments limit with the DropOldest policy
+/// Validates:
+/// - The oldest closed segments are dropped until the topic is within the limit
+/// - The lagging subscription skips the dropped segments
+/// - The lagging subscription cursor moves to the first retained segment
+#[tokio::test]
+async fn test_retention_limits_drop_oldest() {
+    let storage = Arc::new(InMemoryStorage::new());
+    let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 3600)
+        .with_retention_limits(0, 2, RetentionLimitPolicy::DropOldest);
+    let topic_name = "/default/test_topic";
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+    let topic_store = TopicStore::new(topic_name, topic_cache.clone(), reliable_options);
+
+    // segments 0, 1 and 2 are filled up and closed, segment 3 is the open one
+    for payload in [
+        vec![0; 1024 * 1024],
+        vec![1; 1024 * 1024],
+        vec![2; 1024 * 1024],
+    ] {
+        topic_store
+            .store_message(create_test_message(0, 0, payload))
+            .await
+            .unwrap();
+    }
+    topic_store
+        .store_message(create_test_message(0, 0, vec![3]))
+        .await
+        .unwrap();
+
+    let subscriptions = DashMap::new();
+    subscriptions.insert("lagging".to_string(), Arc::new(AckedSegment::default()));
+    let cursors = DashMap::new();
+    cursors.insert(
+        "lagging".to_string(),
+        Arc::new(Mutex::new(SubscriptionCursor::new(0))),
+    );
+
+    topic_store
+        .enforce_retention_limits(&subscriptions, &cursors, &mut HashMap::new())
+        .await;
+
+    let index: Vec<usize> = topic_store
+        .segments_index
+        .read()
+        .await
+        .iter()
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(index, vec![2, 3]);
+    assert!(topic_cache
+        .get_segment(topic_name, 0)
+        .await
+        .unwrap()
+        .is_none());
+
+    let last_acked = subscriptions.get("lagging").unwrap().value().clone();
+    assert_eq!(last_acked.get(), Some(1));
+    let cursor = cursors.get("lagging").unwrap().value().clone();
+    assert_eq!(cursor.lock().await.segment_id(), 2);
+
+    // the messages are still accepted
+    topic_store
+        .store_message(create_test_message(0, 0, vec![4]))
+        .await
+        .unwrap();
+}
+
+/// Tests the max segments limit with the Backpressure policy
+/// Validates:
+/// - The messages are rejected while the topic is over the limit
+/// - No segment is dropped
+/// - The messages are accepted again once the acknowledged segments are removed
+#[tokio::test]
+async fn test_retention_limits_backpressure() {
+    let storage = Arc::new(InMemoryStorage::new());
+    let reliable_options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 3600)
+        .with_retention_limits(0, 2, RetentionLimitPolicy::Backpressure);
+    let topic_name = "/default/test_topic";
+    let topic_cache = TopicCache::new(storage, 1024 * 1024, 10);
+    let topic_store = TopicStore::new(topic_name, topic_cache.clone(), reliable_options);
+
+    // segments 0 and 1 are filled up and closed, segment 2 is the open one
+    for payload in [vec![0; 1024 * 1024], vec![1; 1024 * 1024], vec![2]] {
+        topic_store
+            .store_message(create_test_message(0, 0, payload))
+            .await
+            .unwrap();
+    }
+
+    let subscriptions = Arc::new(DashMap::new());
+    let last_acked = Arc::new(AckedSegment::default());
+    subscriptions.insert("slow".to_string(), last_acked.clone());
+    let cursors = DashMap::new();
+    let mut segment_sizes = HashMap::new();
+
+    topic_store
+        .enforce_retention_limits(&subscriptions, &cursors, &mut segment_sizes)
+        .await;
+
+    let result = topic_store
+        .store_message(create_test_message(0, 0, vec![3]))
+        .await;
+    assert!(matches!(
+        result,
+        Err(ReliableDispatchError::RetentionLimitReached(_))
+    ));
+    assert_eq!(topic_store.segments_index.read().await.len(), 3);
+
+    // the subscription acknowledges the closed segments
+    last_acked.set(1);
+    TopicStore::cleanup_acknowledged_segments(
+        topic_name,
+        &topic_cache,
+        &topic_store.segments_index,
+        &subscriptions,
+    )
+    .await;
+    topic_store
+        .enforce_retention_limits(&subscriptions, &cursors, &mut segment_sizes)
+        .await;
+
+    topic_store
+        .store_message(create_test_message(0, 0, vec![3]))
+        .await
+        .unwrap();
+}
+
+/// Tests the compression of the closed segments, per broker and per topic
+/// Validates:
+/// - The topics without a codec use the broker storage compression
+/// - The codec of the reliable options overrides the broker compression
+/// - The segments stored with different codecs are read back
+#[tokio::test]
+async fn test_segment_compression() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let storage = Arc::new(DiskStorage::new(temp_dir.path().to_str().unwrap()));
+    let topic_cache =
+        TopicCache::new(storage.clone(), 1024 * 1024, 10).with_compression(CompressionCodec::Lz4);
+
+    let default_topic = "/default/default_codec";
+    let zstd_topic = "/default/zstd_codec";
+    let plain_topic = "/default/no_codec";
+    let options = ReliableOptions::new(1, RetentionPolicy::RetainUntilAck, 3600);
+    let stores = [
+        TopicStore::new(default_topic, topic_cache.clone(), options.clone()),
+        TopicStore::new(
+            zstd_topic,
+            topic_cache.clone(),
+            options.clone().with_compression(CompressionCodec::Zstd),
+        ),
+        TopicStore::new(
+            plain_topic,
+            topic_cache.clone(),
+            options.with_compression(CompressionCodec::None),
+        ),
+    ];
+
+    // the JSON payloads repeat the field names
+    let payload = br#"{"sensor":"temperature","unit":"celsius","value":21.5}"#.to_vec();
+    for topic_store in &stores {
+        for _ in 0..20_000 {
+            topic_store
+                .store_message(create_test_message(0, 0, payload.clone()))
+                .await
+                .unwrap();
+        }
+        // closes the first segment
+        topic_store
+            .store_message(create_test_message(0, 0, vec![0; 1024 * 1024]))
+            .await
+            .unwrap();
+    }
+
+    let segment_file_size = |topic_name: &str| {
+        let path = temp_dir
+            .path()
+            .join(topic_name.trim_start_matches('/'))
+            .join("segment_0.log");
+        std::fs::metadata(path).unwrap().len()
+    };
+    let plain_size = segment_file_size(plain_topic);
+    assert!(segment_file_size(default_topic) * 5 < plain_size);
+    assert!(segment_file_size(zstd_topic) * 5 < plain_size);
+
+    // read back from the disk, not from the memory cache
+    for topic_name in [default_topic, zstd_topic, plain_topic] {
+        let segment = storage.get_segment(topic_name, 0).await.unwrap().unwrap();
+        let segment = segment.read().await;
+        assert!(segment.close_time > 0);
+        assert!(segment.messages.iter().all(|m| m.payload == payload));
+    }
+}
+
+/// Validates:
+/// - the cached segments are weighted by their size and stay within the byte budget
+/// - the open segments are pinned, these are never evicted
+/// - the evicted segments are loaded again from the storage backend
+#[tokio::test]
+async fn test_cache_byte_budget() {
+    let storage = Arc::new(InMemoryStorage::new());
+    let topic_cache = TopicCache::new(storage, 4096, 10);
+    let topic_name = "/default/test-topic";
+
+    let fill_segment = |segment_id: usize| {
+        let mut segment = Segment::new(segment_id, 1024 * 1024);
+        for offset in 0..4 {
+            segment.add_message(create_test_message(segment_id as u64, offset, vec![0; 500]));
+        }
+        segment
+    };
+
+    // the open segment alone is over the budget
+    let mut open_segment = fill_segment(100);
+    for offset in 4..16 {
+        open_segment.add_message(create_test_message(100, offset, vec![0; 500]));
+    }
+    topic_cache
+        .put_segment(topic_name, 100, Arc::new(RwLock::new(open_segment)))
+        .await
+        .unwrap();
+
+    for segment_id in 0..10 {
+        let mut segment = fill_segment(segment_id);
+        segment.close_time = 1;
+        topic_cache
+            .put_segment(topic_name, segment_id, Arc::new(RwLock::new(segment)))
+            .await
+            .unwrap();
+    }
+
+    assert!(topic_cache.cached_bytes().await <= 4096);
+    assert!(topic_cache.is_cached(topic_name, 100).await);
+
+    let mut evicted = 0;
+    for segment_id in 0..10 {
+        if !topic_cache.is_cached(topic_name, segment_id).await {
+            evicted += 1;
+        }
+        let segment = topic_cache
+            .get_segment(topic_name, segment_id)
+            .await
+            .unwrap()
+            .expect("the evicted segment is in the storage backend");
+        assert_eq!(segment.read().await.messages.len(), 4);
+    }
+    assert!(evicted >= 8);
+    assert!(topic_cache.cached_bytes().await <= 4096);
+}
+
+/// Tests the time based rolling of the open segment
+/// Validates:
+/// - The open segment is kept while younger than segment_max_age
+/// - The aged segment is closed and persisted even if not full
+/// - The next messages are stored in the following segment
+#[tokio::test]
+async fn test_segment_max_age() {
+    let storage = Arc::new(InMemoryStorage::new());
+    let reliable_options =
+        ReliableOptions::new(1, RetentionPolicy::RetainUntilExpire, 3600).with_segment_max_age(60);
+    let topic_name = "/default/test_topic";
+    let topic_cache = TopicCache::new(storage.clone(), 1024 * 1024, 10);
+    let topic_store = TopicStore::new(topic_name, topic_cache, reliable_options);
+
+    // an empty open segment is not rolled
+    topic_store.roll_aged_segment().await.unwrap();
+    assert!(topic_store.segments_index.read().await.is_empty());
+
+    for offset in 0..2 {
+        topic_store
+            .store_message(create_test_message(0, offset, vec![1, 2, 3]))
+            .await
+            .unwrap();
+    }
+    topic_store.roll_aged_segment().await.unwrap();
+    assert_eq!(*topic_store.segments_index.read().await, vec![(0, 0)]);
+    assert!(storage.get_segment(topic_name, 0).await.unwrap().is_none());
+
+    // the first message of the segment is older than segment_max_age
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    topic_store
+        .segment_opened_at
+        .store(now - 120, std::sync::atomic::Ordering::Release);
+    topic_store.roll_aged_segment().await.unwrap();
+
+    let index = topic_store.segments_index.read().await.clone();
+    assert_eq!(index.len(), 2);
+    assert!(index[0].1 > 0);
+    assert_eq!(index[1], (1, 0));
+    assert_eq!(*topic_store.current_segment_id.read().await, 1);
+
+    let stored = storage.get_segment(topic_name, 0).await.unwrap().unwrap();
+    assert!(stored.read().await.close_time > 0);
+    assert_eq!(stored.read().await.messages.len(), 2);
+
+    topic_store
+        .store_message(create_test_message(0, 0, vec![4, 5, 6]))
+        .await
+        .unwrap();
+    let segment = topic_store
+        .get_next_segment(Some(0))
+        .await
+        .unwrap()
+        .unwrap();
+    let segment = segment.read().await;
+    assert_eq!(segment.id, 1);
+    assert_eq!(segment.messages.len(), 1);
+    assert_eq!(segment.messages[0].msg_id.segment_id, 1);
+    assert_eq!(segment.messages[0].msg_id.segment_offset, 0);
+}